@@ -0,0 +1,92 @@
+//! Serialization of large image payloads
+//!
+//! `cargo bench --bench image_upload` compares the owned chunks of
+//! [Serializable::as_bytes_chunks] with the borrowed chunks of [Serializable::chunks], and
+//! measures a whole upload through [ClientSender] into a sink.
+use core::convert::Infallible;
+
+use activelook_rs::{
+    client::ClientSender,
+    commands::{ImgFormat, Point},
+    image::Image,
+    protocol::PACKET_DATA_MAX_SIZE,
+    traits::*,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+
+/// Full screen 4bpp image
+const WIDTH: u16 = 304;
+const HEIGHT: usize = 256;
+
+/// Rx characteristic discarding every write
+struct Sink;
+
+impl ErrorType for Sink {
+    type Error = Infallible;
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Control characteristic without notifications
+struct NoCtrl;
+
+impl ErrorType for NoCtrl {
+    type Error = Infallible;
+}
+
+impl Read for NoCtrl {
+    fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(0)
+    }
+}
+
+impl ReadReady for NoCtrl {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+}
+
+fn image_upload(c: &mut Criterion) {
+    let data: Vec<u8> = (0..WIDTH as usize / 2 * HEIGHT).map(|i| i as u8).collect();
+    let image = Image {
+        width: WIDTH,
+        format: ImgFormat::Img4bpp,
+        data: &data,
+    };
+    let cmd = image.save_command(1);
+
+    let mut group = c.benchmark_group("image_upload");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("as_bytes_chunks", |b| {
+        b.iter(|| black_box(&cmd).as_bytes_chunks(PACKET_DATA_MAX_SIZE))
+    });
+    group.bench_function("chunks", |b| {
+        b.iter(|| black_box(&cmd).chunks(PACKET_DATA_MAX_SIZE))
+    });
+    group.bench_function("send_chunked", |b| {
+        let mut sender = ClientSender::new(Sink, NoCtrl);
+        sender.set_mtu(247);
+        b.iter(|| sender.send_chunked(black_box(&cmd), PACKET_DATA_MAX_SIZE))
+    });
+    group.bench_function("stream_image", |b| {
+        let mut sender = ClientSender::new(Sink, NoCtrl);
+        let image = Image {
+            format: ImgFormat::Img1bpp,
+            ..image
+        };
+        b.iter(|| sender.stream_image(black_box(&image), Point { x: 0, y: 0 }, 247))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, image_upload);
+criterion_main!(benches);
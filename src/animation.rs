@@ -0,0 +1,246 @@
+//! Animations
+//!
+//! [Command::AnimSave] only carries the header of an animation. Its data follows in the next
+//! packets, like a chunked [Command::ImgSave]: the reference frame in 4bpp, then each following
+//! frame encoded as its differences with the previous one.
+//!
+//! **Experimental**: the frame encoding and the `compressedSize` field are not described by
+//! `spec/ActiveLook_API.md`, which only lists the fields of [Command::AnimSave]. The format
+//! summarized on [Animation::encode] is an assumption, not yet checked on real glasses: the API
+//! of this module may change once it is.
+
+use thiserror::Error;
+
+use crate::{
+    commands::{split_aligned, Command},
+    protocol::RawPayload,
+    traits::*,
+};
+
+/// Data bytes of the [Command::AnimSave] header
+pub const ANIM_SAVE_HEADER_LEN: usize = 16;
+
+/// Errors building an animation
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum AnimationError {
+    #[error("Frame has {0} pixels, expected {1}")]
+    FrameSize(usize, usize),
+    #[error("Grey level {0} is out of range 0..15")]
+    InvalidColor(u8),
+    #[error("An animation needs at least one frame")]
+    NoFrame,
+}
+
+/// Sequence of frames of the same size
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Animation {
+    width: u16,
+    height: u16,
+    /// Grey level of each pixel, line by line
+    frames: Vec<Vec<u8>>,
+}
+
+impl Animation {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    pub fn nb_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Append a frame, given as the grey level (0..15) of each pixel, line by line
+    pub fn push_frame(&mut self, pixels: &[u8]) -> Result<(), AnimationError> {
+        let expected = self.width as usize * self.height as usize;
+        if pixels.len() != expected {
+            return Err(AnimationError::FrameSize(pixels.len(), expected));
+        }
+        if let Some(level) = pixels.iter().find(|level| **level > 15) {
+            return Err(AnimationError::InvalidColor(*level));
+        }
+        self.frames.push(pixels.to_vec());
+        Ok(())
+    }
+
+    /// Bytes of a 4bpp line: 2 pixels per byte, the first one in the low nibble
+    fn pack(pixels: &[u8]) -> Vec<u8> {
+        pixels
+            .chunks(2)
+            .map(|pair| pair[0] | pair.get(1).map_or(0, |p| p << 4))
+            .collect()
+    }
+
+    fn line<'a>(&self, frame: &'a [u8], y: usize) -> &'a [u8] {
+        let width = self.width as usize;
+        &frame[y * width..(y + 1) * width]
+    }
+
+    /// Reference frame, in 4bpp
+    pub fn reference(&self) -> Vec<u8> {
+        self.frames
+            .first()
+            .map(|frame| {
+                (0..self.height as usize)
+                    .flat_map(|y| Self::pack(self.line(frame, y)))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Differences between `frame` and its previous frame
+    fn delta(&self, previous: &[u8], frame: &[u8]) -> Vec<u8> {
+        let mut nb_lines: u16 = 0;
+        let mut lines = Vec::new();
+        for y in 0..self.height as usize {
+            let (old, new) = (self.line(previous, y), self.line(frame, y));
+            let Some(first) = old.iter().zip(new).position(|(a, b)| a != b) else {
+                continue;
+            };
+            let last = old
+                .iter()
+                .zip(new)
+                .rposition(|(a, b)| a != b)
+                .unwrap_or(first);
+            // Spans start on a byte boundary
+            let start = first & !1;
+            let bytes = Self::pack(&new[start..=last]);
+            lines.extend((y as u16).to_be_bytes());
+            lines.extend((start as u16).to_be_bytes());
+            lines.extend((bytes.len() as u16).to_be_bytes());
+            lines.extend(bytes);
+            nb_lines += 1;
+        }
+        let mut res = nb_lines.to_be_bytes().to_vec();
+        res.extend(lines);
+        res
+    }
+
+    /// Whole animation data: the reference frame in 4bpp, followed by each next frame.
+    ///
+    /// A frame lists the lines differing from the previous frame:
+    /// - `uint16` number of lines
+    /// - for each line: `uint16 y`, `uint16 x` of the first changed pixel rounded down to an even
+    ///   value, `uint16` number of bytes, then the 4bpp pixels from `x` to the last changed pixel
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = self.reference();
+        for pair in self.frames.windows(2) {
+            res.extend(self.delta(&pair[0], &pair[1]));
+        }
+        res
+    }
+
+    /// [Command::AnimSave] header of the animation, for `data` returned by [Animation::encode]
+    ///
+    /// The reference frame is not compressed: its compressed size is assumed to be its size.
+    fn save_header(&self, id: u8, data: &[u8]) -> Command {
+        let img_size = self.reference().len() as u32;
+        Command::AnimSave {
+            id,
+            total_size: data.len() as u32,
+            img_size,
+            width: self.width,
+            fmt: 0,
+            img_compressed_size: img_size,
+        }
+    }
+
+    /// Payloads uploading the animation as `id`: the [Command::AnimSave] header, then the data
+    /// in chunks of at most `chunk_size` bytes, aligned on the lines of the reference frame
+    /// according to its [crate::commands::ChunkPolicy]. Send them with
+    /// [crate::client::ActiveLookClient::send_bulk].
+    pub fn payloads(&self, id: u8, chunk_size: usize) -> Result<Vec<RawPayload>, AnimationError> {
+        if self.frames.is_empty() {
+            return Err(AnimationError::NoFrame);
+        }
+        let data = self.encode();
+        let header = self.save_header(id, &data);
+        let cmd_id = header.id().expect("AnimSave ID");
+        Ok(split_aligned(&header, &data, chunk_size)
+            .expect("AnimSave serialization")
+            .into_iter()
+            .map(|chunk| RawPayload {
+                id: cmd_id,
+                data: chunk.into_owned(),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Response;
+    use crate::emulator::Emulator;
+    use crate::protocol::{Packet, RawPacket, PACKET_DATA_MAX_SIZE};
+
+    fn square(size: u16, x: usize) -> Vec<u8> {
+        let size = size as usize;
+        let mut frame = vec![0; size * size];
+        for y in 0..2 {
+            frame[y * size + x] = 15;
+            frame[y * size + x + 1] = 15;
+        }
+        frame
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut anim = Animation::new(6, 3);
+        anim.push_frame(&square(6, 0)[..18]).unwrap();
+        let mut moved = vec![0; 18];
+        moved[3] = 15;
+        moved[4] = 15;
+        anim.push_frame(&moved).unwrap();
+        assert_eq!(
+            Err(AnimationError::FrameSize(4, 18)),
+            anim.push_frame(&[0; 4])
+        );
+
+        let data = anim.encode();
+        // Reference: 3 lines of 3 bytes
+        assert_eq!(&[0xFF, 0, 0, 0xFF, 0, 0, 0, 0, 0], &data[..9]);
+        // Line 0 changes from pixel 0 to 4, line 1 from pixel 0 to 1
+        assert_eq!(
+            &[0, 2, 0, 0, 0, 0, 0, 3, 0, 0xF0, 0x0F, 0, 1, 0, 0, 0, 1, 0][..],
+            &data[9..]
+        );
+    }
+
+    #[test]
+    fn test_upload() {
+        let mut anim = Animation::new(40, 40);
+        for x in 0..20 {
+            anim.push_frame(&square(40, x)).unwrap();
+        }
+        let payloads = anim.payloads(3, PACKET_DATA_MAX_SIZE).unwrap();
+        assert_eq!(ANIM_SAVE_HEADER_LEN, payloads[0].data.len());
+
+        let mut emulator = Emulator::new();
+        emulator.handle(&Command::CfgWrite {
+            name: String::from("anim"),
+            version: 1,
+            password: 0,
+        });
+        for payload in &payloads {
+            let bytes = Packet::new(payload).unwrap().to_bytes();
+            let packet = RawPacket::from_bytes(&bytes).unwrap();
+            assert!(emulator.handle_packet(&packet).is_none());
+        }
+        assert_eq!(
+            Some(Response::AnimList { list: vec![3] }),
+            emulator.handle(&Command::AnimList)
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Construction of arcs and circles
+//!
+//! [Command::Arc] takes whole degrees, and a thickness drawn inwards from the radius. [CircleArc]
+//! takes angles in any range and converts them to commands the firmware draws as expected:
+//! - angles are normalized to `0..=360`, an arc crossing 0° is split in two commands,
+//! - an arc of 360° or more is drawn as a full circle,
+//! - with [CircleArc::max_thickness], a thicker arc is drawn as several concentric arcs.
+//!
+//! Angles begin at 3 o'clock and increase clockwise, like [Command::Arc]. An arc goes clockwise
+//! from its start to its end angle: from 350° to 10° is the 20° arc across 3 o'clock.
+
+use crate::commands::{Command, Point};
+
+/// Part of a ring, between two angles in degrees
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CircleArc {
+    center: Point,
+    r: u8,
+    start: f32,
+    end: f32,
+    thickness: u8,
+    max_thickness: Option<u8>,
+}
+
+impl CircleArc {
+    /// Arc of radius `r`, from `start` to `end` degrees, one pixel thick
+    pub fn new(center: Point, r: u8, start: f32, end: f32) -> Self {
+        Self {
+            center,
+            r,
+            start,
+            end,
+            thickness: 1,
+            max_thickness: None,
+        }
+    }
+
+    /// Whole circle of radius `r`
+    pub fn circle(center: Point, r: u8) -> Self {
+        Self::new(center, r, 0.0, 360.0)
+    }
+
+    /// Width of the arc in pixels, inwards from the radius
+    pub fn thickness(mut self, thickness: u8) -> Self {
+        self.thickness = thickness;
+        self
+    }
+
+    /// Thickest arc drawn by one command. Thicker arcs are drawn as concentric arcs, each one at
+    /// most `max_thickness` pixels wide.
+    pub fn max_thickness(mut self, max_thickness: u8) -> Self {
+        self.max_thickness = Some(max_thickness.max(1));
+        self
+    }
+
+    /// Angle ranges of the arc, each one within `0..=360` with its start before its end
+    fn ranges(&self) -> Vec<(i16, i16)> {
+        let sweep = self.end - self.start;
+        if !sweep.is_finite() || sweep == 0.0 {
+            return Vec::new();
+        }
+        if sweep.abs() >= 360.0 {
+            return vec![(0, 360)];
+        }
+        let start = self.start.rem_euclid(360.0);
+        let end = (start + sweep.rem_euclid(360.0)).round() as i16;
+        let (start, end) = match start.round() as i16 {
+            360 => (0, end - 360),
+            start => (start, end),
+        };
+        match end <= 360 {
+            true => vec![(start, end)],
+            false => vec![(start, 360), (0, end - 360)],
+        }
+    }
+
+    /// Radius and thickness of each concentric arc
+    fn rings(&self) -> Vec<(u8, u8)> {
+        let thickness = self.thickness.max(1);
+        let step = self.max_thickness.unwrap_or(thickness);
+        let mut rings = Vec::new();
+        let mut r = self.r;
+        let mut remaining = thickness.min(self.r.saturating_add(1));
+        while remaining > 0 {
+            let width = remaining.min(step);
+            rings.push((r, width));
+            remaining -= width;
+            r = r.saturating_sub(width);
+        }
+        rings
+    }
+
+    /// Commands drawing the arc, in the order of [CircleArc::ranges] then outer rings first
+    pub fn commands(&self) -> Vec<Command> {
+        let rings = self.rings();
+        self.ranges()
+            .into_iter()
+            .flat_map(|(angle_start, angle_end)| {
+                rings.iter().map(move |&(r, thickness)| Command::Arc {
+                    center: self.center,
+                    r,
+                    angle_start,
+                    angle_end,
+                    thickness,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CENTER: Point = Point { x: 150, y: 128 };
+
+    fn arc(r: u8, angle_start: i16, angle_end: i16, thickness: u8) -> Command {
+        Command::Arc {
+            center: CENTER,
+            r,
+            angle_start,
+            angle_end,
+            thickness,
+        }
+    }
+
+    #[test]
+    fn test_angles() {
+        assert_eq!(
+            vec![arc(50, 10, 100, 1)],
+            CircleArc::new(CENTER, 50, 10.2, 99.6).commands()
+        );
+        // Crossing 0°, given both ways
+        let crossing = vec![arc(50, 350, 360, 1), arc(50, 0, 10, 1)];
+        assert_eq!(crossing, CircleArc::new(CENTER, 50, 350.0, 10.0).commands());
+        assert_eq!(crossing, CircleArc::new(CENTER, 50, -10.0, 10.0).commands());
+        assert_eq!(
+            crossing,
+            CircleArc::new(CENTER, 50, 710.0, 730.0).commands()
+        );
+        // More than a turn
+        assert_eq!(
+            vec![arc(50, 0, 360, 1)],
+            CircleArc::new(CENTER, 50, 45.0, 500.0).commands()
+        );
+        assert_eq!(
+            CircleArc::new(CENTER, 50, 0.0, 360.0),
+            CircleArc::circle(CENTER, 50)
+        );
+        assert_eq!(
+            vec![arc(50, 0, 10, 1)],
+            CircleArc::new(CENTER, 50, 359.8, 370.0).commands()
+        );
+        assert!(CircleArc::new(CENTER, 50, 30.0, 30.0).commands().is_empty());
+        assert!(CircleArc::new(CENTER, 50, 0.0, f32::NAN)
+            .commands()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_thick_arc() {
+        let thick = CircleArc::new(CENTER, 50, 0.0, 90.0).thickness(25);
+        assert_eq!(vec![arc(50, 0, 90, 25)], thick.commands());
+        assert_eq!(
+            vec![arc(50, 0, 90, 10), arc(40, 0, 90, 10), arc(30, 0, 90, 5)],
+            thick.max_thickness(10).commands()
+        );
+        // No thicker than a disc
+        assert_eq!(
+            vec![arc(5, 0, 90, 4), arc(1, 0, 90, 2)],
+            CircleArc::new(CENTER, 5, 0.0, 90.0)
+                .thickness(20)
+                .max_thickness(4)
+                .commands()
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! ActiveLook command line tool
+use std::{fs, io, path::PathBuf};
+
+use activelook_rs::vectors::{self, Vector};
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(name = "activelook-cli", version, about = "ActiveLook protocol tools")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Write the encoding of every Command and Response variant, for interoperability tests
+    GenVectors {
+        /// Output directory
+        #[arg(long)]
+        out: PathBuf,
+    },
+}
+
+fn write_vectors(path: PathBuf, vectors: &[Vector]) -> io::Result<()> {
+    let mut content = String::from(vectors::HEADER);
+    content.push('\n');
+    for vector in vectors {
+        content.push_str(&vector.to_line());
+        content.push('\n');
+    }
+    fs::write(&path, content)?;
+    println!("{}: {} vectors", path.display(), vectors.len());
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Commands::GenVectors { out } => {
+            fs::create_dir_all(&out)?;
+            write_vectors(out.join("commands.tsv"), &vectors::command_vectors())?;
+            write_vectors(out.join("responses.tsv"), &vectors::response_vectors())?;
+        }
+    }
+    Ok(())
+}
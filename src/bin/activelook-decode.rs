@@ -0,0 +1,71 @@
+//! Decoder of captured ActiveLook BLE traffic
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use activelook_rs::{
+    recorder::Event,
+    sniffer::{self, Decoded, Direction, BTSNOOP_MAGIC},
+};
+use clap::Parser;
+
+/// Print the commands and responses of a btsnoop capture or a hex dump
+#[derive(Parser)]
+#[command(name = "activelook-decode", version)]
+struct Cli {
+    /// btsnoop capture, or hex dump with one `>` (sent) or `<` (received) line per BLE value.
+    /// Read from standard input if missing.
+    input: Option<PathBuf>,
+}
+
+fn print(decoded: &Decoded) {
+    let time = match decoded.time {
+        Some(time) => format!("{:>12.6} ", time.as_secs_f64()),
+        None => String::new(),
+    };
+    let direction = match decoded.direction {
+        Direction::ToGlasses => '>',
+        Direction::FromGlasses => '<',
+    };
+    let query_id = match &decoded.query_id {
+        Some(query_id) => format!(" (query {query_id:02X?})"),
+        None => String::new(),
+    };
+    match &decoded.message {
+        Ok(Event::Sent(cmd)) => println!("{time}{direction} {cmd:?}{query_id}"),
+        Ok(Event::Received(response)) => println!("{time}{direction} {response:?}{query_id}"),
+        Err(error) => println!("{time}{direction} Error: {error}"),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut input = Vec::new();
+    let read = match &cli.input {
+        Some(path) => fs::read(path).map(|bytes| input = bytes),
+        None => io::stdin().read_to_end(&mut input).map(|_| ()),
+    };
+    if let Err(error) = read {
+        eprintln!("Cannot read the capture: {error}");
+        return ExitCode::FAILURE;
+    }
+
+    let decoded = if input.starts_with(BTSNOOP_MAGIC) {
+        sniffer::parse_btsnoop(&input)
+    } else {
+        sniffer::parse_hex_dump(&String::from_utf8_lossy(&input))
+    };
+    match decoded {
+        Ok(decoded) => {
+            decoded.iter().for_each(print);
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{error}");
+            ExitCode::FAILURE
+        }
+    }
+}
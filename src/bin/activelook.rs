@@ -0,0 +1,296 @@
+//! Command line tool for common operations on ActiveLook glasses
+//!
+//! Real glasses are reached through the `btleplug` feature. `--emulator` runs the commands
+//! against the in-process [Emulator] instead, to try the tool without glasses.
+use std::{error::Error, fs::File, path::PathBuf, process::ExitCode, thread, time::Duration};
+
+use activelook_rs::{
+    client::ActiveLookClient,
+    commands::{Command, DemoID, DeviceInfo, ImgFormat, Response},
+    device_info::DeviceInfoValue,
+    emulator::Emulator,
+    glasses::{Glasses, GlassesApi},
+    image::Image,
+    server::ActiveLookServer,
+    transport::loopback::loopback,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(Parser)]
+#[command(name = "activelook", version, about = "Drive ActiveLook glasses")]
+struct Cli {
+    /// Use the built-in emulator instead of real glasses
+    #[arg(long)]
+    emulator: bool,
+    /// Connect to the glasses whose advertised name contains this text
+    #[arg(long)]
+    name: Option<String>,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Print the device information parameters
+    Info,
+    /// Print the battery level
+    Battery,
+    /// Manage the images of a configuration
+    #[command(subcommand)]
+    Img(ImgCommands),
+    /// Manage the configurations
+    #[command(subcommand)]
+    Cfg(CfgCommands),
+    /// Run a demonstration pattern of the firmware
+    Demo {
+        #[arg(value_enum, default_value_t = Demo::Fill)]
+        demo: Demo,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImgCommands {
+    /// List the images of the current configuration
+    List,
+    /// Save a PNG image, converted to 8 bits per pixel (grey level and alpha)
+    Upload {
+        file: PathBuf,
+        #[arg(long)]
+        id: u8,
+        /// Configuration receiving the image
+        #[arg(long, default_value = "cli")]
+        cfg: String,
+        /// Version given to the configuration
+        #[arg(long, default_value_t = 1)]
+        cfg_version: u32,
+        #[arg(long, default_value_t = 0)]
+        password: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum CfgCommands {
+    /// List the configurations stored in the glasses
+    List,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Demo {
+    Fill,
+    Rect,
+    Images,
+}
+
+impl From<Demo> for DemoID {
+    fn from(demo: Demo) -> Self {
+        match demo {
+            Demo::Fill => DemoID::Fill,
+            Demo::Rect => DemoID::Rect,
+            Demo::Images => DemoID::Images,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+const INFO: [(DeviceInfo, &str); 10] = [
+    (DeviceInfo::Manufacturer, "Manufacturer"),
+    (DeviceInfo::Model, "Model"),
+    (DeviceInfo::SubModel, "Sub-model"),
+    (DeviceInfo::HWPlatform, "Hardware platform"),
+    (DeviceInfo::FWVersion, "Firmware"),
+    (DeviceInfo::SerialNumber, "Serial number"),
+    (DeviceInfo::BatteryModel, "Battery"),
+    (DeviceInfo::LensModel, "Lens"),
+    (DeviceInfo::DisplayModel, "Display"),
+    (DeviceInfo::DisplayOrientation, "Display orientation"),
+];
+
+fn info(glasses: &mut impl GlassesApi) -> Result<()> {
+    for (id, label) in INFO {
+        let value = match glasses.device_info(id)? {
+            DeviceInfoValue::Text(text) => text,
+            DeviceInfoValue::FirmwareVersion(version) => version.to_string(),
+            DeviceInfoValue::ManufacturerId(id) => format!("0x{id:04X}"),
+            DeviceInfoValue::DisplayOrientation(orientation) => format!("{orientation:?}"),
+            DeviceInfoValue::Raw(bytes) => format!("{bytes:02X?}"),
+        };
+        println!("{label:<20} {value}");
+    }
+    Ok(())
+}
+
+/// RGBA pixels of a PNG file, with its width
+fn read_png(path: &PathBuf) -> Result<(u16, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buf)?;
+    let pixels = &buf[..frame.buffer_size()];
+    let rgba = match frame.color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|px| [px[0], px[1], px[2], 0xFF])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|px| [px[0], px[0], px[0], px[1]])
+            .collect(),
+        png::ColorType::Grayscale => pixels.iter().flat_map(|&g| [g, g, g, 0xFF]).collect(),
+        other => return Err(format!("Unsupported PNG color type {other:?}").into()),
+    };
+    Ok((u16::try_from(frame.width)?, rgba))
+}
+
+fn run(glasses: &mut impl GlassesApi, command: Commands) -> Result<()> {
+    match command {
+        Commands::Info => info(glasses)?,
+        Commands::Battery => println!("{}%", glasses.battery()?),
+        Commands::Img(ImgCommands::List) => match glasses.query(&Command::ImgList)? {
+            Response::ImgList { list } => {
+                for item in list {
+                    println!("{:>3}  {}x{}", item.id, item.width, item.height);
+                }
+            }
+            other => return Err(format!("Unexpected response {other:?}").into()),
+        },
+        Commands::Img(ImgCommands::Upload {
+            file,
+            id,
+            cfg,
+            cfg_version,
+            password,
+        }) => {
+            let (width, rgba) = read_png(&file)?;
+            let data = Image::rgba_data(&rgba)?;
+            let image = Image {
+                width,
+                format: ImgFormat::Img8bpp,
+                data: &data,
+            };
+            let mut session = glasses.config_session(&cfg, cfg_version, password)?;
+            session.send_chunked(&image.save_command(id))?;
+            println!("{}: saved as image {} of {}", file.display(), id, cfg);
+        }
+        Commands::Cfg(CfgCommands::List) => match glasses.query(&Command::CfgList)? {
+            Response::CfgList { list } => {
+                println!("Name         Version      Size  Used  Installed");
+                for item in list {
+                    println!(
+                        "{:<12} {:>7} {:>9} {:>5} {:>10}{}",
+                        item.name,
+                        item.version,
+                        item.size,
+                        item.usage_counter,
+                        item.install_counter,
+                        if item.is_system { "  (system)" } else { "" }
+                    );
+                }
+            }
+            other => return Err(format!("Unexpected response {other:?}").into()),
+        },
+        Commands::Demo { demo } => glasses.run_demo(demo.into())?,
+    }
+    Ok(())
+}
+
+/// Serve an [Emulator] from a background thread
+fn run_emulated(command: Commands) -> Result<()> {
+    let (client, server) = loopback();
+    let mut server = ActiveLookServer::new(server.rx, server.tx, server.ctrl);
+    thread::spawn(move || {
+        let mut emulator = Emulator::new();
+        loop {
+            if server.serve(&mut emulator).is_err() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    });
+    let mut glasses = Glasses::new(ActiveLookClient::new(client.tx, client.rx, client.ctrl));
+    run(&mut glasses, command)
+}
+
+#[cfg(feature = "btleplug")]
+fn run_ble(name: Option<String>, command: Commands) -> Result<()> {
+    use activelook_rs::transport::{
+        btleplug::{connect, ConnectionConfig},
+        ACTIVELOOK_SERVICE_UUID,
+    };
+    use btleplug::{
+        api::{Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter},
+        platform::{Manager, Peripheral},
+    };
+    use futures::StreamExt;
+    use uuid::Uuid;
+
+    async fn find(name: Option<String>) -> Result<Peripheral> {
+        let manager = Manager::new().await?;
+        let adapter = manager
+            .adapters()
+            .await?
+            .into_iter()
+            .next()
+            .ok_or("No Bluetooth adapter")?;
+        let mut events = adapter.events().await?;
+        adapter
+            .start_scan(ScanFilter {
+                services: vec![Uuid::from_u128(ACTIVELOOK_SERVICE_UUID)],
+            })
+            .await?;
+        eprintln!("Scanning...");
+        while let Some(event) = events.next().await {
+            let CentralEvent::DeviceDiscovered(id) = event else {
+                continue;
+            };
+            let peripheral = adapter.peripheral(&id).await?;
+            let local_name = peripheral
+                .properties()
+                .await?
+                .and_then(|properties| properties.local_name)
+                .unwrap_or_default();
+            if name.as_ref().is_none_or(|name| local_name.contains(name)) {
+                adapter.stop_scan().await?;
+                eprintln!("Connecting to {local_name}");
+                return Ok(peripheral);
+            }
+        }
+        Err("Scan ended".into())
+    }
+
+    // The transport is blocking: drive the runtime from its own thread
+    let runtime = tokio::runtime::Builder::new_current_thread().build()?;
+    let connection = runtime.block_on(async {
+        let peripheral = find(name).await?;
+        Ok::<_, Box<dyn Error>>(connect(peripheral, ConnectionConfig::default()).await?)
+    })?;
+    thread::spawn(move || runtime.block_on(std::future::pending::<()>()));
+    let mut glasses = Glasses::new(ActiveLookClient::new(
+        connection.tx,
+        connection.rx,
+        connection.ctrl,
+    ));
+    run(&mut glasses, command)
+}
+
+#[cfg(not(feature = "btleplug"))]
+fn run_ble(_name: Option<String>, _command: Commands) -> Result<()> {
+    Err("Built without the btleplug feature, only --emulator is available".into())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = if cli.emulator {
+        run_emulated(cli.command)
+    } else {
+        run_ble(cli.name, cli.command)
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Glasses character set
+//!
+//! The firmware draws 8-bit characters: one byte per glyph, looked up in the selected font. We
+//! assume ISO-8859-1 (Latin-1), matching the code points of the fonts generated from Unicode
+//! fonts: characters U+0001 to U+00FF are sent as a single byte, and any other character is
+//! replaced by the [fallback] character. The glyphs actually available depend on the font.
+//!
+//! Strings of [crate::commands::Command]s are encoded with [encode_lossy] when serialized, and
+//! decoded with [decode]. Use [Txt] to check or transcode a text before sending it.
+//!
+//! Fonts may also hold pictograms, see [IconGlyph]. [Txt::icon] mixes them with text, in any
+//! string of a command such as [crate::commands::Command::LayoutDisplay] or
+//! [crate::commands::Command::Txt].
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use thiserror::Error;
+
+/// Replacement of the characters missing in the glasses charset, by default
+pub const DEFAULT_FALLBACK: u8 = b'?';
+
+/// Errors transcoding text to the glasses charset
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum CharsetError {
+    #[error("Character {0:?} is not in the glasses charset")]
+    Unsupported(char),
+}
+
+static FALLBACK: AtomicU8 = AtomicU8::new(DEFAULT_FALLBACK);
+
+/// Set the replacement of the characters missing in the glasses charset
+pub fn set_fallback(c: char) -> Result<(), CharsetError> {
+    FALLBACK.store(encode_char(c)?, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Replacement of the characters missing in the glasses charset
+pub fn fallback() -> char {
+    FALLBACK.load(Ordering::Relaxed) as char
+}
+
+/// Byte of `c` in the glasses charset. NUL terminates strings, it cannot be sent.
+pub fn encode_char(c: char) -> Result<u8, CharsetError> {
+    match u8::try_from(c) {
+        Ok(byte) if byte != 0 => Ok(byte),
+        _ => Err(CharsetError::Unsupported(c)),
+    }
+}
+
+/// Encode `text`, failing on the first character missing in the glasses charset
+pub fn encode(text: &str) -> Result<Vec<u8>, CharsetError> {
+    text.chars().map(encode_char).collect()
+}
+
+/// Encode `text`, replacing the characters missing in the glasses charset with [fallback]
+pub fn encode_lossy(text: &str) -> Vec<u8> {
+    lossy_bytes(text).collect()
+}
+
+/// Bytes of [encode_lossy], without allocating
+pub fn lossy_bytes(text: &str) -> impl Iterator<Item = u8> + '_ {
+    let fallback = FALLBACK.load(Ordering::Relaxed);
+    text.chars()
+        .map(move |c| encode_char(c).unwrap_or(fallback))
+}
+
+/// Decode bytes received from the glasses
+pub fn decode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| *byte as char).collect()
+}
+
+/// Pictogram of a font, drawn in place of a character.
+///
+/// The API documentation does not list the pictograms of the built-in fonts. We assume they sit
+/// in the C1 control range, from 0x80 to 0x9F, which text never uses. Fonts placing them
+/// elsewhere can use [IconGlyph::Other].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IconGlyph {
+    Battery,
+    BatteryLow,
+    BatteryCharging,
+    HeartRate,
+    Speed,
+    ArrowUp,
+    ArrowDown,
+    ArrowLeft,
+    ArrowRight,
+    Bluetooth,
+    /// Any other character of the font
+    Other(u8),
+}
+
+impl IconGlyph {
+    /// Byte of the glyph in the font
+    pub const fn code(self) -> u8 {
+        match self {
+            IconGlyph::Battery => 0x80,
+            IconGlyph::BatteryLow => 0x81,
+            IconGlyph::BatteryCharging => 0x82,
+            IconGlyph::HeartRate => 0x83,
+            IconGlyph::Speed => 0x84,
+            IconGlyph::ArrowUp => 0x85,
+            IconGlyph::ArrowDown => 0x86,
+            IconGlyph::ArrowLeft => 0x87,
+            IconGlyph::ArrowRight => 0x88,
+            IconGlyph::Bluetooth => 0x89,
+            IconGlyph::Other(code) => code,
+        }
+    }
+}
+
+impl From<u8> for IconGlyph {
+    fn from(code: u8) -> Self {
+        match code {
+            0x80 => IconGlyph::Battery,
+            0x81 => IconGlyph::BatteryLow,
+            0x82 => IconGlyph::BatteryCharging,
+            0x83 => IconGlyph::HeartRate,
+            0x84 => IconGlyph::Speed,
+            0x85 => IconGlyph::ArrowUp,
+            0x86 => IconGlyph::ArrowDown,
+            0x87 => IconGlyph::ArrowLeft,
+            0x88 => IconGlyph::ArrowRight,
+            0x89 => IconGlyph::Bluetooth,
+            other => IconGlyph::Other(other),
+        }
+    }
+}
+
+/// Text containing only characters of the glasses charset
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Txt(String);
+
+impl Txt {
+    /// Check that every character of `text` can be displayed
+    pub fn new(text: &str) -> Result<Self, CharsetError> {
+        encode(text)?;
+        Ok(Self(String::from(text)))
+    }
+
+    /// Replace the characters missing in the glasses charset with [fallback]
+    pub fn new_lossy(text: &str) -> Self {
+        Self(decode(&encode_lossy(text)))
+    }
+
+    /// Append `text`, checking that every character can be displayed
+    pub fn text(mut self, text: &str) -> Result<Self, CharsetError> {
+        encode(text)?;
+        self.0.push_str(text);
+        Ok(self)
+    }
+
+    /// Append a pictogram. [IconGlyph::Other] with NUL is skipped, it would end the string.
+    pub fn icon(mut self, icon: IconGlyph) -> Self {
+        if icon.code() != 0 {
+            self.0.push(icon.code() as char);
+        }
+        self
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Bytes sent to the glasses
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_lossy(&self.0)
+    }
+}
+
+impl From<Txt> for String {
+    fn from(txt: Txt) -> Self {
+        txt.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::Command,
+        traits::{Deserializable, Serializable},
+    };
+
+    #[test]
+    fn test_transcoding() {
+        assert_eq!(Ok(vec![b'a', 0xE9, 0xB0]), encode("aé°"));
+        assert_eq!(Err(CharsetError::Unsupported('€')), encode("1€"));
+        assert_eq!(Err(CharsetError::Unsupported('\0')), encode("\0"));
+        assert_eq!("Température", decode(&encode("Température").unwrap()));
+
+        assert_eq!(vec![b'1', b'?', b'x'], encode_lossy("1€x"));
+        assert_eq!("5 ?/h", Txt::new_lossy("5 €/h").as_str());
+        assert_eq!(Ok("déjà"), Txt::new("déjà").as_ref().map(Txt::as_str));
+        assert!(Txt::new("→").is_err());
+        assert!(set_fallback('€').is_err());
+        assert_eq!(DEFAULT_FALLBACK as char, fallback());
+    }
+
+    #[test]
+    fn test_icons() {
+        let txt = Txt::default()
+            .icon(IconGlyph::HeartRate)
+            .text(" 72 bpm ")
+            .unwrap()
+            .icon(IconGlyph::ArrowUp)
+            .icon(IconGlyph::Other(0));
+        assert_eq!(b"\x83 72 bpm \x85", &txt.to_bytes()[..]);
+        assert!(Txt::default().text("→").is_err());
+        assert_eq!(IconGlyph::Speed, IconGlyph::from(IconGlyph::Speed.code()));
+        assert_eq!(IconGlyph::Other(b'A'), IconGlyph::from(b'A'));
+
+        let cmd = Command::LayoutDisplay {
+            id: 1,
+            text: txt.into(),
+        };
+        assert_eq!(b"\x01\x83 72 bpm \x85\0", &cmd.data_bytes().unwrap()[..]);
+        assert_eq!(
+            cmd,
+            Command::from_data(0x62, Some(&cmd.data_bytes().unwrap())).unwrap()
+        );
+    }
+}
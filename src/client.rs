@@ -1,30 +1,70 @@
-use embedded_io::{Read, Write};
+use core::time::Duration;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+};
+
+use embedded_io::{Read, ReadReady, Write};
 use log::*;
-use thiserror::Error;
 
 use crate::{
-    commands::{Command, Response},
-    protocol::{CommandPacket, Packet, ProtocolError, ResponsePacket, PACKET_MAX_SIZE},
+    cmd_error::CommandError,
+    commands::{split_aligned, Command, Point, Response, StreamImgFormat},
+    image::Image,
+    observer::{ClientObserver, Observed, SentPacket},
+    pacing::RateLimiter,
+    protocol::{
+        consts, write_packet, FlowErrorCtrl, PacketAssembler, PayloadRef, ProtocolError,
+        ResponsePacket, PACKET_DATA_MAX_SIZE, PACKET_MAX_SIZE,
+    },
+    redact::Redacted,
+    stats::ClientStats,
+    time::Clock,
     traits::*,
+    transport::{ATT_HEADER_LEN, DEFAULT_MTU},
+    upload::{CancelToken, ChunkProgress},
 };
 
+/// Size of the query_id added by the client to each command, by default
+pub const QUERY_ID_LEN: usize = core::mem::size_of::<u32>();
+
+/// Settings of an [ActiveLookClient]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ClientConfig {
+    /// Size of the query_id added to each command, from 1 to [QUERY_ID_LEN] bytes.
+    /// Shorter query_ids save bytes on every packet, but wrap around sooner: at most 256
+    /// queries can be pending with a single byte.
+    ///
+    /// With 0, no query_id is sent: responses are matched to the oldest pending query, relying
+    /// on the glasses answering in order.
+    pub query_id_len: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            query_id_len: QUERY_ID_LEN,
+        }
+    }
+}
+
 /// Client which uses:
 /// - Connection to Tx Activelook Server (Notify)
 /// - Connection to Rx Activelook Server (Write)
 /// - Connection to Control server (Notify)
+///
+/// Use [ActiveLookClient::split] to send and receive from different tasks or threads.
 pub struct ActiveLookClient<TxActiveLook, RxActiveLook, Ctrl>
 where
     TxActiveLook: Read,
     RxActiveLook: Write,
-    Ctrl: Read,
+    Ctrl: Read + ReadReady,
 {
-    /// Client Rx is connected to ActiveLook Tx
-    rx: TxActiveLook,
-    /// Client Tx is connected to ActiveLook Rx
-    tx: RxActiveLook,
-    ctrl: Ctrl,
-    /// Sequence number
-    query_id: u32,
+    sender: ClientSender<RxActiveLook, Ctrl>,
+    receiver: ClientReceiver<TxActiveLook>,
+    pending: PendingRequests,
+    /// Time to wait for a response, and the clock measuring it
+    response_timeout: Option<(Duration, Box<dyn Clock + Send>)>,
 }
 
 /// Protocol implementation
@@ -33,88 +73,1658 @@ impl<TxActiveLook, RxActiveLook, Ctrl> ActiveLookClient<TxActiveLook, RxActiveLo
 where
     TxActiveLook: Read,
     RxActiveLook: Write,
-    Ctrl: Read,
+    Ctrl: Read + ReadReady,
 {
     pub fn new(rx: TxActiveLook, tx: RxActiveLook, ctrl: Ctrl) -> Self {
         Self {
-            rx,
+            sender: ClientSender::new(tx, ctrl),
+            receiver: ClientReceiver::new(rx),
+            pending: PendingRequests::new(),
+            response_timeout: None,
+        }
+    }
+
+    /// Split the client into its sending and receiving halves, which can live in separate
+    /// tasks or threads.
+    /// The sending half keeps the Control characteristic, to handle flow control.
+    pub fn split(
+        self,
+    ) -> (
+        ClientSender<RxActiveLook, Ctrl>,
+        ClientReceiver<TxActiveLook>,
+    ) {
+        (self.sender, self.receiver)
+    }
+
+    /// Reunite halves previously obtained with [ActiveLookClient::split]
+    pub fn unsplit(
+        sender: ClientSender<RxActiveLook, Ctrl>,
+        receiver: ClientReceiver<TxActiveLook>,
+    ) -> Self {
+        Self {
+            sender,
+            receiver,
+            pending: PendingRequests::new(),
+            response_timeout: None,
+        }
+    }
+
+    /// Change the settings of the client, see [ClientSender::set_config]
+    pub fn set_config(&mut self, config: ClientConfig) {
+        self.sender.set_config(config)
+    }
+
+    /// Use the ATT MTU negotiated by the transport, see [ClientSender::set_mtu]
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.sender.set_mtu(mtu)
+    }
+
+    /// Start collecting statistics, timed with `clock`, see [ClientStats]
+    pub fn enable_stats(&mut self, clock: impl Clock + Send + 'static) {
+        self.sender.enable_stats(clock)
+    }
+
+    /// Stop waiting for a response after `timeout` on `clock`, see
+    /// [ActiveLookClient::wait_response]. Without timeout, the client waits until the response
+    /// is received or the transport fails.
+    pub fn set_response_timeout(&mut self, timeout: Duration, clock: impl Clock + Send + 'static) {
+        self.response_timeout = Some((timeout, Box::new(clock)));
+    }
+
+    /// Statistics collected since [ActiveLookClient::enable_stats], if enabled
+    pub fn stats(&self) -> Option<&ClientStats> {
+        self.sender.stats()
+    }
+
+    /// Notify `observer` of the traffic, timed with `clock`, see [crate::observer]
+    pub fn set_observer(
+        &mut self,
+        observer: impl ClientObserver + Send + 'static,
+        clock: impl Clock + Send + 'static,
+    ) {
+        self.sender.set_observer(observer, clock)
+    }
+
+    /// Stop notifying the observer
+    pub fn clear_observer(&mut self) {
+        self.sender.clear_observer()
+    }
+
+    /// Pace the writes of bulk operations, see [ClientSender::set_rate_limiter]
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<RateLimiter>) {
+        self.sender.set_rate_limiter(rate_limiter)
+    }
+
+    /// Send a command, waiting for the glasses to accept data if needed
+    pub fn send(&mut self, cmd: &impl Serializable) -> Result<(), ProtocolError> {
+        self.sender.send(cmd).map(|_| ())
+    }
+
+    /// Send a command too big for a single packet, split in chunks of at most `chunk_size` data
+    /// bytes. Each chunk is sent in its own packet, with the command ID.
+    pub fn send_chunked(
+        &mut self,
+        cmd: &impl Serializable,
+        chunk_size: usize,
+    ) -> Result<(), ProtocolError> {
+        self.sender.send_chunked(cmd, chunk_size)
+    }
+
+    /// Send many commands, see [ClientSender::send_bulk]
+    pub fn send_bulk<T: Serializable>(&mut self, cmds: &[T]) -> Result<(), ProtocolError> {
+        self.sender.send_bulk(cmds)
+    }
+
+    /// Display an image without saving it, see [ClientSender::stream_image]
+    pub fn stream_image(
+        &mut self,
+        image: &Image,
+        coord: Point,
+        mtu: usize,
+    ) -> Result<(), ProtocolError> {
+        self.sender.stream_image(image, coord, mtu)
+    }
+
+    /// Save an image with progress and cancellation, see [ClientSender::upload_image]
+    pub fn upload_image(
+        &mut self,
+        image: &Image,
+        id: u8,
+        cancel: &CancelToken,
+        progress: impl FnMut(ChunkProgress),
+    ) -> Result<(), ProtocolError> {
+        self.sender.upload_image(image, id, cancel, progress)
+    }
+
+    /// Send a command and wait for its response
+    pub fn send_command_expect_response(
+        &mut self,
+        cmd: &impl Serializable,
+    ) -> Result<Response, ProtocolError> {
+        debug!("Sending command id {:?}, expecting Response", cmd.id().ok());
+        let query_id = self.send_query(cmd)?;
+        self.wait_response(query_id)
+    }
+
+    /// Send a command missing from [Command], with its ID and raw data bytes, see
+    /// [crate::protocol::Packet::raw]
+    pub fn send_raw(&mut self, cmd_id: u8, data: &[u8]) -> Result<(), ProtocolError> {
+        self.send(&PayloadRef { id: cmd_id, data })
+    }
+
+    /// Send a command missing from [Command] and wait for its response. The response must be
+    /// one of [Response], a [Response::CmdError] for instance: others fail to decode with
+    /// [ProtocolError::ParseError].
+    pub fn query_raw(&mut self, cmd_id: u8, data: &[u8]) -> Result<Response, ProtocolError> {
+        self.send_command_expect_response(&PayloadRef { id: cmd_id, data })
+    }
+
+    /// Send a command expecting a response, without waiting for it.
+    /// Multiple queries can be in flight, use [ActiveLookClient::wait_response] with the returned
+    /// query_id to get each response.
+    pub fn send_query(&mut self, cmd: &impl Serializable) -> Result<u32, ProtocolError> {
+        let query_id = self.sender.send(cmd)?;
+        self.pending.insert(query_id);
+        if let Some(stats) = &mut self.sender.stats {
+            stats.on_query(query_id, cmd.id()?);
+        }
+        if let Some(observed) = &mut self.sender.observed {
+            observed.on_query(query_id, cmd.id()?);
+        }
+        Ok(query_id)
+    }
+
+    /// Wait for the response to `query_id`.
+    /// Responses to other pending queries received meanwhile are kept for their callers.
+    ///
+    /// Returns [ProtocolError::Timeout] and forgets the query if the response is not received
+    /// within the timeout set by [ActiveLookClient::set_response_timeout].
+    pub fn wait_response(&mut self, query_id: u32) -> Result<Response, ProtocolError> {
+        if !self.pending.is_pending(query_id) {
+            return Err(ProtocolError::IncorrectQueryId);
+        }
+        let deadline = self
+            .response_timeout
+            .as_ref()
+            .map(|(timeout, clock)| clock.now() + *timeout);
+        loop {
+            if let Some(response) = self.pending.take(query_id) {
+                return Ok(response);
+            }
+            match self.read_tx_char() {
+                Ok(packet) => {
+                    let is_error = matches!(packet.data, Response::CmdError { .. });
+                    let response = self.sender.observed.is_some().then(|| packet.data.clone());
+                    let query_id = self.pending.route(packet);
+                    if let Some(stats) = &mut self.sender.stats {
+                        stats.on_response(query_id, is_error);
+                    }
+                    if let (Some(observed), Some(response)) = (&mut self.sender.observed, response)
+                    {
+                        observed.on_response(query_id, &response);
+                    }
+                }
+                Err(ProtocolError::Empty) => {
+                    let expired = match (&self.response_timeout, deadline) {
+                        (Some((timeout, clock)), Some(deadline)) if clock.now() >= deadline => {
+                            Some(*timeout)
+                        }
+                        _ => None,
+                    };
+                    if let Some(timeout) = expired {
+                        self.pending.cancel(query_id);
+                        let error = ProtocolError::Timeout(timeout);
+                        if let Some(observed) = &mut self.sender.observed {
+                            observed.on_error(&error);
+                        }
+                        return Err(error);
+                    }
+                }
+                Err(error) => {
+                    if let Some(observed) = &mut self.sender.observed {
+                        observed.on_error(&error);
+                    }
+                    return Err(error);
+                }
+            }
+        }
+    }
+
+    /// Queries sent and responses not consumed yet
+    pub fn pending(&mut self) -> &mut PendingRequests {
+        &mut self.pending
+    }
+
+    /// Get notifications on TX characteristic, until a whole packet is received.
+    pub fn read_tx_char(&mut self) -> Result<ResponsePacket, ProtocolError> {
+        self.receiver.read_tx_char()
+    }
+
+    /// Get notification on Control characteristic
+    pub fn read_ctrl_char(&mut self) -> Result<u8, ProtocolError> {
+        self.sender.read_ctrl_char()
+    }
+
+    /// Handle all pending notifications of the Control characteristic, without blocking
+    pub fn poll_ctrl(&mut self) -> Result<(), ProtocolError> {
+        self.sender.poll_ctrl()
+    }
+
+    /// Returns false if the glasses asked to stop sending data
+    pub fn can_send(&self) -> bool {
+        self.sender.can_send()
+    }
+
+    /// Last error reported on the Control characteristic, if any
+    pub fn take_flow_error(&mut self) -> Option<FlowErrorCtrl> {
+        self.sender.take_flow_error()
+    }
+}
+
+/// Handler of responses which do not answer a pending query
+pub type UnsolicitedHandler = Box<dyn FnMut(ResponsePacket) + Send>;
+
+/// Correlation table between the query_ids sent and the responses received.
+///
+/// Responses can come out of order, and some are sent asynchronously, like
+/// [Response::CmdError]. Responses to pending queries are kept until their caller takes them,
+/// the others are given to the unsolicited handler.
+#[derive(Default)]
+pub struct PendingRequests {
+    /// Response received for each pending query_id, if any
+    pending: BTreeMap<u32, Option<Response>>,
+    /// Pending query_ids, in send order: the query_ids wrap around
+    order: VecDeque<u32>,
+    /// Beginning of the list responses continued by further packets, see
+    /// [Response::is_partial_list]
+    partial: BTreeMap<u32, Response>,
+    on_unsolicited: Option<UnsolicitedHandler>,
+}
+
+impl PendingRequests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handle the responses which do not answer a pending query, like asynchronous
+    /// [Response::CmdError]. Without handler they are logged and dropped.
+    pub fn set_unsolicited_handler(&mut self, handler: UnsolicitedHandler) {
+        self.on_unsolicited = Some(handler);
+    }
+
+    /// Register a query waiting for its response
+    pub fn insert(&mut self, query_id: u32) {
+        if self.pending.insert(query_id, None).is_some() {
+            warn!(
+                "Query {} is still pending, its query_id wrapped around",
+                query_id
+            );
+            self.order.retain(|pending| *pending != query_id);
+        }
+        self.order.push_back(query_id);
+    }
+
+    /// Returns true if `query_id` was sent and its response not taken yet
+    pub fn is_pending(&self, query_id: u32) -> bool {
+        self.pending.contains_key(&query_id)
+    }
+
+    /// Number of queries waiting for their response to be taken
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Route a received response to its query, or to the unsolicited handler.
+    /// A response without query_id answers the oldest query still waiting, see
+    /// [ClientConfig::query_id_len].
+    /// A list split across several packets is kept until its last packet is received, see
+    /// [Response::split_list].
+    pub fn dispatch(&mut self, packet: ResponsePacket) {
+        self.route(packet);
+    }
+
+    /// [PendingRequests::dispatch], returning the query_id answered by `packet`
+    fn route(&mut self, packet: ResponsePacket) -> Option<u32> {
+        let query_id = match (&packet.query_id, &packet.data) {
+            (_, Response::CmdError { .. }) | (Some(_), _) => response_query_id(&packet).ok(),
+            (None, _) => self
+                .order
+                .iter()
+                .find(|query_id| matches!(self.pending.get(query_id), Some(None)))
+                .copied(),
+        };
+        if let Some(query_id) = query_id {
+            if let Some(slot @ None) = self.pending.get_mut(&query_id) {
+                debug!(
+                    "Received response to query {}: {:?}",
+                    query_id,
+                    Redacted(&packet.data)
+                );
+                let more = packet.data.is_partial_list();
+                let response = match self.partial.remove(&query_id) {
+                    Some(mut list) => match list.extend_list(packet.data) {
+                        Ok(()) => list,
+                        Err(other) => {
+                            warn!("Query {} list interrupted by {:?}", query_id, other);
+                            other
+                        }
+                    },
+                    None => packet.data,
+                };
+                if more {
+                    self.partial.insert(query_id, response);
+                    return None;
+                }
+                *slot = Some(response);
+                return Some(query_id);
+            }
+        }
+        match &mut self.on_unsolicited {
+            Some(handler) => handler(packet),
+            None => match CommandError::from_response(&packet.data) {
+                Some(error) => warn!("{}", error),
+                None => warn!("Dropping unsolicited response {:?}", Redacted(&packet.data)),
+            },
+        }
+        None
+    }
+
+    /// Take the response to `query_id`, if received
+    pub fn take(&mut self, query_id: u32) -> Option<Response> {
+        match self.pending.get(&query_id) {
+            Some(Some(_)) => {
+                self.order.retain(|pending| *pending != query_id);
+                self.pending.remove(&query_id).flatten()
+            }
+            _ => None,
+        }
+    }
+
+    /// Forget a query, for instance after a timeout
+    pub fn cancel(&mut self, query_id: u32) {
+        self.pending.remove(&query_id);
+        self.partial.remove(&query_id);
+        self.order.retain(|pending| *pending != query_id);
+    }
+}
+
+/// Extract the query_id sent by [ClientSender] from a response, of 1 to [QUERY_ID_LEN] bytes
+pub fn response_query_id(packet: &ResponsePacket) -> Result<u32, ProtocolError> {
+    match &packet.query_id {
+        Some(id) if (1..=QUERY_ID_LEN).contains(&id.len()) => Ok(id
+            .iter()
+            .fold(0, |query_id, byte| query_id << 8 | u32::from(*byte))),
+        _ => Err(ProtocolError::IncorrectQueryId),
+    }
+}
+
+/// Sending half of an [ActiveLookClient].
+///
+/// Monitors the Control characteristic: when the glasses buffer reaches 75%, they notify
+/// [FlowErrorCtrl::ClientShouldWait] and the sender waits for [FlowErrorCtrl::ClientCanSend]
+/// before sending anything else.
+pub struct ClientSender<RxActiveLook, Ctrl>
+where
+    RxActiveLook: Write,
+    Ctrl: Read + ReadReady,
+{
+    /// Client Tx is connected to ActiveLook Rx
+    tx: RxActiveLook,
+    ctrl: Ctrl,
+    /// Sequence number
+    query_id: u32,
+    /// Flow control state
+    can_send: bool,
+    /// Last error notified on the Control characteristic
+    flow_error: Option<FlowErrorCtrl>,
+    /// ATT MTU, packets are split in writes fitting in it
+    mtu: usize,
+    config: ClientConfig,
+    stats: Option<ClientStats>,
+    observed: Option<Observed>,
+    rate_limiter: Option<RateLimiter>,
+}
+
+impl<RxActiveLook, Ctrl> ClientSender<RxActiveLook, Ctrl>
+where
+    RxActiveLook: Write,
+    Ctrl: Read + ReadReady,
+{
+    pub fn new(tx: RxActiveLook, ctrl: Ctrl) -> Self {
+        Self {
             tx,
             ctrl,
             query_id: 0,
+            can_send: true,
+            flow_error: None,
+            mtu: DEFAULT_MTU,
+            config: ClientConfig::default(),
+            stats: None,
+            observed: None,
+            rate_limiter: None,
         }
     }
 
-    /// Send a command
-    pub fn send(&mut self, cmd: &impl Serializable) -> Result<(), ProtocolError> {
-        self.query_id += 1;
-        debug!("Sending command id {}", cmd.id().expect("Not a command?"));
-        let packet = Packet::new_with_query_id(cmd, &self.query_id.to_be_bytes());
-        let res = self.tx.write(&packet.to_bytes()[..]);
-        match res {
-            Ok(_) => Ok(()),
-            Err(error) => {
+    /// Start collecting statistics, timed with `clock`, see [ClientStats]
+    pub fn enable_stats(&mut self, clock: impl Clock + Send + 'static) {
+        self.stats = Some(ClientStats::new(clock));
+    }
+
+    /// Statistics collected since [ClientSender::enable_stats], if enabled
+    pub fn stats(&self) -> Option<&ClientStats> {
+        self.stats.as_ref()
+    }
+
+    /// Stop collecting statistics, returning them
+    pub fn take_stats(&mut self) -> Option<ClientStats> {
+        self.stats.take()
+    }
+
+    /// Notify `observer` of the packets sent and the errors, timed with `clock`, see
+    /// [crate::observer]
+    pub fn set_observer(
+        &mut self,
+        observer: impl ClientObserver + Send + 'static,
+        clock: impl Clock + Send + 'static,
+    ) {
+        self.observed = Some(Observed::new(observer, clock));
+    }
+
+    /// Stop notifying the observer
+    pub fn clear_observer(&mut self) {
+        self.observed = None;
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Change the settings of the sender. A `query_id_len` above [QUERY_ID_LEN] is reduced to
+    /// it.
+    pub fn set_config(&mut self, mut config: ClientConfig) {
+        if config.query_id_len > QUERY_ID_LEN {
+            warn!("query_id of {} bytes not supported", config.query_id_len);
+            config.query_id_len = QUERY_ID_LEN;
+        }
+        self.config = config;
+    }
+
+    /// ATT MTU negotiated with the glasses, [DEFAULT_MTU] until set
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Use the ATT MTU negotiated by the transport: packets are split in writes of `mtu` minus
+    /// the ATT header
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    /// Pace the writes of bulk operations, or stop pacing them with `None`
+    pub fn set_rate_limiter(&mut self, rate_limiter: Option<RateLimiter>) {
+        self.rate_limiter = rate_limiter;
+    }
+
+    pub fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.rate_limiter.as_ref()
+    }
+
+    /// Send a command, returns the query_id identifying its response.
+    /// Waits for the glasses to accept data if needed.
+    pub fn send(&mut self, cmd: &impl Serializable) -> Result<u32, ProtocolError> {
+        self.send_packet(cmd, false)
+    }
+
+    /// Send a command, with each write waiting for the rate limiter if `paced`
+    fn send_packet(&mut self, cmd: &impl Serializable, paced: bool) -> Result<u32, ProtocolError> {
+        let result = self.write_command(cmd, paced);
+        if let (Err(error), Some(observed)) = (&result, &mut self.observed) {
+            observed.on_error(error);
+        }
+        result
+    }
+
+    /// [ClientSender::send_packet], without notifying the errors
+    fn write_command(
+        &mut self,
+        cmd: &impl Serializable,
+        paced: bool,
+    ) -> Result<u32, ProtocolError> {
+        self.wait_until_can_send()?;
+        let query_id_len = self.config.query_id_len;
+        self.query_id = match query_id_len {
+            1..QUERY_ID_LEN => self.query_id.wrapping_add(1) % (1 << (8 * query_id_len)),
+            _ => self.query_id.wrapping_add(1),
+        };
+        debug!("Sending command id {:?}", cmd.id().ok());
+        let mut buf = [0; PACKET_MAX_SIZE];
+        let query_id = &self.query_id.to_be_bytes()[QUERY_ID_LEN - query_id_len..];
+        let len = write_packet(cmd, query_id, &mut buf)?;
+        let write_len = self.mtu.saturating_sub(ATT_HEADER_LEN).max(1);
+        for frame in buf[..len].chunks(write_len) {
+            if let (true, Some(rate_limiter)) = (paced, &mut self.rate_limiter) {
+                rate_limiter.acquire();
+            }
+            if let Err(error) = self.tx.write(frame) {
                 error!("{:?}", error);
-                Err(ProtocolError::EmbeddedIOError)
+                return Err(ProtocolError::EmbeddedIOError);
             }
         }
+        if let Some(stats) = &mut self.stats {
+            stats.on_packet(len);
+        }
+        if let Some(observed) = &mut self.observed {
+            observed.on_send(SentPacket {
+                cmd_id: cmd.id()?,
+                query_id: self.query_id,
+                len,
+            });
+        }
+        Ok(self.query_id)
     }
 
-    pub fn send_command_expect_response(
+    /// Send a command too big for a single packet, split in chunks of at most `chunk_size` data
+    /// bytes. Each chunk is sent in its own packet, with the command ID.
+    /// Aborts if the glasses report an error on the Control characteristic.
+    pub fn send_chunked(
         &mut self,
         cmd: &impl Serializable,
-    ) -> Result<Response, ProtocolError> {
-        self.query_id += 1;
-        debug!(
-            "Sending command id {}, expecting Response",
-            cmd.id().expect("Not a command?")
-        );
-        let packet = Packet::new_with_query_id(cmd, &self.query_id.to_be_bytes());
-        let res = self.tx.write(&packet.to_bytes()[..]);
-        if let Err(error) = res {
-            return Err(ProtocolError::EmbeddedIOError);
+        chunk_size: usize,
+    ) -> Result<(), ProtocolError> {
+        let (id, chunks) = cmd.chunks(chunk_size)?;
+        self.send_payloads(id, &chunks)
+    }
+
+    /// Send each chunk in its own packet, with the command `id`
+    fn send_payloads(&mut self, id: u8, chunks: &[Cow<[u8]>]) -> Result<(), ProtocolError> {
+        self.send_bulk(
+            &chunks
+                .iter()
+                .map(|data| PayloadRef { id, data })
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Send many commands, pausing when the glasses buffer is full, and pacing the writes with
+    /// the rate limiter if set.
+    /// Aborts if the glasses report an error on the Control characteristic.
+    pub fn send_bulk<T: Serializable>(&mut self, cmds: &[T]) -> Result<(), ProtocolError> {
+        self.take_flow_error();
+        for cmd in cmds {
+            self.send_packet(cmd, true)?;
+            self.poll_ctrl()?;
+            if let Some(error) = self.take_flow_error() {
+                return Err(ProtocolError::FlowControl(error));
+            }
         }
+        Ok(())
+    }
+
+    /// Display `image` at `coord` without saving it, with [crate::commands::Command::ImgStream].
+    ///
+    /// Each packet holds whole image lines and fits in one BLE write of the ATT `mtu`, unless a
+    /// single line is longer. Aborts if the glasses report an error on the Control characteristic.
+    pub fn stream_image(
+        &mut self,
+        image: &Image,
+        coord: Point,
+        mtu: usize,
+    ) -> Result<(), ProtocolError> {
+        let format = StreamImgFormat::try_from(image.format)
+            .map_err(|_| ProtocolError::StreamFormat(image.format))?;
+        // The image data is sent from `image`, the command only holds the header
+        let header = Command::ImgStream {
+            size: image.data.len() as u32,
+            width: image.width,
+            coord,
+            format,
+            data: Vec::new(),
+        };
+        let line_len = image.format.nb_of_bytes(image.width as usize);
+        let chunks = split_aligned(
+            &header,
+            image.data,
+            stream_chunk_size(line_len, mtu, self.config.query_id_len),
+        )?;
+        self.send_payloads(header.id()?, &chunks)
+    }
 
-        let mut response_pkt: ResponsePacket;
+    /// Save `image` as `id` with [crate::commands::Command::ImgSave], one chunk at a time.
+    ///
+    /// `progress` is called after each chunk, and when the glasses ask to pause. The upload
+    /// resumes once they accept data again. `cancel` is checked before each chunk, and while
+    /// paused: a cancelled upload returns [ProtocolError::Cancelled], and leaves an incomplete
+    /// image to delete or upload again. Aborts if the glasses report an error on the Control
+    /// characteristic.
+    pub fn upload_image(
+        &mut self,
+        image: &Image,
+        id: u8,
+        cancel: &CancelToken,
+        mut progress: impl FnMut(ChunkProgress),
+    ) -> Result<(), ProtocolError> {
+        // The image data is sent from `image`, the command only holds the header
+        let header = Command::ImgSave {
+            id,
+            size: image.data.len() as u32,
+            width: image.width,
+            format: image.format,
+            data: Vec::new(),
+        };
+        let chunks = split_aligned(&header, image.data, PACKET_DATA_MAX_SIZE)?;
+        let cmd_id = header.id()?;
+        let mut state = ChunkProgress {
+            total_bytes: image.data.len(),
+            total_chunks: chunks.len(),
+            ..Default::default()
+        };
+        self.take_flow_error();
+        for (index, data) in chunks.iter().enumerate() {
+            if cancel.is_cancelled() {
+                return Err(ProtocolError::Cancelled);
+            }
+            self.poll_ctrl()?;
+            if !self.can_send {
+                progress(ChunkProgress {
+                    waiting: true,
+                    ..state
+                });
+                self.wait_until_can_send_or_cancelled(cancel)?;
+            }
+            self.send_packet(&PayloadRef { id: cmd_id, data }, true)?;
+            self.poll_ctrl()?;
+            if let Some(error) = self.take_flow_error() {
+                return Err(ProtocolError::FlowControl(error));
+            }
+            if index > 0 {
+                state.bytes_sent += data.len();
+            }
+            state.chunks_sent += 1;
+            progress(state);
+        }
+        Ok(())
+    }
+
+    /// Returns false if the glasses asked to stop sending data
+    pub fn can_send(&self) -> bool {
+        self.can_send
+    }
+
+    /// Last error reported on the Control characteristic, if any
+    pub fn take_flow_error(&mut self) -> Option<FlowErrorCtrl> {
+        self.flow_error.take()
+    }
+
+    /// Get notification on Control characteristic.
+    /// Returns [ProtocolError::Empty] if none is pending, [ProtocolError::EmbeddedIOError] if the
+    /// transport failed.
+    pub fn read_ctrl_char(&mut self) -> Result<u8, ProtocolError> {
+        // Control values are single bytes: read them one by one, in case the transport
+        // delivers several notifications at once
+        let mut rxbuf = [0; 1];
+        match self.ctrl.read(&mut rxbuf) {
+            Ok(0) => Err(ProtocolError::Empty),
+            Ok(_) => Ok(rxbuf[0]),
+            Err(error) => {
+                error!("{:?}", error);
+                Err(ProtocolError::EmbeddedIOError)
+            }
+        }
+    }
+
+    /// Handle all pending notifications of the Control characteristic, without blocking
+    pub fn poll_ctrl(&mut self) -> Result<(), ProtocolError> {
+        while self
+            .ctrl
+            .read_ready()
+            .map_err(|_| ProtocolError::EmbeddedIOError)?
+        {
+            let value = self.read_ctrl_char()?;
+            self.handle_ctrl(value);
+        }
+        Ok(())
+    }
+
+    fn wait_until_can_send(&mut self) -> Result<(), ProtocolError> {
+        self.poll_ctrl()?;
+        while !self.can_send {
+            debug!("Waiting for the glasses to accept data");
+            let value = self.read_ctrl_char()?;
+            self.handle_ctrl(value);
+        }
+        Ok(())
+    }
+
+    /// Wait for the glasses to accept data, polling the Control characteristic instead of
+    /// blocking on it, to stop as soon as `cancel` is cancelled
+    fn wait_until_can_send_or_cancelled(
+        &mut self,
+        cancel: &CancelToken,
+    ) -> Result<(), ProtocolError> {
         loop {
-            let resp = self.read_tx_char();
-            if let Ok(pkt) = resp {
-                response_pkt = pkt;
-                break;
+            self.poll_ctrl()?;
+            if self.can_send {
+                return Ok(());
             }
+            if cancel.is_cancelled() {
+                return Err(ProtocolError::Cancelled);
+            }
+            std::thread::yield_now();
         }
-        debug!("Received response {:?}", &response_pkt.data);
-        if let Some(id) = response_pkt.query_id {
-            if id.len() != core::mem::size_of::<u32>() {
-                return Err(ProtocolError::IncorrectQueryId);
+    }
+
+    fn handle_ctrl(&mut self, value: u8) {
+        match FlowErrorCtrl::try_from(value) {
+            Ok(FlowErrorCtrl::ClientCanSend) => self.can_send = true,
+            Ok(FlowErrorCtrl::ClientShouldWait) => {
+                if let (true, Some(stats)) = (self.can_send, &mut self.stats) {
+                    stats.on_stall();
+                }
+                self.can_send = false
             }
-            // Here unwrap() is safe, because we checked the vec length beforehand
-            if u32::from_be_bytes(id.try_into().unwrap()) == self.query_id {
-                Ok(response_pkt.data)
-            } else {
-                Err(ProtocolError::IncorrectQueryId)
+            Ok(error) => {
+                warn!("Flow control error {:?}", error);
+                if let Some(observed) = &mut self.observed {
+                    observed.on_error(&ProtocolError::FlowControl(error));
+                }
+                self.flow_error = Some(error);
             }
-        } else {
-            Err(ProtocolError::IncorrectQueryId)
+            Err(_) => warn!("Unknown control value {}", value),
         }
     }
+}
+
+/// Data bytes of each packet of an image stream, for packets fitting in one write of `mtu`.
+/// At least one line of `line_len` bytes.
+fn stream_chunk_size(line_len: usize, mtu: usize, query_id_len: usize) -> usize {
+    let write_len = mtu.saturating_sub(ATT_HEADER_LEN).min(PACKET_MAX_SIZE);
+    let overhead = consts::header_overhead(query_id_len, write_len > consts::SHORT_LENGTH_MAX);
+    write_len
+        .saturating_sub(overhead)
+        .min(PACKET_DATA_MAX_SIZE)
+        .max(line_len.max(1))
+}
+
+/// Receiving half of an [ActiveLookClient]
+pub struct ClientReceiver<TxActiveLook>
+where
+    TxActiveLook: Read,
+{
+    /// Client Rx is connected to ActiveLook Tx
+    rx: TxActiveLook,
+    /// Reconstructs packets split across multiple notifications
+    assembler: PacketAssembler,
+}
 
-    // Get notification on TX characteristic
+impl<TxActiveLook> ClientReceiver<TxActiveLook>
+where
+    TxActiveLook: Read,
+{
+    pub fn new(rx: TxActiveLook) -> Self {
+        Self {
+            rx,
+            assembler: PacketAssembler::new(),
+        }
+    }
+
+    /// Get notifications on TX characteristic, until a whole packet is received.
+    /// A packet can be split across multiple notifications, and a notification can contain the
+    /// beginning of the next packet.
+    ///
+    /// Returns [ProtocolError::Empty] until a whole packet is received,
+    /// [ProtocolError::EmbeddedIOError] if the transport failed.
     pub fn read_tx_char(&mut self) -> Result<ResponsePacket, ProtocolError> {
         let mut rxbuf = [0; PACKET_MAX_SIZE];
-        if let Ok(len) = self.rx.read(&mut rxbuf) {
-            ResponsePacket::from_bytes(&rxbuf[..len])
-        } else {
-            Err(ProtocolError::Empty)
+        loop {
+            if let Some(bytes) = self.assembler.next_packet()? {
+                return ResponsePacket::from_bytes(&bytes);
+            }
+            match self.rx.read(&mut rxbuf) {
+                Ok(0) => return Err(ProtocolError::Empty),
+                Ok(len) => self.assembler.push(&rxbuf[..len]),
+                Err(error) => {
+                    error!("{:?}", error);
+                    return Err(ProtocolError::EmbeddedIOError);
+                }
+            }
         }
     }
+}
 
-    // Get notification on TX characteristic
-    pub fn read_ctrl_char(&mut self) -> Result<u8, ProtocolError> {
-        let mut rxbuf = [0; PACKET_MAX_SIZE];
-        if let Ok(_len) = self.ctrl.read(&mut rxbuf) {
-            Ok(rxbuf[0])
-        } else {
-            Err(ProtocolError::Empty)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::ImgFormat;
+    use crate::observer::ReceivedResponse;
+    use crate::protocol::{Packet, RawPacket};
+    use crate::time::VirtualClock;
+    use core::convert::Infallible;
+    use core::time::Duration;
+    use embedded_io::ErrorType;
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Delivers the data one byte per read, like the worst possible BLE stack
+    struct OneByteReader {
+        data: Vec<u8>,
+        index: usize,
+    }
+
+    impl ErrorType for OneByteReader {
+        type Error = Infallible;
+    }
+
+    impl Read for OneByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            match self.data.get(self.index) {
+                Some(byte) => {
+                    buf[0] = *byte;
+                    self.index += 1;
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        }
+    }
+
+    impl ReadReady for OneByteReader {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.index < self.data.len())
+        }
+    }
+
+    /// Discards everything
+    struct Sink;
+
+    impl ErrorType for Sink {
+        type Error = Infallible;
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_one_byte_at_a_time() {
+        let first = Response::Battery { level: 42 };
+        let second = Response::CfgGetNb { nb_config: 3 };
+        let mut data = Packet::new(&first).unwrap().to_bytes();
+        data.extend(
+            Packet::new_with_query_id(&second, &1u32.to_be_bytes())
+                .unwrap()
+                .to_bytes(),
+        );
+
+        let rx = OneByteReader { data, index: 0 };
+        let ctrl = OneByteReader {
+            data: vec![],
+            index: 0,
+        };
+        let mut client = ActiveLookClient::new(rx, Sink, ctrl);
+
+        assert_eq!(first, client.read_tx_char().unwrap().data);
+        assert_eq!(second, client.read_tx_char().unwrap().data);
+        assert_eq!(Err(ProtocolError::Empty), client.read_tx_char().map(|_| ()));
+    }
+
+    #[test]
+    fn test_response_split_across_reads() {
+        let response = Response::Battery { level: 42 };
+        let data = Packet::new_with_query_id(&response, &1u32.to_be_bytes())
+            .unwrap()
+            .to_bytes();
+        let rx = OneByteReader { data, index: 0 };
+        let ctrl = OneByteReader {
+            data: vec![],
+            index: 0,
+        };
+        let mut client = ActiveLookClient::new(rx, Sink, ctrl);
+
+        let res = client.send_command_expect_response(&Command::Battery);
+        assert_eq!(Ok(response), res);
+    }
+
+    #[test]
+    fn test_split() {
+        let response = Response::Battery { level: 42 };
+        let data = Packet::new_with_query_id(&response, &1u32.to_be_bytes())
+            .unwrap()
+            .to_bytes();
+        let rx = OneByteReader { data, index: 0 };
+        let ctrl = OneByteReader {
+            data: vec![],
+            index: 0,
+        };
+        let (mut sender, mut receiver) = ActiveLookClient::new(rx, Sink, ctrl).split();
+
+        let reader = std::thread::spawn(move || {
+            let packet = receiver.read_tx_char().unwrap();
+            (response_query_id(&packet).unwrap(), packet.data)
+        });
+        assert_eq!(Ok(1), sender.send(&Command::Battery));
+        assert_eq!((1, response), reader.join().unwrap());
+    }
+
+    /// Records written bytes, shared with the test
+    #[derive(Clone, Default)]
+    struct Recorder(Rc<RefCell<Vec<u8>>>);
+
+    impl ErrorType for Recorder {
+        type Error = Infallible;
+    }
+
+    impl Write for Recorder {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// Records each write separately
+    #[derive(Clone, Default)]
+    struct Writes(Rc<RefCell<Vec<Vec<u8>>>>);
+
+    impl ErrorType for Writes {
+        type Error = Infallible;
+    }
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.borrow_mut().push(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_writes_fit_mtu() {
+        let ctrl = OneByteReader {
+            data: vec![],
+            index: 0,
+        };
+        let writes = Writes::default();
+        let mut sender = ClientSender::new(writes.clone(), ctrl);
+        let cmd = Command::LayoutDisplay {
+            id: 1,
+            text: "x".repeat(40),
+        };
+        sender.send(&cmd).unwrap();
+        let packet = Packet::new_with_query_id(&cmd, &1u32.to_be_bytes())
+            .unwrap()
+            .to_bytes();
+        assert!(packet.len() > 40);
+        assert!(writes.0.borrow().iter().all(|write| write.len() <= 20));
+        assert_eq!(packet, writes.0.borrow().concat());
+
+        writes.0.borrow_mut().clear();
+        sender.set_mtu(247);
+        sender.send(&cmd).unwrap();
+        assert_eq!(1, writes.0.borrow().len());
+        assert_eq!(
+            Packet::new_with_query_id(&cmd, &2u32.to_be_bytes())
+                .unwrap()
+                .to_bytes(),
+            writes.0.borrow()[0]
+        );
+    }
+
+    #[test]
+    fn test_flow_control_wait() {
+        let ctrl = OneByteReader {
+            data: vec![
+                FlowErrorCtrl::ClientShouldWait as u8,
+                FlowErrorCtrl::ClientCanSend as u8,
+            ],
+            index: 0,
+        };
+        let recorder = Recorder::default();
+        let mut sender = ClientSender::new(recorder.clone(), ctrl);
+
+        assert_eq!(Ok(1), sender.send(&Command::Clear));
+        // Both control notifications were handled before sending
+        assert!(sender.can_send());
+        assert_eq!(
+            Packet::new_with_query_id(&Command::Clear, &1u32.to_be_bytes())
+                .unwrap()
+                .to_bytes(),
+            *recorder.0.borrow()
+        );
+    }
+
+    #[test]
+    fn test_bulk_aborts_on_overflow() {
+        let ctrl = OneByteReader {
+            data: vec![FlowErrorCtrl::MessageQueueOverflow as u8],
+            index: 0,
+        };
+        let mut sender = ClientSender::new(Sink, ctrl);
+        let cmds = [Command::Clear, Command::Clear];
+        assert_eq!(
+            Err(ProtocolError::FlowControl(
+                FlowErrorCtrl::MessageQueueOverflow
+            )),
+            sender.send_bulk(&cmds)
+        );
+    }
+
+    #[test]
+    fn test_flow_control_blocked() {
+        let ctrl = OneByteReader {
+            data: vec![FlowErrorCtrl::ClientShouldWait as u8],
+            index: 0,
+        };
+        let mut sender = ClientSender::new(Sink, ctrl);
+        // The control characteristic never allows sending again
+        assert_eq!(Err(ProtocolError::Empty), sender.send(&Command::Clear));
+    }
+
+    #[test]
+    fn test_stream_image() {
+        let ctrl = OneByteReader {
+            data: vec![],
+            index: 0,
+        };
+        let recorder = Recorder::default();
+        let mut sender = ClientSender::new(recorder.clone(), ctrl);
+        // 8 bytes per line
+        let data: Vec<u8> = (0..=255).collect();
+        let image = Image {
+            width: 64,
+            format: ImgFormat::Img1bpp,
+            data: &data,
+        };
+        let coord = Point { x: 10, y: 20 };
+        sender.stream_image(&image, coord, 23).unwrap();
+
+        let mut assembler = PacketAssembler::new();
+        assembler.push(&recorder.0.borrow());
+        let mut received: Vec<u8> = Vec::new();
+        let mut nb_packets = 0;
+        while let Some(bytes) = assembler.next_packet().unwrap() {
+            assert!(bytes.len() <= 23 - ATT_HEADER_LEN);
+            let packet = RawPacket::from_bytes(&bytes).unwrap();
+            assert_eq!(0x44, packet.cmd_id());
+            received.extend(packet.data.unwrap());
+            nb_packets += 1;
+        }
+        // Header, then one line per packet
+        assert_eq!(1 + 32, nb_packets);
+        let (_, expected) = image.stream_command(coord).unwrap().as_bytes().unwrap();
+        assert_eq!(expected, received);
+
+        assert_eq!(235, stream_chunk_size(8, 247, QUERY_ID_LEN));
+        assert_eq!(238, stream_chunk_size(8, 247, 1));
+        assert_eq!(
+            PACKET_DATA_MAX_SIZE,
+            stream_chunk_size(8, 527, QUERY_ID_LEN)
+        );
+        // Lines longer than a write
+        assert_eq!(100, stream_chunk_size(100, 23, QUERY_ID_LEN));
+        let image = Image {
+            width: 64,
+            format: ImgFormat::Img4bpp,
+            data: &data,
+        };
+        assert_eq!(
+            Err(ProtocolError::StreamFormat(ImgFormat::Img4bpp)),
+            sender.stream_image(&image, coord, 23)
+        );
+    }
+
+    /// Notifies a pause when polled, and the end of the pause a few polls later
+    struct Pause(OneByteReader, usize);
+
+    impl ErrorType for Pause {
+        type Error = Infallible;
+    }
+
+    impl Read for Pause {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.0.read(buf)
+        }
+    }
+
+    impl ReadReady for Pause {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            self.1 += 1;
+            Ok(match self.0.index {
+                0 => true,
+                1 => self.1 > 3,
+                _ => false,
+            })
+        }
+    }
+
+    #[test]
+    fn test_upload_image() {
+        let ctrl = Pause(
+            OneByteReader {
+                data: vec![
+                    FlowErrorCtrl::ClientShouldWait as u8,
+                    FlowErrorCtrl::ClientCanSend as u8,
+                ],
+                index: 0,
+            },
+            0,
+        );
+        let recorder = Recorder::default();
+        let mut sender = ClientSender::new(recorder.clone(), ctrl);
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        let image = Image {
+            width: 100,
+            format: ImgFormat::Img8bpp,
+            data: &data,
+        };
+        let mut reports = Vec::new();
+        sender
+            .upload_image(&image, 3, &CancelToken::new(), |progress| {
+                reports.push(progress)
+            })
+            .unwrap();
+        // Paused before the header, then lines of 100 bytes, 5 per packet
+        assert!(reports[0].waiting);
+        let sent: Vec<_> = reports.iter().filter(|report| !report.waiting).collect();
+        assert_eq!(3, sent.len());
+        assert_eq!((0, 1000), (sent[0].bytes_sent, sent[0].total_bytes));
+        assert_eq!(500, sent[1].bytes_sent);
+        assert!(sent[2].is_done());
+        assert_eq!(1.0, sent[2].fraction());
+
+        let mut assembler = PacketAssembler::new();
+        assembler.push(&recorder.0.borrow());
+        let mut received: Vec<u8> = Vec::new();
+        while let Some(bytes) = assembler.next_packet().unwrap() {
+            received.extend(RawPacket::from_bytes(&bytes).unwrap().data.unwrap());
+        }
+        let (_, expected) = image.save_command(3).as_bytes().unwrap();
+        assert_eq!(expected, received);
+
+        let cancel = CancelToken::new();
+        let token = cancel.clone();
+        let mut chunks_sent = 0;
+        assert_eq!(
+            Err(ProtocolError::Cancelled),
+            sender.upload_image(&image, 3, &cancel, |progress| {
+                chunks_sent = progress.chunks_sent;
+                token.cancel();
+            })
+        );
+        assert_eq!(1, chunks_sent);
+    }
+
+    /// Notifies a pause, then never the end of the pause: the upload is cancelled by another
+    /// thread after a few polls. Reading without notification would block forever.
+    struct EndlessPause {
+        polls: usize,
+        cancel: CancelToken,
+        notified: bool,
+    }
+
+    impl ErrorType for EndlessPause {
+        type Error = Infallible;
+    }
+
+    impl Read for EndlessPause {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            assert!(!self.notified, "Blocked on the Control characteristic");
+            self.notified = true;
+            buf[0] = FlowErrorCtrl::ClientShouldWait as u8;
+            Ok(1)
+        }
+    }
+
+    impl ReadReady for EndlessPause {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            self.polls += 1;
+            if self.polls == 5 {
+                self.cancel.cancel();
+            }
+            Ok(!self.notified)
+        }
+    }
+
+    #[test]
+    fn test_cancel_paused_upload() {
+        let cancel = CancelToken::new();
+        let ctrl = EndlessPause {
+            polls: 0,
+            cancel: cancel.clone(),
+            notified: false,
+        };
+        let mut sender = ClientSender::new(Sink, ctrl);
+        let image = Image {
+            width: 100,
+            format: ImgFormat::Img8bpp,
+            data: &[0; 1000],
+        };
+        let mut waiting = false;
+        assert_eq!(
+            Err(ProtocolError::Cancelled),
+            sender.upload_image(&image, 3, &cancel, |progress| waiting |= progress.waiting)
+        );
+        assert!(waiting);
+    }
+
+    #[test]
+    fn test_pipelined_queries() {
+        let battery = Response::Battery { level: 42 };
+        let nb = Response::CfgGetNb { nb_config: 3 };
+        let error = Response::CmdError {
+            cmd_id: 0x30,
+            error: crate::commands::CmdError::Generic,
+            sub_error: 0,
+        };
+        // Responses come out of order, with an asynchronous error in between
+        let mut data = Packet::new_with_query_id(&nb, &2u32.to_be_bytes())
+            .unwrap()
+            .to_bytes();
+        data.extend(Packet::new(&error).unwrap().to_bytes());
+        data.extend(
+            Packet::new_with_query_id(&battery, &1u32.to_be_bytes())
+                .unwrap()
+                .to_bytes(),
+        );
+        let rx = OneByteReader { data, index: 0 };
+        let ctrl = OneByteReader {
+            data: Vec::new(),
+            index: 0,
+        };
+        let mut client = ActiveLookClient::new(rx, Sink, ctrl);
+
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let handler_errors = errors.clone();
+        client
+            .pending()
+            .set_unsolicited_handler(Box::new(move |packet| {
+                handler_errors.lock().unwrap().push(packet.data)
+            }));
+
+        let first = client.send_query(&Command::Battery).unwrap();
+        let second = client.send_query(&Command::CfgGetNb).unwrap();
+        assert_eq!(2, client.pending().len());
+
+        assert_eq!(Ok(battery), client.wait_response(first));
+        assert_eq!(vec![error], *errors.lock().unwrap());
+        // Already received while waiting for the first one
+        assert_eq!(Some(nb), client.pending().take(second));
+        assert!(client.pending().is_empty());
+        assert_eq!(
+            Err(ProtocolError::IncorrectQueryId),
+            client.wait_response(second)
+        );
+    }
+
+    #[test]
+    fn test_raw_commands() {
+        let response = Response::CmdError {
+            cmd_id: 0xF0,
+            error: crate::commands::CmdError::ProtocolDecoding,
+            sub_error: 0,
+        };
+        let data = Packet::new_with_query_id(&response, &2u32.to_be_bytes())
+            .unwrap()
+            .to_bytes();
+        let rx = OneByteReader { data, index: 0 };
+        let ctrl = OneByteReader {
+            data: Vec::new(),
+            index: 0,
+        };
+        let recorder = Recorder::default();
+        let mut client = ActiveLookClient::new(rx, recorder.clone(), ctrl);
+        client.send_raw(0xF0, &[1, 2]).unwrap();
+        assert_eq!(Ok(response), client.query_raw(0xF0, &[3]));
+
+        let mut expected = Packet::raw_with_query_id(0xF0, &[1, 2], &1u32.to_be_bytes())
+            .unwrap()
+            .to_bytes();
+        expected.extend(
+            Packet::raw_with_query_id(0xF0, &[3], &2u32.to_be_bytes())
+                .unwrap()
+                .to_bytes(),
+        );
+        assert_eq!(expected, *recorder.0.borrow());
+    }
+
+    #[test]
+    fn test_query_id_len() {
+        let ctrl = OneByteReader {
+            data: vec![],
+            index: 0,
+        };
+        let recorder = Recorder::default();
+        let mut sender = ClientSender::new(recorder.clone(), ctrl);
+        sender.set_config(ClientConfig { query_id_len: 1 });
+        sender.query_id = 0xFE;
+        assert_eq!(Ok(0xFF), sender.send(&Command::Clear));
+        // Wraps around on a single byte
+        assert_eq!(Ok(0), sender.send(&Command::Clear));
+        let mut expected = Packet::new_with_query_id(&Command::Clear, &[0xFF])
+            .unwrap()
+            .to_bytes();
+        expected.extend(
+            Packet::new_with_query_id(&Command::Clear, &[0])
+                .unwrap()
+                .to_bytes(),
+        );
+        assert_eq!(expected, *recorder.0.borrow());
+
+        recorder.0.borrow_mut().clear();
+        sender.set_config(ClientConfig { query_id_len: 0 });
+        sender.send(&Command::Clear).unwrap();
+        assert_eq!(
+            Packet::new(&Command::Clear).unwrap().to_bytes(),
+            *recorder.0.borrow()
+        );
+        sender.set_config(ClientConfig { query_id_len: 8 });
+        assert_eq!(QUERY_ID_LEN, sender.config().query_id_len);
+    }
+
+    #[test]
+    fn test_responses_without_query_id() {
+        let battery = Response::Battery { level: 42 };
+        let nb = Response::CfgGetNb { nb_config: 3 };
+        let error = Response::CmdError {
+            cmd_id: 0x30,
+            error: crate::commands::CmdError::Generic,
+            sub_error: 0,
+        };
+        let mut pending = PendingRequests::new();
+        pending.insert(1);
+        pending.insert(2);
+        pending.dispatch(Packet::new(&battery).unwrap());
+        // Asynchronous errors never answer a query
+        pending.dispatch(Packet::new(&error).unwrap());
+        pending.dispatch(Packet::new(&nb).unwrap());
+        assert_eq!(Some(nb), pending.take(2));
+        assert_eq!(Some(battery.clone()), pending.take(1));
+
+        pending.insert(0x1234);
+        pending.dispatch(Packet::new_with_query_id(&battery, &[0x12, 0x34]).unwrap());
+        assert_eq!(Some(battery.clone()), pending.take(0x1234));
+
+        // In send order, after the query_id wrapped around
+        pending.insert(255);
+        pending.insert(0);
+        pending.dispatch(Packet::new(&battery).unwrap());
+        assert_eq!(None, pending.take(0));
+        assert_eq!(Some(battery), pending.take(255));
+        assert!(pending.is_pending(0));
+    }
+
+    #[test]
+    fn test_long_list_responses() {
+        // Exactly two full packets, followed by an empty one
+        let images = Response::ImgList {
+            list: (0..204)
+                .map(|id| crate::commands::ImgListItem {
+                    id: id as u8,
+                    height: id,
+                    width: 2 * id,
+                })
+                .collect(),
+        };
+        let parts = images.clone().split_list();
+        assert_eq!(3, parts.len());
+        assert!(parts[0].is_partial_list() && parts[1].is_partial_list());
+        assert_eq!(Response::ImgList { list: vec![] }, parts[2]);
+        let data = parts
+            .iter()
+            .flat_map(|part| {
+                Packet::new_with_query_id(part, &1u32.to_be_bytes())
+                    .unwrap()
+                    .to_bytes()
+            })
+            .collect();
+        let rx = OneByteReader { data, index: 0 };
+        let ctrl = OneByteReader {
+            data: vec![],
+            index: 0,
+        };
+        let mut client = ActiveLookClient::new(rx, Sink, ctrl);
+        assert_eq!(
+            Ok(images),
+            client.send_command_expect_response(&Command::ImgList)
+        );
+
+        // Without query_id, the continuations go to the same query
+        let configs = Response::CfgList {
+            list: (0..50)
+                .map(|index| crate::commands::CfgItem {
+                    name: format!("cfg{}", index),
+                    size: index,
+                    version: 1,
+                    usage_counter: 0,
+                    install_counter: 0,
+                    is_system: false,
+                })
+                .collect(),
+        };
+        let parts = configs.clone().split_list();
+        assert_eq!(3, parts.len());
+        let mut pending = PendingRequests::new();
+        pending.insert(1);
+        pending.insert(2);
+        for part in &parts[..2] {
+            pending.dispatch(Packet::new(part).unwrap());
+            assert_eq!(None, pending.take(1));
+        }
+        pending.dispatch(Packet::new(&parts[2]).unwrap());
+        pending.dispatch(Packet::new(&Response::Battery { level: 42 }).unwrap());
+        assert_eq!(Some(configs), pending.take(1));
+        assert_eq!(Some(Response::Battery { level: 42 }), pending.take(2));
+    }
+
+    /// Advances the clock by 1ms on each read, like a slow link
+    struct SlowReader(OneByteReader, VirtualClock);
+
+    impl ErrorType for SlowReader {
+        type Error = Infallible;
+    }
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.1.advance(Duration::from_millis(1));
+            self.0.read(buf)
+        }
+    }
+
+    /// Fails every read, like a disconnected transport
+    struct Disconnected;
+
+    impl ErrorType for Disconnected {
+        type Error = embedded_io::ErrorKind;
+    }
+
+    impl Read for Disconnected {
+        fn read(&mut self, _buf: &mut [u8]) -> Result<usize, Self::Error> {
+            Err(embedded_io::ErrorKind::NotConnected)
         }
     }
+
+    impl ReadReady for Disconnected {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let mut client = ActiveLookClient::new(Disconnected, Sink, Disconnected);
+        assert_eq!(
+            Err(ProtocolError::EmbeddedIOError),
+            client.read_tx_char().map(|_| ())
+        );
+        assert_eq!(Err(ProtocolError::EmbeddedIOError), client.read_ctrl_char());
+
+        let ctrl = OneByteReader {
+            data: Vec::new(),
+            index: 0,
+        };
+        let mut client = ActiveLookClient::new(Disconnected, Sink, ctrl);
+        assert_eq!(
+            Err(ProtocolError::EmbeddedIOError),
+            client.send_command_expect_response(&Command::Battery)
+        );
+    }
+
+    #[test]
+    fn test_response_timeout() {
+        let clock = VirtualClock::new();
+        let rx = SlowReader(
+            OneByteReader {
+                data: Vec::new(),
+                index: 0,
+            },
+            clock.clone(),
+        );
+        let ctrl = OneByteReader {
+            data: Vec::new(),
+            index: 0,
+        };
+        let mut client = ActiveLookClient::new(rx, Sink, ctrl);
+        client.set_response_timeout(Duration::from_millis(10), clock.clone());
+        let query_id = client.send_query(&Command::Battery).unwrap();
+        assert_eq!(
+            Err(ProtocolError::Timeout(Duration::from_millis(10))),
+            client.wait_response(query_id)
+        );
+        assert!(!client.pending().is_pending(query_id));
+        assert_eq!(Duration::from_millis(10), clock.now());
+    }
+
+    #[test]
+    fn test_observer() {
+        #[derive(Default)]
+        struct Log(Vec<String>);
+
+        impl ClientObserver for Log {
+            fn on_send(&mut self, at: Duration, packet: &SentPacket) {
+                self.0.push(format!(
+                    "{:?} send 0x{:02X} #{}",
+                    at, packet.cmd_id, packet.query_id
+                ));
+            }
+
+            fn on_response(&mut self, at: Duration, response: &ReceivedResponse) {
+                self.0.push(format!(
+                    "{:?} response {:?} {:?} {:?}",
+                    at, response.query_id, response.cmd_id, response.latency
+                ));
+            }
+
+            fn on_error(&mut self, at: Duration, error: &ProtocolError) {
+                self.0.push(format!("{:?} error {:?}", at, error));
+            }
+        }
+
+        let battery = Response::Battery { level: 42 };
+        let data = Packet::new_with_query_id(&battery, &2u32.to_be_bytes())
+            .unwrap()
+            .to_bytes();
+        let clock = VirtualClock::new();
+        let rx = SlowReader(OneByteReader { data, index: 0 }, clock.clone());
+        let ctrl = OneByteReader {
+            data: Vec::new(),
+            index: 0,
+        };
+        let mut client = ActiveLookClient::new(rx, Sink, ctrl);
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Log::default()));
+        client.set_observer(log.clone(), clock.clone());
+
+        client.send(&Command::Clear).unwrap();
+        assert_eq!(
+            Ok(battery),
+            client.send_command_expect_response(&Command::Battery)
+        );
+        let too_large = [0; PACKET_DATA_MAX_SIZE + 1];
+        assert_eq!(
+            Err(ProtocolError::PayloadTooLarge(too_large.len())),
+            client.send_raw(0xF0, &too_large)
+        );
+        let latency = clock.now();
+        assert_eq!(
+            vec![
+                String::from("0ns send 0x01 #1"),
+                String::from("0ns send 0x05 #2"),
+                format!("{:?} response Some(2) Some(5) Some({:?})", latency, latency),
+                format!("{:?} error PayloadTooLarge(513)", latency),
+            ],
+            log.lock().unwrap().0
+        );
+
+        client.clear_observer();
+        client.send_raw(0xF0, &too_large).unwrap_err();
+        assert_eq!(4, log.lock().unwrap().0.len());
+    }
+
+    #[test]
+    fn test_stats() {
+        let battery = Response::Battery { level: 42 };
+        let error = Response::CmdError {
+            cmd_id: 0x30,
+            error: crate::commands::CmdError::Generic,
+            sub_error: 0,
+        };
+        let mut data = Packet::new(&error).unwrap().to_bytes();
+        data.extend(
+            Packet::new_with_query_id(&battery, &2u32.to_be_bytes())
+                .unwrap()
+                .to_bytes(),
+        );
+        let clock = VirtualClock::new();
+        let rx = SlowReader(OneByteReader { data, index: 0 }, clock.clone());
+        let ctrl = OneByteReader {
+            data: vec![
+                FlowErrorCtrl::ClientShouldWait as u8,
+                FlowErrorCtrl::ClientCanSend as u8,
+            ],
+            index: 0,
+        };
+        let mut client = ActiveLookClient::new(rx, Sink, ctrl);
+        assert!(client.stats().is_none());
+        client.enable_stats(clock.clone());
+
+        client.send(&Command::Clear).unwrap();
+        assert_eq!(
+            Ok(battery),
+            client.send_command_expect_response(&Command::Battery)
+        );
+        let stats = client.stats().unwrap();
+        assert_eq!(2, stats.commands_sent());
+        assert_eq!(
+            (Packet::new_with_query_id(&Command::Clear, &[0; 4])
+                .unwrap()
+                .to_bytes()
+                .len()
+                + Packet::new_with_query_id(&Command::Battery, &[0; 4])
+                    .unwrap()
+                    .to_bytes()
+                    .len()) as u64,
+            stats.bytes_written()
+        );
+        assert_eq!(1, stats.stalls());
+        assert_eq!(1, stats.error_responses());
+        // One read per byte of both responses
+        let latency = stats.latency(0x05).unwrap();
+        assert_eq!(1, latency.count);
+        assert_eq!(stats.elapsed(), latency.mean());
+        assert!(stats.bytes_per_second() > 0.0);
+        assert_eq!(None, stats.latency(0x01));
+    }
 }
@@ -0,0 +1,157 @@
+//! Decoding of the errors reported by the glasses
+//!
+//! The firmware reports the failure of a command with [Response::CmdError]: the ID of the
+//! command, a [CmdError] and a sub error. [CommandError] names the command and the subsystem it
+//! belongs to, following the sections of the API documentation.
+//!
+//! The API documentation of firmware 4.12.0 does not describe the sub errors: a non-zero value is
+//! kept as [SubError::Undocumented], to be reported along with the command which failed.
+use core::fmt;
+
+use thiserror::Error;
+
+use crate::commands::{CmdError, Command, Response};
+
+/// Group of commands, as sectioned in the API documentation
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Subsystem {
+    General,
+    Luminance,
+    OpticalSensor,
+    Graphics,
+    Image,
+    Font,
+    Layout,
+    Gauge,
+    Page,
+    Animation,
+    Statistics,
+    Configuration,
+    Device,
+    /// Command ID outside of the documented ranges
+    Unknown,
+}
+
+impl Subsystem {
+    /// Subsystem of command `cmd_id`. Each section of the API documentation uses its own range
+    /// of 16 IDs.
+    pub fn of(cmd_id: u8) -> Self {
+        match cmd_id >> 4 {
+            0x0 => Subsystem::General,
+            0x1 => Subsystem::Luminance,
+            0x2 => Subsystem::OpticalSensor,
+            0x3 => Subsystem::Graphics,
+            0x4 => Subsystem::Image,
+            0x5 => Subsystem::Font,
+            0x6 => Subsystem::Layout,
+            0x7 => Subsystem::Gauge,
+            0x8 => Subsystem::Page,
+            0x9 => Subsystem::Animation,
+            0xA => Subsystem::Statistics,
+            0xD => Subsystem::Configuration,
+            0xE => Subsystem::Device,
+            _ => Subsystem::Unknown,
+        }
+    }
+}
+
+/// Detail of a [CmdError]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SubError {
+    /// No detail given
+    None,
+    /// Detail whose meaning is not documented for firmware 4.12.0
+    Undocumented(u8),
+}
+
+impl From<u8> for SubError {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => SubError::None,
+            value => SubError::Undocumented(value),
+        }
+    }
+}
+
+/// Decoded [Response::CmdError]
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub struct CommandError {
+    pub cmd_id: u8,
+    pub subsystem: Subsystem,
+    pub error: CmdError,
+    pub sub_error: SubError,
+}
+
+impl CommandError {
+    pub fn new(cmd_id: u8, error: CmdError, sub_error: u8) -> Self {
+        Self {
+            cmd_id,
+            subsystem: Subsystem::of(cmd_id),
+            error,
+            sub_error: sub_error.into(),
+        }
+    }
+
+    /// Decode `response` if it is a [Response::CmdError]
+    pub fn from_response(response: &Response) -> Option<Self> {
+        match response {
+            Response::CmdError {
+                cmd_id,
+                error,
+                sub_error,
+            } => Some(Self::new(*cmd_id, error.clone(), *sub_error)),
+            _ => None,
+        }
+    }
+
+    /// Name of the failed command in the API documentation, if known
+    pub fn cmd_name(&self) -> Option<&'static str> {
+        Command::descriptor(self.cmd_id).map(|desc| desc.name)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cmd_name() {
+            Some(name) => write!(f, "{} (0x{:02X})", name, self.cmd_id)?,
+            None => write!(f, "Command 0x{:02X}", self.cmd_id)?,
+        }
+        write!(f, " failed, {:?} error: {:?}", self.subsystem, self.error)?;
+        match self.sub_error {
+            SubError::None => Ok(()),
+            SubError::Undocumented(code) => write!(f, ", sub error {}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_error() {
+        let error = CommandError::from_response(&Response::CmdError {
+            cmd_id: 0x61,
+            error: CmdError::MissingCfgWrite,
+            sub_error: 0,
+        })
+        .unwrap();
+        assert_eq!(Subsystem::Layout, error.subsystem);
+        assert_eq!(SubError::None, error.sub_error);
+        assert_eq!(
+            "layoutDelete (0x61) failed, Layout error: MissingCfgWrite",
+            error.to_string()
+        );
+
+        let error = CommandError::new(0xF7, CmdError::Generic, 3);
+        assert_eq!(Subsystem::Unknown, error.subsystem);
+        assert_eq!(
+            "Command 0xF7 failed, Unknown error: Generic, sub error 3",
+            error.to_string()
+        );
+        assert_eq!(
+            None,
+            CommandError::from_response(&Response::CfgGetNb { nb_config: 0 })
+        );
+    }
+}
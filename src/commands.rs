@@ -17,16 +17,48 @@
 //! - `deku` Enums plus de/serialization traits and implementations
 //! - a lower-level protocol handling the serialization, Query ID etc.
 //!
+//! The items used by the commands live in submodules named after the sections of the API
+//! documentation, and are re-exported here. [prelude] brings the commands, the responses and
+//! their items into scope at once.
+//!
+//! All the multi-byte integers are big endian on the wire. [Command] and [Response] set the
+//! endianness of their fields, and the items inherit it through their deku `endian` context:
+//! a field added to any of them is big endian without annotation. The items default to big
+//! endian when encoded on their own.
 //use binrw::{binrw, io::Cursor, BinRead, BinWrite};
-use crate::image::Image;
+use crate::charset;
+use crate::protocol::PACKET_DATA_MAX_SIZE;
 use crate::traits::*;
-use deku::bitvec::{BitVec, Msb0};
 use deku::ctx::BitSize;
 use deku::prelude::*;
 use deku::reader::Reader;
 use log::*;
+use std::borrow::Cow;
 use std::cmp;
 
+mod anim;
+mod cfg;
+mod device;
+mod font;
+mod gauge;
+mod general;
+mod graphics;
+mod image;
+mod layout;
+mod page;
+pub mod prelude;
+
+pub use anim::*;
+pub use cfg::*;
+pub use device::*;
+pub use font::*;
+pub use gauge::*;
+pub use general::*;
+pub use graphics::*;
+pub use image::*;
+pub use layout::*;
+use page::{read_cstr_list, write_cstr_list};
+
 // ---------------------------------------------------------------------------
 // All command and response items
 // ---------------------------------------------------------------------------
@@ -39,343 +71,6 @@ pub const NAME_LEN: usize = 12;
 /// Max size for free text
 pub const TEXT_LEN: usize = 255;
 
-/// Errors returned by ActiveLook glasses
-#[deku_derive(DekuRead, DekuWrite)]
-#[derive(Clone, Debug, Eq, PartialEq)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum CmdError {
-    #[deku(id = "1")]
-    Generic,
-    /// Missing the `cgfWrite` command before configuration modification
-    #[deku(id = "2")]
-    MissingCfgWrite,
-    /// Memory read/write error
-    #[deku(id = "3")]
-    MemoryAccess,
-    /// Protocol decoding error
-    #[deku(id = "4")]
-    ProtocolDecoding,
-}
-
-/// Available Demo values for [Command::Demo]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum DemoID {
-    #[deku(id = "0")]
-    Fill = 0,
-    #[deku(id = "1")]
-    Rect = 1,
-    #[deku(id = "2")]
-    Images = 2,
-}
-
-/// Available state values for [Command::Led]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum LedState {
-    #[deku(id = "0")]
-    Off = 0,
-    #[deku(id = "1")]
-    On = 1,
-    #[deku(id = "2")]
-    Toggle = 2,
-    #[deku(id = "3")]
-    Blinking = 3,
-}
-
-/// Available values for [Command::Info]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum DeviceInfo {
-    #[deku(id = "0")]
-    HWPlatform,
-    #[deku(id = "1")]
-    Manufacturer,
-    #[deku(id = "2")]
-    AdvertisingManufacturerID,
-    #[deku(id = "3")]
-    Model,
-    #[deku(id = "4")]
-    SubModel,
-    #[deku(id = "5")]
-    FWVersion,
-    #[deku(id = "6")]
-    SerialNumber,
-    #[deku(id = "7")]
-    BatteryModel,
-    #[deku(id = "8")]
-    LensModel,
-    #[deku(id = "9")]
-    DisplayModel,
-    #[deku(id = "10")]
-    DisplayOrientation,
-    #[deku(id = "11")]
-    Certification1,
-    #[deku(id = "12")]
-    Certification2,
-    #[deku(id = "13")]
-    Certification3,
-    #[deku(id = "14")]
-    Certification4,
-    #[deku(id = "15")]
-    Certification5,
-    #[deku(id = "16")]
-    Certification6,
-}
-
-/// Hold or Flush the graphic engine.
-///
-/// When held, new display commands are stored in memory and are displayed when the graphic engine
-/// is flushed. This allows stacking multiple graphic operations and displaying them simultaneously
-/// without screen flickering.
-/// The command is nested, the [HoldFlushAction::Flush] action must be used the same number of times
-/// [HoldFlushAction::Hold] was used.
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum HoldFlushAction {
-    /// Hold display
-    #[deku(id = "0")]
-    Hold,
-    /// Flush display
-    #[deku(id = "1")]
-    Flush,
-    /// Reset and flush all stacked hold. To be used when the state of the device is unknown.
-    /// After a BLE disconnect or an overflow error, graphic engine is reset and flushed.
-    #[deku(id = "255")]
-    ResetFlush,
-}
-
-/// Common Point type used globally in commands
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
-pub struct Point {
-    pub x: i16,
-    pub y: i16,
-}
-
-/// Common Shift type used globally in commands
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
-pub struct Shift {
-    pub x: i16,
-    pub y: i16,
-}
-
-/// List item returned in [Response::ImgList]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
-pub struct ImgListItem {
-    pub id: u8,
-    pub height: u16,
-    pub width: u16,
-}
-
-/// Font item used in [Response::FontList]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-pub struct FontItem {
-    pub id: u8,
-    pub height: u8,
-}
-
-/// Default fonts stored in ActiveLook glasses
-#[derive(Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum DefaultFont {
-    #[deku(id = "0")]
-    Default24,
-    #[deku(id = "1")]
-    ComputerModernSansSerif24,
-    #[deku(id = "2")]
-    ComputerModernSansSerif35,
-    #[deku(id = "3")]
-    ComputerModernSansSerif49,
-}
-
-impl Into<u8> for DefaultFont {
-    fn into(self) -> u8 {
-        self.deku_id().unwrap()
-    }
-}
-
-impl From<u8> for DefaultFont {
-    fn from(id: u8) -> Self {
-        match id {
-            1 => DefaultFont::ComputerModernSansSerif24,
-            2 => DefaultFont::ComputerModernSansSerif35,
-            3 => DefaultFont::ComputerModernSansSerif49,
-            _ => {
-                warn!("Unknown font {}", id);
-                DefaultFont::Default24
-            }
-        }
-    }
-}
-
-/// Configuration item used in [Response::CfgList]
-#[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
-pub struct CfgItem {
-    /// Name of the configuration
-    #[deku(
-        reader = "read_fixed_size_cstr(deku::reader, NAME_LEN)",
-        writer = "write_fixed_size_cstr(deku::writer, name, NAME_LEN)"
-    )]
-    pub name: String,
-    /// Size in bytes
-    pub size: u32,
-    /// Provided by user
-    pub version: u32,
-    /// Used to sort configurations, most recent used configuration have higher values
-    pub usage_counter: u8,
-    /// Used to sort configurations, most recent installed configuration have higher values
-    pub install_counter: u8,
-    /// Indicate system configuration, can't be deleted.
-    pub is_system: u8,
-}
-
-/// Layout position item used in [Command::LayoutPosition] for instance
-#[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(endian = "big")]
-pub struct LayoutPosition {
-    pub x: u16,
-    pub y: u8,
-}
-
-/// Layout parameters
-#[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-pub struct LayoutParameters {
-    /// Size of additional commands in bytes
-    size: u8,
-    /// Upper left clipping region in the display
-    pos: LayoutPosition,
-    /// Width of the clipping region
-    #[deku(endian = "big")]
-    width: u16,
-    /// Height of the clipping region
-    height: u8,
-    /// Foreground color (0..15)
-    fore_color: u8,
-    /// Background color (0..15)
-    back_color: u8,
-    font: u8,
-    text_valid: u8,
-    /// Test position in the clipping region
-    text_pos: LayoutPosition,
-    text_rotation: u8,
-    /// If true, the background of each character should be drawn.
-    /// Else, it leaves the background as is
-    text_opacity: u8,
-    /// Additional graphical commands
-    #[deku(count = "size")]
-    commands: Vec<u8>,
-}
-
-/// Image format
-/// - 0x00: 4bpp
-/// - 0x01: 1bpp, transformed into 4bpp by the firmware before saving
-/// - 0x02: 4bpp with Heatshrink compression, decompressed into 4bpp by the firmware before saving
-/// - 0x03: 4bpp with Heatshrink compression, stored compressed, decompressed into 4bpp before display
-/// - 0x08: 8bpp with 4 bits for grey level and 4 bits for alpha channel
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum ImgFormat {
-    /// 4 bits per pixel (16 gray levels)
-    #[deku(id = "0")]
-    Img4bpp,
-    /// 1 bit per pixel (black and white)
-    #[deku(id = "1")]
-    Img1bpp,
-    /// 4 bits per pixel with heatshrink compression (16 gray levels), decompress before saving
-    #[deku(id = "2")]
-    Img4bppDecompressBeforeSaving,
-    /// 4 bits per pixel with heatshrink compression (16 gray levels), decompress before displaying
-    #[deku(id = "3")]
-    Img4bppDecompressBeforeDisplaying,
-    /// 8 bits per pixel (16 gray levels + 16 alpha channels)
-    #[deku(id = "8")]
-    Img8bpp,
-}
-
-impl ImgFormat {
-    fn nb_of_bytes(&self, width: usize) -> usize {
-        let res = match self {
-            // 1 pixel per byte
-            ImgFormat::Img8bpp => width,
-            // 2 pixels per byte
-            ImgFormat::Img4bpp => (width + 1) / 2,
-            // 8 pixels per byte
-            ImgFormat::Img1bpp => (width + 7) / 8,
-            // Unknown
-            ImgFormat::Img4bppDecompressBeforeSaving
-            | ImgFormat::Img4bppDecompressBeforeDisplaying => width,
-        };
-        debug!("ImgFormat {:?}, width {} -> nb_bytes {}", self, width, res);
-        res
-    }
-}
-
-/// Valid image format for streaming
-/// - 0x01: 1bpp, transformed into 4bpp by the firmware before saving
-/// - 0x02: 4bpp with Heatshrink compression, decompressed into 4bpp by the firmware before saving
-#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum StreamImgFormat {
-    /// 1 bit per pixel (black and white)
-    #[deku(id = "1")]
-    Img1bpp,
-    /// 4 bits per pixel with heatshrink compression (16 gray levels), decompress before saving
-    #[deku(id = "2")]
-    Img4bppDecompressBeforeSaving,
-}
-
-impl StreamImgFormat {
-    fn nb_of_bytes(&self, width: usize) -> usize {
-        match self {
-            // 8 pixels per byte
-            StreamImgFormat::Img1bpp => (width + 7) / 8,
-            // Unknown
-            StreamImgFormat::Img4bppDecompressBeforeSaving => width,
-        }
-    }
-}
-
-impl TryFrom<ImgFormat> for StreamImgFormat {
-    type Error = ();
-
-    fn try_from(value: ImgFormat) -> Result<Self, Self::Error> {
-        match value {
-            ImgFormat::Img1bpp => Ok(StreamImgFormat::Img1bpp),
-            ImgFormat::Img4bppDecompressBeforeSaving => {
-                Ok(StreamImgFormat::Img4bppDecompressBeforeSaving)
-            }
-            _ => Err(()),
-        }
-    }
-}
-
-/// Valid image format for animations
-/// - 0x00: 4bpp
-/// - 0x02: 4bpp with Heatshrink compression, decompressed into 4bpp by the firmware before saving
-#[derive(Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
-#[repr(u8)]
-pub enum AnimImgFormat {
-    /// 4 bits per pixel (16 gray levels)
-    #[deku(id = "0")]
-    Img4bpp,
-    /// 4 bits per pixel with heatshrink compression (16 gray levels), decompress before saving
-    #[deku(id = "2")]
-    Img4bppDecompressBeforeSaving,
-}
-
 // ---------------------------------------------------------------------------
 // Deku readers and writers
 // ---------------------------------------------------------------------------
@@ -385,15 +80,15 @@ fn read_fixed_size_cstr<R: deku::no_std_io::Read + deku::no_std_io::Seek>(
     reader: &mut Reader<R>,
     len: usize,
 ) -> Result<String, DekuError> {
-    let mut res = String::new();
+    let mut res = Vec::new();
     for _ in 0..len {
         let val = u8::from_reader_with_ctx(reader, BitSize(8))?;
         if val == b'\0' {
             break;
         }
-        res.push(val as char);
+        res.push(val);
     }
-    Ok(res)
+    Ok(charset::decode(&res))
 }
 
 fn write_fixed_size_cstr<W: deku::no_std_io::Write + deku::no_std_io::Seek>(
@@ -401,36 +96,44 @@ fn write_fixed_size_cstr<W: deku::no_std_io::Write + deku::no_std_io::Seek>(
     string: &str,
     len: usize,
 ) -> Result<(), DekuError> {
-    let mut string = string.to_owned();
-    string.truncate(len);
-    let s = string.as_bytes();
-    s.to_writer(writer, BitSize(8))?;
-    //s.write(output, BitSize(8))?;
-    if s.len() < len {
+    let mut written = 0;
+    for byte in charset::lossy_bytes(string).take(len) {
+        byte.to_writer(writer, BitSize(8))?;
+        written += 1;
+    }
+    if written < len {
         //0u8.write(output, BitSize(8))?;
         0u8.to_writer(writer, BitSize(8))?;
     }
     Ok(())
 }
 
+/// Serialize `item` into `buf`, without the ID in the first byte
+fn write_without_id<T: DekuContainerWrite>(item: &T, buf: &mut [u8]) -> Result<usize, DekuError> {
+    let len = item.to_slice(buf)?;
+    buf.copy_within(1..len, 0);
+    Ok(len - 1)
+}
+
 // ---------------------------------------------------------------------------
 // All commands
 // ---------------------------------------------------------------------------
 /// These map to the commands MasterToActiveLook
 #[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(id_type = "u8", endian = "big")]
 #[repr(u8)]
 pub enum Command {
     // --- General commands --
     /// Enable / disable power of the display
     #[deku(id = "0x00")]
-    PowerDisplay { en: u8 },
+    PowerDisplay { en: bool },
     /// Clear the display memory (black screen)
     #[deku(id = "0x01")]
     Clear,
     /// Set the whole display to the corresponding grey level (0 to 15)
     #[deku(id = "0x02")]
-    Grey { lvl: u8 },
+    Grey { lvl: Grey },
     /// Display demonstration
     #[deku(id = "0x03")]
     Demo { demo_id: DemoID },
@@ -453,7 +156,7 @@ pub enum Command {
     // --- Luminance commands ---
     /// Set the display luminance to the corresponding level (0 to 15)
     #[deku(id = "0x10")]
-    Luma { level: u8 },
+    Luma { level: Luma },
 
     // --- Optical sensor commands ---
     /// Turn on/off the auto-brightness adjustment and gesture detection.
@@ -469,7 +172,7 @@ pub enum Command {
     // --- Graphics commands ---
     /// Set the grey level (0 to 15) used to draw the next graphical element
     #[deku(id = "0x30")]
-    Color { color: u8 },
+    Color { color: Grey },
     /// Set a pixel on at the corresponding coordinates
     #[deku(id = "0x31")]
     Point { coord: Point },
@@ -492,9 +195,9 @@ pub enum Command {
     #[deku(id = "0x37")]
     Txt {
         pos: Point,
-        rotation: u8,
+        rotation: TextRotation,
         font_size: u8,
-        color: u8,
+        color: Grey,
         #[deku(
             reader = "read_fixed_size_cstr(deku::reader, TEXT_LEN)",
             writer = "write_fixed_size_cstr(deku::writer, string, TEXT_LEN)"
@@ -503,6 +206,7 @@ pub enum Command {
     },
     /// Draw multiple connected lines at the corresponding coordinates.
     /// Size: 3 + (n+1) * 4
+    /// Prefer building it with [crate::polyline::Polyline].
     #[deku(id = "0x38")]
     Polyline {
         thickness: u8,
@@ -526,9 +230,7 @@ pub enum Command {
     Arc {
         center: Point,
         r: u8,
-        #[deku(endian = "big")]
         angle_start: i16,
-        #[deku(endian = "big")]
         angle_end: i16,
         thickness: u8,
     },
@@ -539,9 +241,7 @@ pub enum Command {
     #[deku(id = "0x41")]
     ImgSave {
         id: u8,
-        #[deku(endian = "big")]
         size: u32,
-        #[deku(endian = "big")]
         width: u16,
         format: ImgFormat,
         /// XXX Image data is static in memory, no need to copy in a Vec
@@ -558,9 +258,7 @@ pub enum Command {
     /// - 0x02: 4bpp with Heatshrink compression
     #[deku(id = "0x44")]
     ImgStream {
-        #[deku(endian = "big")]
         size: u32,
-        #[deku(endian = "big")]
         width: u16,
         coord: Point,
         format: StreamImgFormat,
@@ -569,9 +267,9 @@ pub enum Command {
         data: Vec<u8>,
     },
     /// Delete image.
-    /// If `id` = 0xFF, delete all images.
+    /// If `id` is [Selector::All], delete all images.
     #[deku(id = "0x46")]
-    ImgDelete { id: u8 },
+    ImgDelete { id: Selector },
     /// Give the list of saved images.
     #[deku(id = "0x47")]
     ImgList,
@@ -580,16 +278,22 @@ pub enum Command {
     /// Give the list of saved fonts with their height
     #[deku(id = "0x50")]
     FontList,
-    /// Save font `id` of `size` bytes
-    ///#[deku(id = "0x51")]
-    ///Complicated non-regular use, need special treatment.
-
+    /// Save font `id` of `size` bytes.
+    /// The first packet only contains `id` and `size`, the font data is sent in the following
+    /// packets, see [Serializable::as_bytes_chunks].
+    #[deku(id = "0x51")]
+    FontSave {
+        id: u8,
+        size: u16,
+        #[deku(count = "size")]
+        data: Vec<u8>,
+    },
     /// Select font which will be used for following text commands
     #[deku(id = "0x52")]
     FontSelect { id: u8 },
-    /// Delete font from memory. If `id` = 0xFF, delete all fonts.
+    /// Delete font from memory. If `id` is [Selector::All], delete all fonts.
     #[deku(id = "0x53")]
-    FontDelete { id: u8 },
+    FontDelete { id: Selector },
 
     // --- Layout commands ---
     /// Save a layout.
@@ -599,9 +303,9 @@ pub enum Command {
         id: u8,
         params: LayoutParameters,
     },
-    /// Delete a layout. If `id` = 0xFF, delete all layouts.
+    /// Delete a layout. If `id` is [Selector::All], delete all layouts.
     #[deku(id = "0x61")]
-    LayoutDelete { id: u8 },
+    LayoutDelete { id: Selector },
     /// Display `text` with layout `id` parameters.
     #[deku(id = "0x62")]
     LayoutDisplay {
@@ -674,20 +378,10 @@ pub enum Command {
     GaugeDisplay { id: u8, value: u8 },
     /// Save the parameters for gauge `id`
     #[deku(id = "0x71")]
-    GaugeSave {
-        id: u8,
-        pos: Point,
-        #[deku(endian = "big")]
-        radius: u16,
-        #[deku(endian = "big")]
-        inner: u16,
-        start: u8,
-        end: u8,
-        clockwise: u8,
-    },
-    /// Delete a gauge. if `id` = [ALL], delete all gauges
+    GaugeSave { id: u8, params: GaugeParameters },
+    /// Delete a gauge. if `id` is [Selector::All], delete all gauges
     #[deku(id = "0x72")]
-    GaugeDelete { id: u8 },
+    GaugeDelete { id: Selector },
     /// Give the list of saved gauges
     #[deku(id = "0x73")]
     GaugeList,
@@ -696,30 +390,45 @@ pub enum Command {
     GaugeGet { id: u8 },
 
     // --- Page commands ---
-    /// Save a page of layouts
-    /// TODO
+    /// Save page `id`, displaying `layouts`. See [crate::page::Page] for their encoding.
     #[deku(id = 0x80)]
-    PageSave,
+    PageSave {
+        id: u8,
+        #[deku(read_all)]
+        layouts: Vec<u8>,
+    },
     /// Get a page
     #[deku(id = 0x81)]
     PageGet { id: u8 },
-    /// Delete a page. If `id` = 0xFF, delete all pages.
+    /// Delete a page. If `id` is [Selector::All], delete all pages.
     #[deku(id = 0x82)]
-    PageDelete { id: u8 },
-    /// Display a page, each string are NUL separated
-    /// TODO
+    PageDelete { id: Selector },
+    /// Display a page, with one string for each layout of the page
     #[deku(id = 0x83)]
-    PageDisplay { id: u8 },
+    PageDisplay {
+        id: u8,
+        #[deku(
+            reader = "read_cstr_list(deku::reader)",
+            writer = "write_cstr_list(deku::writer, strings)"
+        )]
+        strings: Vec<String>,
+    },
     /// Clear screen of the corresponding page area
     #[deku(id = 0x84)]
     PageClear { id: u8 },
     /// List pages in memory
     #[deku(id = 0x85)]
     PageList,
-    /// Clear area and display a page, each string are NUL separated
-    /// TODO
+    /// Clear area and display a page, with one string for each layout of the page
     #[deku(id = 0x86)]
-    PageClearAndDisplay { id: u8 },
+    PageClearAndDisplay {
+        id: u8,
+        #[deku(
+            reader = "read_cstr_list(deku::reader)",
+            writer = "write_cstr_list(deku::writer, strings)"
+        )]
+        strings: Vec<String>,
+    },
 
     // --- Animation commands ---
     /// save an animation
@@ -727,13 +436,10 @@ pub enum Command {
     AnimSave {
         id: u8,
         /// Total animation size, in bytes
-        #[deku(endian = "big")]
         total_size: u32,
         /// Reference frame size in bytes
-        #[deku(endian = "big")]
         img_size: u32,
         /// Reference image width in pixel
-        #[deku(endian = "big")]
         width: u16,
         /// format of reference frame
         /// 0x00: 4bpp
@@ -741,12 +447,11 @@ pub enum Command {
         /// saving
         fmt: u8,
         /// Reference frame size before it is decompressed. for 4bpp it's equal to img_size
-        #[deku(endian = "big")]
         img_compressed_size: u32,
     },
-    /// Delete an animation. If `id` = 0xFF, delete all animations
+    /// Delete an animation. If `id` is [Selector::All], delete all animations
     #[deku(id = "0x96")]
-    AnimDelete { id: u8 },
+    AnimDelete { id: Selector },
     /// Display animation `id` to the corresponding coordinates.
     #[deku(id = "0x97")]
     AnimDisplay {
@@ -755,16 +460,15 @@ pub enum Command {
         /// Animation `id`
         id: u8,
         /// Set the inter-frame duration in ms
-        #[deku(endian = "big")]
         delay: u16,
         /// Repeat count, or 0xFF for infinite repetition
         repeat: u8,
         pos: Point,
     },
     /// Stop and clear the screen of the corresponding animation.
-    /// If `handler_id` = 0xFF, clear all animations.
+    /// If `handler_id` is [Selector::All], clear all animations.
     #[deku(id = "0x98")]
-    AnimClear { handler_id: u8 },
+    AnimClear { handler_id: Selector },
     /// Get list of saved animations
     #[deku(id = "0x99")]
     AnimList,
@@ -786,11 +490,9 @@ pub enum Command {
         )]
         name: String,
         /// Provided by the user for tracking versions
-        #[deku(endian = "big")]
         version: u32,
         /// If the configuration already exists, the same password must be provided as the one
         /// during the creation.
-        #[deku(endian = "big")]
         password: u32,
     },
     /// Get the number of elements stored in the configuration
@@ -826,7 +528,6 @@ pub enum Command {
             writer = "write_fixed_size_cstr(deku::writer, new, NAME_LEN)"
         )]
         new: String,
-        #[deku(endian = "big")]
         password: u32,
     },
     /// Delete a configuration and all elements associated
@@ -849,11 +550,11 @@ pub enum Command {
     CfgGetNb,
 
     // --- Device commands ---
-    /// Shutdown the device. The key must be equal to `0x6f 0x7f 0xc4 0xee`
+    /// Shutdown the device. The key must be equal to [SHUTDOWN_KEY], see [Command::shutdown].
     /// Shutdown is **NOT** allowed while USB powered.
     #[deku(id = "0xE0")]
     Shutdown { key: [u8; 4] },
-    /// Reset the device. The key must be equal to `0x5c 0x1e 0x2d 0xe9`
+    /// Reset the device. The key must be equal to [RESET_KEY], see [Command::reset].
     /// Reset is allowed **only** while USB powered.
     #[deku(id = "0xE1")]
     Reset { key: [u8; 4] },
@@ -877,6 +578,11 @@ impl Serializable for Command {
         Ok(bytes)
     }
 
+    /// Serialize the whole Command and drop its ID, without allocating
+    fn write_data_into(&self, buf: &mut [u8]) -> Result<usize, DekuError> {
+        write_without_id(self, buf)
+    }
+
     /// Extract CommandID and data bytes from Command
     fn as_bytes(&self) -> Result<(u8, Vec<u8>), DekuError> {
         let data = self.data_bytes()?;
@@ -885,62 +591,477 @@ impl Serializable for Command {
 
     /// Extract CommandID and data bytes from Command, in smaller chunks
     fn as_bytes_chunks(&self, chunk_size: usize) -> Result<(u8, Vec<Vec<u8>>), DekuError> {
-        let mut res = Vec::new();
-        let data = self.data_bytes()?;
-        let len = data.len();
-        let mut index: usize = 0;
+        let (id, chunks) = self.chunks(chunk_size)?;
+        Ok((id, chunks.into_iter().map(Cow::into_owned).collect()))
+    }
+
+    /// Split the data like [Serializable::as_bytes_chunks], following the [ChunkPolicy] of the
+    /// command. The data of [Command::ImgSave], [Command::ImgStream] and [Command::FontSave] is
+    /// borrowed, only their header is serialized.
+    fn chunks(&self, chunk_size: usize) -> Result<Chunks<'_>, DekuError> {
+        let id = self.id()?;
+        if self.chunk_policy().is_none() {
+            let data = self.data_bytes()?;
+            if data.is_empty() {
+                // The command is still sent, in a packet without data
+                return Ok((id, vec![Cow::Owned(data)]));
+            }
+            let chunks = data
+                .chunks(chunk_size.max(1))
+                .map(|chunk| Cow::Owned(chunk.to_vec()))
+                .collect();
+            return Ok((id, chunks));
+        }
+        let (header, data) = self.split_data();
+        Ok((id, split_aligned(&header, data, chunk_size)?))
+    }
+}
+
+/// Chunks of a command split in multiple packets: the first one only has the serialized
+/// `header`, `data` is split in chunks of at most `chunk_size` bytes, aligned according to the
+/// [ChunkPolicy] of the header.
+pub(crate) fn split_aligned<'a>(
+    header: &Command,
+    data: &'a [u8],
+    chunk_size: usize,
+) -> Result<Vec<Cow<'a, [u8]>>, DekuError> {
+    let byte_align = header
+        .chunk_policy()
+        .map_or(1, |policy| policy.alignment(header))
+        .max(1);
+    let chunk = (chunk_size / byte_align).max(1) * byte_align;
+    let header = header.data_bytes()?;
+    debug!("header_len: {}, chunk: {}", header.len(), chunk);
+    let mut res = vec![Cow::Owned(header)];
+    res.extend(data.chunks(chunk).map(Cow::Borrowed));
+    Ok(res)
+}
+
+/// Alignment of the chunks of data, see [ChunkPolicy]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ChunkAlignment {
+    /// Chunks of any length
+    Byte,
+    /// Whole lines of the image described by the header
+    ImageLine,
+}
+
+/// How a command too big for a single packet is split, see [Serializable::chunks].
+///
+/// The first packet only holds the `header_len` data bytes of the header, which give the size of
+/// the data. The data follows in the next packets, with the same command ID.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ChunkPolicy {
+    pub id: u8,
+    /// Data bytes of the header
+    pub header_len: usize,
+    /// Offset and length in the header of the data size
+    size_field: (usize, usize),
+    pub alignment: ChunkAlignment,
+}
+
+/// Commands split in multiple packets, sorted by ID
+const CHUNK_POLICIES: &[ChunkPolicy] = &[
+    // ImgSave: id, size, width, format
+    ChunkPolicy::new(0x41, 8, (1, 4), ChunkAlignment::ImageLine),
+    // ImgStream: size, width, coord, format
+    ChunkPolicy::new(0x44, 11, (0, 4), ChunkAlignment::ImageLine),
+    // FontSave: id, size
+    ChunkPolicy::new(0x51, 3, (1, 2), ChunkAlignment::Byte),
+    // AnimSave: id, total_size, img_size, width, fmt, img_compressed_size
+    ChunkPolicy::new(0x95, 16, (1, 4), ChunkAlignment::ImageLine),
+];
+
+impl ChunkPolicy {
+    const fn new(
+        id: u8,
+        header_len: usize,
+        size_field: (usize, usize),
+        alignment: ChunkAlignment,
+    ) -> Self {
+        Self {
+            id,
+            header_len,
+            size_field,
+            alignment,
+        }
+    }
+
+    /// Policy of command `id`, if its data is split in multiple packets
+    pub fn for_id(id: u8) -> Option<&'static ChunkPolicy> {
+        CHUNK_POLICIES.iter().find(|policy| policy.id == id)
+    }
 
-        let mut header_len: usize = 0;
-        // For most commands we don't care about data alignment.
-        // For imgSave and imgStream, they need to be aligned to the img line.
-        let mut byte_align: usize = 1;
+    /// Size of the data following the `header` data bytes, if they are the header of the command
+    pub fn data_size(&self, header: &[u8]) -> Option<usize> {
+        if header.len() != self.header_len {
+            return None;
+        }
+        let (offset, len) = self.size_field;
+        Some(
+            header[offset..offset + len]
+                .iter()
+                .fold(0, |size, byte| (size << 8) | *byte as usize),
+        )
+    }
+
+    /// Chunks of data are a multiple of this size, in bytes
+    pub fn alignment(&self, header: &Command) -> usize {
+        match self.alignment {
+            ChunkAlignment::Byte => 1,
+            ChunkAlignment::ImageLine => header.line_len().unwrap_or(1),
+        }
+    }
+}
+
+impl Command {
+    /// How the command is split in multiple packets, if it is too big for one
+    pub fn chunk_policy(&self) -> Option<&'static ChunkPolicy> {
+        ChunkPolicy::for_id(self.id().ok()?)
+    }
 
-        // First chunk only has the imgSave/imgStream data header.
-        // Otherwise we don't care and we just split the remaining data according to length
+    /// Length of the image lines in the data, for [ChunkAlignment::ImageLine]
+    fn line_len(&self) -> Option<usize> {
+        match self {
+            Command::ImgSave { width, format, .. } => Some(format.nb_of_bytes(*width as usize)),
+            Command::ImgStream { width, format, .. } => Some(format.nb_of_bytes(*width as usize)),
+            // The reference frame is in 4bpp, the next frames have no line alignment
+            Command::AnimSave { width, fmt: 0, .. } => {
+                Some(ImgFormat::Img4bpp.nb_of_bytes(*width as usize))
+            }
+            _ => None,
+        }
+    }
+
+    /// Header and borrowed data of the commands split in multiple packets, see
+    /// [Serializable::chunks]. [Command::AnimSave] only has a header, its data is sent separately.
+    fn split_data(&self) -> (Cow<'_, Command>, &[u8]) {
         match self {
             Command::ImgSave {
-                id: _,
-                size: _,
+                id,
+                size,
                 width,
                 format,
-                data: _,
-            } => {
-                header_len = 8;
-                byte_align = format.nb_of_bytes(*width as usize);
-            }
+                data,
+            } => (
+                Cow::Owned(Command::ImgSave {
+                    id: *id,
+                    size: *size,
+                    width: *width,
+                    format: *format,
+                    data: Vec::new(),
+                }),
+                data,
+            ),
             Command::ImgStream {
-                size: _,
+                size,
                 width,
-                coord: _,
+                coord,
                 format,
-                data: _,
-            } => {
-                header_len = 11;
-                byte_align = format.nb_of_bytes(*width as usize);
-            }
-            _ => {}
-        };
+                data,
+            } => (
+                Cow::Owned(Command::ImgStream {
+                    size: *size,
+                    width: *width,
+                    coord: *coord,
+                    format: *format,
+                    data: Vec::new(),
+                }),
+                data,
+            ),
+            Command::FontSave { id, size, data } => (
+                Cow::Owned(Command::FontSave {
+                    id: *id,
+                    size: *size,
+                    data: Vec::new(),
+                }),
+                data,
+            ),
+            _ => (Cow::Borrowed(self), &[]),
+        }
+    }
+}
 
-        debug!("header_len: {}, byte_align: {}", header_len, byte_align);
+/// Description of a command of the API, see [Command::registry]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CommandDescriptor {
+    pub id: u8,
+    /// Name in the API documentation
+    pub name: &'static str,
+    /// Minimal data size, in bytes: empty strings and variable size data
+    pub min_size: usize,
+    /// Maximal data size, in bytes. Variable size data is only bounded by the packet size.
+    pub max_size: usize,
+    /// The glasses answer with a [Response]
+    pub response: bool,
+}
 
-        if header_len > 0 {
-            res.push(data[index..header_len].to_vec());
-            index += header_len;
+impl CommandDescriptor {
+    const fn send(id: u8, name: &'static str, min_size: usize, max_size: usize) -> Self {
+        Self {
+            id,
+            name,
+            min_size,
+            max_size,
+            response: false,
         }
+    }
 
-        // Push all remaining data, split at image line end.
-        let nblines = chunk_size / byte_align;
-        let chunk = nblines * byte_align;
-        while index < len {
-            let end = cmp::min(len, index + chunk);
-            debug!(
-                "nblines {}, chunk {}, index {}, end {}",
-                nblines, chunk, index, end
-            );
-            res.push(data[index..end].to_vec());
-            index = end;
+    const fn query(id: u8, name: &'static str, min_size: usize, max_size: usize) -> Self {
+        Self {
+            response: true,
+            ..Self::send(id, name, min_size, max_size)
+        }
+    }
+}
+
+/// Every command of the API, sorted by ID
+const REGISTRY: &[CommandDescriptor] = &[
+    CommandDescriptor::send(0x00, "power", 1, 1),
+    CommandDescriptor::send(0x01, "clear", 0, 0),
+    CommandDescriptor::send(0x02, "grey", 1, 1),
+    CommandDescriptor::send(0x03, "demo", 1, 1),
+    CommandDescriptor::query(0x05, "battery", 0, 0),
+    CommandDescriptor::query(0x06, "vers", 0, 0),
+    CommandDescriptor::send(0x08, "led", 1, 1),
+    CommandDescriptor::send(0x09, "shift", 4, 4),
+    CommandDescriptor::query(0x0A, "settings", 0, 0),
+    CommandDescriptor::send(0x10, "luma", 1, 1),
+    CommandDescriptor::send(0x20, "sensor", 1, 1),
+    CommandDescriptor::send(0x21, "gesture", 1, 1),
+    CommandDescriptor::send(0x22, "als", 1, 1),
+    CommandDescriptor::send(0x30, "color", 1, 1),
+    CommandDescriptor::send(0x31, "point", 4, 4),
+    CommandDescriptor::send(0x32, "line", 8, 8),
+    CommandDescriptor::send(0x33, "rect", 8, 8),
+    CommandDescriptor::send(0x34, "rectf", 8, 8),
+    CommandDescriptor::send(0x35, "circ", 5, 5),
+    CommandDescriptor::send(0x36, "circf", 5, 5),
+    CommandDescriptor::send(0x37, "txt", 8, 7 + TEXT_LEN),
+    CommandDescriptor::send(0x38, "polyline", 3, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::send(0x39, "holdFlush", 1, 1),
+    CommandDescriptor::send(0x3C, "arc", 10, 10),
+    CommandDescriptor::send(0x41, "imgSave", 8, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::send(0x42, "imgDisplay", 5, 5),
+    CommandDescriptor::send(0x44, "imgStream", 11, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::send(0x46, "imgDelete", 1, 1),
+    CommandDescriptor::query(0x47, "imgList", 0, 0),
+    CommandDescriptor::query(0x50, "fontList", 0, 0),
+    CommandDescriptor::send(0x51, "fontSave", 3, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::send(0x52, "fontSelect", 1, 1),
+    CommandDescriptor::send(0x53, "fontDelete", 1, 1),
+    CommandDescriptor::send(0x60, "layoutSave", 17, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::send(0x61, "layoutDelete", 1, 1),
+    CommandDescriptor::send(0x62, "layoutDisplay", 2, 1 + TEXT_LEN),
+    CommandDescriptor::send(0x63, "layoutClear", 1, 1),
+    CommandDescriptor::query(0x64, "layoutList", 0, 0),
+    CommandDescriptor::send(0x65, "layoutPosition", 4, 4),
+    CommandDescriptor::send(0x66, "layoutDisplayExtended", 5, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::query(0x67, "layoutGet", 1, 1),
+    CommandDescriptor::send(0x68, "layoutClearExtended", 4, 4),
+    CommandDescriptor::send(0x69, "layoutClearAndDisplay", 2, 1 + TEXT_LEN),
+    CommandDescriptor::send(
+        0x6A,
+        "layoutClearAndDisplayExtended",
+        5,
+        PACKET_DATA_MAX_SIZE,
+    ),
+    CommandDescriptor::send(0x70, "gaugeDisplay", 2, 2),
+    CommandDescriptor::send(0x71, "gaugeSave", 12, 12),
+    CommandDescriptor::send(0x72, "gaugeDelete", 1, 1),
+    CommandDescriptor::query(0x73, "gaugeList", 0, 0),
+    CommandDescriptor::query(0x74, "gaugeGet", 1, 1),
+    CommandDescriptor::send(0x80, "pageSave", 1, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::query(0x81, "pageGet", 1, 1),
+    CommandDescriptor::send(0x82, "pageDelete", 1, 1),
+    CommandDescriptor::send(0x83, "pageDisplay", 1, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::send(0x84, "pageClear", 1, 1),
+    CommandDescriptor::query(0x85, "pageList", 0, 0),
+    CommandDescriptor::send(0x86, "pageClearAndDisplay", 1, PACKET_DATA_MAX_SIZE),
+    CommandDescriptor::send(0x95, "animSave", 16, 16),
+    CommandDescriptor::send(0x96, "animDelete", 1, 1),
+    CommandDescriptor::send(0x97, "animDisplay", 9, 9),
+    CommandDescriptor::send(0x98, "animClear", 1, 1),
+    CommandDescriptor::query(0x99, "animList", 0, 0),
+    CommandDescriptor::query(0xA5, "pixelCount", 0, 0),
+    CommandDescriptor::send(0xD0, "cfgWrite", 9, NAME_LEN + 8),
+    CommandDescriptor::query(0xD1, "cfgRead", 1, NAME_LEN),
+    CommandDescriptor::send(0xD2, "cfgSet", 1, NAME_LEN),
+    CommandDescriptor::query(0xD3, "cfgList", 0, 0),
+    CommandDescriptor::send(0xD4, "cfgRename", 6, 2 * NAME_LEN + 4),
+    CommandDescriptor::send(0xD5, "cfgDelete", 1, NAME_LEN),
+    CommandDescriptor::send(0xD6, "cfgDeleteLessUsed", 0, 0),
+    CommandDescriptor::query(0xD7, "cfgFreeSpace", 0, 0),
+    CommandDescriptor::query(0xD8, "cfgGetNb", 0, 0),
+    CommandDescriptor::send(0xE0, "shutdown", 4, 4),
+    CommandDescriptor::send(0xE1, "reset", 4, 4),
+    CommandDescriptor::query(0xE3, "rdDevInfo", 1, 1),
+];
+
+// Checked at compile time: the registry is sorted for the binary search of
+// [Command::descriptor], and every size fits in a packet
+const _: () = {
+    let mut index = 0;
+    while index < REGISTRY.len() {
+        let desc = &REGISTRY[index];
+        assert!(desc.min_size <= desc.max_size);
+        assert!(desc.max_size <= PACKET_DATA_MAX_SIZE);
+        assert!(index == 0 || REGISTRY[index - 1].id < desc.id);
+        index += 1;
+    }
+};
+
+impl Command {
+    /// Every command of the API, sorted by ID
+    pub fn registry() -> &'static [CommandDescriptor] {
+        REGISTRY
+    }
+
+    /// Description of command `id`, if it exists
+    pub fn descriptor(id: u8) -> Option<&'static CommandDescriptor> {
+        REGISTRY
+            .binary_search_by_key(&id, |desc| desc.id)
+            .ok()
+            .map(|index| &REGISTRY[index])
+    }
+
+    /// IDs of every command of the API, in increasing order
+    pub fn ids() -> impl Iterator<Item = u8> {
+        REGISTRY.iter().map(|desc| desc.id)
+    }
+
+    /// [Command::Shutdown] with the documented key.
+    /// Not allowed while USB powered, see [crate::power::PowerGuard].
+    pub const fn shutdown() -> Self {
+        Command::Shutdown { key: SHUTDOWN_KEY }
+    }
+
+    /// [Command::Reset] with the documented key.
+    /// Only allowed while USB powered, see [crate::power::PowerGuard].
+    pub const fn reset() -> Self {
+        Command::Reset { key: RESET_KEY }
+    }
+
+    /// Returns true if the glasses answer this command with a [Response].
+    ///
+    /// Other commands are not acknowledged: only a failure is notified, with an unsolicited
+    /// [Response::CmdError].
+    pub fn expects_response(&self) -> bool {
+        self.id()
+            .ok()
+            .and_then(Self::descriptor)
+            .is_some_and(|desc| desc.response)
+    }
+
+    /// Size of the data of the command in a packet, in bytes.
+    /// Above [PACKET_DATA_MAX_SIZE], the command can not be sent in a single packet: split a
+    /// [Command::Polyline] with [crate::polyline::Polyline], and upload images and fonts with
+    /// [Serializable::chunks]. Texts are cut to 255 bytes: wrap long texts in several
+    /// [Command::Txt] with [crate::text::TextWrap].
+    pub fn wire_size(&self) -> Result<usize, DekuError> {
+        Ok(self.data_bytes()?.len())
+    }
+}
+
+impl Response {
+    /// Command saving the element fetched by [Response::LayoutGet], [Response::GaugeGet] or
+    /// [Response::PageGet] as `id`, to copy it or migrate it to other glasses
+    pub fn to_save_command(&self, id: u8) -> Option<Command> {
+        match self {
+            Response::PageGet { layouts, .. } => Some(Command::PageSave {
+                id,
+                layouts: layouts.clone(),
+            }),
+            Response::LayoutGet { params } => Some(Command::LayoutSave {
+                id,
+                params: params.clone(),
+            }),
+            Response::GaugeGet { params } => Some(Command::GaugeSave {
+                id,
+                params: *params,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Size of an item of [Response::ImgList], [Response::FontList] and [Response::CfgList],
+    /// the lists which may not fit in a single packet
+    fn list_item_len(&self) -> Option<usize> {
+        match self {
+            Response::ImgList { .. } => Some(IMG_LIST_ITEM_LEN),
+            Response::FontList { .. } => Some(FONT_LIST_ITEM_LEN),
+            Response::CfgList { .. } => Some(CFG_LIST_ITEM_LEN),
+            _ => None,
+        }
+    }
+
+    fn list_len(&self) -> usize {
+        match self {
+            Response::ImgList { list } => list.len(),
+            Response::FontList { list } => list.len(),
+            Response::CfgList { list } => list.len(),
+            _ => 0,
+        }
+    }
+
+    /// Returns true if this list response fills a whole packet, and is continued by the next
+    /// response to the same query. See [Response::split_list].
+    pub fn is_partial_list(&self) -> bool {
+        self.list_item_len()
+            .is_some_and(|len| (self.list_len() + 1) * len > PACKET_DATA_MAX_SIZE)
+    }
+
+    /// Append the items of the continuation `next` to this list response.
+    /// Returns `next` unchanged if it is not a list of the same kind.
+    pub fn extend_list(&mut self, next: Response) -> Result<(), Response> {
+        match (self, next) {
+            (Response::ImgList { list }, Response::ImgList { list: next }) => list.extend(next),
+            (Response::FontList { list }, Response::FontList { list: next }) => list.extend(next),
+            (Response::CfgList { list }, Response::CfgList { list: next }) => list.extend(next),
+            (_, next) => return Err(next),
+        }
+        Ok(())
+    }
+
+    /// Split a list response into responses fitting in a packet each.
+    ///
+    /// The API documentation does not describe how the firmware sends a list which does not fit
+    /// in a packet. We assume each packet is filled with as many items as possible, and that a
+    /// packet which is not full ends the list: when the last packet is full, an empty list
+    /// follows. Other responses are returned as is.
+    pub fn split_list(self) -> Vec<Response> {
+        let Some(item_len) = self.list_item_len() else {
+            return vec![self];
+        };
+        let per_packet = PACKET_DATA_MAX_SIZE / item_len;
+        fn split<T>(
+            list: Vec<T>,
+            per_packet: usize,
+            wrap: impl Fn(Vec<T>) -> Response,
+        ) -> Vec<Response> {
+            let mut responses = Vec::new();
+            let mut items = list.into_iter();
+            loop {
+                let chunk: Vec<T> = items.by_ref().take(per_packet).collect();
+                let full = chunk.len() == per_packet;
+                responses.push(wrap(chunk));
+                if !full {
+                    return responses;
+                }
+            }
+        }
+        match self {
+            Response::ImgList { list } => {
+                split(list, per_packet, |list| Response::ImgList { list })
+            }
+            Response::FontList { list } => {
+                split(list, per_packet, |list| Response::FontList { list })
+            }
+            Response::CfgList { list } => {
+                split(list, per_packet, |list| Response::CfgList { list })
+            }
+            other => vec![other],
         }
-        Ok((self.id()?, res))
     }
 }
 
@@ -964,7 +1085,8 @@ impl Deserializable for Command {
 
 /// These map to the responses ActiveLookToMaster
 #[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u8")]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(id_type = "u8", endian = "big")]
 #[repr(u8)]
 pub enum Response {
     // --- General commands --
@@ -984,9 +1106,9 @@ pub enum Response {
     Settings {
         x: i8,
         y: i8,
-        luma: u8,
-        als_enable: u8,
-        gesture_enable: u8,
+        luma: Luma,
+        als_enable: bool,
+        gesture_enable: bool,
     },
 
     // --- Image commands ---
@@ -1025,21 +1147,16 @@ pub enum Response {
     },
     /// Gauge parameters without `id`
     #[deku(id = "0x74")]
-    GaugeGet {
-        pos: Point,
-        #[deku(endian = "big")]
-        radius: u16,
-        #[deku(endian = "big")]
-        inner: u16,
-        start: u8,
-        end: u8,
-        clockwise: u8,
-    },
+    GaugeGet { params: GaugeParameters },
 
     // --- Page commands ---
-    /// Page with layout parameters
+    /// Page `id`, displaying `layouts`
     #[deku(id = "0x81")]
-    PageGet { id: u8 },
+    PageGet {
+        id: u8,
+        #[deku(read_all)]
+        layouts: Vec<u8>,
+    },
     /// List of page IDs in memory. Listing is not sorted
     #[deku(id = 0x85)]
     PageList {
@@ -1058,16 +1175,12 @@ pub enum Response {
     // --- Statistics commands ---
     /// Number of pixels activated on the display
     #[deku(id = "0xA5")]
-    PixelCount {
-        #[deku(endian = "big")]
-        count: u32,
-    },
+    PixelCount { count: u32 },
 
     // --- Configuration commands ---
     /// Number of elements stored in the configuration
-    #[deku(id = "0xD2")]
+    #[deku(id = "0xD1")]
     CfgRead {
-        #[deku(endian = "big")]
         version: u32,
         nb_img: u8,
         nb_layout: u8,
@@ -1083,10 +1196,8 @@ pub enum Response {
     #[deku(id = "0xD7")]
     CfgFreeSpace {
         /// Total size available in bytes
-        #[deku(endian = "big")]
         total_size: u32,
         /// Free space available in bytes
-        #[deku(endian = "big")]
         free_space: u32,
     },
     /// Number of configurations stored in memory
@@ -1102,7 +1213,7 @@ pub enum Response {
         error: CmdError,
         sub_error: u8,
     },
-    ///
+    /// Device information parameter, as requested with [Command::Info]
     #[deku(id = "0xE3")]
     RdDevInfo {
         #[deku(read_all)]
@@ -1125,6 +1236,11 @@ impl Serializable for Response {
         Ok(bytes)
     }
 
+    /// Serialize the whole Response and drop its ID, without allocating
+    fn write_data_into(&self, buf: &mut [u8]) -> Result<usize, DekuError> {
+        write_without_id(self, buf)
+    }
+
     /// Extract CommandID and data bytes from Response
     fn as_bytes(&self) -> Result<(u8, Vec<u8>), DekuError> {
         let data = self.data_bytes()?;
@@ -1164,14 +1280,31 @@ impl Deserializable for Response {
 // ---------------------------------------------------------------------------
 // Tests
 // ---------------------------------------------------------------------------
+/// Check the wire data of `commands` and `responses`, both encoded and decoded
+#[cfg(test)]
+fn check_wire_data(commands: &[(Command, &[u8])], responses: &[(Response, &[u8])]) {
+    for (cmd, data) in commands {
+        assert_eq!(*data, cmd.data_bytes().unwrap(), "{:?}", cmd);
+        let data = (!data.is_empty()).then_some(*data);
+        assert_eq!(*cmd, Command::from_data(cmd.id().unwrap(), data).unwrap());
+    }
+    for (response, data) in responses {
+        assert_eq!(*data, response.data_bytes().unwrap(), "{:?}", response);
+        let data = (!data.is_empty()).then_some(*data);
+        assert_eq!(
+            *response,
+            Response::from_data(response.id().unwrap(), data).unwrap()
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use test_log;
 
     #[test]
     fn test_id() {
-        assert_eq!(0, Command::PowerDisplay { en: true as u8 }.id().unwrap());
+        assert_eq!(0, Command::PowerDisplay { en: true }.id().unwrap());
         assert_eq!(1, Command::Clear.id().unwrap());
         assert_eq!(0x0A, Command::Settings.id().unwrap());
     }
@@ -1180,7 +1313,7 @@ mod tests {
     fn test_simple_serialization() {
         // Serialization
         let expected: &[u8] = &[0x00, 0x01];
-        let cmd = Command::PowerDisplay { en: true as u8 };
+        let cmd = Command::PowerDisplay { en: true };
         let bytes = cmd.to_bytes().unwrap();
         assert_eq!(expected, bytes);
 
@@ -1201,21 +1334,6 @@ mod tests {
         assert_eq!(expected, cmd);
     }
 
-    #[test]
-    fn test_vec_serialization() {
-        let bytes: &[u8] = &[1, 2, 3];
-        let expected = Response::RdDevInfo {
-            parameters: vec![1, 2, 3],
-        };
-        // Serialization
-        let data = expected.data_bytes().unwrap();
-        assert_eq!(bytes, data);
-
-        // Deserialization
-        let res = Response::from_data(0xE3, Some(&bytes)).unwrap();
-        assert_eq!(expected, res);
-    }
-
     #[test]
     fn test_fixed_string_short() {
         let bytes: &[u8] = &[
@@ -1234,8 +1352,8 @@ mod tests {
 
         // how to access the returned value
         match cmd {
-            Command::LayoutDisplay { id, text } => assert_eq!(text, "012"),
-            _ => assert!(false),
+            Command::LayoutDisplay { id: _, text } => assert_eq!(text, "012"),
+            _ => panic!("Not a LayoutDisplay"),
         }
     }
 
@@ -1254,57 +1372,249 @@ mod tests {
     }
 
     #[test]
-    fn test_endianness() {
-        let point = Point {
-            x: 0x1234,
-            y: 0x5678,
+    fn test_fixed_string_charset() {
+        let cmd = Command::LayoutDisplay {
+            id: 1,
+            text: String::from("12°C → 5€"),
         };
-        let cmd = Command::Point { coord: point };
-        let expected: &[u8] = &[0x12, 0x34, 0x56, 0x78];
         let data = cmd.data_bytes().unwrap();
-        assert_eq!(expected, data);
-    }
+        assert_eq!(
+            &[1, b'1', b'2', 0xB0, b'C', b' ', b'?', b' ', b'5', b'?', 0][..],
+            data
+        );
+        let expected = Command::LayoutDisplay {
+            id: 1,
+            text: String::from("12°C ? 5?"),
+        };
+        assert_eq!(expected, Command::from_data(0x62, Some(&data)).unwrap());
 
-    #[test]
-    fn test_img_format_bytes() {
-        let a = ImgFormat::Img1bpp;
-        assert_eq!(a.nb_of_bytes(7), 1);
-        assert_eq!(a.nb_of_bytes(8), 1);
-        assert_eq!(a.nb_of_bytes(9), 2);
+        // Truncated by characters, not UTF-8 bytes
+        let cmd = Command::LayoutDisplay {
+            id: 1,
+            text: "é".repeat(TEXT_LEN + 1),
+        };
+        assert_eq!(TEXT_LEN + 1, cmd.data_bytes().unwrap().len());
     }
 
     #[test]
-    fn test_image_split_big_chunk_size() {
-        let cmd = Command::ImgSave {
-            id: 0,
-            size: 10, // 10 data bytes
-            width: 8,
-            format: ImgFormat::Img1bpp,
-            data: vec![0; 10],
+    fn test_split_without_data() {
+        // Still sent, in one packet without data
+        let (id, split) = Command::Clear.as_bytes_chunks(512).unwrap();
+        assert_eq!(0x01, id);
+        assert_eq!(vec![Vec::<u8>::new()], split);
+        // Commands with data are not changed
+        let shift = Command::Shift {
+            shift: Shift { x: 1, y: 2 },
         };
+        let (_, split) = shift.as_bytes_chunks(512).unwrap();
+        assert_eq!(vec![shift.data_bytes().unwrap()], split);
+    }
+
+    /// Command tables of the official API documentation
+    const API_SPEC: &str = include_str!("../spec/ActiveLook_API.md");
 
-        let (id, split) = cmd.as_bytes_chunks(255).unwrap();
-        assert_eq!(2, split.len());
-        assert_eq!(8, split[0].len());
-        assert_eq!(10, split[1].len());
+    /// A row of [API_SPEC]
+    struct SpecCommand {
+        id: u8,
+        name: String,
+        /// Minimal size of each field
+        fields: Vec<usize>,
+        /// The command is answered
+        response: bool,
     }
 
-    #[test_log::test]
-    fn test_image_split_small_chunk_size() {
-        let cmd = Command::ImgSave {
-            id: 0,
-            size: 10, // 10 data bytes
-            width: 7,
-            format: ImgFormat::Img1bpp,
-            data: vec![0; 10],
+    /// Minimal size of a field, from its type.
+    /// Strings contain at least the NUL terminator, variable size data can be empty.
+    fn spec_field_size(field: &str) -> usize {
+        let ty = field.split_whitespace().next().unwrap();
+        let (base, count) = match ty.split_once('[') {
+            Some((base, count)) => (base, Some(count.trim_end_matches(']'))),
+            None => (ty, None),
         };
+        let size = match base {
+            "uint8" | "int8" => 1,
+            "uint16" | "int16" => 2,
+            "uint32" | "int32" => 4,
+            "string" => return 1,
+            other => panic!("Unknown type {} in API spec", other),
+        };
+        match count {
+            None => size,
+            Some("") => 0,
+            Some(n) => size * n.parse::<usize>().unwrap(),
+        }
+    }
+
+    fn parse_api_spec() -> Vec<SpecCommand> {
+        API_SPEC
+            .lines()
+            .filter(|line| line.starts_with("| 0x"))
+            .map(|line| {
+                let columns: Vec<&str> = line.split('|').map(str::trim).collect();
+                let data = columns[3].trim_matches('`');
+                SpecCommand {
+                    id: u8::from_str_radix(&columns[1][2..], 16).unwrap(),
+                    name: String::from(columns[2]),
+                    fields: data
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|field| !field.is_empty())
+                        .map(spec_field_size)
+                        .collect(),
+                    response: !columns[4].is_empty(),
+                }
+            })
+            .collect()
+    }
+
+    /// Number of primitive fields of a command, nested structs being flattened, lists and texts
+    /// counting as one field.
+    /// Relies on the derived Debug output: `Name { field: value, nested: Struct { .. } }`
+    fn primitive_fields(cmd: &Command) -> usize {
+        let debug = format!("{:?}", cmd);
+        let (mut depth, mut quoted, mut escaped) = (0, false, false);
+        let mut top_level = String::new();
+        for c in debug.chars() {
+            match (quoted, c) {
+                (true, _) if escaped => escaped = false,
+                (true, '\\') => escaped = true,
+                (_, '"') => quoted = !quoted,
+                (false, '[') => depth += 1,
+                (false, ']') => depth -= 1,
+                (false, _) if depth == 0 => top_level.push(c),
+                _ => {}
+            }
+        }
+        top_level
+            .match_indices(": ")
+            .filter(|(index, _)| {
+                let value = &top_level[index + 2..];
+                let token_len = value
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(value.len());
+                let is_struct = value.starts_with(|c: char| c.is_ascii_uppercase())
+                    && value[token_len..].starts_with(" {");
+                !is_struct
+            })
+            .count()
+    }
+
+    #[test]
+    fn test_api_spec() {
+        let spec = parse_api_spec();
+        let samples = crate::vectors::sample_commands();
+        assert_eq!(
+            spec.len(),
+            samples.len(),
+            "Commands missing in spec or samples"
+        );
+
+        for cmd in &samples {
+            let id = cmd.id().unwrap();
+            let row = spec
+                .iter()
+                .find(|row| row.id == id)
+                .unwrap_or_else(|| panic!("{:?} not in API spec", cmd));
+            let desc = Command::descriptor(id).unwrap();
+            assert_eq!(row.name, desc.name, "ID 0x{:02X}", id);
+            assert_eq!(
+                row.response,
+                cmd.expects_response(),
+                "Response of {}",
+                row.name
+            );
+            assert_eq!(
+                row.fields.len(),
+                primitive_fields(cmd),
+                "Field count of {}",
+                row.name
+            );
+            let len = cmd.data_bytes().unwrap().len();
+            assert!(
+                desc.min_size <= len && len <= desc.max_size,
+                "Size of {}",
+                row.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let spec = parse_api_spec();
+        assert_eq!(spec.len(), Command::registry().len());
+        assert!(Command::ids()
+            .zip(Command::ids().skip(1))
+            .all(|(a, b)| a < b));
+        for row in &spec {
+            let desc = Command::descriptor(row.id).unwrap();
+            assert_eq!(row.name, desc.name);
+            assert_eq!(row.response, desc.response, "Response of {}", row.name);
+            assert_eq!(
+                row.fields.iter().sum::<usize>(),
+                desc.min_size,
+                "Size of {}",
+                row.name
+            );
+            assert!(desc.min_size <= desc.max_size);
+        }
+        assert_eq!(None, Command::descriptor(0x04));
+        assert_eq!(262, Command::descriptor(0x37).unwrap().max_size);
+
+        // Strings at their maximal length are written without NUL
+        let text = "t".repeat(TEXT_LEN);
+        let name = "n".repeat(NAME_LEN);
+        let longest = [
+            Command::Txt {
+                pos: Point { x: 0, y: 0 },
+                rotation: TextRotation::TOP_LR,
+                font_size: 1,
+                color: Grey::WHITE,
+                string: text.clone(),
+            },
+            Command::LayoutDisplay {
+                id: 1,
+                text: text.clone(),
+            },
+            Command::LayoutClearAndDisplay { id: 1, text },
+            Command::CfgWrite {
+                name: name.clone(),
+                version: 1,
+                password: 0,
+            },
+            Command::CfgRead { name: name.clone() },
+            Command::CfgSet { name: name.clone() },
+            Command::CfgRename {
+                old: name.clone(),
+                new: name.clone(),
+                password: 0,
+            },
+            Command::CfgDelete { name },
+        ];
+        for cmd in longest {
+            let (id, data) = cmd.as_bytes().unwrap();
+            let desc = Command::descriptor(id).unwrap();
+            assert_eq!(desc.max_size, data.len(), "Size of {}", desc.name);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_roundtrip() {
+        use crate::vectors::{sample_commands, sample_responses};
+
+        for cmd in sample_commands() {
+            let json = serde_json::to_string(&cmd).unwrap();
+            assert_eq!(cmd, serde_json::from_str::<Command>(&json).unwrap());
+        }
+        for response in sample_responses() {
+            let json = serde_json::to_string(&response).unwrap();
+            assert_eq!(response, serde_json::from_str::<Response>(&json).unwrap());
+        }
 
-        let (id, split) = cmd.as_bytes_chunks(3).unwrap();
-        assert_eq!(5, split.len());
-        assert_eq!(8, split[0].len());
-        assert_eq!(3, split[1].len());
-        assert_eq!(3, split[2].len());
-        assert_eq!(3, split[3].len());
-        assert_eq!(1, split[4].len());
+        let json = serde_json::to_string(&Command::Luma {
+            level: Luma::from(8),
+        })
+        .unwrap();
+        assert_eq!(r#"{"Luma":{"level":8}}"#, json);
     }
 }
@@ -0,0 +1,62 @@
+//! Items of the animation commands
+use deku::{ctx::Endian, prelude::*};
+
+/// Valid image format for animations
+/// - 0x00: 4bpp
+/// - 0x02: 4bpp with Heatshrink compression, decompressed into 4bpp by the firmware before saving
+#[derive(Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum AnimImgFormat {
+    /// 4 bits per pixel (16 gray levels)
+    #[deku(id = "0")]
+    Img4bpp,
+    /// 4 bits per pixel with heatshrink compression (16 gray levels), decompress before saving
+    #[deku(id = "2")]
+    Img4bppDecompressBeforeSaving,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{check_wire_data, Command, Point};
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_anim_fixtures() {
+        check_wire_data(
+            &[
+                (
+                    Command::AnimSave {
+                        id: 8,
+                        total_size: 0x01020304,
+                        img_size: 0x05060708,
+                        width: 0x090A,
+                        fmt: 0,
+                        img_compressed_size: 0x0B0C0D0E,
+                    },
+                    &[
+                        8, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0, 0x0B,
+                        0x0C, 0x0D, 0x0E,
+                    ],
+                ),
+                (
+                    Command::AnimDisplay {
+                        handler_id: 1,
+                        id: 9,
+                        delay: 0x0102,
+                        repeat: 0xFF,
+                        pos: Point { x: 0x0304, y: -1 },
+                    },
+                    &[1, 9, 0x01, 0x02, 0xFF, 0x03, 0x04, 0xFF, 0xFF],
+                ),
+            ],
+            &[],
+        );
+    }
+}
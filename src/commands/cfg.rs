@@ -0,0 +1,119 @@
+//! Items of the configuration commands
+use deku::{ctx::Endian, prelude::*};
+
+use super::{read_fixed_size_cstr, write_fixed_size_cstr, NAME_LEN};
+
+/// Encoded size of a [CfgItem]
+pub(super) const CFG_LIST_ITEM_LEN: usize = NAME_LEN + 11;
+
+/// Configuration item used in [Response::CfgList](super::Response::CfgList)
+#[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct CfgItem {
+    /// Name of the configuration
+    #[deku(
+        reader = "read_fixed_size_cstr(deku::reader, NAME_LEN)",
+        writer = "write_fixed_size_cstr(deku::writer, name, NAME_LEN)"
+    )]
+    pub name: String,
+    /// Size in bytes
+    pub size: u32,
+    /// Provided by user
+    pub version: u32,
+    /// Used to sort configurations, most recent used configuration have higher values
+    pub usage_counter: u8,
+    /// Used to sort configurations, most recent installed configuration have higher values
+    pub install_counter: u8,
+    /// Indicate system configuration, can't be deleted.
+    pub is_system: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{check_wire_data, Command, Response};
+    use crate::traits::*;
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_cfg_fixtures() {
+        check_wire_data(
+            &[
+                (
+                    Command::CfgWrite {
+                        name: String::from("cfg"),
+                        version: 0x01020304,
+                        password: 0x05060708,
+                    },
+                    &[
+                        b'c', b'f', b'g', 0, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
+                    ],
+                ),
+                (
+                    Command::CfgRename {
+                        old: String::from("a"),
+                        new: String::from("b"),
+                        password: 0x01020304,
+                    },
+                    &[b'a', 0, b'b', 0, 0x01, 0x02, 0x03, 0x04],
+                ),
+            ],
+            &[
+                (
+                    Response::CfgRead {
+                        version: 0x01020304,
+                        nb_img: 1,
+                        nb_layout: 2,
+                        nb_font: 3,
+                        nb_page: 4,
+                        nb_gauge: 5,
+                    },
+                    &[0x01, 0x02, 0x03, 0x04, 1, 2, 3, 4, 5],
+                ),
+                (
+                    Response::CfgList {
+                        list: vec![CfgItem {
+                            name: String::from("twelve_chars"),
+                            size: 0x01020304,
+                            version: 0x05060708,
+                            usage_counter: 9,
+                            install_counter: 10,
+                            is_system: false,
+                        }],
+                    },
+                    &[
+                        b't', b'w', b'e', b'l', b'v', b'e', b'_', b'c', b'h', b'a', b'r', b's',
+                        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 9, 10, 0,
+                    ],
+                ),
+                (
+                    Response::CfgFreeSpace {
+                        total_size: 0x100000,
+                        free_space: 0x8000,
+                    },
+                    &[0, 0x10, 0, 0, 0, 0, 0x80, 0],
+                ),
+                (Response::CfgGetNb { nb_config: 3 }, &[3]),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_cfg_read_response() {
+        // cfgRead answer of the API documentation: uint32 version, uint8 nbImg, uint8 nbLayout,
+        // uint8 nbFont, uint8 nbPage, uint8 nbGauge
+        let bytes: &[u8] = &[0x00, 0x00, 0x00, 0x02, 3, 10, 1, 0, 2];
+        let expected = Response::CfgRead {
+            version: 2,
+            nb_img: 3,
+            nb_layout: 10,
+            nb_font: 1,
+            nb_page: 0,
+            nb_gauge: 2,
+        };
+        assert_eq!(0xD1, expected.id().unwrap());
+        assert_eq!(bytes, expected.data_bytes().unwrap());
+        assert_eq!(expected, Response::from_data(0xD1, Some(bytes)).unwrap());
+    }
+}
@@ -0,0 +1,101 @@
+//! Items of the device commands: device information, errors and power keys
+use deku::{ctx::Endian, prelude::*};
+
+/// Key of [Command::Shutdown](super::Command::Shutdown), see [Command::shutdown](super::Command::shutdown)
+pub const SHUTDOWN_KEY: [u8; 4] = [0x6f, 0x7f, 0xc4, 0xee];
+
+/// Key of [Command::Reset](super::Command::Reset), see [Command::reset](super::Command::reset)
+pub const RESET_KEY: [u8; 4] = [0x5c, 0x1e, 0x2d, 0xe9];
+
+/// Errors returned by ActiveLook glasses
+#[deku_derive(DekuRead, DekuWrite)]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum CmdError {
+    #[deku(id = "1")]
+    Generic,
+    /// Missing the `cgfWrite` command before configuration modification
+    #[deku(id = "2")]
+    MissingCfgWrite,
+    /// Memory read/write error
+    #[deku(id = "3")]
+    MemoryAccess,
+    /// Protocol decoding error
+    #[deku(id = "4")]
+    ProtocolDecoding,
+}
+
+/// Available values for [Command::Info](super::Command::Info)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum DeviceInfo {
+    #[deku(id = "0")]
+    HWPlatform,
+    #[deku(id = "1")]
+    Manufacturer,
+    #[deku(id = "2")]
+    AdvertisingManufacturerID,
+    #[deku(id = "3")]
+    Model,
+    #[deku(id = "4")]
+    SubModel,
+    #[deku(id = "5")]
+    FWVersion,
+    #[deku(id = "6")]
+    SerialNumber,
+    #[deku(id = "7")]
+    BatteryModel,
+    #[deku(id = "8")]
+    LensModel,
+    #[deku(id = "9")]
+    DisplayModel,
+    #[deku(id = "10")]
+    DisplayOrientation,
+    #[deku(id = "11")]
+    Certification1,
+    #[deku(id = "12")]
+    Certification2,
+    #[deku(id = "13")]
+    Certification3,
+    #[deku(id = "14")]
+    Certification4,
+    #[deku(id = "15")]
+    Certification5,
+    #[deku(id = "16")]
+    Certification6,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::Response;
+    use crate::traits::*;
+
+    #[test]
+    fn test_vec_serialization() {
+        let bytes: &[u8] = &[1, 2, 3];
+        let expected = Response::RdDevInfo {
+            parameters: vec![1, 2, 3],
+        };
+        // Serialization
+        let data = expected.data_bytes().unwrap();
+        assert_eq!(bytes, data);
+
+        // Deserialization
+        let res = Response::from_data(0xE3, Some(bytes)).unwrap();
+        assert_eq!(expected, res);
+    }
+}
@@ -0,0 +1,94 @@
+//! Items of the font commands: font list and fonts stored in the glasses
+use deku::{ctx::Endian, prelude::*};
+use log::*;
+
+/// Font item used in [Response::FontList](super::Response::FontList)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct FontItem {
+    pub id: u8,
+    pub height: u8,
+}
+
+/// Default fonts stored in ActiveLook glasses
+#[derive(Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum DefaultFont {
+    #[deku(id = "0")]
+    Default24,
+    #[deku(id = "1")]
+    ComputerModernSansSerif24,
+    #[deku(id = "2")]
+    ComputerModernSansSerif35,
+    #[deku(id = "3")]
+    ComputerModernSansSerif49,
+}
+
+impl From<DefaultFont> for u8 {
+    fn from(font: DefaultFont) -> Self {
+        font.deku_id().unwrap()
+    }
+}
+
+impl From<u8> for DefaultFont {
+    fn from(id: u8) -> Self {
+        match id {
+            1 => DefaultFont::ComputerModernSansSerif24,
+            2 => DefaultFont::ComputerModernSansSerif35,
+            3 => DefaultFont::ComputerModernSansSerif49,
+            _ => {
+                warn!("Unknown font {}", id);
+                DefaultFont::Default24
+            }
+        }
+    }
+}
+
+/// Encoded size of a [FontItem]
+pub(super) const FONT_LIST_ITEM_LEN: usize = 2;
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{check_wire_data, Command};
+    use crate::traits::*;
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_font_fixtures() {
+        check_wire_data(
+            &[(
+                Command::FontSave {
+                    id: 3,
+                    size: 2,
+                    data: vec![0xAA; 2],
+                },
+                &[3, 0x00, 0x02, 0xAA, 0xAA],
+            )],
+            &[],
+        );
+    }
+
+    #[test]
+    fn test_font_save_split() {
+        let cmd = Command::FontSave {
+            id: 5,
+            size: 600,
+            data: vec![0; 600],
+        };
+
+        let (id, split) = cmd.as_bytes_chunks(512).unwrap();
+        assert_eq!(0x51, id);
+        assert_eq!(3, split.len());
+        assert_eq!(&[5, 0x02, 0x58], &split[0][..]);
+        assert_eq!(512, split[1].len());
+        assert_eq!(88, split[2].len());
+    }
+}
@@ -0,0 +1,62 @@
+//! Items of the gauge commands, see [crate::gauge::GaugeBuilder] to build them
+use deku::{ctx::Endian, prelude::*};
+
+use super::Point;
+
+/// Gauge parameters, used in [Command::GaugeSave](super::Command::GaugeSave) and [Response::GaugeGet](super::Response::GaugeGet)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct GaugeParameters {
+    /// Center of the gauge
+    pub pos: Point,
+    /// Outer radius
+    pub radius: u16,
+    /// Inner radius
+    pub inner: u16,
+    /// Start of the arc
+    pub start: u8,
+    /// End of the arc
+    pub end: u8,
+    pub clockwise: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{check_wire_data, Command, Response};
+    use crate::traits::*;
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_gauge_fixtures() {
+        let gauge = GaugeParameters {
+            pos: Point {
+                x: 0x012C,
+                y: 0x00FA,
+            },
+            radius: 0x0102,
+            inner: 0x0304,
+            start: 2,
+            end: 14,
+            clockwise: true,
+        };
+        check_wire_data(
+            &[],
+            &[(
+                Response::GaugeGet { params: gauge },
+                &[0x01, 0x2C, 0x00, 0xFA, 0x01, 0x02, 0x03, 0x04, 2, 14, 1],
+            )],
+        );
+    }
+
+    #[test]
+    fn test_to_save_command() {
+        let data = [0x01, 0x2C, 0x00, 0xFA, 0x00, 0x32, 0x00, 0x28, 2, 14, 1];
+        let response = Response::from_data(0x74, Some(&data)).unwrap();
+        let cmd = response.to_save_command(7).unwrap();
+        assert!(matches!(cmd, Command::GaugeSave { id: 7, .. }));
+        assert_eq!(&data, &cmd.data_bytes().unwrap()[1..]);
+        assert_eq!(None, Response::Battery { level: 5 }.to_save_command(7));
+    }
+}
@@ -0,0 +1,330 @@
+//! Items of the general commands: positions, colors, luminance, demos and LED
+use deku::{ctx::Endian, prelude::*};
+use thiserror::Error;
+
+/// Elements concerned by a delete or clear command: one ID, or all of them.
+///
+/// On the wire, all elements are selected by the ID [ALL](super::ALL). Build single selections
+/// with [Selector::one], which refuses it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+pub enum Selector {
+    #[deku(id = "0xFF")]
+    All,
+    #[deku(id_pat = "_")]
+    One(ElementId),
+}
+
+impl Selector {
+    /// Select element `id`, `None` if `id` is [ALL](super::ALL)
+    pub fn one(id: u8) -> Option<Self> {
+        ElementId::new(id).ok().map(Selector::One)
+    }
+
+    /// Selection encoded as `id`, as read by the firmware
+    pub fn from_raw(id: u8) -> Self {
+        Selector::one(id).unwrap_or(Selector::All)
+    }
+
+    /// ID of the selected element, `None` if all are selected
+    pub fn id(&self) -> Option<u8> {
+        match self {
+            Selector::All => None,
+            Selector::One(id) => Some(id.value()),
+        }
+    }
+}
+
+impl From<Selector> for u8 {
+    fn from(selector: Selector) -> Self {
+        selector.id().unwrap_or(super::ALL)
+    }
+}
+
+/// ID of a single element, any value but [ALL](super::ALL) which selects every element.
+/// See [Selector::one].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, DekuRead, DekuWrite)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u8", into = "u8")
+)]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct ElementId(u8);
+
+impl ElementId {
+    pub fn new(id: u8) -> Result<Self, RangeError> {
+        match id != super::ALL {
+            true => Ok(Self(id)),
+            false => Err(RangeError::ElementId(id)),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+impl TryFrom<u8> for ElementId {
+    type Error = RangeError;
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        Self::new(id)
+    }
+}
+
+impl From<ElementId> for u8 {
+    fn from(id: ElementId) -> Self {
+        id.0
+    }
+}
+
+/// Available Demo values for [Command::Demo](super::Command::Demo)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum DemoID {
+    #[deku(id = "0")]
+    Fill = 0,
+    #[deku(id = "1")]
+    Rect = 1,
+    #[deku(id = "2")]
+    Images = 2,
+}
+
+/// Available state values for [Command::Led](super::Command::Led)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum LedState {
+    #[deku(id = "0")]
+    Off = 0,
+    #[deku(id = "1")]
+    On = 1,
+    #[deku(id = "2")]
+    Toggle = 2,
+    #[deku(id = "3")]
+    Blinking = 3,
+}
+
+/// Common Point type used globally in commands
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct Point {
+    pub x: i16,
+    pub y: i16,
+}
+
+/// Common Shift type used globally in commands
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct Shift {
+    pub x: i16,
+    pub y: i16,
+}
+
+/// Value out of the range accepted by the glasses
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum RangeError {
+    #[error("Grey level {0} is out of range 0..=15")]
+    Grey(u8),
+    #[error("Luminance {0} is out of range 0..=15")]
+    Luma(u8),
+    #[error("Text rotation {0} is out of range 0..=7")]
+    TextRotation(u8),
+    #[error("Element ID {0} selects all the elements")]
+    ElementId(u8),
+}
+
+/// Grey level used to draw, from 0 (black) to 15 (white).
+/// `From<u8>` does not check the range, prefer [Grey::new].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, DekuRead, DekuWrite)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct Grey(u8);
+
+impl Grey {
+    pub const BLACK: Grey = Grey(0);
+    pub const WHITE: Grey = Grey(15);
+
+    pub fn new(level: u8) -> Result<Self, RangeError> {
+        match level <= Self::WHITE.0 {
+            true => Ok(Self(level)),
+            false => Err(RangeError::Grey(level)),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+impl From<u8> for Grey {
+    fn from(level: u8) -> Self {
+        Self(level)
+    }
+}
+
+impl From<Grey> for u8 {
+    fn from(grey: Grey) -> Self {
+        grey.0
+    }
+}
+
+/// Display luminance, from 0 to 15.
+/// `From<u8>` does not check the range, prefer [Luma::new].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, DekuRead, DekuWrite)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct Luma(u8);
+
+impl Luma {
+    pub const MIN: Luma = Luma(0);
+    pub const MAX: Luma = Luma(15);
+
+    pub fn new(level: u8) -> Result<Self, RangeError> {
+        match level <= Self::MAX.0 {
+            true => Ok(Self(level)),
+            false => Err(RangeError::Luma(level)),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+impl From<u8> for Luma {
+    fn from(level: u8) -> Self {
+        Self(level)
+    }
+}
+
+impl From<Luma> for u8 {
+    fn from(luma: Luma) -> Self {
+        luma.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{check_wire_data, Command, Response, TextRotation};
+    use crate::traits::*;
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(Ok(Grey::WHITE), Grey::new(15));
+        assert_eq!(Err(RangeError::Grey(200)), Grey::new(200));
+        assert_eq!(Err(RangeError::Luma(16)), Luma::new(16));
+        assert_eq!(Ok(TextRotation::RIGHT_BT), TextRotation::new(7));
+        assert_eq!(Err(RangeError::TextRotation(8)), TextRotation::new(8));
+        // Unchecked conversions keep the raw value on the wire
+        let cmd = Command::Color {
+            color: Grey::from(200),
+        };
+        assert_eq!(&[200][..], cmd.data_bytes().unwrap());
+        assert_eq!(cmd, Command::from_data(0x30, Some(&[200])).unwrap());
+    }
+
+    #[test]
+    fn test_selector() {
+        let cmd = Command::ImgDelete { id: Selector::All };
+        assert_eq!(&[0xFF][..], cmd.data_bytes().unwrap());
+        assert_eq!(cmd, Command::from_data(0x46, Some(&[0xFF])).unwrap());
+        assert_eq!(
+            Command::ImgDelete {
+                id: Selector::one(3).unwrap()
+            },
+            Command::from_data(0x46, Some(&[3])).unwrap()
+        );
+        assert_eq!(None, Selector::one(0xFF));
+        assert_eq!(Err(RangeError::ElementId(0xFF)), ElementId::new(0xFF));
+        assert_eq!(Selector::All, Selector::from_raw(0xFF));
+        assert_eq!(Some(7), Selector::from_raw(7).id());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_selector_serde() {
+        let one: Selector = serde_json::from_str(r#"{"One":3}"#).unwrap();
+        assert_eq!(Selector::one(3), Some(one));
+        assert!(serde_json::from_str::<Selector>(r#"{"One":255}"#).is_err());
+    }
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_general_fixtures() {
+        check_wire_data(
+            &[(
+                Command::Shift {
+                    shift: Shift { x: -2, y: 0x0102 },
+                },
+                &[0xFF, 0xFE, 0x01, 0x02],
+            )],
+            &[
+                (Response::Battery { level: 87 }, &[87]),
+                (
+                    Response::Version {
+                        fw_version: [4, 12, 0, b'b'],
+                        mfc_year: 24,
+                        mfc_week: 10,
+                        serial_number: [1, 2, 3],
+                    },
+                    &[4, 12, 0, b'b', 24, 10, 0x01, 0x02, 0x03],
+                ),
+                (
+                    Response::Settings {
+                        x: -2,
+                        y: 3,
+                        luma: Luma::from(12),
+                        als_enable: true,
+                        gesture_enable: false,
+                    },
+                    &[0xFE, 3, 12, 1, 0],
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_endianness() {
+        let point = Point {
+            x: 0x1234,
+            y: 0x5678,
+        };
+        let cmd = Command::Point { coord: point };
+        let expected: &[u8] = &[0x12, 0x34, 0x56, 0x78];
+        let data = cmd.data_bytes().unwrap();
+        assert_eq!(expected, data);
+        assert_eq!(expected, point.to_bytes().unwrap());
+    }
+}
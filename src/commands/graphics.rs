@@ -0,0 +1,171 @@
+//! Items of the graphics commands: text rotation and hold/flush of the graphic engine
+use deku::{ctx::Endian, prelude::*};
+
+use super::RangeError;
+
+/// Hold or Flush the graphic engine.
+///
+/// When held, new display commands are stored in memory and are displayed when the graphic engine
+/// is flushed. This allows stacking multiple graphic operations and displaying them simultaneously
+/// without screen flickering.
+/// The command is nested, the [HoldFlushAction::Flush] action must be used the same number of times
+/// [HoldFlushAction::Hold] was used.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum HoldFlushAction {
+    /// Hold display
+    #[deku(id = "0")]
+    Hold,
+    /// Flush display
+    #[deku(id = "1")]
+    Flush,
+    /// Reset and flush all stacked hold. To be used when the state of the device is unknown.
+    /// After a BLE disconnect or an overflow error, graphic engine is reset and flushed.
+    #[deku(id = "255")]
+    ResetFlush,
+}
+
+/// Direction of a text: the side of the display where the text starts, and its writing
+/// direction, named like the ActiveLook SDK rotations.
+/// `From<u8>` does not check the range, prefer [TextRotation::new].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct TextRotation(u8);
+
+impl TextRotation {
+    pub const BOTTOM_RL: TextRotation = TextRotation(0);
+    pub const BOTTOM_LR: TextRotation = TextRotation(1);
+    pub const LEFT_BT: TextRotation = TextRotation(2);
+    pub const LEFT_TB: TextRotation = TextRotation(3);
+    /// Usual reading direction
+    pub const TOP_LR: TextRotation = TextRotation(4);
+    pub const TOP_RL: TextRotation = TextRotation(5);
+    pub const RIGHT_TB: TextRotation = TextRotation(6);
+    pub const RIGHT_BT: TextRotation = TextRotation(7);
+
+    pub fn new(rotation: u8) -> Result<Self, RangeError> {
+        match rotation <= Self::RIGHT_BT.0 {
+            true => Ok(Self(rotation)),
+            false => Err(RangeError::TextRotation(rotation)),
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for TextRotation {
+    fn default() -> Self {
+        Self::TOP_LR
+    }
+}
+
+impl From<u8> for TextRotation {
+    fn from(rotation: u8) -> Self {
+        Self(rotation)
+    }
+}
+
+impl From<TextRotation> for u8 {
+    fn from(rotation: TextRotation) -> Self {
+        rotation.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{check_wire_data, Command, Grey, Point, Response};
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_graphics_fixtures() {
+        let p = |x, y| Point { x, y };
+        check_wire_data(
+            &[
+                (
+                    Command::Line {
+                        from: p(0x0102, -1),
+                        to: p(0x0130, 0x00FF),
+                    },
+                    &[0x01, 0x02, 0xFF, 0xFF, 0x01, 0x30, 0x00, 0xFF],
+                ),
+                (
+                    Command::Rect {
+                        from: p(1, 2),
+                        to: p(0x0130, 3),
+                    },
+                    &[0x00, 0x01, 0x00, 0x02, 0x01, 0x30, 0x00, 0x03],
+                ),
+                (
+                    Command::RectFull {
+                        from: p(0x0102, 0x0304),
+                        to: p(5, 6),
+                    },
+                    &[0x01, 0x02, 0x03, 0x04, 0x00, 0x05, 0x00, 0x06],
+                ),
+                (
+                    Command::Circ {
+                        center: p(0x0102, 7),
+                        r: 8,
+                    },
+                    &[0x01, 0x02, 0x00, 0x07, 8],
+                ),
+                (
+                    Command::CircFull {
+                        center: p(-3, 0x0203),
+                        r: 9,
+                    },
+                    &[0xFF, 0xFD, 0x02, 0x03, 9],
+                ),
+                (
+                    Command::Txt {
+                        pos: p(0x0102, 0x0034),
+                        rotation: TextRotation::TOP_LR,
+                        font_size: 1,
+                        color: Grey::WHITE,
+                        string: String::from("A"),
+                    },
+                    &[0x01, 0x02, 0x00, 0x34, 4, 1, 15, b'A', 0],
+                ),
+                (
+                    Command::Polyline {
+                        thickness: 2,
+                        _reserved: 0x0102,
+                        points: vec![p(0x0304, 5), p(6, 0x0708)],
+                    },
+                    &[
+                        2, 0x01, 0x02, 0x03, 0x04, 0x00, 0x05, 0x00, 0x06, 0x07, 0x08,
+                    ],
+                ),
+                (
+                    Command::Arc {
+                        center: p(0x0102, 3),
+                        r: 4,
+                        angle_start: -90,
+                        angle_end: 0x0168,
+                        thickness: 5,
+                    },
+                    &[0x01, 0x02, 0x00, 0x03, 4, 0xFF, 0xA6, 0x01, 0x68, 5],
+                ),
+            ],
+            &[(
+                Response::PixelCount { count: 0x01020304 },
+                &[0x01, 0x02, 0x03, 0x04],
+            )],
+        );
+    }
+}
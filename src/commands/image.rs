@@ -0,0 +1,262 @@
+//! Items of the image commands: image formats and image list
+use deku::{ctx::Endian, prelude::*};
+use log::*;
+
+/// List item returned in [Response::ImgList](super::Response::ImgList)
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct ImgListItem {
+    pub id: u8,
+    pub height: u16,
+    pub width: u16,
+}
+
+/// Encoded size of an [ImgListItem]
+pub(super) const IMG_LIST_ITEM_LEN: usize = 5;
+
+/// Image format
+/// - 0x00: 4bpp
+/// - 0x01: 1bpp, transformed into 4bpp by the firmware before saving
+/// - 0x02: 4bpp with Heatshrink compression, decompressed into 4bpp by the firmware before saving
+/// - 0x03: 4bpp with Heatshrink compression, stored compressed, decompressed into 4bpp before display
+/// - 0x08: 8bpp with 4 bits for grey level and 4 bits for alpha channel
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum ImgFormat {
+    /// 4 bits per pixel (16 gray levels)
+    #[deku(id = "0")]
+    Img4bpp,
+    /// 1 bit per pixel (black and white)
+    #[deku(id = "1")]
+    Img1bpp,
+    /// 4 bits per pixel with heatshrink compression (16 gray levels), decompress before saving
+    #[deku(id = "2")]
+    Img4bppDecompressBeforeSaving,
+    /// 4 bits per pixel with heatshrink compression (16 gray levels), decompress before displaying
+    #[deku(id = "3")]
+    Img4bppDecompressBeforeDisplaying,
+    /// 8 bits per pixel (16 gray levels + 16 alpha channels)
+    #[deku(id = "8")]
+    Img8bpp,
+}
+
+impl ImgFormat {
+    /// Number of bytes of an image line
+    pub(crate) fn nb_of_bytes(&self, width: usize) -> usize {
+        let res = match self {
+            // 1 pixel per byte
+            ImgFormat::Img8bpp => width,
+            // 2 pixels per byte
+            ImgFormat::Img4bpp => width.div_ceil(2),
+            // 8 pixels per byte
+            ImgFormat::Img1bpp => width.div_ceil(8),
+            // Unknown
+            ImgFormat::Img4bppDecompressBeforeSaving
+            | ImgFormat::Img4bppDecompressBeforeDisplaying => width,
+        };
+        debug!("ImgFormat {:?}, width {} -> nb_bytes {}", self, width, res);
+        res
+    }
+}
+
+/// Valid image format for streaming
+/// - 0x01: 1bpp, transformed into 4bpp by the firmware before saving
+/// - 0x02: 4bpp with Heatshrink compression, decompressed into 4bpp by the firmware before saving
+#[derive(Copy, Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: Endian",
+    ctx_default = "Endian::Big"
+)]
+#[repr(u8)]
+pub enum StreamImgFormat {
+    /// 1 bit per pixel (black and white)
+    #[deku(id = "1")]
+    Img1bpp,
+    /// 4 bits per pixel with heatshrink compression (16 gray levels), decompress before saving
+    #[deku(id = "2")]
+    Img4bppDecompressBeforeSaving,
+}
+
+impl StreamImgFormat {
+    pub(super) fn nb_of_bytes(&self, width: usize) -> usize {
+        match self {
+            // 8 pixels per byte
+            StreamImgFormat::Img1bpp => width.div_ceil(8),
+            // Unknown
+            StreamImgFormat::Img4bppDecompressBeforeSaving => width,
+        }
+    }
+}
+
+impl TryFrom<ImgFormat> for StreamImgFormat {
+    type Error = ();
+
+    fn try_from(value: ImgFormat) -> Result<Self, Self::Error> {
+        match value {
+            ImgFormat::Img1bpp => Ok(StreamImgFormat::Img1bpp),
+            ImgFormat::Img4bppDecompressBeforeSaving => {
+                Ok(StreamImgFormat::Img4bppDecompressBeforeSaving)
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{
+        check_wire_data, split_aligned, Command, Point, Response, CHUNK_POLICIES,
+    };
+    use crate::traits::*;
+
+    #[test]
+    fn test_img_format_bytes() {
+        let a = ImgFormat::Img1bpp;
+        assert_eq!(a.nb_of_bytes(7), 1);
+        assert_eq!(a.nb_of_bytes(8), 1);
+        assert_eq!(a.nb_of_bytes(9), 2);
+    }
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_image_fixtures() {
+        let p = |x, y| Point { x, y };
+        check_wire_data(
+            &[
+                (
+                    Command::ImgSave {
+                        id: 1,
+                        size: 3,
+                        width: 0x0506,
+                        format: ImgFormat::Img4bpp,
+                        data: vec![0xAA; 3],
+                    },
+                    &[1, 0x00, 0x00, 0x00, 0x03, 0x05, 0x06, 0, 0xAA, 0xAA, 0xAA],
+                ),
+                (
+                    Command::ImgDisplay {
+                        id: 2,
+                        coord: p(-0x0102, 0x0304),
+                    },
+                    &[2, 0xFE, 0xFE, 0x03, 0x04],
+                ),
+                (
+                    Command::ImgStream {
+                        size: 1,
+                        width: 0x0506,
+                        coord: p(0x0708, 0x090A),
+                        format: StreamImgFormat::Img1bpp,
+                        data: vec![0xAA],
+                    },
+                    &[
+                        0x00, 0x00, 0x00, 0x01, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 1, 0xAA,
+                    ],
+                ),
+            ],
+            &[(
+                Response::ImgList {
+                    list: vec![ImgListItem {
+                        id: 1,
+                        height: 0x0102,
+                        width: 0x0304,
+                    }],
+                },
+                &[1, 0x01, 0x02, 0x03, 0x04],
+            )],
+        );
+    }
+
+    #[test]
+    fn test_image_split_big_chunk_size() {
+        let cmd = Command::ImgSave {
+            id: 0,
+            size: 10, // 10 data bytes
+            width: 8,
+            format: ImgFormat::Img1bpp,
+            data: vec![0; 10],
+        };
+
+        let (_id, split) = cmd.as_bytes_chunks(255).unwrap();
+        assert_eq!(2, split.len());
+        assert_eq!(8, split[0].len());
+        assert_eq!(10, split[1].len());
+    }
+
+    #[test_log::test]
+    fn test_image_split_small_chunk_size() {
+        let cmd = Command::ImgSave {
+            id: 0,
+            size: 10, // 10 data bytes
+            width: 7,
+            format: ImgFormat::Img1bpp,
+            data: vec![0; 10],
+        };
+
+        let (_id, split) = cmd.as_bytes_chunks(3).unwrap();
+        assert_eq!(5, split.len());
+        assert_eq!(8, split[0].len());
+        assert_eq!(3, split[1].len());
+        assert_eq!(3, split[2].len());
+        assert_eq!(3, split[3].len());
+        assert_eq!(1, split[4].len());
+    }
+
+    #[test]
+    fn test_chunk_policies() {
+        let headers = [
+            Command::ImgSave {
+                id: 1,
+                size: 300,
+                width: 30,
+                format: ImgFormat::Img4bpp,
+                data: Vec::new(),
+            },
+            Command::ImgStream {
+                size: 300,
+                width: 30,
+                coord: Point { x: 0, y: 0 },
+                format: StreamImgFormat::Img1bpp,
+                data: Vec::new(),
+            },
+            Command::FontSave {
+                id: 1,
+                size: 300,
+                data: Vec::new(),
+            },
+            Command::AnimSave {
+                id: 1,
+                total_size: 300,
+                img_size: 150,
+                width: 30,
+                fmt: 0,
+                img_compressed_size: 150,
+            },
+        ];
+        assert_eq!(headers.len(), CHUNK_POLICIES.len());
+        for header in &headers {
+            let policy = header.chunk_policy().unwrap();
+            let bytes = header.data_bytes().unwrap();
+            assert_eq!(Some(300), policy.data_size(&bytes), "{header:?}");
+        }
+        assert!(CHUNK_POLICIES.windows(2).all(|w| w[0].id < w[1].id));
+        assert_eq!(None, Command::Clear.chunk_policy());
+
+        // The animation data is aligned on the lines of the 4bpp reference frame
+        let data = [0; 300];
+        let split = split_aligned(&headers[3], &data, 100).unwrap();
+        assert_eq!(5, split.len());
+        assert_eq!(90, split[1].len());
+    }
+}
@@ -0,0 +1,183 @@
+//! Items of the layout commands, see [crate::layout::LayoutBuilder] to build them
+use deku::{ctx::Endian, prelude::*};
+
+use super::{Grey, TextRotation};
+use crate::layout::{decode_commands, LayoutBuilder, LayoutCommand};
+
+/// Layout position item used in [Command::LayoutPosition](super::Command::LayoutPosition) for instance
+#[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct LayoutPosition {
+    pub x: u16,
+    pub y: u8,
+}
+
+/// Layout parameters, built with [crate::layout::LayoutBuilder]
+#[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[deku(endian = "endian", ctx = "endian: Endian", ctx_default = "Endian::Big")]
+pub struct LayoutParameters {
+    /// Size of additional commands in bytes
+    pub(crate) size: u8,
+    /// Upper left clipping region in the display
+    pub(crate) pos: LayoutPosition,
+    /// Width of the clipping region
+    pub(crate) width: u16,
+    /// Height of the clipping region
+    pub(crate) height: u8,
+    pub(crate) fore_color: Grey,
+    pub(crate) back_color: Grey,
+    pub(crate) font: u8,
+    /// The text is displayed at `text_pos`
+    pub(crate) text_valid: bool,
+    /// Test position in the clipping region
+    pub(crate) text_pos: LayoutPosition,
+    pub(crate) text_rotation: TextRotation,
+    /// If true, the background of each character should be drawn.
+    /// Else, it leaves the background as is
+    pub(crate) text_opacity: bool,
+    /// Additional graphical commands
+    #[deku(count = "size")]
+    pub(crate) commands: Vec<u8>,
+}
+
+/// The fields are read with getters, and modified through [LayoutParameters::into_builder], which
+/// keeps the size of the additional commands consistent and checks the colors.
+impl LayoutParameters {
+    /// Upper left corner of the clipping region in the display
+    pub fn pos(&self) -> &LayoutPosition {
+        &self.pos
+    }
+
+    /// Width of the clipping region
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    /// Height of the clipping region
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    pub fn fore_color(&self) -> Grey {
+        self.fore_color
+    }
+
+    pub fn back_color(&self) -> Grey {
+        self.back_color
+    }
+
+    /// Font used to display the text
+    pub fn font(&self) -> u8 {
+        self.font
+    }
+
+    /// Position of the text in the clipping region, if the layout displays a text
+    pub fn text_pos(&self) -> Option<&LayoutPosition> {
+        self.text_valid.then_some(&self.text_pos)
+    }
+
+    pub fn text_rotation(&self) -> TextRotation {
+        self.text_rotation
+    }
+
+    /// If true, the background of each character is drawn
+    pub fn text_opacity(&self) -> bool {
+        self.text_opacity
+    }
+
+    /// Encoded additional commands
+    pub fn commands_bytes(&self) -> &[u8] {
+        &self.commands
+    }
+
+    /// Decode the additional commands
+    pub fn decode_commands(&self) -> Result<Vec<LayoutCommand>, DekuError> {
+        decode_commands(&self.commands)
+    }
+
+    /// Builder with the same parameters, to modify them. Fails if the additional commands can
+    /// not be decoded.
+    pub fn into_builder(self) -> Result<LayoutBuilder, DekuError> {
+        let commands = self.decode_commands()?;
+        let builder = LayoutBuilder::new(self.pos, self.width, self.height)
+            .colors(self.fore_color, self.back_color)
+            .font(self.font)
+            .text_rotation(self.text_rotation)
+            .text_opacity(self.text_opacity);
+        let builder = match self.text_valid {
+            true => builder.text_at(self.text_pos),
+            false => builder,
+        };
+        Ok(commands.into_iter().fold(builder, LayoutBuilder::command))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{check_wire_data, Command, Grey, Response, TextRotation};
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_layout_fixtures() {
+        let pos = LayoutPosition { x: 0x0123, y: 0x45 };
+        let layout = LayoutParameters {
+            size: 0,
+            pos: pos.clone(),
+            width: 0x0130,
+            height: 0x40,
+            fore_color: Grey::WHITE,
+            back_color: Grey::BLACK,
+            font: 1,
+            text_valid: true,
+            text_pos: LayoutPosition { x: 0x0005, y: 6 },
+            text_rotation: TextRotation::TOP_LR,
+            text_opacity: true,
+            commands: vec![],
+        };
+        check_wire_data(
+            &[
+                (
+                    Command::LayoutPosition {
+                        id: 4,
+                        pos: pos.clone(),
+                    },
+                    &[4, 0x01, 0x23, 0x45],
+                ),
+                (
+                    Command::LayoutDisplayExtended {
+                        id: 5,
+                        pos: pos.clone(),
+                        text: String::from("B"),
+                        extra_cmd: vec![],
+                    },
+                    &[5, 0x01, 0x23, 0x45, b'B', 0],
+                ),
+                (
+                    Command::LayoutClearExtended {
+                        id: 6,
+                        pos: pos.clone(),
+                    },
+                    &[6, 0x01, 0x23, 0x45],
+                ),
+                (
+                    Command::LayoutClearAndDisplayExtended {
+                        id: 7,
+                        pos: pos.clone(),
+                        text: String::from("C"),
+                        extra_cmd: vec![],
+                    },
+                    &[7, 0x01, 0x23, 0x45, b'C', 0],
+                ),
+            ],
+            &[(
+                Response::LayoutGet { params: layout },
+                &[
+                    0, 0x01, 0x23, 0x45, 0x01, 0x30, 0x40, 15, 0, 1, 1, 0x00, 0x05, 6, 4, 1,
+                ],
+            )],
+        );
+    }
+}
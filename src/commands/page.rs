@@ -0,0 +1,104 @@
+//! Items of the page commands: the texts of [Command::PageDisplay](super::Command::PageDisplay)
+//! and [Command::PageClearAndDisplay](super::Command::PageClearAndDisplay). See [crate::page::Page]
+//! to build the pages.
+use deku::{ctx::BitSize, prelude::*, reader::Reader};
+
+use super::{write_fixed_size_cstr, TEXT_LEN};
+use crate::charset;
+
+/// NUL terminated strings, until the end of the data
+pub(super) fn read_cstr_list<R: deku::no_std_io::Read + deku::no_std_io::Seek>(
+    reader: &mut Reader<R>,
+) -> Result<Vec<String>, DekuError> {
+    let mut strings = Vec::new();
+    let mut current = Vec::new();
+    while !reader.end() {
+        match u8::from_reader_with_ctx(reader, BitSize(8))? {
+            b'\0' => strings.push(charset::decode(&core::mem::take(&mut current))),
+            val => current.push(val),
+        }
+    }
+    if !current.is_empty() {
+        strings.push(charset::decode(&current));
+    }
+    Ok(strings)
+}
+
+pub(super) fn write_cstr_list<W: deku::no_std_io::Write + deku::no_std_io::Seek>(
+    writer: &mut Writer<W>,
+    strings: &[String],
+) -> Result<(), DekuError> {
+    for string in strings {
+        write_fixed_size_cstr(writer, string, TEXT_LEN)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::{check_wire_data, Command, Response};
+    use crate::traits::*;
+
+    /// Wire data written from the API documentation
+    #[test]
+    fn test_page_fixtures() {
+        check_wire_data(
+            &[
+                (
+                    Command::PageSave {
+                        id: 4,
+                        layouts: vec![1, 0x01, 0x23, 0x45],
+                    },
+                    &[4, 1, 0x01, 0x23, 0x45],
+                ),
+                (
+                    Command::PageDisplay {
+                        id: 3,
+                        strings: vec![String::from("a"), String::from("bc")],
+                    },
+                    &[3, b'a', 0, b'b', b'c', 0],
+                ),
+                (
+                    Command::PageClearAndDisplay {
+                        id: 3,
+                        strings: vec![String::from("d")],
+                    },
+                    &[3, b'd', 0],
+                ),
+            ],
+            &[
+                (
+                    Response::PageGet {
+                        id: 4,
+                        layouts: vec![1, 2, 7],
+                    },
+                    &[4, 1, 2, 7],
+                ),
+                (Response::PageList { list: vec![] }, &[]),
+            ],
+        );
+        // The last NUL is optional
+        assert_eq!(
+            Command::PageDisplay {
+                id: 3,
+                strings: vec![String::from("a"), String::from("bc")],
+            },
+            Command::from_data(0x83, Some(&[3, b'a', 0, b'b', b'c'])).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_to_save_command() {
+        let page = Response::PageGet {
+            id: 4,
+            layouts: vec![1, 2],
+        };
+        assert_eq!(
+            Some(Command::PageSave {
+                id: 9,
+                layouts: vec![1, 2]
+            }),
+            page.to_save_command(9)
+        );
+    }
+}
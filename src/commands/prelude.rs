@@ -0,0 +1,13 @@
+//! Commands, responses and the items they use
+//!
+//! ```
+//! use activelook_rs::commands::prelude::*;
+//!
+//! let cmd = Command::Color { color: Grey::WHITE };
+//! ```
+pub use super::{
+    AnimImgFormat, CfgItem, CmdError, Command, DefaultFont, DemoID, DeviceInfo, ElementId,
+    FontItem, GaugeParameters, Grey, HoldFlushAction, ImgFormat, ImgListItem, LayoutParameters,
+    LayoutPosition, LedState, Luma, Point, RangeError, Response, Selector, Shift, StreamImgFormat,
+    TextRotation, ALL, NAME_LEN, TEXT_LEN,
+};
@@ -0,0 +1,1314 @@
+//! ActiveLook configurations
+//!
+//! A configuration groups images, fonts, layouts, gauges, pages and animations.
+//! Some elements reference others: a layout uses fonts, and the images and gauges drawn by its
+//! additional commands, a page uses layouts.
+//! The referenced elements must be uploaded first, otherwise the glasses reject the command with
+//! a [crate::commands::CmdError] we can only observe once on-device.
+//!
+//! [ConfigBuilder] models these references as a dependency graph, checks that every reference
+//! exists, and orders the upload commands accordingly.
+//!
+//! A built [Config] can be exported to a portable archive with [Config::to_archive], shipped with
+//! an application, and replayed onto the glasses with [Config::upload].
+//!
+//! Elements can also be written one by one inside a [ConfigSession], which replicates the firmware
+//! rules for configuration writes.
+//!
+//! A configuration is protected by the password given when it is created: writing or renaming it
+//! with another password fails, reported as [GlassesError::ConfigAuth]. [ConfigCredentials]
+//! remembers the password of each configuration, and [Config::change_password] rewrites a
+//! configuration to change its password.
+use core::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+
+use deku::prelude::*;
+use log::*;
+use thiserror::Error;
+
+use crate::{
+    commands::{
+        CmdError, Command, DefaultFont, GaugeParameters, LayoutParameters, Response, Selector,
+        NAME_LEN,
+    },
+    font::{Font, FontError},
+    glasses::{GlassesApi, GlassesError},
+    image::Image,
+    layout::LayoutCommand,
+    page::Page,
+    traits::*,
+};
+
+/// Kind of element stored in a configuration
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, DekuRead, DekuWrite)]
+#[deku(id_type = "u8")]
+#[repr(u8)]
+pub enum ElementKind {
+    #[deku(id = "0")]
+    Image,
+    #[deku(id = "1")]
+    Font,
+    #[deku(id = "2")]
+    Layout,
+    #[deku(id = "3")]
+    Gauge,
+    #[deku(id = "4")]
+    Page,
+    #[deku(id = "5")]
+    Animation,
+}
+
+impl ElementKind {
+    /// Command listing the elements of this kind
+    pub fn list_command(&self) -> Command {
+        match self {
+            ElementKind::Image => Command::ImgList,
+            ElementKind::Font => Command::FontList,
+            ElementKind::Layout => Command::LayoutList,
+            ElementKind::Gauge => Command::GaugeList,
+            ElementKind::Page => Command::PageList,
+            ElementKind::Animation => Command::AnimList,
+        }
+    }
+
+    /// Command deleting the elements of this kind selected by `id`
+    pub fn delete_command(&self, id: Selector) -> Command {
+        match self {
+            ElementKind::Image => Command::ImgDelete { id },
+            ElementKind::Font => Command::FontDelete { id },
+            ElementKind::Layout => Command::LayoutDelete { id },
+            ElementKind::Gauge => Command::GaugeDelete { id },
+            ElementKind::Page => Command::PageDelete { id },
+            ElementKind::Animation => Command::AnimDelete { id },
+        }
+    }
+}
+
+/// Reference to an element of a configuration
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, DekuRead, DekuWrite)]
+pub struct ElementRef {
+    pub kind: ElementKind,
+    pub id: u8,
+}
+
+impl ElementRef {
+    pub fn new(kind: ElementKind, id: u8) -> Self {
+        Self { kind, id }
+    }
+
+    /// Element saved or deleted by `cmd`, if it modifies the configuration
+    pub fn written_by(cmd: &Command) -> Option<Self> {
+        // Deleting all elements is reported with the ID [crate::commands::ALL]
+        let (kind, id) = match cmd {
+            Command::ImgSave { id, .. } => (ElementKind::Image, *id),
+            Command::ImgDelete { id } => (ElementKind::Image, u8::from(*id)),
+            Command::FontSave { id, .. } => (ElementKind::Font, *id),
+            Command::FontDelete { id } => (ElementKind::Font, u8::from(*id)),
+            Command::LayoutSave { id, .. } => (ElementKind::Layout, *id),
+            Command::LayoutDelete { id } => (ElementKind::Layout, u8::from(*id)),
+            Command::GaugeSave { id, .. } => (ElementKind::Gauge, *id),
+            Command::GaugeDelete { id } => (ElementKind::Gauge, u8::from(*id)),
+            Command::PageSave { id, .. } => (ElementKind::Page, *id),
+            Command::PageDelete { id } => (ElementKind::Page, u8::from(*id)),
+            Command::AnimSave { id, .. } => (ElementKind::Animation, *id),
+            Command::AnimDelete { id } => (ElementKind::Animation, u8::from(*id)),
+            _ => return None,
+        };
+        Some(Self::new(kind, id))
+    }
+
+    /// Elements always available in the glasses, which do not need to be uploaded
+    pub(crate) fn is_builtin(&self) -> bool {
+        self.kind == ElementKind::Font
+            && self.id <= u8::from(DefaultFont::ComputerModernSansSerif49)
+    }
+}
+
+/// An element of a configuration, with the commands needed to upload it
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigElement {
+    pub element: ElementRef,
+    pub commands: Vec<Command>,
+    /// Elements which must exist before this one is uploaded
+    pub depends_on: Vec<ElementRef>,
+}
+
+/// Errors detected while building a configuration
+#[derive(Error, Debug, PartialEq)]
+pub enum ConfigError {
+    /// An element references another element which is not part of the configuration
+    #[error("{from:?} references missing {missing:?}")]
+    MissingReference {
+        from: ElementRef,
+        missing: ElementRef,
+    },
+    /// Elements reference each other
+    #[error("Dependency cycle involving {0:?}")]
+    Cycle(ElementRef),
+    /// The same element is defined twice
+    #[error("{0:?} is defined twice")]
+    Duplicate(ElementRef),
+    /// The additional commands of a layout can not be decoded to find its references
+    #[error("Invalid commands of {0:?}: {1}")]
+    InvalidCommands(ElementRef, DekuError),
+}
+
+/// Build a configuration from its elements
+#[derive(Clone, Debug)]
+pub struct ConfigBuilder {
+    name: String,
+    version: u32,
+    password: u32,
+    elements: Vec<ConfigElement>,
+    /// Elements already stored in the glasses
+    existing: BTreeSet<ElementRef>,
+}
+
+impl ConfigBuilder {
+    pub fn new(name: &str, version: u32, password: u32) -> Self {
+        Self {
+            name: String::from(name),
+            version,
+            password,
+            elements: Vec::new(),
+            existing: BTreeSet::new(),
+        }
+    }
+
+    /// Add any element, with explicit dependencies
+    pub fn element(mut self, element: ConfigElement) -> Self {
+        self.elements.push(element);
+        self
+    }
+
+    /// Declare an element already stored in the glasses, which can be referenced
+    pub fn existing(mut self, element: ElementRef) -> Self {
+        self.existing.insert(element);
+        self
+    }
+
+    /// Add an image
+    pub fn image(self, id: u8, image: &Image) -> Self {
+        self.element(ConfigElement {
+            element: ElementRef::new(ElementKind::Image, id),
+            commands: vec![image.save_command(id)],
+            depends_on: Vec::new(),
+        })
+    }
+
+    /// Add a font
+    pub fn font(self, id: u8, font: &Font) -> Result<Self, FontError> {
+        Ok(self.element(ConfigElement {
+            element: ElementRef::new(ElementKind::Font, id),
+            commands: vec![font.save_command(id)?],
+            depends_on: Vec::new(),
+        }))
+    }
+
+    /// Add a layout, depending on its font, and on the fonts, images and gauges of its
+    /// additional commands
+    pub fn layout(self, id: u8, params: LayoutParameters) -> Result<Self, ConfigError> {
+        let element = ElementRef::new(ElementKind::Layout, id);
+        let commands = params
+            .decode_commands()
+            .map_err(|error| ConfigError::InvalidCommands(element, error))?;
+        let mut depends_on = vec![ElementRef::new(ElementKind::Font, params.font())];
+        for cmd in commands {
+            let dep = match cmd {
+                LayoutCommand::Image { id, .. } => ElementRef::new(ElementKind::Image, id),
+                LayoutCommand::Font { id } => ElementRef::new(ElementKind::Font, id),
+                LayoutCommand::Gauge { id } => ElementRef::new(ElementKind::Gauge, id),
+                _ => continue,
+            };
+            if !depends_on.contains(&dep) {
+                depends_on.push(dep);
+            }
+        }
+        Ok(self.element(ConfigElement {
+            element,
+            commands: vec![Command::LayoutSave { id, params }],
+            depends_on,
+        }))
+    }
+
+    /// Add a gauge
+    pub fn gauge(self, id: u8, params: GaugeParameters) -> Self {
+        self.element(ConfigElement {
+            element: ElementRef::new(ElementKind::Gauge, id),
+            commands: vec![Command::GaugeSave { id, params }],
+            depends_on: Vec::new(),
+        })
+    }
+
+    /// Add a page, depending on the layouts of its slots
+    pub fn page(self, page: &Page) -> Self {
+        let mut depends_on = Vec::new();
+        for slot in page.slots() {
+            let dep = ElementRef::new(ElementKind::Layout, slot.layout);
+            if !depends_on.contains(&dep) {
+                depends_on.push(dep);
+            }
+        }
+        self.element(ConfigElement {
+            element: ElementRef::new(ElementKind::Page, page.id()),
+            commands: vec![page.save_command()],
+            depends_on,
+        })
+    }
+
+    /// Check every reference and order the elements so that dependencies are uploaded first.
+    /// Elements without dependencies between them keep the order in which they were added.
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let mut defined = BTreeSet::new();
+        for element in &self.elements {
+            if !defined.insert(element.element) || self.existing.contains(&element.element) {
+                return Err(ConfigError::Duplicate(element.element));
+            }
+        }
+        for element in &self.elements {
+            for dep in &element.depends_on {
+                if !defined.contains(dep) && !self.existing.contains(dep) && !dep.is_builtin() {
+                    return Err(ConfigError::MissingReference {
+                        from: element.element,
+                        missing: *dep,
+                    });
+                }
+            }
+        }
+
+        // Topological sort: repeatedly pick the first element whose dependencies are uploaded
+        let mut remaining = self.elements;
+        let mut ordered = Vec::with_capacity(remaining.len());
+        let mut uploaded = BTreeSet::new();
+        while !remaining.is_empty() {
+            let ready = remaining.iter().position(|element| {
+                element
+                    .depends_on
+                    .iter()
+                    .all(|dep| uploaded.contains(dep) || !defined.contains(dep))
+            });
+            match ready {
+                Some(index) => {
+                    let element = remaining.remove(index);
+                    uploaded.insert(element.element);
+                    ordered.push(element);
+                }
+                None => return Err(ConfigError::Cycle(remaining[0].element)),
+            }
+        }
+
+        Ok(Config {
+            name: self.name,
+            version: self.version,
+            password: self.password,
+            elements: ordered,
+        })
+    }
+}
+
+/// A configuration with its elements ordered for upload
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub name: String,
+    pub version: u32,
+    pub password: u32,
+    elements: Vec<ConfigElement>,
+}
+
+impl Config {
+    /// Elements, in upload order
+    pub fn elements(&self) -> &[ConfigElement] {
+        &self.elements
+    }
+
+    /// All commands needed to upload the configuration, starting with [Command::CfgWrite]
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = vec![self.cfg_write()];
+        for element in &self.elements {
+            commands.extend(element.commands.iter().cloned());
+        }
+        commands
+    }
+
+    fn cfg_write(&self) -> Command {
+        Command::CfgWrite {
+            name: self.name.clone(),
+            version: self.version,
+            password: self.password,
+        }
+    }
+
+    /// Upload the configuration, calling `progress` after each command
+    pub fn upload<G: GlassesApi>(
+        &self,
+        glasses: &mut G,
+        mut progress: impl FnMut(UploadProgress),
+    ) -> Result<(), GlassesError> {
+        let cfg_write = self.cfg_write();
+        let mut commands = vec![(None, &cfg_write)];
+        for element in &self.elements {
+            commands.extend(
+                element
+                    .commands
+                    .iter()
+                    .map(|cmd| (Some(element.element), cmd)),
+            );
+        }
+        let total = commands.len();
+        for (index, (element, cmd)) in commands.into_iter().enumerate() {
+            match cmd.chunk_policy() {
+                Some(_) => glasses.send_chunked(cmd),
+                None => glasses.send(cmd),
+            }
+            .map_err(|error| auth_error(&self.name, cmd, error))?;
+            progress(UploadProgress {
+                element,
+                sent: index + 1,
+                total,
+            });
+        }
+        Ok(())
+    }
+
+    /// Replace the password of the configuration stored in the glasses, returning the
+    /// configuration with its new password.
+    ///
+    /// The firmware only sets the password when a configuration is created. The configuration is
+    /// uploaded under a temporary name with the new `password`, checked with [Command::CfgRead],
+    /// and only then the previous one is deleted and the copy renamed: if the upload fails, the
+    /// copy is deleted and the glasses keep the configuration with its previous password. If the
+    /// delete or the rename fails, the copy is left under the temporary name, returned in
+    /// [GlassesError::ConfigLeftAs].
+    ///
+    /// The glasses can not give back the images and fonts they store: the stored configuration
+    /// must match this one, with the same version and no other elements, like the ones declared
+    /// with [ConfigBuilder::existing]. Otherwise nothing is written, and
+    /// [GlassesError::ConfigMismatch] is returned.
+    pub fn change_password<G: GlassesApi>(
+        &self,
+        glasses: &mut G,
+        password: u32,
+    ) -> Result<Config, GlassesError> {
+        let stored = glasses.query(&Command::CfgRead {
+            name: self.name.clone(),
+        })?;
+        if !self.is_stored_as(&stored) {
+            return Err(GlassesError::ConfigMismatch(self.name.clone()));
+        }
+        let copy = Config {
+            name: temporary_name(&self.name),
+            password,
+            ..self.clone()
+        };
+        // The copy only exists once its CfgWrite is accepted: a configuration already stored
+        // under the temporary name is never deleted
+        let mut created = false;
+        let copied = copy
+            .upload(glasses, |progress| created = progress.sent > 0)
+            .and_then(|()| {
+                glasses.query(&Command::CfgRead {
+                    name: copy.name.clone(),
+                })
+            })
+            .and_then(|response| match self.is_stored_as(&response) {
+                true => Ok(()),
+                false => Err(GlassesError::UnexpectedResponse(response)),
+            });
+        if let Err(error) = copied {
+            if created {
+                let delete = Command::CfgDelete {
+                    name: copy.name.clone(),
+                };
+                if let Err(delete_error) = glasses.send(&delete) {
+                    warn!("Could not delete {}: {}", copy.name, delete_error);
+                }
+            }
+            return Err(error);
+        }
+        let left_as = |error| GlassesError::ConfigLeftAs {
+            temporary: copy.name.clone(),
+            error: Box::new(error),
+        };
+        glasses
+            .send(&Command::CfgDelete {
+                name: self.name.clone(),
+            })
+            .map_err(left_as)?;
+        let rename = Command::CfgRename {
+            old: copy.name.clone(),
+            new: self.name.clone(),
+            password,
+        };
+        glasses
+            .send(&rename)
+            .map_err(|error| left_as(auth_error(&copy.name, &rename, error)))?;
+        Ok(Config {
+            name: self.name.clone(),
+            ..copy
+        })
+    }
+
+    /// Returns true if the [Response::CfgRead] matches the version and elements
+    fn is_stored_as(&self, response: &Response) -> bool {
+        let count = |kind| {
+            self.elements
+                .iter()
+                .filter(|element| element.element.kind == kind)
+                .count()
+        };
+        match *response {
+            Response::CfgRead {
+                version,
+                nb_img,
+                nb_layout,
+                nb_font,
+                nb_page,
+                nb_gauge,
+            } => {
+                version == self.version
+                    && nb_img as usize == count(ElementKind::Image)
+                    && nb_layout as usize == count(ElementKind::Layout)
+                    && nb_font as usize == count(ElementKind::Font)
+                    && nb_page as usize == count(ElementKind::Page)
+                    && nb_gauge as usize == count(ElementKind::Gauge)
+            }
+            _ => false,
+        }
+    }
+
+    /// Serialize the configuration to a portable archive.
+    /// The password is stored in clear, so it can be replayed with [Config::upload].
+    pub fn to_archive(&self) -> Result<Vec<u8>, ArchiveError> {
+        let mut elements = Vec::with_capacity(self.elements.len());
+        for element in &self.elements {
+            let mut commands = Vec::with_capacity(element.commands.len());
+            for cmd in &element.commands {
+                let (cmd_id, data) = cmd.as_bytes()?;
+                commands.push(ArchiveCommand {
+                    cmd_id,
+                    len: archive_len("command", data.len())?,
+                    data,
+                });
+            }
+            elements.push(ArchiveElement {
+                element: element.element,
+                nb_deps: archive_len("dependencies", element.depends_on.len())?,
+                depends_on: element.depends_on.clone(),
+                nb_commands: archive_len("commands", commands.len())?,
+                commands,
+            });
+        }
+        let archive = Archive {
+            format_version: ARCHIVE_VERSION,
+            name_len: archive_len("name", self.name.len())?,
+            name: self.name.as_bytes().to_vec(),
+            version: self.version,
+            password: self.password,
+            nb_elements: archive_len("elements", elements.len())?,
+            elements,
+        };
+        Ok(archive.to_bytes()?)
+    }
+
+    /// Read a configuration exported with [Config::to_archive]
+    pub fn from_archive(bytes: &[u8]) -> Result<Config, ArchiveError> {
+        let (_, archive) = Archive::from_bytes((bytes, 0))?;
+        if archive.format_version != ARCHIVE_VERSION {
+            return Err(ArchiveError::UnsupportedVersion(archive.format_version));
+        }
+        let mut elements = Vec::with_capacity(archive.elements.len());
+        for element in archive.elements {
+            let mut commands = Vec::with_capacity(element.commands.len());
+            for cmd in element.commands {
+                commands.push(Command::from_data(cmd.cmd_id, Some(&cmd.data))?);
+            }
+            elements.push(ConfigElement {
+                element: element.element,
+                commands,
+                depends_on: element.depends_on,
+            });
+        }
+        Ok(Config {
+            name: String::from_utf8_lossy(&archive.name).into_owned(),
+            version: archive.version,
+            password: archive.password,
+            elements,
+        })
+    }
+}
+
+/// Progress of [Config::upload]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct UploadProgress {
+    /// Element being uploaded, `None` for [Command::CfgWrite]
+    pub element: Option<ElementRef>,
+    /// Number of commands sent
+    pub sent: usize,
+    /// Total number of commands
+    pub total: usize,
+}
+
+/// Battery level, in %, below which the firmware refuses configuration writes
+pub const MIN_WRITE_BATTERY: u8 = 5;
+
+/// Password used for the configurations without stored credentials
+pub const DEFAULT_PASSWORD: u32 = 0;
+
+/// Name different from `name`, within [NAME_LEN] characters
+fn temporary_name(name: &str) -> String {
+    let mut res: String = name.chars().take(NAME_LEN - 1).collect();
+    res.push(if name.ends_with('~') { '#' } else { '~' });
+    res
+}
+
+/// Report the refusal of `cmd` caused by the password of configuration `name` as
+/// [GlassesError::ConfigAuth].
+///
+/// The API documentation does not describe this failure. Like the emulator, we assume a wrong
+/// password makes [Command::CfgWrite] and [Command::CfgRename] fail with [CmdError::Generic].
+/// Their other errors, like [CmdError::MemoryAccess], and the errors of the other commands are
+/// kept.
+pub(crate) fn auth_error(name: &str, cmd: &Command, error: GlassesError) -> GlassesError {
+    let refused = match &error {
+        GlassesError::Command(error) => {
+            matches!(cmd, Command::CfgWrite { .. } | Command::CfgRename { .. })
+                && cmd.id() == Ok(error.cmd_id)
+                && error.error == CmdError::Generic
+        }
+        _ => false,
+    };
+    match refused {
+        true => GlassesError::ConfigAuth(String::from(name)),
+        false => error,
+    }
+}
+
+/// Passwords of the configurations, by name.
+///
+/// Used by [crate::glasses::Glasses] to write and rename configurations without repeating their
+/// password. The passwords are not printed by [Debug], only the names.
+#[derive(Clone, Default, PartialEq)]
+pub struct ConfigCredentials {
+    passwords: BTreeMap<String, u32>,
+}
+
+impl ConfigCredentials {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remember the `password` of configuration `name`
+    pub fn insert(&mut self, name: &str, password: u32) {
+        self.passwords.insert(String::from(name), password);
+    }
+
+    /// Password of configuration `name`, [DEFAULT_PASSWORD] if unknown
+    pub fn password(&self, name: &str) -> u32 {
+        self.passwords
+            .get(name)
+            .copied()
+            .unwrap_or(DEFAULT_PASSWORD)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.passwords.contains_key(name)
+    }
+
+    /// Forget the password of configuration `name`
+    pub fn remove(&mut self, name: &str) -> Option<u32> {
+        self.passwords.remove(name)
+    }
+
+    /// Keep the password of configuration `old` under its `new` name
+    pub fn rename(&mut self, old: &str, new: &str) {
+        if let Some(password) = self.passwords.remove(old) {
+            self.passwords.insert(String::from(new), password);
+        }
+    }
+}
+
+impl fmt::Debug for ConfigCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigCredentials")
+            .field("names", &self.passwords.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Errors opening a [ConfigSession]
+#[derive(Error, Debug, PartialEq)]
+pub enum SessionError {
+    /// The battery level, in %, is below [MIN_WRITE_BATTERY]
+    #[error("Battery too low to write a configuration: {0}%")]
+    LowBattery(u8),
+    #[error(transparent)]
+    Glasses(#[from] GlassesError),
+}
+
+/// Guard for the modification of a configuration, see [GlassesApi::config_session].
+///
+/// The firmware answers [crate::commands::CmdError::MissingCfgWrite] to save and delete commands
+/// not preceded by [Command::CfgWrite]. The session checks the battery level, sends the
+/// [Command::CfgWrite], and records the elements written through it. It implements [GlassesApi],
+/// so every helper can be used inside the session.
+pub struct ConfigSession<'a, G: GlassesApi + ?Sized> {
+    glasses: &'a mut G,
+    name: String,
+    version: u32,
+    password: u32,
+    written: Vec<ElementRef>,
+}
+
+impl<'a, G: GlassesApi + ?Sized> ConfigSession<'a, G> {
+    /// Check the battery level and start writing configuration `name`
+    pub fn open(
+        glasses: &'a mut G,
+        name: &str,
+        version: u32,
+        password: u32,
+    ) -> Result<Self, SessionError> {
+        let battery = glasses.battery()?;
+        if battery < MIN_WRITE_BATTERY {
+            return Err(SessionError::LowBattery(battery));
+        }
+        let cfg_write = Command::CfgWrite {
+            name: String::from(name),
+            version,
+            password,
+        };
+        glasses
+            .send(&cfg_write)
+            .map_err(|error| auth_error(name, &cfg_write, error))?;
+        Ok(Self {
+            glasses,
+            name: String::from(name),
+            version,
+            password,
+            written: Vec::new(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn password(&self) -> u32 {
+        self.password
+    }
+
+    /// Elements saved or deleted during the session, in order
+    pub fn written(&self) -> &[ElementRef] {
+        &self.written
+    }
+
+    /// End the session, returning the elements saved or deleted
+    pub fn close(self) -> Vec<ElementRef> {
+        self.written
+    }
+
+    fn track(&mut self, cmd: &Command) {
+        match cmd {
+            // Switching to another configuration keeps the session consistent with the glasses
+            Command::CfgWrite {
+                name,
+                version,
+                password,
+            } => {
+                self.name.clone_from(name);
+                self.version = *version;
+                self.password = *password;
+            }
+            _ => self.written.extend(ElementRef::written_by(cmd)),
+        }
+    }
+}
+
+impl<G: GlassesApi + ?Sized> GlassesApi for ConfigSession<'_, G> {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.glasses
+            .send(cmd)
+            .map_err(|error| auth_error(&self.name, cmd, error))?;
+        self.track(cmd);
+        Ok(())
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.glasses.query(cmd)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.glasses
+            .send_chunked(cmd)
+            .map_err(|error| auth_error(&self.name, cmd, error))?;
+        self.track(cmd);
+        Ok(())
+    }
+}
+
+/// Errors reading or writing a configuration archive
+#[derive(Error, Debug, PartialEq)]
+pub enum ArchiveError {
+    #[error("Invalid archive: {0}")]
+    Format(#[from] DekuError),
+    #[error("Unsupported archive version {0}")]
+    UnsupportedVersion(u8),
+    /// A length does not fit its archive field
+    #[error("Length of the {0} does not fit the archive: {1}")]
+    TooLarge(&'static str, usize),
+}
+
+/// `len` as the integer type of its archive field
+fn archive_len<T: TryFrom<usize>>(field: &'static str, len: usize) -> Result<T, ArchiveError> {
+    T::try_from(len).map_err(|_| ArchiveError::TooLarge(field, len))
+}
+
+/// Version of the archive format, increased on incompatible changes
+pub const ARCHIVE_VERSION: u8 = 1;
+
+/// Archive layout, all integers are big endian:
+/// - magic `ALCFG`, format version
+/// - configuration name, version and password
+/// - elements in upload order, with their dependencies and the raw data of their commands
+#[derive(Debug, DekuRead, DekuWrite)]
+#[deku(magic = b"ALCFG")]
+struct Archive {
+    format_version: u8,
+    name_len: u8,
+    #[deku(count = "name_len")]
+    name: Vec<u8>,
+    #[deku(endian = "big")]
+    version: u32,
+    #[deku(endian = "big")]
+    password: u32,
+    #[deku(endian = "big")]
+    nb_elements: u16,
+    #[deku(count = "nb_elements")]
+    elements: Vec<ArchiveElement>,
+}
+
+#[derive(Debug, DekuRead, DekuWrite)]
+struct ArchiveElement {
+    element: ElementRef,
+    nb_deps: u8,
+    #[deku(count = "nb_deps")]
+    depends_on: Vec<ElementRef>,
+    #[deku(endian = "big")]
+    nb_commands: u16,
+    #[deku(count = "nb_commands")]
+    commands: Vec<ArchiveCommand>,
+}
+
+#[derive(Debug, DekuRead, DekuWrite)]
+struct ArchiveCommand {
+    cmd_id: u8,
+    #[deku(endian = "big")]
+    len: u32,
+    #[deku(count = "len")]
+    data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn font(id: u8) -> ConfigElement {
+        ConfigElement {
+            element: ElementRef::new(ElementKind::Font, id),
+            commands: Vec::new(),
+            depends_on: Vec::new(),
+        }
+    }
+
+    fn layout(id: u8, font: u8) -> ConfigElement {
+        ConfigElement {
+            element: ElementRef::new(ElementKind::Layout, id),
+            commands: vec![Command::LayoutClear { id }],
+            depends_on: vec![ElementRef::new(ElementKind::Font, font)],
+        }
+    }
+
+    #[test]
+    fn test_missing_reference() {
+        let res = ConfigBuilder::new("test", 1, 0)
+            .element(layout(1, 5))
+            .build();
+        assert_eq!(
+            Err(ConfigError::MissingReference {
+                from: ElementRef::new(ElementKind::Layout, 1),
+                missing: ElementRef::new(ElementKind::Font, 5),
+            }),
+            res
+        );
+
+        // Built-in fonts and existing elements can be referenced
+        let res = ConfigBuilder::new("test", 1, 0)
+            .element(layout(1, 1))
+            .element(layout(2, 5))
+            .existing(ElementRef::new(ElementKind::Font, 5))
+            .build();
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_layout_and_page_references() {
+        let element = |kind, id| ConfigElement {
+            element: ElementRef::new(kind, id),
+            commands: Vec::new(),
+            depends_on: Vec::new(),
+        };
+        let origin = crate::commands::Point { x: 0, y: 0 };
+        let params = crate::layout::LayoutBuilder::new(
+            crate::commands::LayoutPosition { x: 0, y: 10 },
+            100,
+            30,
+        )
+        .command(LayoutCommand::Image { id: 7, pos: origin })
+        .command(LayoutCommand::Font { id: 20 })
+        .text(origin, "km/h")
+        .command(LayoutCommand::Gauge { id: 2 })
+        .build()
+        .unwrap();
+        let page = Page::new(4)
+            .slot(3, crate::commands::LayoutPosition { x: 0, y: 0 })
+            .unwrap()
+            .slot(3, crate::commands::LayoutPosition { x: 0, y: 100 })
+            .unwrap();
+        let builder = ConfigBuilder::new("test", 1, 0)
+            .page(&page)
+            .layout(3, params)
+            .unwrap()
+            .element(element(ElementKind::Gauge, 2))
+            .element(element(ElementKind::Image, 7));
+        assert_eq!(
+            Err(ConfigError::MissingReference {
+                from: ElementRef::new(ElementKind::Layout, 3),
+                missing: ElementRef::new(ElementKind::Font, 20),
+            }),
+            builder.clone().build()
+        );
+
+        let config = builder
+            .element(element(ElementKind::Font, 20))
+            .build()
+            .unwrap();
+        let order: Vec<ElementRef> = config.elements().iter().map(|e| e.element).collect();
+        assert_eq!(
+            order,
+            [
+                ElementRef::new(ElementKind::Gauge, 2),
+                ElementRef::new(ElementKind::Image, 7),
+                ElementRef::new(ElementKind::Font, 20),
+                ElementRef::new(ElementKind::Layout, 3),
+                ElementRef::new(ElementKind::Page, 4),
+            ]
+        );
+        assert_eq!(
+            vec![ElementRef::new(ElementKind::Layout, 3)],
+            config.elements()[4].depends_on
+        );
+    }
+
+    #[test]
+    fn test_dependencies_first() {
+        let config = ConfigBuilder::new("test", 1, 0)
+            .element(layout(1, 5))
+            .element(layout(2, 1))
+            .element(font(5))
+            .build()
+            .unwrap();
+        let order: Vec<ElementRef> = config.elements().iter().map(|e| e.element).collect();
+        assert_eq!(
+            order,
+            [
+                ElementRef::new(ElementKind::Layout, 2),
+                ElementRef::new(ElementKind::Font, 5),
+                ElementRef::new(ElementKind::Layout, 1),
+            ]
+        );
+        assert_eq!(
+            config.commands()[0],
+            Command::CfgWrite {
+                name: String::from("test"),
+                version: 1,
+                password: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_archive() {
+        let font = Font {
+            height: 10,
+            data: &[1, 2, 3],
+        };
+        let config = ConfigBuilder::new("demo", 3, 42)
+            .font(5, &font)
+            .unwrap()
+            .element(layout(1, 5))
+            .gauge(
+                2,
+                GaugeParameters {
+                    pos: crate::commands::Point { x: 150, y: 120 },
+                    radius: 60,
+                    inner: 40,
+                    start: 1,
+                    end: 12,
+                    clockwise: true,
+                },
+            )
+            .build()
+            .unwrap();
+        let archive = config.to_archive().unwrap();
+        assert_eq!(b"ALCFG\x01\x04demo", &archive[..11]);
+        assert_eq!(config, Config::from_archive(&archive).unwrap());
+
+        let mut glasses = crate::glasses::Preview::new();
+        let mut progress = Vec::new();
+        config.upload(&mut glasses, |p| progress.push(p)).unwrap();
+        assert_eq!(4, progress.len());
+        assert_eq!(None, progress[0].element);
+        assert_eq!(
+            UploadProgress {
+                element: Some(ElementRef::new(ElementKind::Gauge, 2)),
+                sent: 4,
+                total: 4
+            },
+            progress[3]
+        );
+
+        let mut bad = archive.clone();
+        bad[5] = 2;
+        assert_eq!(
+            Err(ArchiveError::UnsupportedVersion(2)),
+            Config::from_archive(&bad)
+        );
+        assert!(Config::from_archive(&archive[..20]).is_err());
+
+        let mut long_name = config.clone();
+        long_name.name = "n".repeat(256);
+        assert_eq!(
+            Err(ArchiveError::TooLarge("name", 256)),
+            long_name.to_archive()
+        );
+    }
+
+    #[test]
+    fn test_config_session() {
+        let mut mock = crate::mock::MockClient::new();
+        mock.expect(Command::Battery)
+            .reply(Response::Battery { level: 4 });
+        assert_eq!(
+            Some(SessionError::LowBattery(4)),
+            mock.config_session("app", 2, 42).err()
+        );
+
+        let cfg_write = Command::CfgWrite {
+            name: String::from("app"),
+            version: 2,
+            password: 42,
+        };
+        mock.expect(Command::Battery)
+            .reply(Response::Battery { level: 5 });
+        mock.expect(cfg_write);
+        mock.expect(Command::LayoutDelete {
+            id: Selector::one(10).unwrap(),
+        });
+        mock.expect(Command::Clear);
+        let mut session = mock.config_session("app", 2, 42).unwrap();
+        session
+            .send(&Command::LayoutDelete {
+                id: Selector::one(10).unwrap(),
+            })
+            .unwrap();
+        session.clear().unwrap();
+        assert_eq!(2, session.version());
+        assert_eq!(
+            vec![ElementRef::new(ElementKind::Layout, 10)],
+            session.close()
+        );
+    }
+
+    /// Emulated glasses, reporting the command errors
+    struct Emulated(crate::emulator::Emulator);
+
+    impl GlassesApi for Emulated {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            match self.0.handle(cmd) {
+                Some(response) => Err(crate::cmd_error::CommandError::from_response(&response)
+                    .map_or(
+                        GlassesError::UnexpectedResponse(response),
+                        GlassesError::Command,
+                    )),
+                None => Ok(()),
+            }
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            self.0.handle(cmd).ok_or(GlassesError::Unsupported)
+        }
+    }
+
+    /// [GlassesError::Command] reporting `error` for `cmd`
+    fn command_error(cmd: &Command, error: CmdError) -> GlassesError {
+        let response = Response::CmdError {
+            cmd_id: cmd.id().unwrap(),
+            error,
+            sub_error: 0,
+        };
+        GlassesError::Command(crate::cmd_error::CommandError::from_response(&response).unwrap())
+    }
+
+    #[test]
+    fn test_config_auth() {
+        let mut mock = crate::mock::MockClient::new();
+        let cfg_write = Command::CfgWrite {
+            name: String::from("app"),
+            version: 2,
+            password: 42,
+        };
+        mock.expect(Command::Battery)
+            .reply(Response::Battery { level: 80 });
+        let error = command_error(&cfg_write, CmdError::Generic);
+        mock.expect(cfg_write).fail(error);
+        assert_eq!(
+            Some(SessionError::Glasses(GlassesError::ConfigAuth(
+                String::from("app")
+            ))),
+            mock.config_session("app", 2, 42).err()
+        );
+        mock.verify();
+
+        let params = crate::layout::LayoutBuilder::new(
+            crate::commands::LayoutPosition { x: 0, y: 10 },
+            100,
+            30,
+        )
+        .build()
+        .unwrap();
+        let config = ConfigBuilder::new("app", 2, 42)
+            .layout(10, params.clone())
+            .unwrap()
+            .layout(11, params)
+            .unwrap()
+            .build()
+            .unwrap();
+        let mut glasses = Emulated(crate::emulator::Emulator::new());
+        config.upload(&mut glasses, |_| {}).unwrap();
+        let other = Config {
+            password: 7,
+            ..config.clone()
+        };
+        assert_eq!(
+            Err(GlassesError::ConfigAuth(String::from("app"))),
+            other.upload(&mut glasses, |_| {})
+        );
+
+        // The new password is required, the configuration is unchanged
+        let changed = config.change_password(&mut glasses, 7).unwrap();
+        assert_eq!(other, changed);
+        assert_eq!(
+            Err(GlassesError::ConfigAuth(String::from("app"))),
+            config.upload(&mut glasses, |_| {})
+        );
+        changed.upload(&mut glasses, |_| {}).unwrap();
+        let Some(Response::CfgList { list }) = glasses.0.handle(&Command::CfgList) else {
+            panic!("Expected the list of configurations");
+        };
+        assert_eq!(
+            1,
+            list.iter()
+                .filter(|item| item.name.starts_with("app"))
+                .count()
+        );
+        assert_eq!(
+            Some(Response::CfgRead {
+                version: 2,
+                nb_img: 0,
+                nb_layout: 2,
+                nb_font: 0,
+                nb_page: 0,
+                nb_gauge: 0,
+            }),
+            glasses.0.handle(&Command::CfgRead {
+                name: String::from("app")
+            })
+        );
+    }
+
+    #[test]
+    fn test_auth_error_kept() {
+        let cfg_write = Command::CfgWrite {
+            name: String::from("app"),
+            version: 2,
+            password: 42,
+        };
+        let cfg_delete = Command::CfgDelete {
+            name: String::from("app"),
+        };
+        let kept = [
+            (&cfg_write, &cfg_write, CmdError::MemoryAccess),
+            (&cfg_delete, &cfg_delete, CmdError::MissingCfgWrite),
+            (&cfg_delete, &cfg_delete, CmdError::Generic),
+            // Reported for another command
+            (&cfg_write, &Command::CfgList, CmdError::Generic),
+        ];
+        for (sent, failed, error) in kept {
+            assert_eq!(
+                command_error(failed, error.clone()),
+                auth_error("app", sent, command_error(failed, error))
+            );
+        }
+        assert_eq!(
+            GlassesError::ConfigAuth(String::from("app")),
+            auth_error(
+                "app",
+                &cfg_write,
+                command_error(&cfg_write, CmdError::Generic)
+            )
+        );
+    }
+
+    #[test]
+    fn test_change_password_failures() {
+        let config = ConfigBuilder::new("app", 2, 42).build().unwrap();
+        let copy_write = Command::CfgWrite {
+            name: String::from("app~"),
+            version: 2,
+            password: 7,
+        };
+        let copy_read = Command::CfgRead {
+            name: String::from("app~"),
+        };
+        let copy_delete = Command::CfgDelete {
+            name: String::from("app~"),
+        };
+        let stored = Response::CfgRead {
+            version: 2,
+            nb_img: 0,
+            nb_layout: 0,
+            nb_font: 0,
+            nb_page: 0,
+            nb_gauge: 0,
+        };
+
+        let read = Command::CfgRead {
+            name: String::from("app"),
+        };
+        let wrong = Response::CfgRead {
+            version: 2,
+            nb_img: 1,
+            nb_layout: 0,
+            nb_font: 0,
+            nb_page: 0,
+            nb_gauge: 0,
+        };
+
+        // Another element is stored, like an existing one: nothing is written
+        let mut mock = crate::mock::MockClient::new();
+        mock.expect(read.clone()).reply(wrong.clone());
+        assert_eq!(
+            Err(GlassesError::ConfigMismatch(String::from("app"))),
+            config.change_password(&mut mock, 7)
+        );
+        mock.verify();
+
+        // The copy is refused: nothing to delete
+        let mut mock = crate::mock::MockClient::new();
+        mock.expect(read.clone()).reply(stored.clone());
+        mock.expect(copy_write.clone())
+            .fail(command_error(&copy_write, CmdError::MemoryAccess));
+        assert_eq!(
+            Err(command_error(&copy_write, CmdError::MemoryAccess)),
+            config.change_password(&mut mock, 7)
+        );
+        mock.verify();
+
+        // The copy does not match: it is deleted
+        let mut mock = crate::mock::MockClient::new();
+        mock.expect(read.clone()).reply(stored.clone());
+        mock.expect(copy_write.clone());
+        mock.expect(copy_read.clone()).reply(wrong.clone());
+        mock.expect(copy_delete);
+        assert_eq!(
+            Err(GlassesError::UnexpectedResponse(wrong)),
+            config.change_password(&mut mock, 7)
+        );
+        mock.verify();
+
+        // The previous configuration is not deleted: both are left
+        let mut mock = crate::mock::MockClient::new();
+        let delete = Command::CfgDelete {
+            name: String::from("app"),
+        };
+        mock.expect(read.clone()).reply(stored.clone());
+        mock.expect(copy_write.clone());
+        mock.expect(copy_read.clone()).reply(stored.clone());
+        mock.expect(delete.clone())
+            .fail(command_error(&delete, CmdError::Generic));
+        assert_eq!(
+            Err(GlassesError::ConfigLeftAs {
+                temporary: String::from("app~"),
+                error: Box::new(command_error(&delete, CmdError::Generic)),
+            }),
+            config.change_password(&mut mock, 7)
+        );
+        mock.verify();
+
+        // The rename fails: the configuration is left under the temporary name
+        let mut mock = crate::mock::MockClient::new();
+        let rename = Command::CfgRename {
+            old: String::from("app~"),
+            new: String::from("app"),
+            password: 7,
+        };
+        mock.expect(read).reply(stored.clone());
+        mock.expect(copy_write);
+        mock.expect(copy_read).reply(stored);
+        mock.expect(delete);
+        mock.expect(rename.clone())
+            .fail(command_error(&rename, CmdError::MemoryAccess));
+        assert_eq!(
+            Err(GlassesError::ConfigLeftAs {
+                temporary: String::from("app~"),
+                error: Box::new(command_error(&rename, CmdError::MemoryAccess)),
+            }),
+            config.change_password(&mut mock, 7)
+        );
+        mock.verify();
+    }
+
+    #[test]
+    fn test_credentials() {
+        assert_eq!("abcdefghijk~", temporary_name("abcdefghijklmn"));
+        assert_eq!("abcdefghijk#", temporary_name("abcdefghijk~"));
+        assert_eq!("app~", temporary_name("app"));
+
+        let mut credentials = ConfigCredentials::new();
+        credentials.insert("app", 1234);
+        assert_eq!(1234, credentials.password("app"));
+        assert_eq!(DEFAULT_PASSWORD, credentials.password("other"));
+        credentials.rename("app", "renamed");
+        assert!(!credentials.contains("app"));
+        assert_eq!(1234, credentials.password("renamed"));
+        assert!(!format!("{credentials:?}").contains("1234"));
+        assert_eq!(Some(1234), credentials.remove("renamed"));
+    }
+
+    #[test]
+    fn test_cycle() {
+        let mut a = font(10);
+        a.depends_on.push(ElementRef::new(ElementKind::Layout, 1));
+        let res = ConfigBuilder::new("test", 1, 0)
+            .element(layout(1, 10))
+            .element(a)
+            .build();
+        assert_eq!(
+            Err(ConfigError::Cycle(ElementRef::new(ElementKind::Layout, 1))),
+            res
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Conformance of the encoding to the API documentation
+//!
+//! `spec/fixtures/*.tsv` hold the data bytes of every [Command] and [crate::commands::Response]
+//! variant, encoded by hand from the field types of `spec/ActiveLook_API.md`. Each fixture must
+//! match the encoding of the [crate::vectors] sample with the same name, and decode back to it.
+//!
+//! `spec/fixtures/regression/*.txt` hold whole packets of typical sessions: each one must decode
+//! and encode back to the same bytes. They were generated by this crate, not captured from the
+//! glasses: they only detect changes of the encoding.
+use core::fmt::Debug;
+
+use crate::{
+    commands::{ChunkPolicy, Command},
+    protocol::{CommandPacket, Packet, PayloadRef, RawPacket, ResponsePacket},
+    recorder::from_hex,
+    sniffer::Direction,
+    traits::{Deserializable, Serializable},
+    vectors::{sample_commands, sample_responses, Vector},
+};
+
+const COMMAND_FIXTURES: &str = include_str!("../spec/fixtures/commands.tsv");
+const RESPONSE_FIXTURES: &str = include_str!("../spec/fixtures/responses.tsv");
+
+/// One line of a fixture file: name, ID and data. The last column only documents the fields.
+struct Fixture {
+    name: String,
+    id: u8,
+    data: Vec<u8>,
+}
+
+fn parse(fixtures: &str) -> Vec<Fixture> {
+    fixtures
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .map(|line| {
+            let columns: Vec<&str> = line.split('\t').collect();
+            assert_eq!(4, columns.len(), "Malformed fixture: {}", line);
+            Fixture {
+                name: String::from(columns[0]),
+                id: u8::from_str_radix(columns[1], 16).expect("Valid ID"),
+                data: from_hex(columns[2]).expect("Valid data"),
+            }
+        })
+        .collect()
+}
+
+/// Check both directions: encoding of `samples`, and decoding of the fixtures
+fn check<T>(samples: &[T], fixtures: &[Fixture])
+where
+    T: Serializable + Deserializable<Item = T> + Debug + PartialEq,
+{
+    assert_eq!(samples.len(), fixtures.len());
+    for (sample, fixture) in samples.iter().zip(fixtures) {
+        let vector = Vector::new(sample);
+        assert_eq!(fixture.name, vector.name);
+        assert_eq!(fixture.id, vector.id, "{}", fixture.name);
+        assert_eq!(fixture.data, vector.data, "{}", fixture.name);
+        let data = (!fixture.data.is_empty()).then_some(&fixture.data[..]);
+        let decoded = T::from_data(fixture.id, data).expect(&fixture.name);
+        assert_eq!(sample, &decoded, "{}", fixture.name);
+    }
+}
+
+#[test]
+fn test_command_fixtures() {
+    let fixtures = parse(COMMAND_FIXTURES);
+    check(&sample_commands(), &fixtures);
+    // Every command of the API documentation has a fixture
+    for id in Command::ids() {
+        assert!(
+            fixtures.iter().any(|fixture| fixture.id == id),
+            "No fixture for {}",
+            Command::descriptor(id).unwrap().name
+        );
+    }
+}
+
+#[test]
+fn test_response_fixtures() {
+    check(&sample_responses(), &parse(RESPONSE_FIXTURES));
+}
+
+/// Hex dumps of whole packets, see `spec/fixtures/regression`
+const REGRESSION_VECTORS: &[(&str, &str)] = &[
+    (
+        "cfg_upload",
+        include_str!("../spec/fixtures/regression/cfg_upload.txt"),
+    ),
+    (
+        "image_upload",
+        include_str!("../spec/fixtures/regression/image_upload.txt"),
+    ),
+    (
+        "layouts",
+        include_str!("../spec/fixtures/regression/layouts.txt"),
+    ),
+    (
+        "pages",
+        include_str!("../spec/fixtures/regression/pages.txt"),
+    ),
+];
+
+/// Packets of a hex dump, in the format of [crate::sniffer::parse_hex_dump]
+fn packets(dump: &str) -> Vec<(Direction, Vec<u8>)> {
+    dump.lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .map(|line| {
+            let (direction, hex) = line.split_at(1);
+            let direction = match direction {
+                ">" => Direction::ToGlasses,
+                "<" => Direction::FromGlasses,
+                _ => panic!("Malformed hex dump line: {}", line),
+            };
+            (direction, from_hex(hex.trim()).expect("Valid packet"))
+        })
+        .collect()
+}
+
+/// Packet of `data` for command `id`, with the `query_id` of the original packet
+fn encode(id: u8, data: &[u8], query_id: Option<&[u8]>) -> Vec<u8> {
+    let payload = PayloadRef { id, data };
+    match query_id {
+        Some(query_id) => Packet::new_with_query_id(&payload, query_id),
+        None => Packet::new(&payload),
+    }
+    .unwrap()
+    .to_bytes()
+}
+
+/// Check that every packet decodes and encodes back to the same bytes. The packets of a chunked
+/// upload are decoded as one command, and split again with the chunk size of the hex dump.
+fn check_hex_dump(name: &str, dump: &str) {
+    let mut packets = packets(dump).into_iter();
+    while let Some((direction, bytes)) = packets.next() {
+        if direction == Direction::FromGlasses {
+            let packet = ResponsePacket::from_bytes(&bytes).expect(name);
+            assert_eq!(bytes, packet.to_bytes(), "{}: {:?}", name, packet.data);
+            continue;
+        }
+        let raw = RawPacket::from_bytes(&bytes).expect(name);
+        let header = raw.data.unwrap_or(&[]);
+        let size = ChunkPolicy::for_id(raw.cmd_id()).and_then(|policy| policy.data_size(header));
+        let Some(size @ 1..) = size else {
+            let packet = CommandPacket::from_bytes(&bytes).expect(name);
+            assert_eq!(bytes, packet.to_bytes(), "{}: {:?}", name, packet.data);
+            continue;
+        };
+        let mut frames = vec![bytes.clone()];
+        let mut data = header.to_vec();
+        while data.len() < header.len() + size {
+            let (_, frame) = packets.next().expect("Complete upload");
+            data.extend(RawPacket::from_bytes(&frame).unwrap().data.unwrap_or(&[]));
+            frames.push(frame);
+        }
+        let cmd = Command::from_data(raw.cmd_id(), Some(&data)).expect(name);
+        let chunk_size = RawPacket::from_bytes(&frames[1])
+            .unwrap()
+            .data
+            .unwrap()
+            .len();
+        let (id, chunks) = cmd.chunks(chunk_size).unwrap();
+        assert_eq!(frames.len(), chunks.len(), "{}: {:?}", name, cmd.id());
+        for (frame, chunk) in frames.iter().zip(&chunks) {
+            let query_id = RawPacket::from_bytes(frame).unwrap().query_id;
+            assert_eq!(frame, &encode(id, chunk, query_id.as_deref()), "{}", name);
+        }
+    }
+}
+
+#[test]
+fn test_regression_vectors() {
+    for (name, dump) in REGRESSION_VECTORS {
+        check_hex_dump(name, dump);
+    }
+}
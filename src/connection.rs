@@ -0,0 +1,369 @@
+//! Connection supervision
+//!
+//! [ConnectionManager] wraps any [GlassesApi] and watches for transport errors. On a disconnect,
+//! it calls the reconnect closure given by the application, restores the session state, and sends
+//! the interrupted command again:
+//! - [HoldFlushAction::ResetFlush], as the graphic engine state is unknown,
+//! - the [Command::CfgWrite] of the configuration being written, so an [crate::upload::Uploader]
+//!   running on top of the manager resumes at the interrupted element. The write is over, and
+//!   not restored, after a [Command::CfgSet] or any other command sent which is not a query and
+//!   does not save or delete an element,
+//! - the last [Command::CfgSet], selecting the configuration used by the application.
+use log::{info, warn};
+
+use crate::{
+    commands::{Command, HoldFlushAction, Response},
+    config::ElementRef,
+    glasses::{GlassesApi, GlassesError},
+    protocol::ProtocolError,
+    traits::Serializable,
+};
+
+/// Returns true if `error` means the connection to the glasses is lost
+pub fn is_disconnect(error: &GlassesError) -> bool {
+    matches!(
+        error,
+        GlassesError::Protocol(ProtocolError::EmbeddedIOError)
+    )
+}
+
+/// Returns true if the glasses answer `cmd` with a [Response]
+fn is_query(cmd: &Command) -> bool {
+    cmd.id()
+        .ok()
+        .and_then(Command::descriptor)
+        .is_some_and(|desc| desc.response)
+}
+
+/// [GlassesApi] reconnecting the wrapped glasses when the transport fails
+pub struct ConnectionManager<G: GlassesApi, R: FnMut() -> Result<G, GlassesError>> {
+    glasses: G,
+    reconnect: R,
+    max_attempts: usize,
+    reconnections: usize,
+    cfg_write: Option<Command>,
+    cfg_set: Option<Command>,
+}
+
+impl<G: GlassesApi, R: FnMut() -> Result<G, GlassesError>> ConnectionManager<G, R> {
+    pub const DEFAULT_MAX_ATTEMPTS: usize = 3;
+
+    /// Supervise `glasses`, calling `reconnect` to replace them after a disconnect
+    pub fn new(glasses: G, reconnect: R) -> Self {
+        Self {
+            glasses,
+            reconnect,
+            max_attempts: Self::DEFAULT_MAX_ATTEMPTS,
+            reconnections: 0,
+            cfg_write: None,
+            cfg_set: None,
+        }
+    }
+
+    /// Number of calls to the reconnect closure before giving up
+    pub fn max_attempts(mut self, max_attempts: usize) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    /// Number of successful reconnections
+    pub fn reconnections(&self) -> usize {
+        self.reconnections
+    }
+
+    pub fn into_inner(self) -> G {
+        self.glasses
+    }
+
+    /// Keep the commands defining the session state
+    fn track(&mut self, cmd: &Command) {
+        match cmd {
+            Command::CfgWrite { .. } => self.cfg_write = Some(cmd.clone()),
+            Command::CfgSet { .. } => {
+                self.cfg_write = None;
+                self.cfg_set = Some(cmd.clone());
+            }
+            _ if ElementRef::written_by(cmd).is_none() && !is_query(cmd) => self.cfg_write = None,
+            _ => (),
+        }
+    }
+
+    /// Reconnect and restore the session state. Returns the last error if every attempt failed.
+    fn recover(&mut self, error: GlassesError) -> Result<(), GlassesError> {
+        let mut last_error = error;
+        for attempt in 1..=self.max_attempts {
+            warn!("Connection lost ({}), reconnection {}", last_error, attempt);
+            match (self.reconnect)().and_then(|glasses| self.restore(glasses)) {
+                Ok(()) => {
+                    self.reconnections += 1;
+                    info!("Reconnected");
+                    return Ok(());
+                }
+                Err(error) => last_error = error,
+            }
+        }
+        Err(last_error)
+    }
+
+    fn restore(&mut self, mut glasses: G) -> Result<(), GlassesError> {
+        glasses.hold_flush(HoldFlushAction::ResetFlush)?;
+        for cmd in [&self.cfg_write, &self.cfg_set].into_iter().flatten() {
+            glasses.send(cmd)?;
+        }
+        self.glasses = glasses;
+        Ok(())
+    }
+
+    /// Run `f`, once more after reconnecting if the connection was lost
+    fn supervise<T>(
+        &mut self,
+        cmd: &Command,
+        f: impl Fn(&mut G, &Command) -> Result<T, GlassesError>,
+    ) -> Result<T, GlassesError> {
+        self.track(cmd);
+        match f(&mut self.glasses, cmd) {
+            Err(error) if is_disconnect(&error) => {
+                self.recover(error)?;
+                f(&mut self.glasses, cmd)
+            }
+            res => res,
+        }
+    }
+}
+
+impl<G: GlassesApi, R: FnMut() -> Result<G, GlassesError>> GlassesApi for ConnectionManager<G, R> {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.supervise(cmd, G::send)
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.supervise(cmd, G::query)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.supervise(cmd, G::send_chunked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use embedded_io::{ErrorKind, ErrorType, Read, ReadReady};
+
+    use super::*;
+    use crate::{
+        client::ActiveLookClient,
+        commands::Selector,
+        glasses::Glasses,
+        protocol::Packet,
+        transport::loopback::{LoopbackReader, LoopbackWriter},
+        upload::Uploader,
+    };
+
+    /// Glasses logging the received commands, disconnected after `budget` commands
+    struct Flaky {
+        log: Rc<RefCell<Vec<Command>>>,
+        budget: usize,
+    }
+
+    impl GlassesApi for Flaky {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            if self.budget == 0 {
+                return Err(GlassesError::Protocol(ProtocolError::EmbeddedIOError));
+            }
+            self.budget -= 1;
+            self.log.borrow_mut().push(cmd.clone());
+            Ok(())
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            self.send(cmd)?;
+            Err(GlassesError::Unsupported)
+        }
+    }
+
+    fn cfg_set() -> Command {
+        Command::CfgSet {
+            name: String::from("app"),
+        }
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let glasses = Flaky {
+            log: log.clone(),
+            budget: 1,
+        };
+        let reconnect_log = log.clone();
+        let mut manager = ConnectionManager::new(glasses, move || {
+            Ok(Flaky {
+                log: reconnect_log.clone(),
+                budget: 10,
+            })
+        });
+
+        manager.send(&cfg_set()).unwrap();
+        manager.clear().unwrap();
+        assert_eq!(1, manager.reconnections());
+        assert_eq!(
+            vec![
+                cfg_set(),
+                Command::HoldFlush {
+                    action: HoldFlushAction::ResetFlush
+                },
+                cfg_set(),
+                Command::Clear,
+            ],
+            *log.borrow()
+        );
+    }
+
+    #[test]
+    fn test_resume_upload() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let glasses = Flaky {
+            log: log.clone(),
+            budget: 2,
+        };
+        let reconnect_log = log.clone();
+        let mut manager = ConnectionManager::new(glasses, move || {
+            Ok(Flaky {
+                log: reconnect_log.clone(),
+                budget: 10,
+            })
+        });
+        let cfg_write = Command::CfgWrite {
+            name: String::from("app"),
+            version: 1,
+            password: 0,
+        };
+        let cmds = [
+            cfg_write.clone(),
+            Command::LayoutDelete {
+                id: Selector::one(10).unwrap(),
+            },
+            Command::LayoutDelete {
+                id: Selector::one(11).unwrap(),
+            },
+        ];
+        let report = Uploader::new(&mut manager).upload(&cmds);
+        assert!(report.is_success());
+        let log = log.borrow();
+        assert_eq!(cfg_write, log[3]);
+        assert_eq!(
+            Command::LayoutDelete {
+                id: Selector::one(11).unwrap(),
+            },
+            log[4]
+        );
+    }
+
+    #[test]
+    fn test_reconnect_after_upload() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let glasses = Flaky {
+            log: log.clone(),
+            budget: 4,
+        };
+        let reconnect_log = log.clone();
+        let mut manager = ConnectionManager::new(glasses, move || {
+            Ok(Flaky {
+                log: reconnect_log.clone(),
+                budget: 10,
+            })
+        });
+        let cmds = [
+            Command::CfgWrite {
+                name: String::from("app"),
+                version: 1,
+                password: 0,
+            },
+            Command::LayoutDelete {
+                id: Selector::one(10).unwrap(),
+            },
+        ];
+        assert!(Uploader::new(&mut manager).upload(&cmds).is_success());
+        // Queries do not end the write
+        assert_eq!(
+            Err(GlassesError::Unsupported),
+            manager.query(&Command::LayoutList)
+        );
+        manager.clear().unwrap();
+        manager.clear().unwrap();
+        assert_eq!(1, manager.reconnections());
+        assert_eq!(
+            vec![
+                cmds[0].clone(),
+                cmds[1].clone(),
+                Command::LayoutList,
+                Command::Clear,
+                Command::HoldFlush {
+                    action: HoldFlushAction::ResetFlush
+                },
+                Command::Clear,
+            ],
+            *log.borrow()
+        );
+    }
+
+    /// Notifications of the Tx characteristic, failing once the link is lost
+    struct Link(Option<VecDeque<u8>>);
+
+    impl ErrorType for Link {
+        type Error = ErrorKind;
+    }
+
+    impl Read for Link {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let bytes = self.0.as_mut().ok_or(ErrorKind::NotConnected)?;
+            let len = buf.len().min(bytes.len());
+            for (byte, received) in buf.iter_mut().zip(bytes.drain(..len)) {
+                *byte = received;
+            }
+            Ok(len)
+        }
+    }
+
+    impl ReadReady for Link {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.0.as_ref().is_some_and(|bytes| !bytes.is_empty()))
+        }
+    }
+
+    fn glasses(link: Link) -> Glasses<Link, LoopbackWriter, LoopbackReader> {
+        let client =
+            ActiveLookClient::new(link, LoopbackWriter::default(), LoopbackReader::default());
+        Glasses::new(client)
+    }
+
+    #[test]
+    fn test_reconnect_on_read_error() {
+        let battery = Response::Battery { level: 42 };
+        let mut manager = ConnectionManager::new(glasses(Link(None)), || {
+            // The query is sent again after the HoldFlush restoring the session: query_id 2
+            let packet = Packet::new_with_query_id(&battery, &2u32.to_be_bytes()).unwrap();
+            Ok(glasses(Link(Some(packet.to_bytes().into()))))
+        });
+        assert_eq!(Ok(42), manager.battery());
+        assert_eq!(1, manager.reconnections());
+    }
+
+    #[test]
+    fn test_reconnect_failure() {
+        let glasses = Flaky {
+            log: Rc::default(),
+            budget: 0,
+        };
+        let mut attempts = 0;
+        let mut manager = ConnectionManager::new(glasses, || {
+            attempts += 1;
+            Err(GlassesError::Unsupported)
+        })
+        .max_attempts(2);
+        assert_eq!(Err(GlassesError::Unsupported), manager.clear());
+        drop(manager);
+        assert_eq!(2, attempts);
+    }
+}
@@ -0,0 +1,262 @@
+//! Partial updates of layout texts
+//!
+//! Applications often display the same layout again with a text changing by a few characters,
+//! like a speed readout. [DeltaText] keeps the last text displayed by each tracked layout. When
+//! the new text starts like the previous one, only the characters after the common prefix are
+//! updated: [Command::LayoutClearExtended] and [Command::LayoutDisplayExtended] move the layout
+//! right, past the unchanged characters. Otherwise the whole layout is updated with
+//! [Command::LayoutClearAndDisplay].
+//!
+//! The firmware only reports the height of its fonts: the shift of the layout assumes a
+//! monospace font, with the character width given to [DeltaText::track]. Moving the layout also
+//! moves its clipping region: a partial update clears up to the width of the skipped characters
+//! past the right edge of the layout, which must be free of other content.
+//!
+//! Only layouts drawing a left-to-right text, without additional commands, can be tracked: the
+//! additional commands would be drawn again at the shifted position.
+use std::collections::BTreeMap;
+
+use crate::{
+    commands::{Command, LayoutParameters, LayoutPosition, TextRotation},
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// Layout whose text is tracked
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Tracked {
+    pos: LayoutPosition,
+    width: u16,
+    char_width: u16,
+    /// Text displayed, unknown until the first display
+    last: Option<String>,
+}
+
+/// Last text displayed by each tracked layout, see the [module](self) documentation
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DeltaText {
+    layouts: BTreeMap<u8, Tracked>,
+}
+
+impl DeltaText {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track the text of layout `id`, saved with `params` and drawn with a monospace font of
+    /// `char_width` pixels. Returns false, and the layout is always updated as a whole, if its
+    /// text is not drawn left to right or if it has additional commands.
+    pub fn track(&mut self, id: u8, params: &LayoutParameters, char_width: u16) -> bool {
+        let trackable = params.text_pos().is_some()
+            && params.text_rotation() == TextRotation::TOP_LR
+            && params.commands_bytes().is_empty();
+        match trackable {
+            true => {
+                let tracked = Tracked {
+                    pos: params.pos().clone(),
+                    width: params.width(),
+                    char_width: char_width.max(1),
+                    last: None,
+                };
+                self.layouts.insert(id, tracked);
+            }
+            false => {
+                self.layouts.remove(&id);
+            }
+        }
+        trackable
+    }
+
+    /// Last text displayed by layout `id`, if it is tracked and known
+    pub fn last(&self, id: u8) -> Option<&str> {
+        self.layouts.get(&id)?.last.as_deref()
+    }
+
+    /// Forget the text of layout `id`, after it was cleared or drawn over by other commands.
+    /// Its next display updates it as a whole.
+    pub fn forget(&mut self, id: u8) {
+        if let Some(tracked) = self.layouts.get_mut(&id) {
+            tracked.last = None;
+        }
+    }
+
+    /// Forget the text of every layout, after [Command::Clear] for instance
+    pub fn forget_all(&mut self) {
+        self.layouts
+            .values_mut()
+            .for_each(|tracked| tracked.last = None);
+    }
+
+    /// Commands displaying `text` with layout `id`, nothing if it already displays it
+    pub fn display(&mut self, id: u8, text: &str) -> Vec<Command> {
+        let full = || {
+            vec![Command::LayoutClearAndDisplay {
+                id,
+                text: String::from(text),
+            }]
+        };
+        let Some(tracked) = self.layouts.get_mut(&id) else {
+            return full();
+        };
+        let Some(last) = tracked.last.replace(String::from(text)) else {
+            return full();
+        };
+        if last == text {
+            return Vec::new();
+        }
+        let (prefix_len, suffix_start) = last
+            .chars()
+            .zip(text.char_indices())
+            .take_while(|(old, (_, new))| old == new)
+            .fold((0, 0), |(count, _), (_, (i, c))| {
+                (count + 1, i + c.len_utf8())
+            });
+        let shift = (prefix_len as u32) * (tracked.char_width as u32);
+        if prefix_len == 0 || shift >= tracked.width as u32 {
+            return full();
+        }
+        let Ok(x) = u16::try_from(tracked.pos.x as u32 + shift) else {
+            return full();
+        };
+        let pos = LayoutPosition {
+            x,
+            y: tracked.pos.y,
+        };
+        let mut commands = vec![Command::LayoutClearExtended {
+            id,
+            pos: pos.clone(),
+        }];
+        if suffix_start < text.len() {
+            commands.push(Command::LayoutDisplayExtended {
+                id,
+                pos,
+                text: String::from(&text[suffix_start..]),
+                extra_cmd: Vec::new(),
+            });
+        }
+        commands
+    }
+
+    /// Display `text` with layout `id` on `glasses`, see [DeltaText::display]. On error, the text
+    /// of the layout is forgotten.
+    pub fn send<G: GlassesApi + ?Sized>(
+        &mut self,
+        glasses: &mut G,
+        id: u8,
+        text: &str,
+    ) -> Result<(), GlassesError> {
+        for cmd in self.display(id, text) {
+            if let Err(error) = glasses.send(&cmd) {
+                self.forget(id);
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{layout::LayoutBuilder, mock::MockClient};
+
+    const POS: LayoutPosition = LayoutPosition { x: 20, y: 100 };
+
+    fn layout() -> LayoutParameters {
+        LayoutBuilder::new(POS, 120, 30)
+            .text_at(LayoutPosition { x: 0, y: 0 })
+            .build()
+            .unwrap()
+    }
+
+    fn full(text: &str) -> Command {
+        Command::LayoutClearAndDisplay {
+            id: 3,
+            text: String::from(text),
+        }
+    }
+
+    fn partial(x: u16, text: &str) -> Vec<Command> {
+        let pos = LayoutPosition { x, y: POS.y };
+        vec![
+            Command::LayoutClearExtended {
+                id: 3,
+                pos: pos.clone(),
+            },
+            Command::LayoutDisplayExtended {
+                id: 3,
+                pos,
+                text: String::from(text),
+                extra_cmd: Vec::new(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_delta() {
+        let mut delta = DeltaText::new();
+        assert!(delta.track(3, &layout(), 12));
+        assert_eq!(vec![full("42 km/h")], delta.display(3, "42 km/h"));
+        assert!(delta.display(3, "42 km/h").is_empty());
+        assert_eq!(partial(32, "3 km/h"), delta.display(3, "43 km/h"));
+        // Shorter text: the end is only cleared
+        assert_eq!(
+            vec![Command::LayoutClearExtended {
+                id: 3,
+                pos: LayoutPosition { x: 44, y: 100 }
+            }],
+            delta.display(3, "43")
+        );
+        assert_eq!(vec![full("57")], delta.display(3, "57"));
+        assert_eq!(Some("57"), delta.last(3));
+        assert_eq!(
+            partial(44, "00000000 km"),
+            delta.display(3, "5700000000 km")
+        );
+        // Past the width of the layout
+        assert_eq!(vec![full("5700000000")], delta.display(3, "5700000000"));
+        delta.display(3, "é1");
+        assert_eq!(partial(32, "2"), delta.display(3, "é2"));
+
+        delta.forget_all();
+        assert_eq!(vec![full("5")], delta.display(3, "5"));
+        // Untracked layouts are always updated
+        let untracked = vec![Command::LayoutClearAndDisplay {
+            id: 4,
+            text: String::from("5"),
+        }];
+        assert_eq!(untracked, delta.display(4, "5"));
+        assert_eq!(untracked, delta.display(4, "5"));
+    }
+
+    #[test]
+    fn test_untrackable() {
+        let mut delta = DeltaText::new();
+        let rotated = LayoutBuilder::new(POS, 120, 30)
+            .text_at(LayoutPosition { x: 0, y: 0 })
+            .text_rotation(TextRotation::BOTTOM_RL)
+            .build()
+            .unwrap();
+        assert!(!delta.track(3, &rotated, 12));
+        let no_text = LayoutBuilder::new(POS, 120, 30).build().unwrap();
+        assert!(!delta.track(3, &no_text, 12));
+        delta.display(3, "42");
+        assert_eq!(vec![full("43")], delta.display(3, "43"));
+    }
+
+    #[test]
+    fn test_send() {
+        let mut delta = DeltaText::new();
+        delta.track(3, &layout(), 12);
+        let mut mock = MockClient::new();
+        mock.expect(full("42"));
+        mock.expect(partial(32, "3")[0].clone())
+            .fail(GlassesError::Unsupported);
+        mock.expect(full("43"));
+        delta.send(&mut mock, 3, "42").unwrap();
+        assert_eq!(
+            Err(GlassesError::Unsupported),
+            delta.send(&mut mock, 3, "43")
+        );
+        delta.send(&mut mock, 3, "43").unwrap();
+    }
+}
@@ -0,0 +1,234 @@
+//! Screen design helpers
+//!
+//! A [Screen] describes the widgets an application updates on the glasses.
+//! [estimate] computes how much BLE traffic one update of the screen costs, to check designs
+//! against the link bandwidth before hitting its limits in the field.
+use core::time::Duration;
+
+use deku::DekuError;
+
+use crate::{
+    client::QUERY_ID_LEN,
+    commands::{Command, Grey, HoldFlushAction, Point, TextRotation},
+    locale::Locale,
+    protocol::consts::packet_len,
+    transport::{ATT_HEADER_LEN, DEFAULT_MTU},
+};
+
+/// An element of a [Screen]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Widget {
+    /// Free text drawn with [Command::Txt]
+    Text {
+        pos: Point,
+        rotation: TextRotation,
+        font_size: u8,
+        color: Grey,
+        text: String,
+    },
+    /// Text displayed in a saved layout
+    Layout { id: u8, text: String },
+    /// Saved gauge, displaying a percentage
+    Gauge { id: u8, value: u8 },
+    /// Saved image
+    Image { id: u8, pos: Point },
+}
+
+impl Widget {
+    /// Layout displaying a number formatted according to `locale`
+    pub fn number(id: u8, value: f32, decimals: usize, locale: &Locale) -> Self {
+        Widget::Layout {
+            id,
+            text: locale.format_number(value, decimals),
+        }
+    }
+
+    /// Layout displaying a time of day formatted according to `locale`
+    pub fn time(id: u8, hour: u8, minute: u8, locale: &Locale) -> Self {
+        Widget::Layout {
+            id,
+            text: locale.format_time(hour, minute),
+        }
+    }
+
+    /// Layout displaying a date formatted according to `locale`
+    pub fn date(id: u8, year: u16, month: u8, day: u8, locale: &Locale) -> Self {
+        Widget::Layout {
+            id,
+            text: locale.format_date(year, month, day),
+        }
+    }
+
+    /// Commands needed to draw the widget
+    pub fn commands(&self) -> Vec<Command> {
+        match self {
+            Widget::Text {
+                pos,
+                rotation,
+                font_size,
+                color,
+                text,
+            } => vec![Command::Txt {
+                pos: *pos,
+                rotation: *rotation,
+                font_size: *font_size,
+                color: *color,
+                string: text.clone(),
+            }],
+            Widget::Layout { id, text } => vec![Command::LayoutDisplay {
+                id: *id,
+                text: text.clone(),
+            }],
+            Widget::Gauge { id, value } => vec![Command::GaugeDisplay {
+                id: *id,
+                value: *value,
+            }],
+            Widget::Image { id, pos } => vec![Command::ImgDisplay {
+                id: *id,
+                coord: *pos,
+            }],
+        }
+    }
+}
+
+/// Widgets updated together
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Screen {
+    pub widgets: Vec<Widget>,
+}
+
+impl Screen {
+    pub fn new(widgets: Vec<Widget>) -> Self {
+        Self { widgets }
+    }
+
+    /// Commands sent for one update, wrapped in [Command::HoldFlush] to avoid flickering
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = vec![Command::HoldFlush {
+            action: HoldFlushAction::Hold,
+        }];
+        for widget in &self.widgets {
+            commands.extend(widget.commands());
+        }
+        commands.push(Command::HoldFlush {
+            action: HoldFlushAction::Flush,
+        });
+        commands
+    }
+}
+
+/// Characteristics of the BLE link
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct LinkParameters {
+    /// Negotiated ATT MTU
+    pub mtu: usize,
+    /// BLE connection interval
+    pub connection_interval: Duration,
+    /// Number of writes the stack can send during one connection event
+    pub packets_per_interval: usize,
+}
+
+impl Default for LinkParameters {
+    /// Worst case: default MTU, one write per 20ms connection event
+    fn default() -> Self {
+        Self {
+            mtu: DEFAULT_MTU,
+            connection_interval: Duration::from_millis(20),
+            packets_per_interval: 1,
+        }
+    }
+}
+
+/// Cost of one [Screen] update
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TrafficEstimate {
+    /// Number of commands sent
+    pub commands: usize,
+    /// Bytes sent, including the protocol framing
+    pub bytes_per_update: usize,
+    /// Number of BLE writes
+    pub packets_per_update: usize,
+    /// Time needed to send one update
+    pub update_duration: Duration,
+    /// Maximum update rate sustainable by the link
+    pub max_updates_per_second: f32,
+}
+
+impl TrafficEstimate {
+    /// Returns true if the link can sustain `rate` updates per second
+    pub fn is_feasible(&self, rate: f32) -> bool {
+        rate <= self.max_updates_per_second
+    }
+}
+
+/// Estimate the BLE traffic needed to update `screen` over `link`. Fails if a command of the
+/// screen can not be encoded.
+pub fn estimate(screen: &Screen, link: &LinkParameters) -> Result<TrafficEstimate, DekuError> {
+    let payload = link.mtu.saturating_sub(ATT_HEADER_LEN).max(1);
+    let mut bytes_per_update = 0;
+    let mut packets_per_update = 0;
+    let commands = screen.commands();
+    for cmd in &commands {
+        let len = packet_len(cmd.wire_size()?, QUERY_ID_LEN);
+        bytes_per_update += len;
+        packets_per_update += len.div_ceil(payload);
+    }
+
+    let intervals = packets_per_update.div_ceil(link.packets_per_interval.max(1));
+    let update_duration = link.connection_interval * intervals as u32;
+    let max_updates_per_second = if update_duration.is_zero() {
+        f32::INFINITY
+    } else {
+        1.0 / update_duration.as_secs_f32()
+    };
+
+    Ok(TrafficEstimate {
+        commands: commands.len(),
+        bytes_per_update,
+        packets_per_update,
+        update_duration,
+        max_updates_per_second,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let screen = Screen::new(vec![
+            Widget::Layout {
+                id: 1,
+                text: String::from("42"),
+            },
+            Widget::Gauge { id: 1, value: 50 },
+        ]);
+        let link = LinkParameters::default();
+        let estimate = estimate(&screen, &link).unwrap();
+
+        // Hold + Flush: 10 bytes each, LayoutDisplay: 13 bytes, GaugeDisplay: 11 bytes
+        assert_eq!(4, estimate.commands);
+        assert_eq!(44, estimate.bytes_per_update);
+        assert_eq!(4, estimate.packets_per_update);
+        assert_eq!(Duration::from_millis(80), estimate.update_duration);
+        assert!(estimate.is_feasible(10.0));
+        assert!(!estimate.is_feasible(20.0));
+    }
+
+    #[test]
+    fn test_big_mtu() {
+        let screen = Screen::new(vec![Widget::Layout {
+            id: 1,
+            text: String::from("0123456789"),
+        }]);
+        let link = LinkParameters {
+            mtu: 247,
+            connection_interval: Duration::from_millis(15),
+            packets_per_interval: 4,
+        };
+        let estimate = estimate(&screen, &link).unwrap();
+        assert_eq!(3, estimate.packets_per_update);
+        assert_eq!(Duration::from_millis(15), estimate.update_duration);
+    }
+}
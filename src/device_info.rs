@@ -0,0 +1,136 @@
+//! Typed device information
+//!
+//! [Response::RdDevInfo] returns raw bytes, whose meaning depends on the requested [DeviceInfo].
+//! [DeviceInfoValue::decode] turns them into typed values.
+//!
+//! Text parameters are ASCII, optionally NUL terminated. The firmware version is accepted either
+//! as text (`4.12.1`) or as bytes (`[4, 12, 1, ..]`). Unexpected contents are kept in
+//! [DeviceInfoValue::Raw].
+use crate::{
+    commands::{DeviceInfo, Response},
+    firmware::FirmwareVersion,
+};
+
+/// Orientation of the display
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DisplayOrientation {
+    Normal,
+    /// Rotated by 180°
+    Flipped,
+    /// Value not known by this crate
+    Other(u8),
+}
+
+impl From<u8> for DisplayOrientation {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => DisplayOrientation::Normal,
+            1 => DisplayOrientation::Flipped,
+            other => DisplayOrientation::Other(other),
+        }
+    }
+}
+
+/// Decoded device information parameter
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceInfoValue {
+    /// Manufacturer, model, serial number, certifications, ...
+    Text(String),
+    FirmwareVersion(FirmwareVersion),
+    /// Bluetooth SIG company identifier used in advertising
+    ManufacturerId(u16),
+    DisplayOrientation(DisplayOrientation),
+    /// Parameter which could not be decoded
+    Raw(Vec<u8>),
+}
+
+impl DeviceInfoValue {
+    /// Decode the parameters returned for `id`
+    pub fn decode(id: DeviceInfo, bytes: &[u8]) -> Self {
+        let decoded = match id {
+            DeviceInfo::FWVersion => decode_version(bytes).map(DeviceInfoValue::FirmwareVersion),
+            DeviceInfo::AdvertisingManufacturerID => match bytes {
+                [high, low] => Some(DeviceInfoValue::ManufacturerId(u16::from_be_bytes([
+                    *high, *low,
+                ]))),
+                _ => None,
+            },
+            DeviceInfo::DisplayOrientation => match bytes {
+                [value] => Some(DeviceInfoValue::DisplayOrientation((*value).into())),
+                _ => None,
+            },
+            _ => decode_text(bytes).map(DeviceInfoValue::Text),
+        };
+        decoded.unwrap_or_else(|| DeviceInfoValue::Raw(bytes.to_vec()))
+    }
+
+    /// Decode a [Response::RdDevInfo] answering a request for `id`
+    pub fn from_response(id: DeviceInfo, response: &Response) -> Option<Self> {
+        match response {
+            Response::RdDevInfo { parameters } => Some(Self::decode(id, parameters)),
+            _ => None,
+        }
+    }
+}
+
+/// ASCII text, up to the first NUL
+fn decode_text(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    let text = &bytes[..end];
+    if text.iter().all(|b| b.is_ascii() && !b.is_ascii_control()) {
+        Some(text.iter().map(|b| *b as char).collect())
+    } else {
+        None
+    }
+}
+
+fn decode_version(bytes: &[u8]) -> Option<FirmwareVersion> {
+    if let Some(text) = decode_text(bytes).filter(|text| text.contains('.')) {
+        let mut numbers = text
+            .trim_start_matches(['v', 'V'])
+            .split('.')
+            .map(|n| n.parse::<u8>().ok());
+        return Some(FirmwareVersion::new(
+            numbers.next()??,
+            numbers.next()??,
+            numbers.next().flatten().unwrap_or(0),
+        ));
+    }
+    match bytes {
+        [major, minor, patch, ..] => Some(FirmwareVersion::new(*major, *minor, *patch)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            DeviceInfoValue::Text(String::from("Microoled")),
+            DeviceInfoValue::decode(DeviceInfo::Manufacturer, b"Microoled\0")
+        );
+        assert_eq!(
+            DeviceInfoValue::FirmwareVersion(FirmwareVersion::new(4, 12, 1)),
+            DeviceInfoValue::decode(DeviceInfo::FWVersion, b"4.12.1")
+        );
+        assert_eq!(
+            DeviceInfoValue::FirmwareVersion(FirmwareVersion::new(4, 9, 3)),
+            DeviceInfoValue::decode(DeviceInfo::FWVersion, &[4, 9, 3, b'b'])
+        );
+        assert_eq!(
+            DeviceInfoValue::ManufacturerId(0x08F2),
+            DeviceInfoValue::decode(DeviceInfo::AdvertisingManufacturerID, &[0x08, 0xF2])
+        );
+        assert_eq!(
+            DeviceInfoValue::DisplayOrientation(DisplayOrientation::Flipped),
+            DeviceInfoValue::decode(DeviceInfo::DisplayOrientation, &[1])
+        );
+        assert_eq!(
+            DeviceInfoValue::Raw(vec![0xFF, 0x01]),
+            DeviceInfoValue::decode(DeviceInfo::SerialNumber, &[0xFF, 0x01])
+        );
+    }
+}
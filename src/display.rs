@@ -0,0 +1,410 @@
+//! Display geometry
+//!
+//! ActiveLook glasses draw on a 304 x 256 pixels frame. Drawing outside of it is not an error for
+//! the firmware: the command is accepted and nothing shows up. [Display] describes the visible
+//! frame, and [BoundsChecker] checks every drawing command against it, according to a
+//! [ClipPolicy].
+//!
+//! The API documentation declares signed coordinates for all the drawing commands, without
+//! telling which ones accept negative values. `imgDisplay` draws images partially out of the top
+//! left corner with negative coordinates. The other commands are treated as not drawing anything
+//! with them: [Display::validate] reports [CoordinateError::Negative].
+use log::warn;
+use thiserror::Error;
+
+use crate::{
+    commands::{Command, Point, Response},
+    device_info::{DeviceInfoValue, DisplayOrientation},
+    glasses::{GlassesApi, GlassesError},
+    traits::*,
+};
+
+/// Width of the display, in pixels
+pub const WIDTH: i16 = 304;
+/// Height of the display, in pixels
+pub const HEIGHT: i16 = 256;
+
+/// Rectangle between two corners, included
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Rect {
+    pub from: Point,
+    pub to: Point,
+}
+
+impl Rect {
+    /// Rectangle with corners in any order
+    pub fn new(a: Point, b: Point) -> Self {
+        Self {
+            from: Point {
+                x: a.x.min(b.x),
+                y: a.y.min(b.y),
+            },
+            to: Point {
+                x: a.x.max(b.x),
+                y: a.y.max(b.y),
+            },
+        }
+    }
+
+    /// Square around a circle
+    pub fn around(center: Point, r: i16) -> Self {
+        Self::new(
+            Point {
+                x: center.x.saturating_sub(r),
+                y: center.y.saturating_sub(r),
+            },
+            Point {
+                x: center.x.saturating_add(r),
+                y: center.y.saturating_add(r),
+            },
+        )
+    }
+
+    pub fn contains(&self, p: Point) -> bool {
+        (self.from.x..=self.to.x).contains(&p.x) && (self.from.y..=self.to.y).contains(&p.y)
+    }
+
+    /// Returns true if `other` is entirely inside this rectangle
+    pub fn contains_rect(&self, other: &Rect) -> bool {
+        self.contains(other.from) && self.contains(other.to)
+    }
+
+    /// Part of `other` inside this rectangle, if any
+    pub fn clip(&self, other: &Rect) -> Option<Rect> {
+        let from = Point {
+            x: self.from.x.max(other.from.x),
+            y: self.from.y.max(other.from.y),
+        };
+        let to = Point {
+            x: self.to.x.min(other.to.x),
+            y: self.to.y.min(other.to.y),
+        };
+        (from.x <= to.x && from.y <= to.y).then_some(Rect { from, to })
+    }
+
+    /// Closest point of the rectangle
+    pub fn clamp(&self, p: Point) -> Point {
+        Point {
+            x: p.x.clamp(self.from.x, self.to.x),
+            y: p.y.clamp(self.from.y, self.to.y),
+        }
+    }
+}
+
+/// Coordinates the firmware does not draw
+#[derive(Error, Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CoordinateError {
+    /// The command does not accept negative coordinates, see [Display::accepts_negative]
+    #[error("Command 0x{cmd_id:02X} does not accept the negative coordinates {point:?}")]
+    Negative { cmd_id: u8, point: Point },
+    /// The command draws outside of the display
+    #[error("Command 0x{cmd_id:02X} draws at {rect:?}, outside of the {WIDTH}x{HEIGHT} display")]
+    OffScreen { cmd_id: u8, rect: Rect },
+}
+
+/// What [BoundsChecker] does with commands failing [Display::validate]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum ClipPolicy {
+    /// Log a warning, and send the command
+    #[default]
+    Warn,
+    /// Return [GlassesError::Coordinates] instead of sending the command
+    Error,
+    /// Send the command
+    SilentlyAllow,
+}
+
+/// Geometry of the glasses display
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Display {
+    pub orientation: DisplayOrientation,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self {
+            orientation: DisplayOrientation::Normal,
+        }
+    }
+}
+
+impl Display {
+    /// Display mounted with `orientation`, read with [crate::commands::DeviceInfo::DisplayOrientation]
+    pub fn from_device_info(value: &DeviceInfoValue) -> Option<Self> {
+        match value {
+            DeviceInfoValue::DisplayOrientation(orientation) => Some(Self {
+                orientation: *orientation,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Visible frame
+    pub fn frame(&self) -> Rect {
+        Rect {
+            from: Point { x: 0, y: 0 },
+            to: Point {
+                x: WIDTH - 1,
+                y: HEIGHT - 1,
+            },
+        }
+    }
+
+    pub fn is_visible(&self, p: Point) -> bool {
+        self.frame().contains(p)
+    }
+
+    /// Coordinates of `p` on a [DisplayOrientation::Flipped] display, so the drawing keeps its
+    /// orientation for the user
+    pub fn oriented(&self, p: Point) -> Point {
+        match self.orientation {
+            DisplayOrientation::Flipped => Point {
+                x: WIDTH - 1 - p.x,
+                y: HEIGHT - 1 - p.y,
+            },
+            _ => p,
+        }
+    }
+
+    /// Area drawn by `cmd`, if it draws at known coordinates.
+    /// The extent of texts and images depends on their content, only their origin is returned.
+    pub fn extent(cmd: &Command) -> Option<Rect> {
+        let rect = match cmd {
+            Command::Point { coord } => Rect::new(*coord, *coord),
+            Command::Line { from, to }
+            | Command::Rect { from, to }
+            | Command::RectFull { from, to } => Rect::new(*from, *to),
+            Command::Circ { center, r } | Command::CircFull { center, r } => {
+                Rect::around(*center, *r as i16)
+            }
+            Command::Arc { center, r, .. } => Rect::around(*center, *r as i16),
+            Command::Txt { pos, .. } => Rect::new(*pos, *pos),
+            Command::ImgDisplay { coord, .. } | Command::ImgStream { coord, .. } => {
+                Rect::new(*coord, *coord)
+            }
+            Command::Polyline { points, .. } => {
+                let first = points.first()?;
+                points.iter().fold(Rect::new(*first, *first), |rect, p| {
+                    Rect::new(
+                        Point {
+                            x: rect.from.x.min(p.x),
+                            y: rect.from.y.min(p.y),
+                        },
+                        Point {
+                            x: rect.to.x.max(p.x),
+                            y: rect.to.y.max(p.y),
+                        },
+                    )
+                })
+            }
+            _ => return None,
+        };
+        Some(rect)
+    }
+
+    /// Coordinates given to `cmd`, if it draws at known coordinates
+    pub fn coordinates(cmd: &Command) -> Vec<Point> {
+        match cmd {
+            Command::Point { coord }
+            | Command::ImgDisplay { coord, .. }
+            | Command::ImgStream { coord, .. } => vec![*coord],
+            Command::Line { from, to }
+            | Command::Rect { from, to }
+            | Command::RectFull { from, to } => vec![*from, *to],
+            Command::Circ { center, .. }
+            | Command::CircFull { center, .. }
+            | Command::Arc { center, .. } => vec![*center],
+            Command::Txt { pos, .. } | Command::AnimDisplay { pos, .. } => vec![*pos],
+            Command::Polyline { points, .. } => points.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns true if the firmware draws `cmd` with negative coordinates
+    pub fn accepts_negative(cmd: &Command) -> bool {
+        matches!(cmd, Command::ImgDisplay { .. })
+    }
+
+    /// Check that `cmd` draws inside the frame, with coordinates it accepts.
+    /// Images are only known by their origin: they must start before the bottom right corner.
+    pub fn validate(&self, cmd: &Command) -> Result<(), CoordinateError> {
+        let cmd_id = cmd.id().unwrap_or_default();
+        if !Self::accepts_negative(cmd) {
+            if let Some(point) = Self::coordinates(cmd)
+                .into_iter()
+                .find(|p| p.x < 0 || p.y < 0)
+            {
+                return Err(CoordinateError::Negative { cmd_id, point });
+            }
+        }
+        let Some(rect) = Self::extent(cmd) else {
+            return Ok(());
+        };
+        let frame = self.frame();
+        let visible = match Self::accepts_negative(cmd) {
+            true => rect.to.x <= frame.to.x && rect.to.y <= frame.to.y,
+            false => frame.contains_rect(&rect),
+        };
+        match visible {
+            true => Ok(()),
+            false => Err(CoordinateError::OffScreen { cmd_id, rect }),
+        }
+    }
+
+    /// Returns true if `cmd` draws entirely inside the frame, as far as [Display::extent] knows
+    pub fn is_on_screen(&self, cmd: &Command) -> bool {
+        Self::extent(cmd).is_none_or(|rect| self.frame().contains_rect(&rect))
+    }
+}
+
+/// [GlassesApi] checking the coordinates of drawing commands with [Display::validate].
+/// By default it only warns, and sends the commands unchanged: see [BoundsChecker::policy].
+pub struct BoundsChecker<G: GlassesApi> {
+    glasses: G,
+    display: Display,
+    policy: ClipPolicy,
+}
+
+impl<G: GlassesApi> BoundsChecker<G> {
+    pub fn new(glasses: G, display: Display) -> Self {
+        Self {
+            glasses,
+            display,
+            policy: ClipPolicy::default(),
+        }
+    }
+
+    /// Handle invalid commands according to `policy`
+    pub fn policy(mut self, policy: ClipPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn into_inner(self) -> G {
+        self.glasses
+    }
+
+    fn check(&self, cmd: &Command) -> Result<(), GlassesError> {
+        match (self.display.validate(cmd), self.policy) {
+            (Err(error), ClipPolicy::Error) => Err(GlassesError::Coordinates(error)),
+            (Err(error), ClipPolicy::Warn) => {
+                warn!("{}", error);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<G: GlassesApi> GlassesApi for BoundsChecker<G> {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.check(cmd)?;
+        self.glasses.send(cmd)
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.glasses.query(cmd)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.check(cmd)?;
+        self.glasses.send_chunked(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{Grey, TextRotation},
+        glasses::Preview,
+        mock::MockClient,
+    };
+
+    fn p(x: i16, y: i16) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn test_rect() {
+        let rect = Rect::new(p(10, 20), p(0, 0));
+        assert_eq!(p(0, 0), rect.from);
+        assert!(rect.contains(p(10, 20)));
+        assert!(!rect.contains(p(11, 20)));
+        assert_eq!(
+            Some(Rect::new(p(5, 5), p(10, 20))),
+            rect.clip(&Rect::new(p(5, 5), p(50, 50)))
+        );
+        assert_eq!(None, rect.clip(&Rect::new(p(11, 0), p(50, 50))));
+        assert_eq!(p(10, 0), rect.clamp(p(30, -4)));
+    }
+
+    #[test]
+    fn test_display() {
+        let display = Display::default();
+        assert!(display.is_visible(p(303, 255)));
+        assert!(!display.is_visible(p(304, 0)));
+        assert!(display.is_on_screen(&Command::Clear));
+        assert!(display.is_on_screen(&Command::CircFull {
+            center: p(150, 100),
+            r: 50
+        }));
+        assert!(!display.is_on_screen(&Command::Circ {
+            center: p(10, 100),
+            r: 50
+        }));
+        assert!(!display.is_on_screen(&Command::Polyline {
+            thickness: 1,
+            _reserved: 0,
+            points: vec![p(0, 0), p(100, 300)],
+        }));
+
+        let flipped = Display::from_device_info(&DeviceInfoValue::DisplayOrientation(
+            DisplayOrientation::Flipped,
+        ))
+        .unwrap();
+        assert_eq!(p(303, 255), flipped.oriented(p(0, 0)));
+        assert_eq!(p(0, 0), display.oriented(p(0, 0)));
+    }
+
+    #[test]
+    fn test_bounds_checker() {
+        let mut glasses = BoundsChecker::new(Preview::new(), Display::default());
+        // Off-screen commands are still sent
+        glasses
+            .send(&Command::Line {
+                from: p(0, 0),
+                to: p(400, 0),
+            })
+            .unwrap();
+        assert_eq!(1, glasses.into_inner().displayed().len());
+
+        let mut mock = MockClient::new();
+        let image = Command::ImgDisplay {
+            id: 1,
+            coord: p(-20, -5),
+        };
+        mock.expect(image.clone());
+        let mut glasses = BoundsChecker::new(mock, Display::default()).policy(ClipPolicy::Error);
+        // Images can start out of the top left corner
+        glasses.send(&image).unwrap();
+        assert_eq!(
+            Err(GlassesError::Coordinates(CoordinateError::Negative {
+                cmd_id: 0x37,
+                point: p(-20, 10),
+            })),
+            glasses.text(p(-20, 10), TextRotation::TOP_LR, 1, Grey::WHITE, "x")
+        );
+        assert_eq!(
+            Err(GlassesError::Coordinates(CoordinateError::OffScreen {
+                cmd_id: 0x42,
+                rect: Rect::new(p(310, 0), p(310, 0)),
+            })),
+            glasses.send(&Command::ImgDisplay {
+                id: 1,
+                coord: p(310, 0),
+            })
+        );
+        glasses.into_inner().verify();
+    }
+}
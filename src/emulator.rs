@@ -0,0 +1,1254 @@
+//! ActiveLook glasses emulator
+//!
+//! [Emulator] keeps the resources of emulated glasses in memory: configurations, with their
+//! images, fonts, layouts, gauges, pages and animations. It answers each [Command] with the
+//! [Response] real glasses would send.
+//!
+//! Combined with [crate::server::ActiveLookServer::serve], host applications can be
+//! integration-tested without hardware.
+//!
+//! Drawing commands are rendered into a [Framebuffer], see [Emulator::framebuffer]. Layouts,
+//! gauges, pages and animations are not rendered.
+//!
+//! Time-dependent behaviours (animation playback, battery drain, flow control) follow the
+//! emulator [VirtualClock], so tests can advance time instantly instead of sleeping.
+//!
+//! Commands are validated like the firmware does before they are applied, see
+//! [Emulator::validate]: an application passing emulator tests should not fail on real glasses.
+use core::time::Duration;
+use std::collections::{BTreeMap, VecDeque};
+
+use log::*;
+
+use crate::{
+    commands::{
+        CfgItem, ChunkPolicy, CmdError, Command, DefaultFont, DeviceInfo, FontItem,
+        GaugeParameters, Grey, ImgFormat, ImgListItem, LayoutParameters, Luma, Point, Response,
+        Selector, StreamImgFormat, ALL,
+    },
+    firmware::FirmwareVersion,
+    framebuffer::Framebuffer,
+    protocol::{
+        consts::{CTRL_CLIENT_CAN_SEND, CTRL_CLIENT_SHOULD_WAIT, CTRL_MESSAGE_QUEUE_OVERFLOW},
+        Packet, RawPacket, ResponsePacket,
+    },
+    redact::Redacted,
+    time::{Clock, VirtualClock},
+    traits::*,
+};
+
+/// Used when no configuration is selected
+static NO_CONFIG: EmulatedConfig = EmulatedConfig {
+    name: String::new(),
+    version: 0,
+    password: 0,
+    usage_counter: 0,
+    install_counter: 0,
+    is_system: false,
+    images: BTreeMap::new(),
+    fonts: BTreeMap::new(),
+    layouts: BTreeMap::new(),
+    gauges: BTreeMap::new(),
+    pages: BTreeMap::new(),
+    animations: BTreeMap::new(),
+};
+
+/// An image stored in the emulator
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoredImage {
+    pub width: u16,
+    pub height: u16,
+    pub format: ImgFormat,
+    pub data: Vec<u8>,
+}
+
+/// Animation parameters, as saved by [Command::AnimSave]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StoredAnimation {
+    pub total_size: u32,
+    pub img_size: u32,
+}
+
+impl StoredAnimation {
+    /// Number of frames, assuming every frame has the size of the reference frame.
+    /// Real animations store compressed differences between frames, so they have more.
+    pub fn frames(&self) -> u32 {
+        self.total_size.div_ceil(self.img_size.max(1)).max(1)
+    }
+}
+
+/// A configuration and its elements
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EmulatedConfig {
+    pub name: String,
+    pub version: u32,
+    pub password: u32,
+    pub usage_counter: u8,
+    pub install_counter: u8,
+    /// System configurations can not be deleted
+    pub is_system: bool,
+    pub images: BTreeMap<u8, StoredImage>,
+    /// Font data, by ID
+    pub fonts: BTreeMap<u8, Vec<u8>>,
+    pub layouts: BTreeMap<u8, LayoutParameters>,
+    pub gauges: BTreeMap<u8, GaugeParameters>,
+    /// Layouts of each page
+    pub pages: BTreeMap<u8, Vec<u8>>,
+    pub animations: BTreeMap<u8, StoredAnimation>,
+}
+
+impl EmulatedConfig {
+    pub fn new(name: &str, version: u32, password: u32) -> Self {
+        Self {
+            name: String::from(name),
+            version,
+            password,
+            ..Default::default()
+        }
+    }
+
+    /// Memory used by the elements
+    pub fn size(&self) -> u32 {
+        let images: usize = self.images.values().map(|img| img.data.len()).sum();
+        let fonts: usize = self.fonts.values().map(|font| font.len()).sum();
+        let animations: u32 = self.animations.values().map(|anim| anim.total_size).sum();
+        let layouts: usize = self.layouts.values().map(serialized_len).sum();
+        let gauges: usize = self.gauges.values().map(serialized_len).sum();
+        let pages: usize = self.pages.values().map(Vec::len).sum();
+        (images + fonts + layouts + gauges + pages) as u32 + animations
+    }
+
+    /// Memory used by the element saved by `cmd` and by the element it replaces, for save
+    /// commands
+    fn save_sizes(&self, cmd: &Command) -> Option<(u32, u32)> {
+        let (new, old) = match cmd {
+            Command::ImgSave { id, data, .. } => (
+                data.len(),
+                self.images.get(id).map_or(0, |img| img.data.len()),
+            ),
+            Command::FontSave { id, data, .. } => {
+                (data.len(), self.fonts.get(id).map_or(0, Vec::len))
+            }
+            Command::AnimSave { id, total_size, .. } => {
+                let old = self.animations.get(id).map_or(0, |anim| anim.total_size);
+                return Some((*total_size, old));
+            }
+            Command::LayoutSave { id, params } => (
+                serialized_len(params),
+                self.layouts.get(id).map_or(0, serialized_len),
+            ),
+            Command::GaugeSave { id, params } => (
+                serialized_len(params),
+                self.gauges.get(id).map_or(0, serialized_len),
+            ),
+            Command::PageSave { id, layouts } => {
+                (layouts.len(), self.pages.get(id).map_or(0, Vec::len))
+            }
+            _ => return None,
+        };
+        Some((new as u32, old as u32))
+    }
+}
+
+/// Bytes of a saved element
+fn serialized_len<T: deku::DekuContainerWrite>(element: &T) -> usize {
+    element.to_bytes().map_or(0, |bytes| bytes.len())
+}
+
+/// Upload split across multiple packets
+#[derive(Debug)]
+struct Upload {
+    cmd_id: u8,
+    /// Total data bytes expected, after the header
+    size: usize,
+    bytes: Vec<u8>,
+    header_len: usize,
+}
+
+/// Emulation of the glasses receive buffer
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FlowControlSettings {
+    /// Size of the receive buffer in bytes. Packets overflowing it are dropped.
+    pub buffer_size: usize,
+    /// Bytes processed per second
+    pub rate: u32,
+}
+
+/// Animation being played
+#[derive(Copy, Clone, Debug)]
+struct Playback {
+    /// `None` for infinite repetition
+    end: Option<Duration>,
+}
+
+/// In-memory emulation of ActiveLook glasses
+#[derive(Debug)]
+pub struct Emulator {
+    pub version: FirmwareVersion,
+    pub serial_number: [u8; 3],
+    pub model: String,
+    /// Battery level in %
+    pub battery: u8,
+    /// Memory available for configurations, in bytes
+    pub memory_size: u32,
+    pub display_on: bool,
+    pub luma: Luma,
+    pub shift: Point,
+    pub als: bool,
+    pub gesture: bool,
+    /// Color of the next drawings, set by [Command::Color]
+    pub color: Grey,
+    /// Time to lose 1% of battery while the display is on
+    pub battery_drain: Option<Duration>,
+    /// Emulate flow control, see [Emulator::take_ctrl]
+    pub flow_control: Option<FlowControlSettings>,
+    clock: VirtualClock,
+    last_update: Duration,
+    /// Display time not yet converted into battery drain
+    drain_elapsed: Duration,
+    /// Animations being played, by handler ID
+    playing: BTreeMap<u8, Playback>,
+    /// Bytes waiting in the receive buffer
+    rx_fill: f64,
+    /// [CTRL_CLIENT_SHOULD_WAIT] was sent
+    on_hold: bool,
+    /// Control values to notify
+    ctrl: VecDeque<u8>,
+    configs: Vec<EmulatedConfig>,
+    /// Configuration used for display commands
+    current: Option<usize>,
+    /// Configuration modified by save and delete commands, set by [Command::CfgWrite]
+    writing: Option<usize>,
+    upload: Option<Upload>,
+    framebuffer: Framebuffer,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    /// Emulated memory size. Arbitrary value, large enough for tests.
+    pub const DEFAULT_MEMORY_SIZE: u32 = 1024 * 1024;
+
+    pub fn new() -> Self {
+        Self {
+            version: FirmwareVersion::new(4, 12, 0),
+            serial_number: [0; 3],
+            model: String::from("emulator"),
+            battery: 100,
+            memory_size: Self::DEFAULT_MEMORY_SIZE,
+            display_on: true,
+            luma: Luma::MAX,
+            shift: Point { x: 0, y: 0 },
+            als: true,
+            gesture: true,
+            color: Grey::WHITE,
+            battery_drain: None,
+            flow_control: None,
+            clock: VirtualClock::new(),
+            last_update: Duration::ZERO,
+            drain_elapsed: Duration::ZERO,
+            playing: BTreeMap::new(),
+            rx_fill: 0.0,
+            on_hold: false,
+            ctrl: VecDeque::new(),
+            configs: Vec::new(),
+            current: None,
+            writing: None,
+            upload: None,
+            framebuffer: Framebuffer::new(),
+        }
+    }
+
+    /// Use `clock`, shared with the test, instead of a private one
+    pub fn with_clock(mut self, clock: VirtualClock) -> Self {
+        self.last_update = clock.now();
+        self.clock = clock;
+        self
+    }
+
+    /// Clock driving the time-dependent behaviours
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Apply the time elapsed since the last update: battery drain, end of animations and
+    /// processing of the receive buffer. Called on each command.
+    pub fn update(&mut self) {
+        let now = self.clock.now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        if let Some(period) = self.battery_drain.filter(|period| !period.is_zero()) {
+            if self.display_on {
+                self.drain_elapsed += elapsed;
+            }
+            let drops = self.drain_elapsed.as_micros() / period.as_micros();
+            self.battery = self.battery.saturating_sub(drops.min(100) as u8);
+            self.drain_elapsed -= period * drops as u32;
+        }
+
+        self.playing
+            .retain(|_, playback| playback.end.is_none_or(|end| end > now));
+
+        if let Some(settings) = self.flow_control {
+            let processed = elapsed.as_secs_f64() * settings.rate as f64;
+            self.rx_fill = (self.rx_fill - processed).max(0.0);
+            if self.on_hold && self.rx_fill <= settings.buffer_size as f64 / 2.0 {
+                self.on_hold = false;
+                self.ctrl.push_back(CTRL_CLIENT_CAN_SEND);
+            }
+        }
+    }
+
+    /// Next control value the glasses would notify, see [crate::protocol::consts]
+    pub fn take_ctrl(&mut self) -> Option<u8> {
+        self.update();
+        self.ctrl.pop_front()
+    }
+
+    /// Handler IDs of the animations being played
+    pub fn playing_animations(&mut self) -> Vec<u8> {
+        self.update();
+        self.playing.keys().copied().collect()
+    }
+
+    /// Account for `len` received bytes. Returns false if the receive buffer overflows.
+    fn receive(&mut self, len: usize) -> bool {
+        let Some(settings) = self.flow_control else {
+            return true;
+        };
+        if self.rx_fill + len as f64 > settings.buffer_size as f64 {
+            self.ctrl.push_back(CTRL_MESSAGE_QUEUE_OVERFLOW);
+            return false;
+        }
+        self.rx_fill += len as f64;
+        if !self.on_hold && self.rx_fill > settings.buffer_size as f64 / 2.0 {
+            self.on_hold = true;
+            self.ctrl.push_back(CTRL_CLIENT_SHOULD_WAIT);
+        }
+        true
+    }
+
+    /// Display memory, updated by the drawing commands
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    /// Configurations stored in memory
+    pub fn configs(&self) -> &[EmulatedConfig] {
+        &self.configs
+    }
+
+    /// Configuration used for display commands
+    pub fn current_config(&self) -> Option<&EmulatedConfig> {
+        self.current.map(|index| &self.configs[index])
+    }
+
+    /// Add a configuration, like the system configuration of real glasses
+    pub fn add_config(&mut self, config: EmulatedConfig) {
+        self.configs.push(config);
+    }
+
+    /// Handle a received packet, and build the response packet if any.
+    ///
+    /// The commands with a [ChunkPolicy], such as [Command::ImgSave] and [Command::AnimSave], can
+    /// be split across multiple packets: the first one only contains the header, the following
+    /// ones the data.
+    pub fn handle_packet(&mut self, packet: &RawPacket) -> Option<ResponsePacket> {
+        let cmd_id = packet.cmd_id();
+        let data = packet.data.unwrap_or(&[]);
+        self.update();
+        if !self.receive(data.len()) {
+            warn!("Receive buffer overflow, dropping command 0x{:02X}", cmd_id);
+            return None;
+        }
+        let cmd = match self.receive_chunk(cmd_id, data) {
+            Some(bytes) => Command::from_data(cmd_id, Some(&bytes)),
+            None if self.upload.is_some() => return None,
+            None => Command::from_data(cmd_id, packet.data),
+        };
+        let response = match cmd {
+            Ok(cmd) => self.handle(&cmd)?,
+            Err(error) => {
+                warn!("Invalid command 0x{:02X}: {}", cmd_id, error);
+                return None;
+            }
+        };
+        let packet = match &packet.query_id {
+            Some(query_id) => Packet::new_with_query_id(&response, query_id),
+            None => Packet::new(&response),
+        };
+        packet
+            .map_err(|error| warn!("Invalid response to 0x{:02X}: {}", cmd_id, error))
+            .ok()
+    }
+
+    /// Accumulate chunked uploads. Returns the whole command data once complete.
+    fn receive_chunk(&mut self, cmd_id: u8, data: &[u8]) -> Option<Vec<u8>> {
+        if let Some(upload) = &mut self.upload {
+            if upload.cmd_id == cmd_id {
+                upload.bytes.extend_from_slice(data);
+                if upload.bytes.len() < upload.header_len + upload.size {
+                    return None;
+                }
+                return self.upload.take().map(|upload| upload.bytes);
+            }
+            warn!("Upload of command 0x{:02X} interrupted", upload.cmd_id);
+            self.upload = None;
+        }
+
+        let size = ChunkPolicy::for_id(cmd_id)?.data_size(data)?;
+        if size > 0 {
+            self.upload = Some(Upload {
+                cmd_id,
+                size,
+                bytes: data.to_vec(),
+                header_len: data.len(),
+            });
+        }
+        None
+    }
+
+    /// Apply a command, and return the response the glasses would send
+    pub fn handle(&mut self, cmd: &Command) -> Option<Response> {
+        let cmd_id = cmd.id().ok()?;
+        trace!("Handling {:?}", Redacted(cmd));
+        self.update();
+        match self.apply(cmd) {
+            Ok(response) => response,
+            Err(error) => {
+                debug!("Command 0x{:02X} failed: {:?}", cmd_id, error);
+                Some(Response::CmdError {
+                    cmd_id,
+                    error,
+                    sub_error: 0,
+                })
+            }
+        }
+    }
+
+    /// Reject the commands the firmware refuses:
+    /// - grey levels and luminance above 15,
+    /// - display of an image which is not saved,
+    /// - save commands exceeding the free memory, with [CmdError::MemoryAccess],
+    /// - save and delete commands without [Command::CfgWrite] are rejected when applied, with
+    ///   [CmdError::MissingCfgWrite].
+    ///
+    /// The API documentation does not give the error of each case, nor the meaning of the sub
+    /// errors. Invalid parameters are reported as [CmdError::ProtocolDecoding], missing elements
+    /// as [CmdError::Generic], and the sub error is always 0.
+    pub fn validate(&self, cmd: &Command) -> Result<(), CmdError> {
+        match cmd {
+            Command::Grey { lvl: level }
+            | Command::Color { color: level }
+            | Command::Txt { color: level, .. }
+                if *level > Grey::WHITE =>
+            {
+                return Err(CmdError::ProtocolDecoding);
+            }
+            Command::Luma { level } if *level > Luma::MAX => {
+                return Err(CmdError::ProtocolDecoding);
+            }
+            Command::ImgDisplay { id, .. } if !self.current().images.contains_key(id) => {
+                return Err(CmdError::Generic);
+            }
+            _ => {}
+        }
+        let sizes = self
+            .writing
+            .and_then(|index| self.configs[index].save_sizes(cmd));
+        if let Some((new, old)) = sizes {
+            let used: u32 = self.configs.iter().map(EmulatedConfig::size).sum();
+            if (used - old).saturating_add(new) > self.memory_size {
+                return Err(CmdError::MemoryAccess);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, cmd: &Command) -> Result<Option<Response>, CmdError> {
+        self.validate(cmd)?;
+        let response = match cmd {
+            // --- General commands --
+            Command::PowerDisplay { en } => {
+                self.display_on = *en;
+                None
+            }
+            Command::Battery => Some(Response::Battery {
+                level: self.battery,
+            }),
+            Command::Version => Some(Response::Version {
+                fw_version: [
+                    self.version.major,
+                    self.version.minor,
+                    self.version.patch,
+                    0,
+                ],
+                mfc_year: 0,
+                mfc_week: 0,
+                serial_number: self.serial_number,
+            }),
+            Command::Shift { shift } => {
+                self.shift = Point {
+                    x: shift.x,
+                    y: shift.y,
+                };
+                None
+            }
+            Command::Settings => Some(Response::Settings {
+                x: self.shift.x as i8,
+                y: self.shift.y as i8,
+                luma: self.luma,
+                als_enable: self.als,
+                gesture_enable: self.gesture,
+            }),
+            Command::Luma { level } => {
+                self.luma = *level;
+                None
+            }
+            Command::Sensor { en } => {
+                self.als = *en;
+                self.gesture = *en;
+                None
+            }
+            Command::Gesture { en } => {
+                self.gesture = *en;
+                None
+            }
+            Command::Als { en } => {
+                self.als = *en;
+                None
+            }
+
+            // --- Images ---
+            Command::ImgSave {
+                id,
+                width,
+                format,
+                data,
+                ..
+            } => {
+                let line = format.nb_of_bytes(*width as usize).max(1);
+                let image = StoredImage {
+                    width: *width,
+                    height: (data.len() / line) as u16,
+                    format: *format,
+                    data: data.clone(),
+                };
+                self.writing()?.images.insert(*id, image);
+                None
+            }
+            Command::ImgDelete { id } => {
+                delete(&mut self.writing()?.images, *id);
+                None
+            }
+            Command::ImgList => Some(Response::ImgList {
+                list: self
+                    .current()
+                    .images
+                    .iter()
+                    .map(|(id, img)| ImgListItem {
+                        id: *id,
+                        height: img.height,
+                        width: img.width,
+                    })
+                    .collect(),
+            }),
+
+            // --- Fonts ---
+            Command::FontList => {
+                let mut list: Vec<FontItem> = [
+                    (DefaultFont::Default24, 24),
+                    (DefaultFont::ComputerModernSansSerif24, 24),
+                    (DefaultFont::ComputerModernSansSerif35, 35),
+                    (DefaultFont::ComputerModernSansSerif49, 49),
+                ]
+                .into_iter()
+                .map(|(font, height)| FontItem {
+                    id: font.into(),
+                    height,
+                })
+                .collect();
+                list.extend(self.current().fonts.iter().map(|(id, data)| FontItem {
+                    id: *id,
+                    // Second byte of the font header
+                    height: data.get(1).copied().unwrap_or(0),
+                }));
+                Some(Response::FontList { list })
+            }
+            Command::FontSave { id, data, .. } => {
+                self.writing()?.fonts.insert(*id, data.clone());
+                None
+            }
+            Command::FontDelete { id } => {
+                delete(&mut self.writing()?.fonts, *id);
+                None
+            }
+
+            // --- Layouts ---
+            Command::LayoutSave { id, params } => {
+                self.writing()?.layouts.insert(*id, params.clone());
+                None
+            }
+            Command::LayoutDelete { id } => {
+                delete(&mut self.writing()?.layouts, *id);
+                None
+            }
+            Command::LayoutList => Some(Response::LayoutList {
+                list: self.current().layouts.keys().copied().collect(),
+            }),
+            Command::LayoutGet { id } => Some(Response::LayoutGet {
+                params: self
+                    .current()
+                    .layouts
+                    .get(id)
+                    .cloned()
+                    .ok_or(CmdError::Generic)?,
+            }),
+
+            // --- Gauges ---
+            Command::GaugeSave { id, params } => {
+                self.writing()?.gauges.insert(*id, *params);
+                None
+            }
+            Command::GaugeDelete { id } => {
+                delete(&mut self.writing()?.gauges, *id);
+                None
+            }
+            Command::GaugeList => Some(Response::GaugeList {
+                list: self.current().gauges.keys().copied().collect(),
+            }),
+            Command::GaugeGet { id } => Some(Response::GaugeGet {
+                params: *self.current().gauges.get(id).ok_or(CmdError::Generic)?,
+            }),
+
+            // --- Pages ---
+            Command::PageSave { id, layouts } => {
+                self.writing()?.pages.insert(*id, layouts.clone());
+                None
+            }
+            Command::PageDelete { id } => {
+                delete(&mut self.writing()?.pages, *id);
+                None
+            }
+            Command::PageGet { id } => Some(Response::PageGet {
+                id: *id,
+                layouts: self
+                    .current()
+                    .pages
+                    .get(id)
+                    .ok_or(CmdError::Generic)?
+                    .clone(),
+            }),
+            Command::PageList => Some(Response::PageList {
+                list: self.current().pages.keys().copied().collect(),
+            }),
+
+            // --- Animations ---
+            Command::AnimSave {
+                id,
+                total_size,
+                img_size,
+                ..
+            } => {
+                let anim = StoredAnimation {
+                    total_size: *total_size,
+                    img_size: *img_size,
+                };
+                self.writing()?.animations.insert(*id, anim);
+                None
+            }
+            Command::AnimDisplay {
+                handler_id,
+                id,
+                delay,
+                repeat,
+                ..
+            } => {
+                let anim = *self.current().animations.get(id).ok_or(CmdError::Generic)?;
+                // Played once, then repeated `repeat` times
+                let end = (*repeat != ALL).then(|| {
+                    let frames = anim.frames() * (*repeat as u32 + 1);
+                    self.clock.now() + Duration::from_millis(*delay as u64) * frames
+                });
+                self.playing.insert(*handler_id, Playback { end });
+                None
+            }
+            Command::AnimClear { handler_id } => {
+                delete(&mut self.playing, *handler_id);
+                None
+            }
+            Command::AnimDelete { id } => {
+                delete(&mut self.writing()?.animations, *id);
+                None
+            }
+            Command::AnimList => Some(Response::AnimList {
+                list: self.current().animations.keys().copied().collect(),
+            }),
+
+            // --- Statistics ---
+            Command::PixelCount => Some(Response::PixelCount { count: 0 }),
+
+            // --- Configurations ---
+            Command::CfgWrite {
+                name,
+                version,
+                password,
+            } => {
+                let index = match self.find_config(name) {
+                    Some(index) => {
+                        let config = &mut self.configs[index];
+                        if config.password != *password {
+                            return Err(CmdError::Generic);
+                        }
+                        config.version = *version;
+                        index
+                    }
+                    None => {
+                        let install_counter = self
+                            .configs
+                            .iter()
+                            .map(|config| config.install_counter)
+                            .max()
+                            .map_or(0, |counter| counter.saturating_add(1));
+                        let mut config = EmulatedConfig::new(name, *version, *password);
+                        config.install_counter = install_counter;
+                        self.configs.push(config);
+                        self.configs.len() - 1
+                    }
+                };
+                self.writing = Some(index);
+                self.current = Some(index);
+                None
+            }
+            Command::CfgRead { name } => {
+                let config = &self.configs[self.find_config(name).ok_or(CmdError::Generic)?];
+                Some(Response::CfgRead {
+                    version: config.version,
+                    nb_img: config.images.len() as u8,
+                    nb_layout: config.layouts.len() as u8,
+                    nb_font: config.fonts.len() as u8,
+                    nb_page: config.pages.len() as u8,
+                    nb_gauge: config.gauges.len() as u8,
+                })
+            }
+            Command::CfgSet { name } => {
+                let index = self.find_config(name).ok_or(CmdError::Generic)?;
+                let config = &mut self.configs[index];
+                config.usage_counter = config.usage_counter.saturating_add(1);
+                self.current = Some(index);
+                None
+            }
+            Command::CfgList => Some(Response::CfgList {
+                list: self
+                    .configs
+                    .iter()
+                    .map(|config| CfgItem {
+                        name: config.name.clone(),
+                        size: config.size(),
+                        version: config.version,
+                        usage_counter: config.usage_counter,
+                        install_counter: config.install_counter,
+                        is_system: config.is_system,
+                    })
+                    .collect(),
+            }),
+            Command::CfgRename { old, new, password } => {
+                let index = self.find_config(old).ok_or(CmdError::Generic)?;
+                let config = &mut self.configs[index];
+                if config.password != *password {
+                    return Err(CmdError::Generic);
+                }
+                config.name = new.clone();
+                None
+            }
+            Command::CfgDelete { name } => {
+                let index = self.find_config(name).ok_or(CmdError::Generic)?;
+                if self.configs[index].is_system {
+                    return Err(CmdError::Generic);
+                }
+                self.remove_config(index);
+                None
+            }
+            Command::CfgDeleteLessUsed => {
+                let index = self
+                    .configs
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, config)| !config.is_system)
+                    .min_by_key(|(_, config)| config.usage_counter)
+                    .map(|(index, _)| index)
+                    .ok_or(CmdError::Generic)?;
+                self.remove_config(index);
+                None
+            }
+            Command::CfgFreeSpace => {
+                let used: u32 = self.configs.iter().map(EmulatedConfig::size).sum();
+                Some(Response::CfgFreeSpace {
+                    total_size: self.memory_size,
+                    free_space: self.memory_size.saturating_sub(used),
+                })
+            }
+            Command::CfgGetNb => Some(Response::CfgGetNb {
+                nb_config: self.configs.len() as u8,
+            }),
+
+            // --- Device commands ---
+            Command::Info { id } => Some(Response::RdDevInfo {
+                parameters: match id {
+                    DeviceInfo::Model => self.model.clone().into_bytes(),
+                    DeviceInfo::FWVersion => self.version.to_string().into_bytes(),
+                    _ => Vec::new(),
+                },
+            }),
+
+            _ => {
+                self.draw(cmd);
+                None
+            }
+        };
+        Ok(response)
+    }
+
+    /// Render a drawing command into the framebuffer
+    fn draw(&mut self, cmd: &Command) {
+        let shift = self.shift;
+        let at = |p: &Point| Point {
+            x: p.x.saturating_add(shift.x),
+            y: p.y.saturating_add(shift.y),
+        };
+        let color = self.color;
+        let fb = &mut self.framebuffer;
+        match cmd {
+            Command::Clear => fb.fill(Grey::BLACK),
+            Command::Grey { lvl } => fb.fill(*lvl),
+            Command::Color { color } => self.color = *color,
+            Command::Point { coord } => {
+                let coord = at(coord);
+                fb.set(coord.x, coord.y, color);
+            }
+            Command::Line { from, to } => fb.line(at(from), at(to), color),
+            Command::Rect { from, to } => fb.rect(at(from), at(to), color),
+            Command::RectFull { from, to } => fb.rect_full(at(from), at(to), color),
+            Command::Circ { center, r } => fb.circle(at(center), *r, color),
+            Command::CircFull { center, r } => fb.circle_full(at(center), *r, color),
+            Command::Arc {
+                center,
+                r,
+                angle_start,
+                angle_end,
+                thickness,
+            } => fb.arc(at(center), *r, *angle_start, *angle_end, *thickness, color),
+            Command::Polyline { points, .. } => {
+                for pair in points.windows(2) {
+                    fb.line(at(&pair[0]), at(&pair[1]), color);
+                }
+            }
+            Command::Txt {
+                pos,
+                font_size,
+                color,
+                string,
+                ..
+            } => fb.text(at(pos), *font_size, string, *color),
+            Command::ImgDisplay { id, coord } => {
+                let coord = at(coord);
+                let config = self.current.map(|index| &self.configs[index]);
+                match config.and_then(|config| config.images.get(id)) {
+                    Some(image) => {
+                        if !fb.image(coord, image.width, image.format, &image.data) {
+                            debug!("Image {} in format {:?} not rendered", id, image.format);
+                        }
+                    }
+                    None => debug!("Image {} not found", id),
+                }
+            }
+            Command::ImgStream {
+                width,
+                coord,
+                format: StreamImgFormat::Img1bpp,
+                data,
+                ..
+            } => {
+                fb.image(at(coord), *width, ImgFormat::Img1bpp, data);
+            }
+            _ => (),
+        }
+    }
+
+    fn find_config(&self, name: &str) -> Option<usize> {
+        self.configs.iter().position(|config| config.name == name)
+    }
+
+    fn remove_config(&mut self, index: usize) {
+        self.configs.remove(index);
+        let fix = |selected: Option<usize>| match selected {
+            Some(i) if i == index => None,
+            Some(i) if i > index => Some(i - 1),
+            other => other,
+        };
+        self.current = fix(self.current);
+        self.writing = fix(self.writing);
+    }
+
+    /// Configuration modified by save commands
+    fn writing(&mut self) -> Result<&mut EmulatedConfig, CmdError> {
+        match self.writing {
+            Some(index) => Ok(&mut self.configs[index]),
+            None => Err(CmdError::MissingCfgWrite),
+        }
+    }
+
+    /// Configuration used for display and list commands
+    fn current(&self) -> &EmulatedConfig {
+        self.current_config().unwrap_or(&NO_CONFIG)
+    }
+}
+
+/// Delete the elements selected by `id`
+fn delete<T>(elements: &mut BTreeMap<u8, T>, id: Selector) {
+    match id {
+        Selector::All => elements.clear(),
+        Selector::One(id) => {
+            elements.remove(&id.value());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PACKET_DATA_MAX_SIZE;
+
+    /// Send `cmd` through the emulator, in chunks like a real client
+    fn send(emulator: &mut Emulator, cmd: &Command) -> Vec<Response> {
+        let (id, chunks) = cmd.as_bytes_chunks(PACKET_DATA_MAX_SIZE).unwrap();
+        let chunks = if chunks.is_empty() {
+            vec![Vec::new()]
+        } else {
+            chunks
+        };
+        let mut responses = Vec::new();
+        for data in chunks {
+            let payload = crate::protocol::RawPayload { id, data };
+            let bytes = Packet::new_with_query_id(&payload, &[0, 0, 0, 7])
+                .unwrap()
+                .to_bytes();
+            let raw = RawPacket::from_bytes(&bytes).unwrap();
+            if let Some(response) = emulator.handle_packet(&raw) {
+                assert_eq!(Some(vec![0, 0, 0, 7]), response.query_id);
+                responses.push(response.data);
+            }
+        }
+        responses
+    }
+
+    #[test]
+    fn test_query_id_echo() {
+        let mut emulator = Emulator::new();
+        for query_id in [&[][..], &[9], &[1, 2, 3]] {
+            let bytes = match query_id.len() {
+                0 => Packet::new(&Command::Battery).unwrap().to_bytes(),
+                _ => Packet::new_with_query_id(&Command::Battery, query_id)
+                    .unwrap()
+                    .to_bytes(),
+            };
+            let raw = RawPacket::from_bytes(&bytes).unwrap();
+            let response = emulator.handle_packet(&raw).unwrap();
+            let expected = (!query_id.is_empty()).then(|| query_id.to_vec());
+            assert_eq!(expected, response.query_id);
+            let decoded = ResponsePacket::from_bytes(&response.to_bytes()).unwrap();
+            assert_eq!(expected, decoded.query_id);
+        }
+    }
+
+    fn cfg_write(name: &str, password: u32) -> Command {
+        Command::CfgWrite {
+            name: String::from(name),
+            version: 1,
+            password,
+        }
+    }
+
+    #[test]
+    fn test_missing_cfg_write() {
+        let mut emulator = Emulator::new();
+        assert_eq!(
+            vec![Response::CmdError {
+                cmd_id: 0x53,
+                error: CmdError::MissingCfgWrite,
+                sub_error: 0
+            }],
+            send(
+                &mut emulator,
+                &Command::FontDelete {
+                    id: Selector::one(5).unwrap()
+                }
+            )
+        );
+        assert_eq!(
+            vec![Response::Battery { level: 100 }],
+            send(&mut emulator, &Command::Battery)
+        );
+    }
+
+    #[test]
+    fn test_chunked_image_upload() {
+        let mut emulator = Emulator::new();
+        send(&mut emulator, &cfg_write("test", 0));
+        let image = Command::ImgSave {
+            id: 3,
+            size: 1200,
+            width: 40,
+            format: ImgFormat::Img4bpp,
+            data: vec![0x11; 1200],
+        };
+        assert!(send(&mut emulator, &image).is_empty());
+        assert_eq!(
+            vec![Response::ImgList {
+                list: vec![ImgListItem {
+                    id: 3,
+                    height: 60,
+                    width: 40
+                }]
+            }],
+            send(&mut emulator, &Command::ImgList)
+        );
+
+        send(&mut emulator, &Command::ImgDelete { id: Selector::All });
+        assert_eq!(
+            vec![Response::ImgList { list: vec![] }],
+            send(&mut emulator, &Command::ImgList)
+        );
+    }
+
+    #[test]
+    fn test_configurations() {
+        let mut emulator = Emulator::new();
+        send(&mut emulator, &cfg_write("a", 42));
+        send(
+            &mut emulator,
+            &Command::FontSave {
+                id: 5,
+                size: 4,
+                data: vec![1, 18, 0, 0],
+            },
+        );
+        send(&mut emulator, &cfg_write("b", 0));
+        // Wrong password
+        assert!(matches!(
+            send(&mut emulator, &cfg_write("a", 0))[..],
+            [Response::CmdError { cmd_id: 0xD0, .. }]
+        ));
+
+        assert_eq!(
+            vec![Response::CfgGetNb { nb_config: 2 }],
+            send(&mut emulator, &Command::CfgGetNb)
+        );
+        send(
+            &mut emulator,
+            &Command::CfgSet {
+                name: String::from("a"),
+            },
+        );
+        let fonts = send(&mut emulator, &Command::FontList);
+        match &fonts[..] {
+            [Response::FontList { list }] => {
+                assert_eq!(5, list.len());
+                assert_eq!(FontItem { id: 5, height: 18 }, list[4]);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        send(&mut emulator, &Command::CfgDeleteLessUsed);
+        assert_eq!("a", emulator.configs()[0].name);
+        assert_eq!(1, emulator.configs().len());
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut emulator = Emulator::new();
+        let error = |cmd_id, error| {
+            vec![Response::CmdError {
+                cmd_id,
+                error,
+                sub_error: 0,
+            }]
+        };
+        assert_eq!(
+            error(0x02, CmdError::ProtocolDecoding),
+            send(
+                &mut emulator,
+                &Command::Grey {
+                    lvl: Grey::from(16)
+                }
+            )
+        );
+        assert_eq!(
+            error(0x10, CmdError::ProtocolDecoding),
+            send(
+                &mut emulator,
+                &Command::Luma {
+                    level: Luma::from(16)
+                }
+            )
+        );
+        assert_eq!(
+            error(0x42, CmdError::Generic),
+            send(
+                &mut emulator,
+                &Command::ImgDisplay {
+                    id: 3,
+                    coord: Point { x: 0, y: 0 }
+                }
+            )
+        );
+
+        // Free space: replacing an element only counts the difference
+        send(&mut emulator, &cfg_write("test", 0));
+        emulator.memory_size = 1000;
+        let font = |size: usize| Command::FontSave {
+            id: 5,
+            size: size as u16,
+            data: vec![0; size],
+        };
+        assert!(send(&mut emulator, &font(600)).is_empty());
+        assert!(send(&mut emulator, &font(900)).is_empty());
+        assert_eq!(
+            error(0x51, CmdError::MemoryAccess),
+            send(&mut emulator, &font(1001))
+        );
+        let image = Command::ImgSave {
+            id: 1,
+            size: 200,
+            width: 20,
+            format: ImgFormat::Img4bpp,
+            data: vec![0; 200],
+        };
+        assert_eq!(
+            error(0x41, CmdError::MemoryAccess),
+            send(&mut emulator, &image)
+        );
+        assert_eq!(900, emulator.configs()[0].size());
+    }
+
+    #[test]
+    fn test_virtual_time() {
+        let clock = VirtualClock::new();
+        let mut emulator = Emulator::new().with_clock(clock.clone());
+        emulator.battery_drain = Some(Duration::from_secs(60));
+        send(&mut emulator, &cfg_write("test", 0));
+        // Header only, the data is not needed
+        emulator.handle(&Command::AnimSave {
+            id: 1,
+            total_size: 400,
+            img_size: 100,
+            width: 10,
+            fmt: 0,
+            img_compressed_size: 100,
+        });
+        let display = |handler_id, repeat| Command::AnimDisplay {
+            handler_id,
+            id: 1,
+            delay: 50,
+            repeat,
+            pos: Point { x: 0, y: 0 },
+        };
+        send(&mut emulator, &display(1, 1));
+        send(&mut emulator, &display(2, ALL));
+        assert_eq!(vec![1, 2], emulator.playing_animations());
+
+        // 4 frames of 50ms, played twice
+        clock.advance(Duration::from_millis(400));
+        assert_eq!(vec![2], emulator.playing_animations());
+        send(
+            &mut emulator,
+            &Command::AnimClear {
+                handler_id: Selector::one(2).unwrap(),
+            },
+        );
+        assert!(emulator.playing_animations().is_empty());
+
+        clock.advance(Duration::from_secs(150));
+        assert_eq!(
+            vec![Response::Battery { level: 98 }],
+            send(&mut emulator, &Command::Battery)
+        );
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut emulator = Emulator::new();
+        emulator.flow_control = Some(FlowControlSettings {
+            buffer_size: 1000,
+            rate: 1000,
+        });
+        send(&mut emulator, &cfg_write("test", 0));
+        let font = Command::FontSave {
+            id: 5,
+            size: 900,
+            data: vec![0; 900],
+        };
+        send(&mut emulator, &font);
+        assert_eq!(Some(CTRL_CLIENT_SHOULD_WAIT), emulator.take_ctrl());
+        assert_eq!(None, emulator.take_ctrl());
+        assert_eq!(1, emulator.current().fonts.len());
+
+        // The buffer is full
+        send(&mut emulator, &font);
+        assert_eq!(Some(CTRL_MESSAGE_QUEUE_OVERFLOW), emulator.take_ctrl());
+        while emulator.take_ctrl().is_some() {}
+
+        emulator.clock().advance(Duration::from_millis(500));
+        assert_eq!(Some(CTRL_CLIENT_CAN_SEND), emulator.take_ctrl());
+    }
+
+    #[test]
+    fn test_rendering() {
+        let mut emulator = Emulator::new();
+        send(&mut emulator, &cfg_write("test", 0));
+        send(
+            &mut emulator,
+            &Command::ImgSave {
+                id: 1,
+                size: 2,
+                width: 4,
+                format: ImgFormat::Img4bpp,
+                data: vec![0x21, 0x43],
+            },
+        );
+        send(&mut emulator, &Command::Grey { lvl: 1.into() });
+        send(&mut emulator, &Command::Color { color: 9.into() });
+        send(
+            &mut emulator,
+            &Command::Shift {
+                shift: crate::commands::Shift { x: 10, y: 0 },
+            },
+        );
+        send(
+            &mut emulator,
+            &Command::Line {
+                from: Point { x: 0, y: 0 },
+                to: Point { x: 3, y: 0 },
+            },
+        );
+        send(
+            &mut emulator,
+            &Command::ImgDisplay {
+                id: 1,
+                coord: Point { x: 0, y: 1 },
+            },
+        );
+
+        let fb = emulator.framebuffer();
+        assert_eq!(Some(Grey::from(1)), fb.pixel(0, 0));
+        assert_eq!(Some(Grey::from(9)), fb.pixel(13, 0));
+        assert_eq!(Some(Grey::from(4)), fb.pixel(13, 1));
+        send(&mut emulator, &Command::Clear);
+        assert_eq!(0, emulator.framebuffer().lit_pixels());
+    }
+}
@@ -0,0 +1,236 @@
+//! ActiveLook firmware versions
+//!
+//! Older firmware versions do not support every command of the API documentation. [FirmwareGate]
+//! checks each command against the [COMMAND_REQUIREMENTS] table before sending it, instead of
+//! letting the glasses answer with a generic error.
+use core::fmt;
+
+use log::*;
+
+use crate::{
+    commands::{Command, Response},
+    glasses::{GlassesApi, GlassesError},
+    traits::*,
+};
+
+/// Firmware version, as returned in [Response::Version]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+}
+
+impl FirmwareVersion {
+    pub const fn new(major: u8, minor: u8, patch: u8) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Extract the firmware version from a [Response::Version]
+    pub fn from_response(response: &Response) -> Option<Self> {
+        match response {
+            Response::Version { fw_version, .. } => {
+                Some(Self::new(fw_version[0], fw_version[1], fw_version[2]))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FirmwareVersion {
+    /// First firmware version supporting `cmd`, if it is listed in [COMMAND_REQUIREMENTS]
+    pub fn required_for(cmd: &Command) -> Option<Self> {
+        let cmd_id = cmd.id().ok()?;
+        COMMAND_REQUIREMENTS
+            .iter()
+            .find(|requirement| requirement.cmd_id == cmd_id)
+            .map(|requirement| requirement.since)
+    }
+
+    /// Returns false if `cmd` appeared in a later firmware version
+    pub fn supports(&self, cmd: &Command) -> bool {
+        Self::required_for(cmd).is_none_or(|since| *self >= since)
+    }
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Entry of the [COMMAND_REQUIREMENTS] table
+#[derive(Copy, Clone, Debug)]
+pub struct CommandRequirement {
+    pub cmd_id: u8,
+    /// First version supporting the command
+    pub since: FirmwareVersion,
+}
+
+/// Commands missing in older firmware versions.
+///
+/// Only the commands whose introduction is known are listed, the others are assumed to be
+/// supported by every version. `spec/ActiveLook_API.md` documents firmware 4.12.0.
+pub const COMMAND_REQUIREMENTS: &[CommandRequirement] = &[
+    // Firmware 3.x has no LayoutClearAndDisplay, see crate::quirks::QUIRKS
+    CommandRequirement {
+        cmd_id: 0x69,
+        since: FirmwareVersion::new(4, 0, 0),
+    },
+    // Assumed to come with LayoutClearAndDisplay
+    CommandRequirement {
+        cmd_id: 0x6A,
+        since: FirmwareVersion::new(4, 0, 0),
+    },
+];
+
+/// What [FirmwareGate] does with unsupported commands
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GatePolicy {
+    /// Log a warning, and send the command anyway
+    Warn,
+    /// Return [GlassesError::UnsupportedByFirmware] without sending the command
+    Reject,
+}
+
+/// [GlassesApi] checking that each command is supported by the firmware of the wrapped glasses
+pub struct FirmwareGate<G: GlassesApi> {
+    glasses: G,
+    version: FirmwareVersion,
+    policy: GatePolicy,
+}
+
+impl<G: GlassesApi> FirmwareGate<G> {
+    pub fn new(glasses: G, version: FirmwareVersion, policy: GatePolicy) -> Self {
+        Self {
+            glasses,
+            version,
+            policy,
+        }
+    }
+
+    /// Query the firmware version of `glasses`
+    pub fn detect(mut glasses: G, policy: GatePolicy) -> Result<Self, GlassesError> {
+        let response = glasses.query(&Command::Version)?;
+        let version = FirmwareVersion::from_response(&response)
+            .ok_or(GlassesError::UnexpectedResponse(response))?;
+        Ok(Self::new(glasses, version, policy))
+    }
+
+    pub fn version(&self) -> FirmwareVersion {
+        self.version
+    }
+
+    pub fn into_inner(self) -> G {
+        self.glasses
+    }
+
+    fn check(&self, cmd: &Command) -> Result<(), GlassesError> {
+        let Some(required) = FirmwareVersion::required_for(cmd).filter(|v| self.version < *v)
+        else {
+            return Ok(());
+        };
+        let cmd_id = cmd.id().unwrap_or_default();
+        match self.policy {
+            GatePolicy::Warn => {
+                warn!(
+                    "Command 0x{:02X} needs firmware {}, glasses run {}",
+                    cmd_id, required, self.version
+                );
+                Ok(())
+            }
+            GatePolicy::Reject => Err(GlassesError::UnsupportedByFirmware {
+                cmd_id,
+                required,
+                version: self.version,
+            }),
+        }
+    }
+}
+
+impl<G: GlassesApi> GlassesApi for FirmwareGate<G> {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.check(cmd)?;
+        self.glasses.send(cmd)
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.check(cmd)?;
+        self.glasses.query(cmd)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.check(cmd)?;
+        self.glasses.send_chunked(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    struct Emulated(Emulator);
+
+    impl GlassesApi for Emulated {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            self.0.handle(cmd);
+            Ok(())
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            self.0.handle(cmd).ok_or(GlassesError::Unsupported)
+        }
+    }
+
+    #[test]
+    fn test_from_response() {
+        let response = Response::Version {
+            fw_version: [4, 12, 1, b'b'],
+            mfc_year: 24,
+            mfc_week: 3,
+            serial_number: [0, 1, 2],
+        };
+        let version = FirmwareVersion::from_response(&response).unwrap();
+        assert_eq!(FirmwareVersion::new(4, 12, 1), version);
+        assert_eq!("4.12.1", version.to_string());
+        assert!(version > FirmwareVersion::new(4, 9, 10));
+        assert_eq!(
+            None,
+            FirmwareVersion::from_response(&Response::Battery { level: 1 })
+        );
+    }
+
+    #[test]
+    fn test_gate() {
+        let mut emulator = Emulator::new();
+        emulator.version = FirmwareVersion::new(3, 7, 4);
+        let glasses = Emulated(emulator);
+        let cmd = Command::LayoutClearAndDisplay {
+            id: 1,
+            text: String::from("a"),
+        };
+        assert!(FirmwareVersion::new(4, 0, 0).supports(&cmd));
+        assert!(FirmwareVersion::new(3, 7, 4).supports(&Command::Clear));
+
+        let mut gate = FirmwareGate::detect(glasses, GatePolicy::Reject).unwrap();
+        assert_eq!(FirmwareVersion::new(3, 7, 4), gate.version());
+        assert_eq!(
+            Err(GlassesError::UnsupportedByFirmware {
+                cmd_id: 0x69,
+                required: FirmwareVersion::new(4, 0, 0),
+                version: FirmwareVersion::new(3, 7, 4),
+            }),
+            gate.send(&cmd)
+        );
+        assert!(gate.send(&Command::Clear).is_ok());
+
+        let version = gate.version();
+        let mut gate = FirmwareGate::new(gate.into_inner(), version, GatePolicy::Warn);
+        assert!(gate.send(&cmd).is_ok());
+    }
+}
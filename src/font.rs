@@ -0,0 +1,62 @@
+use thiserror::Error;
+
+use crate::commands::Command;
+
+/// Errors building the commands of a [Font]
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum FontError {
+    /// [Command::FontSave] gives the size of the data in 16 bits
+    #[error("Font data of {0} bytes exceeds the {max} bytes of fontSave", max = u16::MAX)]
+    TooLarge(usize),
+}
+
+/// Contains a font, already encoded in the ActiveLook font format
+pub struct Font<'a> {
+    /// Height of the glyphs, in pixels
+    pub height: u8,
+    /// Glyph data
+    pub data: &'a [u8],
+}
+
+impl<'a> Font<'a> {
+    /// Command saving this font as `id`
+    pub fn save_command(&self, id: u8) -> Result<Command, FontError> {
+        let size =
+            u16::try_from(self.data.len()).map_err(|_| FontError::TooLarge(self.data.len()))?;
+        Ok(Command::FontSave {
+            id,
+            size,
+            data: self.data.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_command() {
+        let data = vec![0; u16::MAX as usize + 1];
+        let font = Font {
+            height: 24,
+            data: &data[..u16::MAX as usize],
+        };
+        assert!(matches!(
+            font.save_command(3),
+            Ok(Command::FontSave {
+                id: 3,
+                size: u16::MAX,
+                ..
+            })
+        ));
+        let font = Font {
+            height: 24,
+            data: &data,
+        };
+        assert_eq!(
+            Err(FontError::TooLarge(u16::MAX as usize + 1)),
+            font.save_command(3)
+        );
+    }
+}
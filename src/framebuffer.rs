@@ -0,0 +1,367 @@
+//! Software rendering of the drawing commands
+//!
+//! [Framebuffer] holds the 304 x 256 pixels of the display, in firmware coordinates, with 16 grey
+//! levels. The [crate::emulator::Emulator] draws every graphic command into it, so the rendering
+//! of layouts can be checked by visual regression tests without glasses.
+//!
+//! The rendering is an approximation of the firmware one:
+//! - the glyphs of the built-in fonts are not part of this crate: each character of a text is
+//!   drawn as an empty box of the font height, and the text rotation is ignored,
+//! - arc angles are in degrees, from the x axis towards the y axis,
+//! - compressed images are not decoded,
+//! - lines are one pixel wide, whatever the polyline thickness.
+use crate::{
+    commands::{DefaultFont, Grey, ImgFormat, Point},
+    display::{HEIGHT, WIDTH},
+};
+
+/// Bytes of a framebuffer line, 2 pixels per byte
+const LINE_LEN: usize = WIDTH as usize / 2;
+
+/// Display memory, 4 bits per pixel
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Framebuffer {
+    data: Vec<u8>,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
+    /// Black framebuffer
+    pub fn new() -> Self {
+        Self {
+            data: vec![0; LINE_LEN * HEIGHT as usize],
+        }
+    }
+
+    /// Pixels in [ImgFormat::Img4bpp] layout: lines of 152 bytes, the leftmost pixel of each byte
+    /// being the low nibble
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Grey level at `x`, `y`, if it is on screen
+    pub fn pixel(&self, x: i16, y: i16) -> Option<Grey> {
+        let (index, shift) = Self::locate(x, y)?;
+        Some(Grey::from((self.data[index] >> shift) & 0x0F))
+    }
+
+    /// Number of pixels which are not black
+    pub fn lit_pixels(&self) -> usize {
+        self.data
+            .iter()
+            .map(|byte| (byte & 0x0F != 0) as usize + (byte >> 4 != 0) as usize)
+            .sum()
+    }
+
+    /// Set every pixel to `color`
+    pub fn fill(&mut self, color: Grey) {
+        let level = color.value() & 0x0F;
+        self.data.fill(level << 4 | level);
+    }
+
+    /// Set the pixel at `x`, `y`. Off-screen pixels are ignored.
+    pub fn set(&mut self, x: i16, y: i16, color: Grey) {
+        if let Some((index, shift)) = Self::locate(x, y) {
+            let byte = &mut self.data[index];
+            *byte = (*byte & !(0x0F << shift)) | ((color.value() & 0x0F) << shift);
+        }
+    }
+
+    fn locate(x: i16, y: i16) -> Option<(usize, u8)> {
+        if !(0..WIDTH).contains(&x) || !(0..HEIGHT).contains(&y) {
+            return None;
+        }
+        let index = y as usize * LINE_LEN + x as usize / 2;
+        Some((index, (x as u8 % 2) * 4))
+    }
+
+    /// Bresenham line, both ends included
+    pub fn line(&mut self, from: Point, to: Point, color: Grey) {
+        let (mut x, mut y) = (from.x as i32, from.y as i32);
+        let (x1, y1) = (to.x as i32, to.y as i32);
+        let dx = (x1 - x).abs();
+        let dy = -(y1 - y).abs();
+        let sx = if x < x1 { 1 } else { -1 };
+        let sy = if y < y1 { 1 } else { -1 };
+        let mut err = dx + dy;
+        loop {
+            self.set(x as i16, y as i16, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    pub fn rect(&mut self, from: Point, to: Point, color: Grey) {
+        let corners = [
+            from,
+            Point { x: to.x, y: from.y },
+            to,
+            Point { x: from.x, y: to.y },
+        ];
+        for (i, corner) in corners.iter().enumerate() {
+            self.line(*corner, corners[(i + 1) % 4], color);
+        }
+    }
+
+    pub fn rect_full(&mut self, from: Point, to: Point, color: Grey) {
+        for y in from.y.min(to.y)..=from.y.max(to.y) {
+            for x in from.x.min(to.x)..=from.x.max(to.x) {
+                self.set(x, y, color);
+            }
+        }
+    }
+
+    /// Pixels at distance `r` of `center`, rounded
+    pub fn circle(&mut self, center: Point, r: u8, color: Grey) {
+        self.ring(center, r, 1, None, color);
+    }
+
+    pub fn circle_full(&mut self, center: Point, r: u8, color: Grey) {
+        self.ring(center, r, r.saturating_add(1), None, color);
+    }
+
+    /// Part of a ring between `angle_start` and `angle_end`, `thickness` pixels wide inwards
+    pub fn arc(
+        &mut self,
+        center: Point,
+        r: u8,
+        angle_start: i16,
+        angle_end: i16,
+        thickness: u8,
+        color: Grey,
+    ) {
+        self.ring(
+            center,
+            r,
+            thickness.max(1),
+            Some((angle_start, angle_end)),
+            color,
+        );
+    }
+
+    fn ring(
+        &mut self,
+        center: Point,
+        r: u8,
+        thickness: u8,
+        angles: Option<(i16, i16)>,
+        color: Grey,
+    ) {
+        let r = r as i32;
+        let inner = r - thickness as i32;
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let distance = ((dx * dx + dy * dy) as f64).sqrt().round() as i32;
+                if distance > r || distance <= inner {
+                    continue;
+                }
+                if let Some((start, end)) = angles {
+                    let angle = (dy as f64).atan2(dx as f64).to_degrees().rem_euclid(360.0);
+                    let start = (start as f64).rem_euclid(360.0);
+                    let end = (end as f64).rem_euclid(360.0);
+                    let inside = match start <= end {
+                        true => (start..=end).contains(&angle),
+                        false => angle >= start || angle <= end,
+                    };
+                    if !inside {
+                        continue;
+                    }
+                }
+                self.set(
+                    (center.x as i32 + dx) as i16,
+                    (center.y as i32 + dy) as i16,
+                    color,
+                );
+            }
+        }
+    }
+
+    /// Placeholder rendering of `text`: one empty box per character, spaces excepted
+    pub fn text(&mut self, pos: Point, font: u8, text: &str, color: Grey) {
+        let height = font_height(font);
+        let advance = height / 2;
+        for (i, c) in text.chars().enumerate() {
+            if c.is_whitespace() {
+                continue;
+            }
+            let x = pos.x.saturating_add(advance.saturating_mul(i as i16));
+            self.rect(
+                Point { x, y: pos.y },
+                Point {
+                    x: x.saturating_add(advance - 2),
+                    y: pos.y.saturating_add(height - 1),
+                },
+                color,
+            );
+        }
+    }
+
+    /// Draw an uncompressed image with its top left corner at `coord`.
+    /// Returns false if the format can not be decoded.
+    pub fn image(&mut self, coord: Point, width: u16, format: ImgFormat, data: &[u8]) -> bool {
+        if !matches!(
+            format,
+            ImgFormat::Img1bpp | ImgFormat::Img4bpp | ImgFormat::Img8bpp
+        ) {
+            return false;
+        }
+        let line_len = format.nb_of_bytes(width as usize);
+        if line_len == 0 {
+            return true;
+        }
+        for (y, line) in data.chunks_exact(line_len).enumerate() {
+            for x in 0..width as usize {
+                let (sx, sy) = (
+                    coord.x.saturating_add(x as i16),
+                    coord.y.saturating_add(y as i16),
+                );
+                let color = match format {
+                    ImgFormat::Img1bpp => match (line[x / 8] >> (x % 8)) & 1 {
+                        1 => Grey::WHITE,
+                        _ => continue,
+                    },
+                    ImgFormat::Img4bpp => Grey::from((line[x / 2] >> ((x % 2) * 4)) & 0x0F),
+                    ImgFormat::Img8bpp => {
+                        let (grey, alpha) = (line[x] & 0x0F, line[x] >> 4);
+                        let background = self.pixel(sx, sy).map_or(0, |bg| bg.value());
+                        let blended = (grey as u16 * alpha as u16
+                            + background as u16 * (15 - alpha) as u16)
+                            / 15;
+                        Grey::from(blended as u8)
+                    }
+                    _ => unreachable!("Compressed formats are rejected"),
+                };
+                self.set(sx, sy, color);
+            }
+        }
+        true
+    }
+
+    /// Encode the framebuffer as a 4 bits greyscale PNG image
+    #[cfg(feature = "png")]
+    pub fn to_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        // PNG packs the leftmost pixel in the high nibble
+        let data: Vec<u8> = self.data.iter().map(|byte| byte.rotate_left(4)).collect();
+        let mut png = Vec::new();
+        let mut encoder = png::Encoder::new(&mut png, WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Four);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(png)
+    }
+}
+
+/// Height of the glyphs of `font`, in pixels. Custom fonts are assumed to be as high as the
+/// default one.
+fn font_height(font: u8) -> i16 {
+    match DefaultFont::from(font) {
+        DefaultFont::ComputerModernSansSerif35 => 35,
+        DefaultFont::ComputerModernSansSerif49 => 49,
+        _ => 24,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p(x: i16, y: i16) -> Point {
+        Point { x, y }
+    }
+
+    #[test]
+    fn test_primitives() {
+        let mut fb = Framebuffer::new();
+        fb.line(p(0, 0), p(9, 3), Grey::WHITE);
+        assert_eq!(10, fb.lit_pixels());
+        assert_eq!(Some(Grey::WHITE), fb.pixel(9, 3));
+
+        fb.fill(Grey::BLACK);
+        fb.rect(p(10, 10), p(14, 12), Grey::from(3));
+        assert_eq!(12, fb.lit_pixels());
+        assert_eq!(Some(Grey::BLACK), fb.pixel(12, 11));
+        fb.rect_full(p(10, 10), p(14, 12), Grey::from(3));
+        assert_eq!(15, fb.lit_pixels());
+
+        fb.fill(Grey::BLACK);
+        fb.circle_full(p(100, 100), 10, Grey::WHITE);
+        assert_eq!(Some(Grey::WHITE), fb.pixel(100, 100));
+        assert_eq!(Some(Grey::WHITE), fb.pixel(110, 100));
+        assert_eq!(Some(Grey::BLACK), fb.pixel(108, 108));
+        let full = fb.lit_pixels();
+        fb.fill(Grey::BLACK);
+        fb.circle(p(100, 100), 10, Grey::WHITE);
+        assert_eq!(Some(Grey::BLACK), fb.pixel(100, 100));
+        assert!(fb.lit_pixels() < full);
+
+        // Quarter of a ring
+        fb.fill(Grey::BLACK);
+        fb.arc(p(100, 100), 10, 0, 90, 3, Grey::WHITE);
+        assert_eq!(Some(Grey::WHITE), fb.pixel(100, 110));
+        assert_eq!(Some(Grey::BLACK), fb.pixel(100, 90));
+        assert_eq!(Some(Grey::BLACK), fb.pixel(100, 100));
+
+        // Clipped
+        fb.fill(Grey::BLACK);
+        fb.line(p(-10, 255), p(400, 255), Grey::WHITE);
+        assert_eq!(WIDTH as usize, fb.lit_pixels());
+        assert_eq!(None, fb.pixel(304, 0));
+    }
+
+    #[test]
+    fn test_images() {
+        let mut fb = Framebuffer::new();
+        fb.fill(Grey::from(2));
+        assert!(fb.image(p(0, 0), 3, ImgFormat::Img4bpp, &[0x21, 0x03]));
+        assert_eq!(Some(Grey::from(1)), fb.pixel(0, 0));
+        assert_eq!(Some(Grey::from(3)), fb.pixel(2, 0));
+
+        // 1bpp images only draw their lit pixels
+        assert!(fb.image(p(0, 1), 3, ImgFormat::Img1bpp, &[0b100]));
+        assert_eq!(Some(Grey::from(2)), fb.pixel(0, 1));
+        assert_eq!(Some(Grey::WHITE), fb.pixel(2, 1));
+
+        // Opaque, transparent, and half transparent pixels
+        assert!(fb.image(p(0, 2), 3, ImgFormat::Img8bpp, &[0xFF, 0x0F, 0x7F]));
+        assert_eq!(Some(Grey::WHITE), fb.pixel(0, 2));
+        assert_eq!(Some(Grey::from(2)), fb.pixel(1, 2));
+        assert_eq!(Some(Grey::from(8)), fb.pixel(2, 2));
+
+        assert!(!fb.image(p(0, 0), 3, ImgFormat::Img4bppDecompressBeforeSaving, &[0]));
+    }
+
+    #[test]
+    fn test_text() {
+        let mut fb = Framebuffer::new();
+        fb.text(p(10, 10), 0, "a b", Grey::WHITE);
+        // Two boxes of 11 x 24 pixels
+        assert_eq!(2 * (2 * 11 + 2 * 22), fb.lit_pixels());
+        assert_eq!(Some(Grey::WHITE), fb.pixel(34, 33));
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_png() {
+        let mut fb = Framebuffer::new();
+        fb.set(0, 0, Grey::WHITE);
+        let png = fb.to_png().unwrap();
+        assert_eq!(b"\x89PNG", &png[..4]);
+    }
+}
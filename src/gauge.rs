@@ -0,0 +1,149 @@
+//! Construction of gauges
+//!
+//! A gauge is an arc between two clock positions, filled according to the percentage given to
+//! [Command::GaugeDisplay]. [GaugeBuilder] checks the parameters before they reach the glasses,
+//! which would reject them with an opaque [crate::commands::CmdError].
+
+use thiserror::Error;
+
+use crate::commands::{Command, GaugeParameters, Point};
+
+/// Highest clock position of the arc ends
+pub const MAX_POSITION: u8 = 16;
+
+/// Errors building a gauge
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum GaugeError {
+    #[error("Clock position {0} is out of range 0..={MAX_POSITION}")]
+    InvalidPosition(u8),
+    #[error("Inner radius {inner} must be smaller than the radius {radius}")]
+    InvalidRadius { radius: u16, inner: u16 },
+}
+
+/// Validated gauge parameters
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Gauge {
+    params: GaugeParameters,
+}
+
+impl Gauge {
+    /// Gauge centered on `pos`, see [GaugeBuilder]
+    pub fn builder(pos: Point, radius: u16) -> GaugeBuilder {
+        GaugeBuilder::new(pos, radius)
+    }
+
+    pub fn params(&self) -> &GaugeParameters {
+        &self.params
+    }
+
+    /// Command saving this gauge as `id`
+    pub fn save_command(&self, id: u8) -> Command {
+        Command::GaugeSave {
+            id,
+            params: self.params,
+        }
+    }
+}
+
+impl TryFrom<GaugeParameters> for Gauge {
+    type Error = GaugeError;
+
+    /// Check parameters read with [Command::GaugeGet]
+    fn try_from(params: GaugeParameters) -> Result<Self, Self::Error> {
+        GaugeBuilder { params }.build()
+    }
+}
+
+/// Builder of [Gauge]s.
+/// By default, the gauge is a full clockwise disc, from position 0 to [MAX_POSITION].
+#[derive(Clone, Debug)]
+pub struct GaugeBuilder {
+    params: GaugeParameters,
+}
+
+impl GaugeBuilder {
+    pub fn new(pos: Point, radius: u16) -> Self {
+        Self {
+            params: GaugeParameters {
+                pos,
+                radius,
+                inner: 0,
+                start: 0,
+                end: MAX_POSITION,
+                clockwise: true,
+            },
+        }
+    }
+
+    /// Inner radius, making a ring. Must be smaller than the radius.
+    pub fn inner(mut self, inner: u16) -> Self {
+        self.params.inner = inner;
+        self
+    }
+
+    /// Clock positions of the ends of the arc, in 0..=[MAX_POSITION]
+    pub fn arc(mut self, start: u8, end: u8) -> Self {
+        self.params.start = start;
+        self.params.end = end;
+        self
+    }
+
+    pub fn clockwise(mut self, clockwise: bool) -> Self {
+        self.params.clockwise = clockwise;
+        self
+    }
+
+    pub fn build(self) -> Result<Gauge, GaugeError> {
+        let params = self.params;
+        for position in [params.start, params.end] {
+            if position > MAX_POSITION {
+                return Err(GaugeError::InvalidPosition(position));
+            }
+        }
+        if params.inner >= params.radius {
+            return Err(GaugeError::InvalidRadius {
+                radius: params.radius,
+                inner: params.inner,
+            });
+        }
+        Ok(Gauge { params })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::*;
+
+    #[test]
+    fn test_build_gauge() {
+        let gauge = Gauge::builder(Point { x: 150, y: 100 }, 60)
+            .inner(40)
+            .arc(4, 12)
+            .clockwise(false)
+            .build()
+            .unwrap();
+        let cmd = gauge.save_command(3);
+        assert_eq!(
+            &[3, 0, 150, 0, 100, 0, 60, 0, 40, 4, 12, 0][..],
+            cmd.data_bytes().unwrap()
+        );
+        assert_eq!(Ok(gauge), Gauge::try_from(*gauge.params()));
+    }
+
+    #[test]
+    fn test_invalid_gauge() {
+        let builder = Gauge::builder(Point { x: 0, y: 0 }, 10);
+        assert_eq!(
+            Err(GaugeError::InvalidPosition(17)),
+            builder.clone().arc(0, 17).build()
+        );
+        assert_eq!(
+            Err(GaugeError::InvalidRadius {
+                radius: 10,
+                inner: 10
+            }),
+            builder.inner(10).build()
+        );
+    }
+}
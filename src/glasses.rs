@@ -0,0 +1,874 @@
+//! High-level access to ActiveLook glasses
+//!
+//! Application code should depend on the [GlassesApi] trait rather than on a concrete client.
+//! This way, the same code can drive:
+//! - real glasses through [Glasses] and a transport,
+//! - a local [Preview], emulating the glasses and keeping the drawing commands on screen,
+//! - [NoopGlasses], which accepts everything and does nothing, for unit tests.
+use std::collections::BTreeMap;
+
+use embedded_io::{Read, ReadReady, Write};
+use thiserror::Error;
+
+use crate::{
+    arc::CircleArc,
+    client::ActiveLookClient,
+    cmd_error::CommandError,
+    commands::{
+        Command, DemoID, DeviceInfo, Grey, HoldFlushAction, Point, Response, Selector, TextRotation,
+    },
+    config::{
+        auth_error, Config, ConfigCredentials, ConfigSession, ElementKind, ElementRef, SessionError,
+    },
+    device_info::DeviceInfoValue,
+    display::CoordinateError,
+    emulator::Emulator,
+    firmware::FirmwareVersion,
+    font::{Font, FontError},
+    polyline::Polyline,
+    power::{PowerGuard, PowerSource},
+    protocol::{ProtocolError, PACKET_DATA_MAX_SIZE},
+    quirks::{Quirk, Quirks},
+    self_test::SelfTestReport,
+    status::Settings,
+    transaction::DisplayTransaction,
+};
+
+/// Errors returned by the high-level API
+#[derive(Error, Debug, PartialEq)]
+pub enum GlassesError {
+    /// Error coming from the [crate::protocol] layer
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+    /// The glasses answered with a [Response] we did not expect for this query
+    #[error("Unexpected response {0:?}")]
+    UnexpectedResponse(Response),
+    /// The glasses answered with [Response::CmdError], see [CommandError]
+    #[error(transparent)]
+    Command(CommandError),
+    /// The implementation can not answer this query
+    #[error("Unsupported query")]
+    Unsupported,
+    /// The command queue is full, see [crate::queue::OverflowPolicy]
+    #[error("Command queue is full")]
+    QueueFull,
+    /// The command draws where the firmware would not, see [crate::display::ClipPolicy]
+    #[error(transparent)]
+    Coordinates(CoordinateError),
+    /// The glasses refused to write or rename the configuration: its password is not the one
+    /// given when it was created, see [crate::config::ConfigCredentials]
+    #[error("Configuration {0} refused the password")]
+    ConfigAuth(String),
+    /// The configuration stored in the glasses holds other elements, or another version, than
+    /// the [crate::config::Config]: rewriting it would lose them
+    #[error("Configuration {0} does not match the one stored in the glasses")]
+    ConfigMismatch(String),
+    /// The configuration was copied under the `temporary` name, but the copy could not replace
+    /// the previous configuration: the copy is left in the glasses, along with the previous
+    /// configuration unless it was deleted, see [crate::config::Config::change_password]
+    #[error("Configuration left as {temporary}: {error}")]
+    ConfigLeftAs {
+        temporary: String,
+        error: Box<GlassesError>,
+    },
+    /// The element is still listed after being deleted
+    #[error("{0:?} is still present after deletion")]
+    DeleteFailed(ElementRef),
+    /// The font can not be saved, see [FontError]
+    #[error(transparent)]
+    Font(#[from] FontError),
+    /// The command needs a more recent firmware, see [crate::firmware::FirmwareGate]
+    #[error("Command 0x{cmd_id:02X} needs firmware {required}, glasses run {version}")]
+    UnsupportedByFirmware {
+        cmd_id: u8,
+        required: FirmwareVersion,
+        version: FirmwareVersion,
+    },
+}
+
+/// Front-end used by applications to drive ActiveLook glasses.
+///
+/// Only [GlassesApi::send] and [GlassesApi::query] must be implemented, all the helpers are built
+/// on top of them.
+pub trait GlassesApi {
+    /// Send a [Command] which does not expect any [Response]
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError>;
+
+    /// Send a [Command] and wait for the corresponding [Response]
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError>;
+
+    /// Send a [Command] whose data does not fit in a single packet, like [Command::ImgSave] or
+    /// [Command::FontSave]
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.send(cmd)
+    }
+
+    /// Returns false while the glasses ask to stop sending data, with
+    /// [crate::protocol::FlowErrorCtrl::ClientShouldWait]. Always true if the implementation
+    /// does not know.
+    fn can_send(&mut self) -> bool {
+        true
+    }
+
+    /// Enable or disable the display
+    fn power_display(&mut self, on: bool) -> Result<(), GlassesError> {
+        self.send(&Command::PowerDisplay { en: on })
+    }
+
+    /// Clear the whole display
+    fn clear(&mut self) -> Result<(), GlassesError> {
+        self.send(&Command::Clear)
+    }
+
+    /// Hold or flush the graphic engine
+    fn hold_flush(&mut self, action: HoldFlushAction) -> Result<(), GlassesError> {
+        self.send(&Command::HoldFlush { action })
+    }
+
+    /// Start a batch of drawing commands displayed at once, see [DisplayTransaction]
+    fn transaction(&mut self) -> Result<DisplayTransaction<'_, Self>, GlassesError> {
+        DisplayTransaction::begin(self)
+    }
+
+    /// Start modifying configuration `name`, see [ConfigSession]
+    fn config_session(
+        &mut self,
+        name: &str,
+        version: u32,
+        password: u32,
+    ) -> Result<ConfigSession<'_, Self>, SessionError> {
+        ConfigSession::open(self, name, version, password)
+    }
+
+    /// Shutdown or reset the glasses according to their power supply, see [PowerGuard]
+    fn power_guard(&mut self, power: PowerSource) -> PowerGuard<'_, Self> {
+        PowerGuard::new(self, power)
+    }
+
+    /// Write `text` at `pos`
+    fn text(
+        &mut self,
+        pos: Point,
+        rotation: TextRotation,
+        font_size: u8,
+        color: Grey,
+        text: &str,
+    ) -> Result<(), GlassesError> {
+        self.send(&Command::Txt {
+            pos,
+            rotation,
+            font_size,
+            color,
+            string: String::from(text),
+        })
+    }
+
+    /// Display `text` with layout `id`
+    fn layout_display(&mut self, id: u8, text: &str) -> Result<(), GlassesError> {
+        self.send(&Command::LayoutDisplay {
+            id,
+            text: String::from(text),
+        })
+    }
+
+    /// Save `font` as `id`
+    fn upload_font(&mut self, id: u8, font: &Font) -> Result<(), GlassesError> {
+        self.send_chunked(&font.save_command(id)?)
+    }
+
+    /// Fill gauge `id` to `percent`, clamped to 100
+    fn display_gauge_percent(&mut self, id: u8, percent: u8) -> Result<(), GlassesError> {
+        self.send(&Command::GaugeDisplay {
+            id,
+            value: percent.min(100),
+        })
+    }
+
+    /// Draw `polyline`, split in as many commands as needed
+    fn polyline(&mut self, polyline: &Polyline) -> Result<(), GlassesError> {
+        polyline
+            .commands()
+            .iter()
+            .try_for_each(|cmd| self.send(cmd))
+    }
+
+    /// Draw `arc`, split in as many commands as needed
+    fn arc(&mut self, arc: &CircleArc) -> Result<(), GlassesError> {
+        arc.commands().iter().try_for_each(|cmd| self.send(cmd))
+    }
+
+    /// Battery level in %
+    fn battery(&mut self) -> Result<u8, GlassesError> {
+        match self.query(&Command::Battery)? {
+            Response::Battery { level } => Ok(level),
+            other => Err(GlassesError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Global settings: display shift, luminance, sensors
+    fn settings(&mut self) -> Result<Settings, GlassesError> {
+        let response = self.query(&Command::Settings)?;
+        Settings::from_response(&response).ok_or(GlassesError::UnexpectedResponse(response))
+    }
+
+    /// Number of pixels activated on the display
+    fn pixel_count(&mut self) -> Result<u32, GlassesError> {
+        match self.query(&Command::PixelCount)? {
+            Response::PixelCount { count } => Ok(count),
+            other => Err(GlassesError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Display demonstration `demo`
+    fn run_demo(&mut self, demo: DemoID) -> Result<(), GlassesError> {
+        self.send(&Command::Demo { demo_id: demo })
+    }
+
+    /// Read and decode a device information parameter
+    fn device_info(&mut self, id: DeviceInfo) -> Result<DeviceInfoValue, GlassesError> {
+        let response = self.query(&Command::Info { id })?;
+        DeviceInfoValue::from_response(id, &response)
+            .ok_or(GlassesError::UnexpectedResponse(response))
+    }
+
+    /// IDs of the elements of `kind` stored in the glasses
+    fn list(&mut self, kind: ElementKind) -> Result<Vec<u8>, GlassesError> {
+        let response = self.query(&kind.list_command())?;
+        let ids = match (kind, response) {
+            (ElementKind::Image, Response::ImgList { list }) => {
+                list.iter().map(|item| item.id).collect()
+            }
+            (ElementKind::Font, Response::FontList { list }) => {
+                list.iter().map(|item| item.id).collect()
+            }
+            (ElementKind::Layout, Response::LayoutList { list })
+            | (ElementKind::Gauge, Response::GaugeList { list })
+            | (ElementKind::Page, Response::PageList { list })
+            | (ElementKind::Animation, Response::AnimList { list }) => list,
+            (_, other) => return Err(GlassesError::UnexpectedResponse(other)),
+        };
+        Ok(ids)
+    }
+
+    /// Delete the elements of `kind` selected by `id`, then list the elements again to confirm
+    /// the removal.
+    ///
+    /// Returns the IDs actually freed. Deleting an element which does not exist frees nothing.
+    /// Elements which can not be deleted, like the built-in fonts, are kept when deleting
+    /// [Selector::All].
+    fn delete_verified(
+        &mut self,
+        kind: ElementKind,
+        id: Selector,
+    ) -> Result<Vec<u8>, GlassesError> {
+        let before = self.list(kind)?;
+        self.send(&kind.delete_command(id))?;
+        let after = self.list(kind)?;
+        if let Some(id) = id.id().filter(|id| after.contains(id)) {
+            return Err(GlassesError::DeleteFailed(ElementRef::new(kind, id)));
+        }
+        Ok(before
+            .into_iter()
+            .filter(|id| !after.contains(id))
+            .collect())
+    }
+
+    /// Delete image `id`, or all images, see [GlassesApi::delete_verified]
+    fn delete_image_verified(&mut self, id: Selector) -> Result<Vec<u8>, GlassesError> {
+        self.delete_verified(ElementKind::Image, id)
+    }
+
+    /// Delete font `id`, or all fonts, see [GlassesApi::delete_verified]
+    fn delete_font_verified(&mut self, id: Selector) -> Result<Vec<u8>, GlassesError> {
+        self.delete_verified(ElementKind::Font, id)
+    }
+
+    /// Delete layout `id`, or all layouts, see [GlassesApi::delete_verified]
+    fn delete_layout_verified(&mut self, id: Selector) -> Result<Vec<u8>, GlassesError> {
+        self.delete_verified(ElementKind::Layout, id)
+    }
+
+    /// Check the main capabilities without modifying the stored configurations, see
+    /// [crate::self_test]
+    fn self_test(&mut self) -> SelfTestReport {
+        crate::self_test::run(self)
+    }
+}
+
+/// ActiveLook glasses, reached through an [ActiveLookClient]
+pub struct Glasses<TxActiveLook, RxActiveLook, Ctrl>
+where
+    TxActiveLook: Read,
+    RxActiveLook: Write,
+    Ctrl: Read + ReadReady,
+{
+    client: ActiveLookClient<TxActiveLook, RxActiveLook, Ctrl>,
+    quirks: Quirks,
+    /// Text last displayed by [Glasses::update_field], by layout
+    fields: BTreeMap<u8, String>,
+    credentials: ConfigCredentials,
+}
+
+impl<TxActiveLook, RxActiveLook, Ctrl> Glasses<TxActiveLook, RxActiveLook, Ctrl>
+where
+    TxActiveLook: Read,
+    RxActiveLook: Write,
+    Ctrl: Read + ReadReady,
+{
+    pub fn new(client: ActiveLookClient<TxActiveLook, RxActiveLook, Ctrl>) -> Self {
+        Self {
+            client,
+            quirks: Quirks::none(),
+            fields: BTreeMap::new(),
+            credentials: ConfigCredentials::new(),
+        }
+    }
+
+    /// Workarounds currently applied
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    /// Replace the workarounds to apply
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Query the firmware version and the display model, and enable the workarounds they need.
+    /// Glasses not giving their display model only get the workarounds of every model.
+    pub fn detect_quirks(&mut self) -> Result<&Quirks, GlassesError> {
+        let response = self
+            .client
+            .send_command_expect_response(&Command::Version)?;
+        let version = FirmwareVersion::from_response(&response)
+            .ok_or(GlassesError::UnexpectedResponse(response))?;
+        let model = match self.device_info(DeviceInfo::DisplayModel) {
+            Ok(DeviceInfoValue::Text(model)) => Some(model),
+            Ok(_) | Err(GlassesError::Command(_)) => None,
+            Err(error) => return Err(error),
+        };
+        self.quirks = Quirks::for_device(version, model.as_deref());
+        Ok(&self.quirks)
+    }
+
+    /// Display `text` in layout `id` without flickering.
+    ///
+    /// The layout area is only cleared when the previous text was longer, since a longer or equal
+    /// text drawn with an opaque background covers it. When clearing is needed,
+    /// [Command::LayoutClearAndDisplay] is used if the firmware supports it.
+    /// Nothing is sent if the text did not change.
+    pub fn update_field(&mut self, id: u8, text: &str) -> Result<(), GlassesError> {
+        let previous = self.fields.get(&id).map(String::as_str);
+        for cmd in field_update_commands(id, previous, text, &self.quirks) {
+            self.client.send(&cmd)?;
+        }
+        self.fields.insert(id, String::from(text));
+        Ok(())
+    }
+
+    /// Passwords used to write and rename configurations
+    pub fn credentials(&mut self) -> &mut ConfigCredentials {
+        &mut self.credentials
+    }
+
+    /// Start modifying configuration `name` with its stored password, see [ConfigSession]
+    pub fn open_config(
+        &mut self,
+        name: &str,
+        version: u32,
+    ) -> Result<ConfigSession<'_, Self>, SessionError> {
+        let password = self.credentials.password(name);
+        ConfigSession::open(self, name, version, password)
+    }
+
+    /// Rename configuration `old` with its stored password, keeping the password for `new`
+    pub fn rename_config(&mut self, old: &str, new: &str) -> Result<(), GlassesError> {
+        let password = self.credentials.password(old);
+        let rename = Command::CfgRename {
+            old: String::from(old),
+            new: String::from(new),
+            password,
+        };
+        self.client
+            .send(&rename)
+            .map_err(|error| auth_error(old, &rename, GlassesError::from(error)))?;
+        self.credentials.rename(old, new);
+        Ok(())
+    }
+
+    /// Change the password of `config` stored in the glasses, see [Config::change_password].
+    /// The new password is stored in [Glasses::credentials].
+    pub fn change_config_password(
+        &mut self,
+        config: &Config,
+        password: u32,
+    ) -> Result<Config, GlassesError> {
+        let config = config.change_password(self, password)?;
+        self.credentials.insert(&config.name, password);
+        Ok(config)
+    }
+
+    /// Access the underlying client, for lower level operations
+    pub fn client(&mut self) -> &mut ActiveLookClient<TxActiveLook, RxActiveLook, Ctrl> {
+        &mut self.client
+    }
+}
+
+impl<TxActiveLook, RxActiveLook, Ctrl> GlassesApi for Glasses<TxActiveLook, RxActiveLook, Ctrl>
+where
+    TxActiveLook: Read,
+    RxActiveLook: Write,
+    Ctrl: Read + ReadReady,
+{
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        match cmd {
+            Command::LayoutDisplay { id, .. }
+                if self.quirks.contains(Quirk::LayoutDisplayNeedsClear) =>
+            {
+                self.client.send(&Command::LayoutClear { id: *id })?;
+            }
+            // The text displayed by update_field is gone
+            Command::Clear => self.fields.clear(),
+            Command::LayoutClear { id } => {
+                self.fields.remove(id);
+            }
+            _ => {}
+        }
+        Ok(self.client.send(cmd)?)
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        let response = self.client.send_command_expect_response(cmd)?;
+        if let Some(error) = CommandError::from_response(&response) {
+            return Err(GlassesError::Command(error));
+        }
+        Ok(response)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        Ok(self.client.send_chunked(cmd, PACKET_DATA_MAX_SIZE)?)
+    }
+
+    fn can_send(&mut self) -> bool {
+        // Errors are reported by the next command
+        let _ = self.client.poll_ctrl();
+        self.client.can_send()
+    }
+}
+
+/// Commands updating the text of layout `id` from `previous`, unknown if `None`
+fn field_update_commands(
+    id: u8,
+    previous: Option<&str>,
+    text: &str,
+    quirks: &Quirks,
+) -> Vec<Command> {
+    let text = String::from(text);
+    let needs_clear = match previous {
+        Some(previous) if previous == text => return Vec::new(),
+        Some(previous) => previous.chars().count() > text.chars().count(),
+        None => true,
+    };
+    match needs_clear {
+        false => vec![Command::LayoutDisplay { id, text }],
+        // Firmware without LayoutClearAndDisplay
+        true if quirks.contains(Quirk::LayoutDisplayNeedsClear) => vec![
+            Command::LayoutClear { id },
+            Command::LayoutDisplay { id, text },
+        ],
+        true => vec![Command::LayoutClearAndDisplay { id, text }],
+    }
+}
+
+/// Local preview of what would be displayed on the glasses.
+///
+/// Commands are applied by an [Emulator]: the screen is rendered into its
+/// [crate::framebuffer::Framebuffer], queries are answered like real glasses would, and commands
+/// the firmware rejects fail with [GlassesError::Command]. The commands sent are also stacked
+/// until the next [Command::Clear], so the current content of the screen can be inspected
+/// without any transport.
+#[derive(Debug, Default)]
+pub struct Preview {
+    emulator: Emulator,
+    displayed: Vec<Command>,
+}
+
+impl Preview {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Preview applying the commands to `emulator`, to start with its configurations
+    pub fn with_emulator(emulator: Emulator) -> Self {
+        Self {
+            emulator,
+            displayed: Vec::new(),
+        }
+    }
+
+    /// Commands drawn since the last [Command::Clear]
+    pub fn displayed(&self) -> &[Command] {
+        &self.displayed
+    }
+
+    /// Emulated glasses, with the rendered screen and the saved elements
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    /// Apply `cmd`, returning its response if any
+    fn apply(&mut self, cmd: &Command) -> Result<Option<Response>, GlassesError> {
+        let response = self.emulator.handle(cmd);
+        if let Some(error) = response.as_ref().and_then(CommandError::from_response) {
+            return Err(GlassesError::Command(error));
+        }
+        Ok(response)
+    }
+}
+
+impl GlassesApi for Preview {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.apply(cmd)?;
+        match cmd {
+            Command::Clear => self.displayed.clear(),
+            cmd => self.displayed.push(cmd.clone()),
+        }
+        Ok(())
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.apply(cmd)?.ok_or(GlassesError::Unsupported)
+    }
+}
+
+/// Glasses accepting every command and doing nothing.
+#[derive(Debug, Default)]
+pub struct NoopGlasses;
+
+impl GlassesApi for NoopGlasses {
+    fn send(&mut self, _cmd: &Command) -> Result<(), GlassesError> {
+        Ok(())
+    }
+
+    fn query(&mut self, _cmd: &Command) -> Result<Response, GlassesError> {
+        Err(GlassesError::Unsupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::FontItem,
+        protocol::{CommandPacket, Packet, PacketAssembler},
+    };
+    use core::convert::Infallible;
+    use embedded_io::ErrorType;
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    /// Delivers the scripted bytes, then nothing
+    struct Script(VecDeque<u8>);
+
+    impl ErrorType for Script {
+        type Error = Infallible;
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.0.len());
+            for (byte, scripted) in buf.iter_mut().zip(self.0.drain(..len)) {
+                *byte = scripted;
+            }
+            Ok(len)
+        }
+    }
+
+    impl ReadReady for Script {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(!self.0.is_empty())
+        }
+    }
+
+    /// Records the commands written, shared with the test
+    #[derive(Clone, Default)]
+    struct Written(Rc<RefCell<Vec<u8>>>);
+
+    impl Written {
+        fn commands(&self) -> Vec<Command> {
+            let mut assembler = PacketAssembler::new();
+            assembler.push(&self.0.borrow());
+            let mut commands = Vec::new();
+            while let Ok(Some(bytes)) = assembler.next_packet() {
+                commands.push(CommandPacket::from_bytes(&bytes).unwrap().data);
+            }
+            commands
+        }
+    }
+
+    impl ErrorType for Written {
+        type Error = Infallible;
+    }
+
+    impl Write for Written {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// Glasses answering the queries with `responses`, in order
+    fn scripted(responses: &[Response]) -> (Glasses<Script, Written, Script>, Written) {
+        let mut bytes = Vec::new();
+        for (query_id, response) in (1u32..).zip(responses) {
+            let packet = Packet::new_with_query_id(response, &query_id.to_be_bytes()).unwrap();
+            bytes.extend(packet.to_bytes());
+        }
+        let written = Written::default();
+        let client = ActiveLookClient::new(
+            Script(bytes.into()),
+            written.clone(),
+            Script(VecDeque::new()),
+        );
+        (Glasses::new(client), written)
+    }
+
+    /// Glasses storing fonts, built-in fonts 0 to 3 can not be deleted.
+    /// `stuck` fonts are never deleted.
+    struct FontStore {
+        fonts: Vec<u8>,
+        stuck: Vec<u8>,
+    }
+
+    impl GlassesApi for FontStore {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            if let Command::FontDelete { id } = cmd {
+                let stuck = &self.stuck;
+                self.fonts.retain(|font| {
+                    *font <= 3 || stuck.contains(font) || id.id().is_some_and(|id| *font != id)
+                });
+            }
+            Ok(())
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            match cmd {
+                Command::FontList => Ok(Response::FontList {
+                    list: self
+                        .fonts
+                        .iter()
+                        .map(|id| FontItem {
+                            id: *id,
+                            height: 24,
+                        })
+                        .collect(),
+                }),
+                _ => Err(GlassesError::Unsupported),
+            }
+        }
+    }
+
+    /// Application code only depends on the trait
+    fn draw_speed(glasses: &mut impl GlassesApi, speed: u16) -> Result<(), GlassesError> {
+        glasses.clear()?;
+        glasses.layout_display(1, &speed.to_string())
+    }
+
+    #[test]
+    fn test_field_update() {
+        let quirks = Quirks::none();
+        let display = |text: &str| Command::LayoutDisplay {
+            id: 3,
+            text: String::from(text),
+        };
+        assert_eq!(
+            vec![Command::LayoutClearAndDisplay {
+                id: 3,
+                text: String::from("12")
+            }],
+            field_update_commands(3, None, "12", &quirks)
+        );
+        assert_eq!(
+            vec![display("13")],
+            field_update_commands(3, Some("12"), "13", &quirks)
+        );
+        assert!(field_update_commands(3, Some("13"), "13", &quirks).is_empty());
+
+        let old = Quirks::for_device(FirmwareVersion::new(3, 7, 0), None);
+        assert_eq!(
+            vec![Command::LayoutClear { id: 3 }, display("9")],
+            field_update_commands(3, Some("10"), "9", &old)
+        );
+        assert_eq!(
+            vec![display("100")],
+            field_update_commands(3, Some("99"), "100", &old)
+        );
+    }
+
+    #[test]
+    fn test_quirks_applied() {
+        let version = Response::Version {
+            fw_version: [3, 7, 4, b'b'],
+            mfc_year: 22,
+            mfc_week: 10,
+            serial_number: [1, 2, 3],
+        };
+        let model = Response::RdDevInfo {
+            parameters: b"D1".to_vec(),
+        };
+        let (mut glasses, written) = scripted(&[version, model]);
+        assert!(glasses
+            .detect_quirks()
+            .unwrap()
+            .contains(Quirk::LayoutDisplayNeedsClear));
+        glasses.layout_display(2, "12").unwrap();
+        assert_eq!(
+            vec![
+                Command::Version,
+                Command::Info {
+                    id: DeviceInfo::DisplayModel
+                },
+                Command::LayoutClear { id: 2 },
+                Command::LayoutDisplay {
+                    id: 2,
+                    text: String::from("12")
+                },
+            ],
+            written.commands()
+        );
+
+        // Without the workaround, the command is sent as is
+        let (mut glasses, written) = scripted(&[]);
+        glasses.layout_display(2, "12").unwrap();
+        assert_eq!(1, written.commands().len());
+    }
+
+    #[test]
+    fn test_noop() {
+        let mut glasses = NoopGlasses;
+        assert!(draw_speed(&mut glasses, 42).is_ok());
+        assert_eq!(Err(GlassesError::Unsupported), glasses.battery());
+    }
+
+    #[test]
+    fn test_preview() {
+        let mut preview = Preview::new();
+        draw_speed(&mut preview, 12).unwrap();
+        draw_speed(&mut preview, 42).unwrap();
+        assert_eq!(
+            preview.displayed(),
+            &[Command::LayoutDisplay {
+                id: 1,
+                text: String::from("42")
+            }]
+        );
+    }
+
+    #[test]
+    fn test_preview_emulated() {
+        let mut preview = Preview::new();
+        preview
+            .send(&Command::Line {
+                from: Point { x: 10, y: 10 },
+                to: Point { x: 20, y: 10 },
+            })
+            .unwrap();
+        assert_eq!(11, preview.emulator().framebuffer().lit_pixels());
+        assert!(preview.battery().is_ok());
+
+        // Rejected like on the glasses
+        let error = preview
+            .send(&Command::ImgDisplay {
+                id: 1,
+                coord: Point { x: 0, y: 0 },
+            })
+            .unwrap_err();
+        assert!(matches!(error, GlassesError::Command(_)));
+        assert_eq!(1, preview.displayed().len());
+    }
+
+    #[test]
+    fn test_gauge_percent() {
+        let mut preview = Preview::new();
+        preview.display_gauge_percent(2, 150).unwrap();
+        assert_eq!(
+            preview.displayed(),
+            &[Command::GaugeDisplay { id: 2, value: 100 }]
+        );
+    }
+
+    #[test]
+    fn test_polyline() {
+        let mut preview = Preview::new();
+        let points = [Point { x: 0, y: 0 }; crate::polyline::MAX_POINTS + 1];
+        preview
+            .polyline(&Polyline::new(1, &points).unwrap())
+            .unwrap();
+        assert_eq!(2, preview.displayed().len());
+    }
+
+    #[test]
+    fn test_typed_queries() {
+        let mut mock = crate::mock::MockClient::new();
+        mock.expect(Command::Settings).reply(Response::Settings {
+            x: 2,
+            y: -1,
+            luma: crate::commands::Luma::new(12).unwrap(),
+            als_enable: true,
+            gesture_enable: false,
+        });
+        mock.expect(Command::PixelCount)
+            .reply(Response::PixelCount { count: 1234 });
+        mock.expect(Command::Demo {
+            demo_id: DemoID::Rect,
+        });
+        mock.expect(Command::PixelCount)
+            .reply(Response::Battery { level: 50 });
+
+        let settings = mock.settings().unwrap();
+        assert_eq!((2, -1), (settings.x, settings.y));
+        assert_eq!(80, settings.luma_percent());
+        assert_eq!(Ok(1234), mock.pixel_count());
+        mock.run_demo(DemoID::Rect).unwrap();
+        assert_eq!(
+            Err(GlassesError::UnexpectedResponse(Response::Battery {
+                level: 50
+            })),
+            mock.pixel_count()
+        );
+    }
+
+    #[test]
+    fn test_delete_verified() {
+        let mut glasses = FontStore {
+            fonts: vec![0, 1, 2, 3, 5, 6, 7],
+            stuck: vec![7],
+        };
+        assert_eq!(
+            Ok(vec![5]),
+            glasses.delete_font_verified(Selector::one(5).unwrap())
+        );
+        // Already deleted
+        assert_eq!(
+            Ok(vec![]),
+            glasses.delete_font_verified(Selector::one(5).unwrap())
+        );
+        assert_eq!(
+            Err(GlassesError::DeleteFailed(ElementRef::new(
+                ElementKind::Font,
+                7
+            ))),
+            glasses.delete_font_verified(Selector::one(7).unwrap())
+        );
+        // Built-in fonts are kept
+        assert_eq!(Ok(vec![6]), glasses.delete_font_verified(Selector::All));
+        assert_eq!(vec![0, 1, 2, 3, 7], glasses.fonts);
+        assert_eq!(
+            Err(GlassesError::Unsupported),
+            glasses.delete_image_verified(Selector::one(1).unwrap())
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Asynchronous access to blocking glasses, for tokio applications
+//!
+//! [ActiveLookHandle::spawn] moves a [GlassesApi] to a blocking task of the tokio runtime. The
+//! handle is cheap to clone: each clone submits commands to the task through a channel, and
+//! awaits their result on a oneshot channel.
+//!
+//! The task sends the commands one at a time, in the order they were submitted. The handle
+//! limits the number of commands submitted and not answered yet, so a GUI producing frames
+//! faster than the BLE link can carry waits instead of queuing without bound. The limit follows
+//! the flow control of the glasses: while [GlassesApi::can_send] is false, after
+//! [crate::protocol::FlowErrorCtrl::ClientShouldWait], the task holds back every free permit, and
+//! no command is submitted until the glasses accept data again. The commands already submitted
+//! are sent once the client can send.
+//!
+//! A query whose response is lost blocks the task, and every command behind it, until the
+//! client gives up. Set [crate::client::ActiveLookClient::set_response_timeout] before spawning
+//! the glasses: the query then fails with [crate::protocol::ProtocolError::Timeout], and the
+//! task serves the next commands. Without it, the task waits forever.
+//!
+//! Dropping the future of a command cancels it, unless the task already started sending it.
+//! Once every handle is dropped, the task stops after the remaining commands and returns the
+//! glasses.
+//!
+//! Enabled by the `tokio` feature.
+use core::time::Duration;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use thiserror::Error;
+use tokio::{
+    sync::{mpsc, mpsc::error::TryRecvError, oneshot, OwnedSemaphorePermit, Semaphore},
+    task::JoinHandle,
+};
+
+use crate::{
+    commands::{Command, Response},
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// Errors returned by [ActiveLookHandle]
+#[derive(Error, Debug, PartialEq)]
+pub enum HandleError {
+    #[error(transparent)]
+    Glasses(#[from] GlassesError),
+    /// The task owning the glasses stopped, after a panic or when the runtime shut down
+    #[error("The glasses task stopped")]
+    Closed,
+    /// The task answered a query without a response
+    #[error("No response to the query")]
+    NoResponse,
+}
+
+/// How the task sends a command
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Kind {
+    Send,
+    SendChunked,
+    Query,
+}
+
+/// Command submitted to the task
+struct Request {
+    cmd: Command,
+    kind: Kind,
+    reply: oneshot::Sender<Result<Option<Response>, GlassesError>>,
+    /// Released once the command is answered
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Send the command of `request` and reply, unless it was cancelled
+fn serve<G: GlassesApi>(glasses: &mut G, request: Request) {
+    if request.reply.is_closed() {
+        return;
+    }
+    let result = match request.kind {
+        Kind::Send => glasses.send(&request.cmd).map(|_| None),
+        Kind::SendChunked => glasses.send_chunked(&request.cmd).map(|_| None),
+        Kind::Query => glasses.query(&request.cmd).map(Some),
+    };
+    // The caller may have given up in the meantime
+    let _ = request.reply.send(result);
+}
+
+/// Interval between two checks of the flow control, while the glasses ask to wait
+const FLOW_CONTROL_POLL: Duration = Duration::from_millis(10);
+
+/// Serve the requests until every handle is dropped. While the glasses ask to wait, the free
+/// permits of `in_flight` are held back, and their number stored in `held`.
+fn run<G: GlassesApi>(
+    mut glasses: G,
+    mut receiver: mpsc::UnboundedReceiver<Request>,
+    in_flight: Arc<Semaphore>,
+    held: Arc<AtomicUsize>,
+) -> G {
+    let mut held_back: Vec<OwnedSemaphorePermit> = Vec::new();
+    loop {
+        if glasses.can_send() {
+            held_back.clear();
+        } else {
+            let available = u32::try_from(in_flight.available_permits()).unwrap_or(u32::MAX);
+            if let (1.., Ok(permits)) = (
+                available,
+                Arc::clone(&in_flight).try_acquire_many_owned(available),
+            ) {
+                held_back.push(permits);
+            }
+        }
+        let count = held_back
+            .iter()
+            .map(OwnedSemaphorePermit::num_permits)
+            .sum();
+        held.store(count, Ordering::Relaxed);
+        // Nothing can be submitted while permits are held back: poll the flow control
+        let request = match held_back.is_empty() {
+            true => receiver.blocking_recv(),
+            false => match receiver.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => {
+                    std::thread::sleep(FLOW_CONTROL_POLL);
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => None,
+            },
+        };
+        match request {
+            Some(request) => serve(&mut glasses, request),
+            None => return glasses,
+        }
+    }
+}
+
+/// Submits commands to glasses owned by a background task, see the [module](self) documentation
+#[derive(Clone)]
+pub struct ActiveLookHandle {
+    requests: mpsc::UnboundedSender<Request>,
+    in_flight: Arc<Semaphore>,
+    /// Permits held back by the task while the glasses ask to wait
+    held: Arc<AtomicUsize>,
+    max_in_flight: usize,
+}
+
+impl ActiveLookHandle {
+    /// Default limit of commands submitted and not answered yet
+    pub const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+
+    /// Move `glasses` to a blocking task, accepting at most `max_in_flight` commands not
+    /// answered yet. The task returns the glasses once every handle is dropped.
+    ///
+    /// No command is submitted while the glasses ask to wait, and lost responses block the task
+    /// unless the client has a response timeout, see the [module](self) documentation.
+    ///
+    /// Must be called from a tokio runtime.
+    pub fn spawn<G>(glasses: G, max_in_flight: usize) -> (Self, JoinHandle<G>)
+    where
+        G: GlassesApi + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::unbounded_channel();
+        let max_in_flight = max_in_flight.max(1);
+        let handle = Self {
+            requests,
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            held: Arc::new(AtomicUsize::new(0)),
+            max_in_flight,
+        };
+        let in_flight = Arc::clone(&handle.in_flight);
+        let held = Arc::clone(&handle.held);
+        let task = tokio::task::spawn_blocking(move || run(glasses, receiver, in_flight, held));
+        (handle, task)
+    }
+
+    /// Send `cmd`, see [GlassesApi::send]
+    pub async fn send(&self, cmd: Command) -> Result<(), HandleError> {
+        self.submit(cmd, Kind::Send).await.map(|_| ())
+    }
+
+    /// Send `cmd` split in multiple packets, see [GlassesApi::send_chunked]
+    pub async fn send_chunked(&self, cmd: Command) -> Result<(), HandleError> {
+        self.submit(cmd, Kind::SendChunked).await.map(|_| ())
+    }
+
+    /// Send `cmd` and wait for its response, see [GlassesApi::query]
+    pub async fn query(&self, cmd: Command) -> Result<Response, HandleError> {
+        self.submit(cmd, Kind::Query)
+            .await?
+            .ok_or(HandleError::NoResponse)
+    }
+
+    /// Number of commands submitted and not answered yet, by every clone of the handle
+    pub fn in_flight(&self) -> usize {
+        let held = self.held.load(Ordering::Relaxed);
+        (self.max_in_flight - self.in_flight.available_permits()).saturating_sub(held)
+    }
+
+    /// Returns true while the task holds back the permits because the glasses ask to wait: no
+    /// command can be submitted
+    pub fn is_paused(&self) -> bool {
+        self.held.load(Ordering::Relaxed) > 0
+    }
+
+    /// Wait for room under the in-flight limit, submit the command and wait for its result
+    async fn submit(&self, cmd: Command, kind: Kind) -> Result<Option<Response>, HandleError> {
+        let permit = Arc::clone(&self.in_flight)
+            .acquire_owned()
+            .await
+            .map_err(|_| HandleError::Closed)?;
+        let (reply, result) = oneshot::channel();
+        self.requests
+            .send(Request {
+                cmd,
+                kind,
+                reply,
+                _permit: permit,
+            })
+            .map_err(|_| HandleError::Closed)?;
+        Ok(result.await.map_err(|_| HandleError::Closed)??)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::{mock::MockClient, protocol::ProtocolError};
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_handle() {
+        let mut mock = MockClient::new();
+        mock.expect(Command::Clear);
+        mock.expect(Command::Battery)
+            .reply(Response::Battery { level: 42 });
+        mock.expect(Command::Settings)
+            .fail(GlassesError::Unsupported);
+
+        runtime().block_on(async {
+            let (handle, task) = ActiveLookHandle::spawn(mock, 2);
+            handle.send(Command::Clear).await.unwrap();
+            let other = handle.clone();
+            assert_eq!(
+                Ok(Response::Battery { level: 42 }),
+                other.query(Command::Battery).await
+            );
+            assert_eq!(
+                Err(HandleError::Glasses(GlassesError::Unsupported)),
+                handle.query(Command::Settings).await
+            );
+            assert_eq!(0, handle.in_flight());
+            drop((handle, other));
+            task.await.unwrap().verify();
+        });
+    }
+
+    #[test]
+    fn test_timeout() {
+        let timeout = GlassesError::Protocol(ProtocolError::Timeout(Duration::from_secs(1)));
+        let mut mock = MockClient::new();
+        mock.expect(Command::Battery).fail(timeout);
+        mock.expect(Command::Battery)
+            .reply(Response::Battery { level: 42 });
+
+        runtime().block_on(async {
+            let (handle, task) = ActiveLookHandle::spawn(mock, 1);
+            assert_eq!(
+                Err(HandleError::Glasses(GlassesError::Protocol(
+                    ProtocolError::Timeout(Duration::from_secs(1))
+                ))),
+                handle.query(Command::Battery).await
+            );
+            // The task serves the next commands
+            assert_eq!(
+                Ok(Response::Battery { level: 42 }),
+                handle.query(Command::Battery).await
+            );
+            drop(handle);
+            task.await.unwrap().verify();
+        });
+    }
+
+    /// Glasses asking to wait while `paused` is set
+    struct Pausable {
+        mock: MockClient,
+        paused: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl GlassesApi for Pausable {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            self.mock.send(cmd)
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            self.mock.query(cmd)
+        }
+
+        fn can_send(&mut self) -> bool {
+            !self.paused.load(Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let mut mock = MockClient::new();
+        mock.expect(Command::Clear);
+        let paused = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let glasses = Pausable {
+            mock,
+            paused: Arc::clone(&paused),
+        };
+
+        runtime().block_on(async {
+            let (handle, task) = ActiveLookHandle::spawn(glasses, 2);
+            while !handle.is_paused() {
+                std::thread::yield_now();
+            }
+            // Every permit is held back
+            assert!(handle.in_flight.try_acquire().is_err());
+            assert_eq!(0, handle.in_flight());
+
+            paused.store(false, Ordering::Relaxed);
+            handle.send(Command::Clear).await.unwrap();
+            assert!(!handle.is_paused());
+            drop(handle);
+            task.await.unwrap().mock.verify();
+        });
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let (reply, result) = oneshot::channel();
+        let request = Request {
+            cmd: Command::Clear,
+            kind: Kind::Send,
+            reply,
+            _permit: Arc::clone(&semaphore).try_acquire_owned().unwrap(),
+        };
+        drop(result);
+        // Nothing is expected: the command is not sent
+        let mut mock = MockClient::new();
+        serve(&mut mock, request);
+        mock.verify();
+        assert_eq!(1, semaphore.available_permits());
+    }
+}
@@ -0,0 +1,214 @@
+//! Automatic display power management
+//!
+//! [IdleManager] wraps a [GlassesApi] and powers the display down (or dims it) when no display
+//! command was sent for a while. The display is restored on the next draw.
+use core::time::Duration;
+
+use crate::{
+    commands::{Command, Luma, Response},
+    glasses::{GlassesApi, GlassesError},
+    time::Clock,
+};
+
+/// What to do when the display is idle
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IdleAction {
+    /// Power the display off
+    PowerOff,
+    /// Lower the luminance to `luma`
+    Dim { luma: Luma },
+}
+
+/// Returns true if `cmd` changes the content of the display
+fn is_display_command(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Clear
+            | Command::Grey { .. }
+            | Command::Demo { .. }
+            | Command::Point { .. }
+            | Command::Line { .. }
+            | Command::Rect { .. }
+            | Command::RectFull { .. }
+            | Command::Circ { .. }
+            | Command::CircFull { .. }
+            | Command::Txt { .. }
+            | Command::Polyline { .. }
+            | Command::Arc { .. }
+            | Command::ImgDisplay { .. }
+            | Command::ImgStream { .. }
+            | Command::LayoutDisplay { .. }
+            | Command::LayoutClear { .. }
+            | Command::LayoutDisplayExtended { .. }
+            | Command::LayoutClearExtended { .. }
+            | Command::LayoutClearAndDisplay { .. }
+            | Command::LayoutClearAndDisplayExtended { .. }
+            | Command::GaugeDisplay { .. }
+            | Command::PageDisplay { .. }
+            | Command::PageClear { .. }
+            | Command::PageClearAndDisplay { .. }
+            | Command::AnimDisplay { .. }
+    )
+}
+
+/// Powers down the display after `timeout` without display commands
+pub struct IdleManager<G, C>
+where
+    G: GlassesApi,
+    C: Clock,
+{
+    glasses: G,
+    clock: C,
+    timeout: Duration,
+    action: IdleAction,
+    last_activity: Duration,
+    idle: bool,
+    /// Luminance to restore after [IdleAction::Dim]
+    luma: Luma,
+}
+
+impl<G, C> IdleManager<G, C>
+where
+    G: GlassesApi,
+    C: Clock,
+{
+    /// Luminance restored if none was set through the manager
+    pub const DEFAULT_LUMA: Luma = Luma::MAX;
+
+    pub fn new(glasses: G, clock: C, timeout: Duration, action: IdleAction) -> Self {
+        let last_activity = clock.now();
+        Self {
+            glasses,
+            clock,
+            timeout,
+            action,
+            last_activity,
+            idle: false,
+            luma: Self::DEFAULT_LUMA,
+        }
+    }
+
+    /// Returns true if the display is currently powered down or dimmed
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Access the wrapped glasses
+    pub fn glasses(&mut self) -> &mut G {
+        &mut self.glasses
+    }
+
+    /// Must be called periodically: powers down the display if the timeout expired.
+    /// Returns true if the display is idle.
+    pub fn poll(&mut self) -> Result<bool, GlassesError> {
+        if !self.idle && self.clock.now().saturating_sub(self.last_activity) >= self.timeout {
+            match self.action {
+                IdleAction::PowerOff => self.glasses.send(&Command::PowerDisplay { en: false })?,
+                IdleAction::Dim { luma } => self.glasses.send(&Command::Luma { level: luma })?,
+            }
+            self.idle = true;
+        }
+        Ok(self.idle)
+    }
+
+    /// Restore the display, if it was powered down
+    pub fn wake(&mut self) -> Result<(), GlassesError> {
+        self.last_activity = self.clock.now();
+        if self.idle {
+            match self.action {
+                IdleAction::PowerOff => self.glasses.send(&Command::PowerDisplay { en: true })?,
+                IdleAction::Dim { .. } => self.glasses.send(&Command::Luma { level: self.luma })?,
+            }
+            self.idle = false;
+        }
+        Ok(())
+    }
+}
+
+impl<G, C> GlassesApi for IdleManager<G, C>
+where
+    G: GlassesApi,
+    C: Clock,
+{
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        match cmd {
+            Command::Luma { level } => {
+                self.luma = *level;
+                self.wake()?;
+            }
+            cmd if is_display_command(cmd) => self.wake()?,
+            _ => {}
+        }
+        self.glasses.send(cmd)
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.glasses.query(cmd)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.glasses.send_chunked(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{glasses::Preview, time::VirtualClock};
+
+    #[test]
+    fn test_power_off_and_restore() {
+        let clock = VirtualClock::new();
+        let mut manager = IdleManager::new(
+            Preview::new(),
+            clock.clone(),
+            Duration::from_secs(10),
+            IdleAction::PowerOff,
+        );
+
+        manager.layout_display(1, "42").unwrap();
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(Ok(false), manager.poll());
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(Ok(true), manager.poll());
+        assert_eq!(
+            Some(&Command::PowerDisplay { en: false }),
+            manager.glasses().displayed().last()
+        );
+
+        manager.layout_display(1, "43").unwrap();
+        assert!(!manager.is_idle());
+        let displayed = manager.glasses().displayed();
+        assert_eq!(
+            Command::PowerDisplay { en: true },
+            displayed[displayed.len() - 2]
+        );
+    }
+
+    #[test]
+    fn test_dim_restores_luma() {
+        let clock = VirtualClock::new();
+        let mut manager = IdleManager::new(
+            Preview::new(),
+            clock.clone(),
+            Duration::from_secs(1),
+            IdleAction::Dim { luma: 2.into() },
+        );
+        manager.send(&Command::Luma { level: 9.into() }).unwrap();
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(Ok(true), manager.poll());
+        manager.layout_display(1, "42").unwrap();
+        assert_eq!(
+            &[
+                Command::Luma { level: 9.into() },
+                Command::Luma { level: 2.into() },
+                Command::Luma { level: 9.into() },
+                Command::LayoutDisplay {
+                    id: 1,
+                    text: String::from("42")
+                },
+            ],
+            manager.glasses().displayed()
+        );
+    }
+}
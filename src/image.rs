@@ -1,7 +1,157 @@
-use crate::commands::{Command, ImgFormat, Point, StreamImgFormat};
-use crate::protocol;
-use crate::traits::Serializable;
-use log::*;
+//! Images and partial image updates
+//!
+//! Streaming a full screen image takes hundreds of BLE packets. [ImagePatch] crops the region of
+//! an image which changed, and streams only this region at the matching screen coordinates.
+//!
+//! Only [ImgFormat::Img1bpp] images can be cropped: the other streamable format is compressed.
+//! Lines are padded to a whole byte, and we assume the leftmost pixel of a byte is its least
+//! significant bit, as the leftmost pixel is the low nibble in [ImgFormat::Img4bpp].
+//!
+//! [ImgFormat::Img8bpp] pixels hold a 4 bits grey level and a 4 bits alpha value, built with
+//! [Image::grey_alpha_data] or [Image::rgba_data]. Following the same convention, we assume the
+//! grey level is the low nibble and the alpha value the high nibble.
+//!
+//! [Image::grey_data] quantizes 8 bits grey levels to [ImgFormat::Img1bpp] or
+//! [ImgFormat::Img4bpp], with the [Dithering] and gamma correction of a [GreyConversion]. The
+//! response of the micro-OLED to its 16 grey levels is not documented: no gamma correction is
+//! applied by default, [GreyConversion::SRGB_GAMMA] assumes the levels are linear in luminance.
+use thiserror::Error;
+
+use crate::commands::{Command, Grey, ImgFormat, Point, StreamImgFormat};
+
+/// Errors building image data or an [ImagePatch]
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum ImageError {
+    #[error("Image format {0:?} cannot be cropped")]
+    Format(ImgFormat),
+    /// Images, or planes of an image, have different sizes
+    #[error("Images have different sizes")]
+    SizeMismatch,
+    #[error("Region {0:?} is outside of the image")]
+    OutOfBounds(Region),
+}
+
+/// Algorithm spreading the quantization error of grey levels
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Dithering {
+    /// Nearest level, without dithering: best for text and line art
+    Threshold,
+    /// 4x4 Bayer matrix: a regular pattern, stable when the image is partially updated
+    Ordered,
+    /// Floyd–Steinberg error diffusion: the smoothest gradients for photos
+    #[default]
+    FloydSteinberg,
+}
+
+/// 4x4 Bayer matrix, thresholds in sixteenths
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Parameters converting 8 bits grey levels to the formats of the glasses, see
+/// [Image::grey_data]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GreyConversion {
+    dithering: Dithering,
+    gamma: f32,
+    contrast: f32,
+    grey_range: (Grey, Grey),
+}
+
+impl Default for GreyConversion {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GreyConversion {
+    /// Gamma of sRGB content, converted to levels linear in luminance
+    pub const SRGB_GAMMA: f32 = 2.2;
+
+    /// [Dithering::FloydSteinberg], without gamma correction
+    pub fn new() -> Self {
+        Self {
+            dithering: Dithering::default(),
+            gamma: 1.0,
+            contrast: 1.0,
+            grey_range: (Grey::BLACK, Grey::WHITE),
+        }
+    }
+
+    pub fn dithering(mut self, dithering: Dithering) -> Self {
+        self.dithering = dithering;
+        self
+    }
+
+    /// Grey levels `v` are converted to `(v / 255) ^ gamma` before quantization
+    pub fn gamma(mut self, gamma: f32) -> Self {
+        self.gamma = gamma;
+        self
+    }
+
+    /// Stretch the grey levels around mid-grey by `contrast` before the gamma correction,
+    /// clipping to black and white. 1.0 keeps them unchanged.
+    pub fn contrast(mut self, contrast: f32) -> Self {
+        self.contrast = contrast;
+        self
+    }
+
+    /// Map black and white to `min` and `max`, for [ImgFormat::Img4bpp] images
+    pub fn grey_range(mut self, min: Grey, max: Grey) -> Self {
+        let (min, max) = (min.min(max), max.max(min).min(Grey::WHITE));
+        self.grey_range = (min.min(max), max);
+        self
+    }
+
+    /// Quantize `grey` pixels, lines of `width` pixels, to `levels` levels
+    fn quantize(&self, grey: &[u8], width: usize, levels: u8) -> Vec<u8> {
+        let max = (levels - 1) as f32;
+        let mut values: Vec<f32> = grey
+            .iter()
+            .map(|&v| {
+                let v = ((v as f32 / 255.0 - 0.5) * self.contrast + 0.5).clamp(0.0, 1.0);
+                v.powf(self.gamma) * max
+            })
+            .collect();
+        let mut res = Vec::with_capacity(values.len());
+        for i in 0..values.len() {
+            let (x, y) = (i % width, i / width);
+            let value = values[i];
+            let level = match self.dithering {
+                Dithering::Threshold | Dithering::FloydSteinberg => value.round(),
+                Dithering::Ordered => {
+                    let threshold = (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0;
+                    (value + threshold).floor()
+                }
+            }
+            .clamp(0.0, max);
+            if self.dithering == Dithering::FloydSteinberg {
+                let error = value - level;
+                let mut spread = |dx: isize, dy: usize, weight: f32| {
+                    let x = x as isize + dx;
+                    if (0..width as isize).contains(&x) {
+                        if let Some(value) = values.get_mut((y + dy) * width + x as usize) {
+                            *value += error * weight / 16.0;
+                        }
+                    }
+                };
+                spread(1, 0, 7.0);
+                spread(-1, 1, 3.0);
+                spread(0, 1, 5.0);
+                spread(1, 1, 1.0);
+            }
+            res.push(level as u8);
+        }
+        res
+    }
+}
+
+/// Rectangle of an image, in pixels
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Region {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
 
 /// Contains an image
 pub struct Image<'a> {
@@ -11,4 +161,396 @@ pub struct Image<'a> {
     //pub coord: Point,
 }
 
-impl<'a> Image<'a> {}
+impl<'a> Image<'a> {
+    /// Command saving this image as `id`
+    pub fn save_command(&self, id: u8) -> Command {
+        Command::ImgSave {
+            id,
+            size: self.data.len() as u32,
+            width: self.width,
+            format: self.format,
+            data: self.data.to_vec(),
+        }
+    }
+
+    /// Command displaying this image at `coord` without saving it, if its format can be streamed
+    pub fn stream_command(&self, coord: Point) -> Option<Command> {
+        let format = StreamImgFormat::try_from(self.format).ok()?;
+        Some(Command::ImgStream {
+            size: self.data.len() as u32,
+            width: self.width,
+            coord,
+            format,
+            data: self.data.to_vec(),
+        })
+    }
+}
+
+impl Image<'_> {
+    /// [ImgFormat::Img8bpp] data from 8 bits grey and alpha planes, quantized to 4 bits
+    pub fn grey_alpha_data(grey: &[u8], alpha: &[u8]) -> Result<Vec<u8>, ImageError> {
+        if grey.len() != alpha.len() {
+            return Err(ImageError::SizeMismatch);
+        }
+        Ok(grey
+            .iter()
+            .zip(alpha)
+            .map(|(&grey, &alpha)| pack_grey_alpha(grey, alpha))
+            .collect())
+    }
+
+    /// [ImgFormat::Img8bpp] data from RGBA pixels, 4 bytes each.
+    /// Colors are converted to grey with the ITU-R BT.601 luma weights.
+    pub fn rgba_data(rgba: &[u8]) -> Result<Vec<u8>, ImageError> {
+        if !rgba.len().is_multiple_of(4) {
+            return Err(ImageError::SizeMismatch);
+        }
+        Ok(rgba
+            .chunks_exact(4)
+            .map(|px| {
+                let luma = (299 * px[0] as u32 + 587 * px[1] as u32 + 114 * px[2] as u32) / 1000;
+                pack_grey_alpha(luma as u8, px[3])
+            })
+            .collect())
+    }
+
+    /// [ImgFormat::Img1bpp] or [ImgFormat::Img4bpp] data from 8 bits grey levels, lines of
+    /// `width` pixels
+    pub fn grey_data(
+        grey: &[u8],
+        width: u16,
+        format: ImgFormat,
+        conversion: &GreyConversion,
+    ) -> Result<Vec<u8>, ImageError> {
+        let (min, max) = conversion.grey_range;
+        let (offset, levels, pixels_per_byte) = match format {
+            ImgFormat::Img1bpp => (0, 2, 8),
+            ImgFormat::Img4bpp => (min.value(), max.value() - min.value() + 1, 2),
+            format => return Err(ImageError::Format(format)),
+        };
+        let width = width as usize;
+        if width == 0 || !grey.len().is_multiple_of(width) {
+            return Err(ImageError::SizeMismatch);
+        }
+        let bits = 8 / pixels_per_byte;
+        let levels = conversion.quantize(grey, width, levels);
+        Ok(levels
+            .chunks(width)
+            .flat_map(|line| {
+                line.chunks(pixels_per_byte).map(|pixels| {
+                    pixels
+                        .iter()
+                        .enumerate()
+                        .fold(0, |byte, (i, level)| byte | (level + offset) << (i * bits))
+                })
+            })
+            .collect())
+    }
+
+    /// Grey level and alpha value, both 4 bits, of the [ImgFormat::Img8bpp] pixel at `x`, `y`
+    pub fn grey_alpha(&self, x: usize, y: usize) -> Option<(Grey, u8)> {
+        if self.format != ImgFormat::Img8bpp || x >= self.width as usize {
+            return None;
+        }
+        let pixel = *self.data.get(y * self.line_len() + x)?;
+        Some((Grey::from(pixel & 0x0F), pixel >> 4))
+    }
+
+    fn line_len(&self) -> usize {
+        self.format.nb_of_bytes(self.width as usize)
+    }
+
+    /// Number of lines of the image, for uncompressed formats
+    pub fn height(&self) -> usize {
+        match self.line_len() {
+            0 => 0,
+            line_len => self.data.len() / line_len,
+        }
+    }
+
+    /// Value of the 1bpp pixel at `x`, `y`
+    fn pixel(&self, x: usize, y: usize) -> bool {
+        let byte = self.data[y * self.line_len() + x / 8];
+        (byte >> (x % 8)) & 1 == 1
+    }
+}
+
+/// [ImgFormat::Img8bpp] pixel from 8 bits levels
+fn pack_grey_alpha(grey: u8, alpha: u8) -> u8 {
+    (alpha & 0xF0) | (grey >> 4)
+}
+
+/// Region of an image, streamed in place of the full image
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ImagePatch {
+    width: u16,
+    coord: Point,
+    data: Vec<u8>,
+}
+
+impl ImagePatch {
+    /// Crop `region` of `image`, the full image being displayed at `origin`
+    pub fn new(image: &Image, origin: Point, region: Region) -> Result<Self, ImageError> {
+        if image.format != ImgFormat::Img1bpp {
+            return Err(ImageError::Format(image.format));
+        }
+        let right = region.x as usize + region.width as usize;
+        let bottom = region.y as usize + region.height as usize;
+        if right > image.width as usize || bottom > image.height() {
+            return Err(ImageError::OutOfBounds(region));
+        }
+
+        let line_len = ImgFormat::Img1bpp.nb_of_bytes(region.width as usize);
+        let mut data = vec![0; line_len * region.height as usize];
+        for y in 0..region.height as usize {
+            for x in 0..region.width as usize {
+                if image.pixel(region.x as usize + x, region.y as usize + y) {
+                    data[y * line_len + x / 8] |= 1 << (x % 8);
+                }
+            }
+        }
+        Ok(Self {
+            width: region.width,
+            coord: Point {
+                x: origin.x.saturating_add(region.x as i16),
+                y: origin.y.saturating_add(region.y as i16),
+            },
+            data,
+        })
+    }
+
+    /// Smallest patch updating `previous` into `current`, both displayed at `origin`.
+    /// Returns `None` if the images are identical.
+    pub fn diff(
+        previous: &Image,
+        current: &Image,
+        origin: Point,
+    ) -> Result<Option<Self>, ImageError> {
+        if previous.format != ImgFormat::Img1bpp {
+            return Err(ImageError::Format(previous.format));
+        }
+        if previous.format != current.format
+            || previous.width != current.width
+            || previous.data.len() != current.data.len()
+        {
+            return Err(ImageError::SizeMismatch);
+        }
+
+        // Bounding box of the changed pixels: left, top, right, bottom
+        let mut changed: Option<(usize, usize, usize, usize)> = None;
+        for y in 0..current.height() {
+            for x in 0..current.width as usize {
+                if previous.pixel(x, y) == current.pixel(x, y) {
+                    continue;
+                }
+                changed = Some(match changed {
+                    None => (x, y, x, y),
+                    Some((l, t, r, b)) => (l.min(x), t.min(y), r.max(x), b.max(y)),
+                });
+            }
+        }
+        let Some((left, top, right, bottom)) = changed else {
+            return Ok(None);
+        };
+        let region = Region {
+            x: left as u16,
+            y: top as u16,
+            width: (right - left + 1) as u16,
+            height: (bottom - top + 1) as u16,
+        };
+        Self::new(current, origin, region).map(Some)
+    }
+
+    /// Screen coordinates of the patch
+    pub fn coord(&self) -> Point {
+        self.coord
+    }
+
+    /// Cropped image, to stream at [ImagePatch::coord] with
+    /// [crate::client::ActiveLookClient::stream_image]
+    pub fn image(&self) -> Image<'_> {
+        Image {
+            width: self.width,
+            format: ImgFormat::Img1bpp,
+            data: &self.data,
+        }
+    }
+
+    /// Command displaying the patch
+    pub fn stream_command(&self) -> Command {
+        Command::ImgStream {
+            size: self.data.len() as u32,
+            width: self.width,
+            coord: self.coord,
+            format: StreamImgFormat::Img1bpp,
+            data: self.data.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16 x 3 pixels
+    const BEFORE: [u8; 6] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    const AFTER: [u8; 6] = [0x00, 0x00, 0x80, 0x01, 0x00, 0x01];
+
+    fn image(data: &[u8]) -> Image<'_> {
+        Image {
+            width: 16,
+            format: ImgFormat::Img1bpp,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_crop() {
+        let region = Region {
+            x: 7,
+            y: 1,
+            width: 2,
+            height: 2,
+        };
+        let origin = Point { x: 100, y: 50 };
+        let patch = ImagePatch::new(&image(&AFTER), origin, region).unwrap();
+        assert_eq!(Point { x: 107, y: 51 }, patch.coord());
+        // Pixels 7 and 8 of lines 1 and 2
+        assert_eq!(&[0x03, 0x02][..], patch.image().data);
+
+        assert_eq!(
+            Err(ImageError::OutOfBounds(Region { x: 15, ..region })),
+            ImagePatch::new(&image(&AFTER), origin, Region { x: 15, ..region })
+        );
+        let gray = Image {
+            format: ImgFormat::Img4bpp,
+            ..image(&AFTER)
+        };
+        assert_eq!(
+            Err(ImageError::Format(ImgFormat::Img4bpp)),
+            ImagePatch::new(&gray, origin, region)
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let origin = Point { x: 0, y: 0 };
+        assert_eq!(
+            Ok(None),
+            ImagePatch::diff(&image(&AFTER), &image(&AFTER), origin)
+        );
+
+        let patch = ImagePatch::diff(&image(&BEFORE), &image(&AFTER), origin)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            Command::ImgStream {
+                size: 2,
+                width: 2,
+                coord: Point { x: 7, y: 1 },
+                format: StreamImgFormat::Img1bpp,
+                data: vec![0x03, 0x02],
+            },
+            patch.stream_command()
+        );
+        assert_eq!(
+            Err(ImageError::SizeMismatch),
+            ImagePatch::diff(&image(&BEFORE), &image(&AFTER[..4]), origin)
+        );
+    }
+
+    #[test]
+    fn test_grey_alpha() {
+        let data = Image::grey_alpha_data(&[0xFF, 0x80, 0x0F], &[0x00, 0xFF, 0x7F]).unwrap();
+        assert_eq!(vec![0x0F, 0xF8, 0x70], data);
+        let image = Image {
+            width: 3,
+            format: ImgFormat::Img8bpp,
+            data: &data,
+        };
+        assert_eq!(Some((Grey::WHITE, 0)), image.grey_alpha(0, 0));
+        assert_eq!(Some((Grey::from(8), 15)), image.grey_alpha(1, 0));
+        assert_eq!(None, image.grey_alpha(3, 0));
+        assert_eq!(
+            Err(ImageError::SizeMismatch),
+            Image::grey_alpha_data(&[0], &[])
+        );
+
+        // Opaque white, half transparent red
+        let rgba = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x80];
+        assert_eq!(Ok(vec![0xFF, 0x84]), Image::rgba_data(&rgba));
+        assert_eq!(Err(ImageError::SizeMismatch), Image::rgba_data(&rgba[..5]));
+    }
+
+    #[test]
+    fn test_grey_data() {
+        // Two lines of an 8 pixels gradient
+        let gradient = [0, 36, 73, 109, 146, 182, 219, 255].repeat(2);
+        let convert = |format, conversion: GreyConversion| {
+            Image::grey_data(&gradient, 8, format, &conversion).unwrap()
+        };
+        let threshold = GreyConversion::new().dithering(Dithering::Threshold);
+        let ordered = GreyConversion::new().dithering(Dithering::Ordered);
+        let floyd_steinberg = GreyConversion::new();
+
+        assert_eq!(vec![0xF0, 0xF0], convert(ImgFormat::Img1bpp, threshold));
+        assert_eq!(vec![0xE8, 0xD4], convert(ImgFormat::Img1bpp, ordered));
+        assert_eq!(
+            vec![0xE8, 0xE8],
+            convert(ImgFormat::Img1bpp, floyd_steinberg)
+        );
+        assert_eq!(
+            vec![0x20, 0x64, 0xB9, 0xFD, 0x20, 0x64, 0xB9, 0xFD],
+            convert(ImgFormat::Img4bpp, threshold)
+        );
+        assert_eq!(
+            vec![0x20, 0x74, 0xB8, 0xFD, 0x20, 0x65, 0xA9, 0xFD],
+            convert(ImgFormat::Img4bpp, ordered)
+        );
+        assert_eq!(
+            vec![0x20, 0x74, 0xB8, 0xFD, 0x20, 0x74, 0xB8, 0xFD],
+            convert(ImgFormat::Img4bpp, floyd_steinberg)
+        );
+        assert_eq!(
+            vec![0x00, 0x21, 0x74, 0xFB, 0x00, 0x21, 0x74, 0xFB],
+            convert(
+                ImgFormat::Img4bpp,
+                threshold.gamma(GreyConversion::SRGB_GAMMA)
+            )
+        );
+        assert_eq!(
+            vec![0x00, 0x51, 0xEA, 0xFF, 0x00, 0x51, 0xEA, 0xFF],
+            convert(ImgFormat::Img4bpp, threshold.contrast(2.0))
+        );
+        // Black and white mapped to levels 2 and 9
+        assert_eq!(
+            vec![0x32, 0x54, 0x76, 0x98, 0x32, 0x54, 0x76, 0x98],
+            convert(
+                ImgFormat::Img4bpp,
+                threshold.grey_range(Grey::new(9).unwrap(), Grey::new(2).unwrap())
+            )
+        );
+        assert_eq!(
+            vec![0xF0, 0xF0],
+            convert(
+                ImgFormat::Img1bpp,
+                threshold.grey_range(Grey::new(2).unwrap(), Grey::new(9).unwrap())
+            )
+        );
+
+        // Lines are padded to a whole byte
+        let conversion = GreyConversion::new();
+        assert_eq!(
+            Ok(vec![0x05, 0x02]),
+            Image::grey_data(&[255, 0, 255, 0, 255, 0], 3, ImgFormat::Img1bpp, &threshold)
+        );
+        assert_eq!(
+            Err(ImageError::SizeMismatch),
+            Image::grey_data(&gradient, 5, ImgFormat::Img4bpp, &conversion)
+        );
+        assert_eq!(
+            Err(ImageError::Format(ImgFormat::Img8bpp)),
+            Image::grey_data(&gradient, 8, ImgFormat::Img8bpp, &conversion)
+        );
+    }
+}
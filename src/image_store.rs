@@ -0,0 +1,289 @@
+//! Allocation of image IDs
+//!
+//! [ImageStore] lists the images of the current configuration with [Command::ImgList], allocates
+//! free IDs for new images, and remembers which image was saved under each ID. Applications name
+//! their images with a key, a file name or a hash, and [ImageStore::ensure_uploaded] only uploads
+//! an image which is not already stored.
+//!
+//! The glasses can not give an image back, and only list its width and height. An image is
+//! considered stored when the same key was uploaded with the same [content_hash], and its ID is
+//! still listed with the same width. Persist [ImageStore::entries] and restore them with
+//! [ImageStore::remember] to skip the uploads across connections.
+//!
+//! Saving images modifies the configuration: upload through a [crate::config::ConfigSession].
+use std::collections::{BTreeMap, BTreeSet};
+
+use thiserror::Error;
+
+use crate::{
+    commands::{Command, ImgListItem, Response, Selector, ALL},
+    glasses::{GlassesApi, GlassesError},
+    image::Image,
+};
+
+/// Errors returned by [ImageStore]
+#[derive(Error, Debug, PartialEq)]
+pub enum ImageStoreError {
+    /// Every image ID is used
+    #[error("No free image ID")]
+    Full,
+    #[error(transparent)]
+    Glasses(#[from] GlassesError),
+}
+
+/// Image uploaded through an [ImageStore]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct StoredImage {
+    pub id: u8,
+    pub width: u16,
+    /// [content_hash] of the image
+    pub hash: u64,
+}
+
+/// 64-bit FNV-1a hash of the format, width and data of `image`. Stable across releases, to
+/// persist it with the [ImageStore] entries.
+pub fn content_hash(image: &Image) -> u64 {
+    const OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01B3;
+    [image.format as u8]
+        .iter()
+        .chain(&image.width.to_be_bytes())
+        .chain(image.data)
+        .fold(OFFSET, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(PRIME)
+        })
+}
+
+/// Image IDs used in the glasses, and images uploaded by key, see the [module](self)
+/// documentation
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ImageStore {
+    /// Images listed by the glasses
+    listed: BTreeMap<u8, ImgListItem>,
+    /// Image uploaded under each key
+    entries: BTreeMap<String, StoredImage>,
+}
+
+impl ImageStore {
+    /// List the images of the current configuration
+    pub fn load<G: GlassesApi + ?Sized>(glasses: &mut G) -> Result<Self, GlassesError> {
+        let mut store = Self::default();
+        store.refresh(glasses)?;
+        Ok(store)
+    }
+
+    /// List the images again, after they were modified without the store. The keys are kept.
+    pub fn refresh<G: GlassesApi + ?Sized>(&mut self, glasses: &mut G) -> Result<(), GlassesError> {
+        self.listed = match glasses.query(&Command::ImgList)? {
+            Response::ImgList { list } => list.into_iter().map(|item| (item.id, item)).collect(),
+            other => return Err(GlassesError::UnexpectedResponse(other)),
+        };
+        Ok(())
+    }
+
+    /// Width and height of image `id`, if it is listed
+    pub fn get(&self, id: u8) -> Option<&ImgListItem> {
+        self.listed.get(&id)
+    }
+
+    /// IDs of the listed images
+    pub fn used_ids(&self) -> BTreeSet<u8> {
+        self.listed.keys().copied().collect()
+    }
+
+    /// Lowest image ID not used. [ALL] is never free: it selects every image.
+    pub fn free_id(&self) -> Option<u8> {
+        (0..ALL).find(|id| !self.listed.contains_key(id))
+    }
+
+    /// Image uploaded under `key`, if it is still listed with the same width
+    pub fn stored(&self, key: &str) -> Option<&StoredImage> {
+        self.entries
+            .get(key)
+            .filter(|stored| self.listed_as(stored))
+    }
+
+    /// Images uploaded by key, to restore them with [ImageStore::remember]
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &StoredImage)> {
+        self.entries
+            .iter()
+            .map(|(key, stored)| (key.as_str(), stored))
+    }
+
+    /// Restore an image uploaded under `key` by an earlier connection
+    pub fn remember(&mut self, key: &str, stored: StoredImage) {
+        self.entries.insert(String::from(key), stored);
+    }
+
+    /// ID of `image` uploaded under `key`, uploading it first if it is not stored.
+    ///
+    /// A changed image keeps its ID: the previous one is deleted first. A new image gets the
+    /// lowest free ID.
+    pub fn ensure_uploaded<G: GlassesApi + ?Sized>(
+        &mut self,
+        glasses: &mut G,
+        key: &str,
+        image: &Image,
+    ) -> Result<u8, ImageStoreError> {
+        let hash = content_hash(image);
+        let previous = self.stored(key).copied();
+        if let Some(stored) = previous.filter(|stored| stored.hash == hash) {
+            return Ok(stored.id);
+        }
+        let id = match previous.and_then(|stored| Selector::one(stored.id)) {
+            Some(selector) => {
+                glasses.send(&Command::ImgDelete { id: selector })?;
+                let id = u8::from(selector);
+                self.listed.remove(&id);
+                id
+            }
+            None => self.free_id().ok_or(ImageStoreError::Full)?,
+        };
+        glasses.send_chunked(&image.save_command(id))?;
+        let item = ImgListItem {
+            id,
+            height: image.height() as u16,
+            width: image.width,
+        };
+        self.listed.insert(id, item);
+        let stored = StoredImage {
+            id,
+            width: image.width,
+            hash,
+        };
+        self.entries.insert(String::from(key), stored);
+        Ok(id)
+    }
+
+    /// Delete the image uploaded under `key`, returning its ID if it was stored
+    pub fn remove<G: GlassesApi + ?Sized>(
+        &mut self,
+        glasses: &mut G,
+        key: &str,
+    ) -> Result<Option<u8>, GlassesError> {
+        let Some(selector) = self.stored(key).and_then(|stored| Selector::one(stored.id)) else {
+            self.entries.remove(key);
+            return Ok(None);
+        };
+        glasses.send(&Command::ImgDelete { id: selector })?;
+        let id = u8::from(selector);
+        self.listed.remove(&id);
+        self.entries.remove(key);
+        Ok(Some(id))
+    }
+
+    fn listed_as(&self, stored: &StoredImage) -> bool {
+        self.listed
+            .get(&stored.id)
+            .is_some_and(|item| item.width == stored.width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::ImgFormat, mock::MockClient};
+
+    const DATA: [u8; 8] = [0x11; 8];
+
+    fn image(data: &[u8]) -> Image<'_> {
+        Image {
+            width: 4,
+            format: ImgFormat::Img4bpp,
+            data,
+        }
+    }
+
+    fn listed(list: Vec<ImgListItem>) -> MockClient {
+        let mut mock = MockClient::new();
+        mock.expect(Command::ImgList)
+            .reply(Response::ImgList { list });
+        mock
+    }
+
+    #[test]
+    fn test_allocation() {
+        let item = |id, width| ImgListItem {
+            id,
+            height: 4,
+            width,
+        };
+        let mut mock = listed(vec![item(0, 4), item(1, 4), item(3, 8)]);
+        let mut store = ImageStore::load(&mut mock).unwrap();
+        assert_eq!(Some(2), store.free_id());
+
+        let logo = image(&DATA);
+        mock.expect(logo.save_command(2));
+        assert_eq!(Ok(2), store.ensure_uploaded(&mut mock, "logo", &logo));
+        assert_eq!(Some(&item(2, 4)), store.get(2));
+        // Already stored
+        assert_eq!(Ok(2), store.ensure_uploaded(&mut mock, "logo", &logo));
+
+        // Changed content keeps the ID
+        let changed = [0x22; 8];
+        mock.expect(Command::ImgDelete {
+            id: Selector::one(2).unwrap(),
+        });
+        mock.expect(image(&changed).save_command(2));
+        assert_eq!(
+            Ok(2),
+            store.ensure_uploaded(&mut mock, "logo", &image(&changed))
+        );
+
+        // Restored from an earlier connection, but replaced by another image since
+        store.remember(
+            "arrow",
+            StoredImage {
+                id: 3,
+                width: 4,
+                hash: content_hash(&logo),
+            },
+        );
+        assert_eq!(None, store.stored("arrow"));
+        mock.expect(logo.save_command(4));
+        assert_eq!(Ok(4), store.ensure_uploaded(&mut mock, "arrow", &logo));
+
+        mock.expect(Command::ImgDelete {
+            id: Selector::one(4).unwrap(),
+        });
+        assert_eq!(Ok(Some(4)), store.remove(&mut mock, "arrow"));
+        assert_eq!(Ok(None), store.remove(&mut mock, "arrow"));
+        assert_eq!(
+            vec![("logo", 2)],
+            store
+                .entries()
+                .map(|(key, stored)| (key, stored.id))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_full() {
+        // Every ID but ALL
+        let list = (0..ALL)
+            .map(|id| ImgListItem {
+                id,
+                height: 1,
+                width: 1,
+            })
+            .collect();
+        let mut mock = listed(list);
+        let mut store = ImageStore::load(&mut mock).unwrap();
+        assert_eq!(None, store.free_id());
+        assert_eq!(
+            Err(ImageStoreError::Full),
+            store.ensure_uploaded(&mut mock, "logo", &image(&DATA))
+        );
+    }
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(content_hash(&image(&DATA)), content_hash(&image(&DATA)));
+        assert_ne!(
+            content_hash(&image(&DATA)),
+            content_hash(&image(&DATA[..4]))
+        );
+        // Format 0, width 4 and no data, must not change between releases
+        assert_eq!(0xD94D_0E18_6C0F_28EB, content_hash(&image(&[])));
+    }
+}
@@ -0,0 +1,367 @@
+//! Construction of layouts
+//!
+//! A layout is a clipping region of the display, with the parameters used to draw a text in it.
+//! It can also hold additional graphical commands, drawn each time the layout is displayed. These
+//! commands are serialized in [LayoutParameters] as a blob of at most 255 bytes, with their own
+//! encoding described by [LayoutCommand].
+//!
+//! [LayoutBuilder] sets the parameters and checks that the result is accepted by the glasses.
+
+use deku::ctx::BitSize;
+use deku::prelude::*;
+use deku::reader::Reader;
+use thiserror::Error;
+
+use crate::charset;
+use crate::commands::{Grey, LayoutParameters, LayoutPosition, Point, TextRotation};
+
+/// Errors building a layout
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum LayoutError {
+    #[error("Color {0} is out of range 0..15")]
+    InvalidColor(u8),
+    #[error("Additional commands take {0} bytes, the maximum is 255")]
+    CommandsTooLong(usize),
+    #[error(transparent)]
+    Serialization(#[from] DekuError),
+}
+
+/// Additional command drawn with the layout.
+/// Coordinates are relative to the layout clipping region.
+#[derive(Clone, Debug, Eq, PartialEq, DekuRead, DekuWrite)]
+#[deku(id_type = "u8", endian = "big")]
+#[repr(u8)]
+pub enum LayoutCommand {
+    /// Display image `id`
+    #[deku(id = "0x00")]
+    Image { id: u8, pos: Point },
+    /// Draw an empty circle
+    #[deku(id = "0x01")]
+    Circle { center: Point, r: u16 },
+    /// Draw a full circle
+    #[deku(id = "0x02")]
+    CircleFull { center: Point, r: u16 },
+    /// Set the grey level (0 to 15) of the following commands
+    #[deku(id = "0x03")]
+    Color { color: Grey },
+    /// Set the font of the following [LayoutCommand::Text]
+    #[deku(id = "0x04")]
+    Font { id: u8 },
+    /// Draw a line
+    #[deku(id = "0x05")]
+    Line { p1: Point, p2: Point },
+    /// Set a pixel on
+    #[deku(id = "0x06")]
+    Point { pos: Point },
+    /// Draw an empty rectangle
+    #[deku(id = "0x07")]
+    Rect { p1: Point, p2: Point },
+    /// Draw a full rectangle
+    #[deku(id = "0x08")]
+    RectFull { p1: Point, p2: Point },
+    /// Write a fixed text, prefixed by its length
+    #[deku(id = "0x09")]
+    Text {
+        pos: Point,
+        #[deku(
+            reader = "read_sized_str(deku::reader)",
+            writer = "write_sized_str(deku::writer, text)"
+        )]
+        text: String,
+    },
+    /// Display gauge `id`
+    #[deku(id = "0x0A")]
+    Gauge { id: u8 },
+}
+
+fn read_sized_str<R: deku::no_std_io::Read + deku::no_std_io::Seek>(
+    reader: &mut Reader<R>,
+) -> Result<String, DekuError> {
+    let len = u8::from_reader_with_ctx(reader, BitSize(8))?;
+    let mut res = Vec::new();
+    for _ in 0..len {
+        res.push(u8::from_reader_with_ctx(reader, BitSize(8))?);
+    }
+    Ok(charset::decode(&res))
+}
+
+fn write_sized_str<W: deku::no_std_io::Write + deku::no_std_io::Seek>(
+    writer: &mut deku::writer::Writer<W>,
+    text: &str,
+) -> Result<(), DekuError> {
+    let bytes = charset::encode_lossy(text);
+    let len = u8::try_from(bytes.len())
+        .map_err(|_| DekuError::InvalidParam("Text longer than 255 bytes".into()))?;
+    len.to_writer(writer, BitSize(8))?;
+    bytes.to_writer(writer, BitSize(8))
+}
+
+/// Decode the additional commands of a layout
+pub(crate) fn decode_commands(mut bytes: &[u8]) -> Result<Vec<LayoutCommand>, DekuError> {
+    let mut res = Vec::new();
+    while !bytes.is_empty() {
+        let ((rest, _), cmd) = LayoutCommand::from_bytes((bytes, 0))?;
+        res.push(cmd);
+        bytes = rest;
+    }
+    Ok(res)
+}
+
+/// Builder of [LayoutParameters]
+#[derive(Clone, Debug)]
+pub struct LayoutBuilder {
+    pos: LayoutPosition,
+    width: u16,
+    height: u8,
+    fore_color: Grey,
+    back_color: Grey,
+    font: u8,
+    text_pos: Option<LayoutPosition>,
+    text_rotation: TextRotation,
+    text_opacity: bool,
+    commands: Vec<LayoutCommand>,
+}
+
+impl LayoutBuilder {
+    /// Layout with a clipping region of `width` x `height` pixels at `pos`.
+    /// By default it draws white on black with font 1, and has no text.
+    pub fn new(pos: LayoutPosition, width: u16, height: u8) -> Self {
+        Self {
+            pos,
+            width,
+            height,
+            fore_color: Grey::WHITE,
+            back_color: Grey::BLACK,
+            font: 1,
+            text_pos: None,
+            text_rotation: TextRotation::TOP_LR,
+            text_opacity: true,
+            commands: Vec::new(),
+        }
+    }
+
+    /// Clipping region of `width` x `height` pixels at `pos`
+    pub fn region(mut self, pos: LayoutPosition, width: u16, height: u8) -> Self {
+        self.pos = pos;
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Foreground and background colors
+    pub fn colors(mut self, fore: Grey, back: Grey) -> Self {
+        self.fore_color = fore;
+        self.back_color = back;
+        self
+    }
+
+    /// Font used to display the text
+    pub fn font(mut self, font: u8) -> Self {
+        self.font = font;
+        self
+    }
+
+    /// Display the text given to [crate::commands::Command::LayoutDisplay] at `pos`, relative to
+    /// the clipping region
+    pub fn text_at(mut self, pos: LayoutPosition) -> Self {
+        self.text_pos = Some(pos);
+        self
+    }
+
+    /// Do not display the text given to [crate::commands::Command::LayoutDisplay]
+    pub fn no_text(mut self) -> Self {
+        self.text_pos = None;
+        self
+    }
+
+    /// Rotation of the text
+    pub fn text_rotation(mut self, rotation: TextRotation) -> Self {
+        self.text_rotation = rotation;
+        self
+    }
+
+    /// If true, the background of each character is drawn
+    pub fn text_opacity(mut self, opaque: bool) -> Self {
+        self.text_opacity = opaque;
+        self
+    }
+
+    /// Append an additional command
+    pub fn command(mut self, cmd: LayoutCommand) -> Self {
+        self.commands.push(cmd);
+        self
+    }
+
+    /// Remove the additional commands
+    pub fn clear_commands(mut self) -> Self {
+        self.commands.clear();
+        self
+    }
+
+    pub fn line(self, p1: Point, p2: Point) -> Self {
+        self.command(LayoutCommand::Line { p1, p2 })
+    }
+
+    pub fn rect(self, p1: Point, p2: Point, full: bool) -> Self {
+        self.command(match full {
+            true => LayoutCommand::RectFull { p1, p2 },
+            false => LayoutCommand::Rect { p1, p2 },
+        })
+    }
+
+    pub fn circle(self, center: Point, r: u16, full: bool) -> Self {
+        self.command(match full {
+            true => LayoutCommand::CircleFull { center, r },
+            false => LayoutCommand::Circle { center, r },
+        })
+    }
+
+    pub fn text(self, pos: Point, text: &str) -> Self {
+        self.command(LayoutCommand::Text {
+            pos,
+            text: String::from(text),
+        })
+    }
+
+    pub fn build(self) -> Result<LayoutParameters, LayoutError> {
+        let colors = self.commands.iter().filter_map(|cmd| match cmd {
+            LayoutCommand::Color { color } => Some(*color),
+            _ => None,
+        });
+        for color in [self.fore_color, self.back_color].into_iter().chain(colors) {
+            Grey::new(color.value()).map_err(|_| LayoutError::InvalidColor(color.value()))?;
+        }
+        let mut commands = Vec::new();
+        for cmd in &self.commands {
+            commands.extend(cmd.to_bytes()?);
+        }
+        let size = u8::try_from(commands.len())
+            .map_err(|_| LayoutError::CommandsTooLong(commands.len()))?;
+        Ok(LayoutParameters {
+            size,
+            pos: self.pos,
+            width: self.width,
+            height: self.height,
+            fore_color: self.fore_color,
+            back_color: self.back_color,
+            font: self.font,
+            text_valid: self.text_pos.is_some(),
+            text_pos: self.text_pos.unwrap_or(LayoutPosition { x: 0, y: 0 }),
+            text_rotation: self.text_rotation,
+            text_opacity: self.text_opacity,
+            commands,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::Command;
+    use crate::traits::*;
+
+    #[test]
+    fn test_build_layout() {
+        let params = LayoutBuilder::new(LayoutPosition { x: 10, y: 20 }, 100, 50)
+            .font(2)
+            .text_at(LayoutPosition { x: 90, y: 5 })
+            .rect(Point { x: 0, y: 0 }, Point { x: 99, y: 49 }, false)
+            .text(Point { x: 5, y: 5 }, "km")
+            .build()
+            .unwrap();
+        assert_eq!(2, params.font());
+        let expected = [
+            0x07, 0, 0, 0, 0, 0, 99, 0, 49, // Rect
+            0x09, 0, 5, 0, 5, 2, b'k', b'm', // Text
+        ];
+        assert_eq!(&expected[..], params.commands_bytes());
+        assert_eq!(
+            vec![
+                LayoutCommand::Rect {
+                    p1: Point { x: 0, y: 0 },
+                    p2: Point { x: 99, y: 49 }
+                },
+                LayoutCommand::Text {
+                    pos: Point { x: 5, y: 5 },
+                    text: String::from("km")
+                },
+            ],
+            params.decode_commands().unwrap()
+        );
+
+        let cmd = Command::LayoutSave { id: 12, params };
+        let bytes = cmd.data_bytes().unwrap();
+        assert_eq!(
+            &[12, 17, 0, 10, 20, 0, 100, 50, 15, 0, 2, 1, 0, 90, 5, 4, 1],
+            &bytes[..17]
+        );
+        assert_eq!(cmd, Command::from_data(0x60, Some(&bytes)).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_layout() {
+        let builder = LayoutBuilder::new(LayoutPosition { x: 0, y: 0 }, 10, 10);
+        assert_eq!(
+            LayoutError::InvalidColor(16),
+            builder
+                .clone()
+                .colors(16.into(), Grey::BLACK)
+                .build()
+                .unwrap_err()
+        );
+        let text = "x".repeat(250);
+        assert_eq!(
+            LayoutError::CommandsTooLong(256 + 9),
+            builder
+                .text(Point { x: 0, y: 0 }, &text)
+                .line(Point { x: 0, y: 0 }, Point { x: 1, y: 1 })
+                .build()
+                .unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_into_builder() {
+        let params = LayoutBuilder::new(LayoutPosition { x: 10, y: 20 }, 100, 50)
+            .colors(Grey::from(12), Grey::from(2))
+            .text_at(LayoutPosition { x: 90, y: 5 })
+            .circle(Point { x: 10, y: 10 }, 5, true)
+            .build()
+            .unwrap();
+        // Parameters read back with LayoutGet
+        let bytes = params.to_bytes().unwrap();
+        let ((rest, _), received) = LayoutParameters::from_bytes((&bytes, 0)).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(&LayoutPosition { x: 10, y: 20 }, received.pos());
+        assert_eq!((100, 50), (received.width(), received.height()));
+        assert_eq!(Grey::from(12), received.fore_color());
+        assert_eq!(Grey::from(2), received.back_color());
+        assert_eq!(Some(&LayoutPosition { x: 90, y: 5 }), received.text_pos());
+        assert_eq!(TextRotation::TOP_LR, received.text_rotation());
+        assert!(received.text_opacity());
+        assert_eq!(
+            params,
+            received.clone().into_builder().unwrap().build().unwrap()
+        );
+
+        let modified = received
+            .into_builder()
+            .unwrap()
+            .region(LayoutPosition { x: 0, y: 0 }, 40, 40)
+            .no_text()
+            .clear_commands()
+            .line(Point { x: 0, y: 0 }, Point { x: 39, y: 39 })
+            .build()
+            .unwrap();
+        assert_eq!(40, modified.width());
+        assert_eq!(None, modified.text_pos());
+        assert_eq!(
+            vec![LayoutCommand::Line {
+                p1: Point { x: 0, y: 0 },
+                p2: Point { x: 39, y: 39 }
+            }],
+            modified.decode_commands().unwrap()
+        );
+        assert_eq!(Grey::from(12), modified.fore_color());
+    }
+}
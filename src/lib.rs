@@ -1,6 +1,58 @@
+pub mod animation;
+pub mod arc;
+pub mod charset;
 pub mod client;
+pub mod cmd_error;
 pub mod commands;
+pub mod config;
+#[cfg(test)]
+mod conformance;
+pub mod connection;
+pub mod delta;
+pub mod design;
+pub mod device_info;
+pub mod display;
+pub mod emulator;
+pub mod firmware;
+pub mod font;
+pub mod framebuffer;
+pub mod gauge;
+pub mod glasses;
+#[cfg(feature = "tokio")]
+pub mod handle;
+pub mod idle;
 pub mod image;
+pub mod image_store;
+pub mod layout;
+pub mod locale;
+pub mod mock;
+pub mod observer;
+pub mod pacing;
+pub mod page;
+pub mod polyline;
+pub mod power;
+pub mod prelude;
+pub mod profile;
 pub mod protocol;
+pub mod queue;
+pub mod quirks;
+pub mod recorder;
+pub mod redact;
+pub mod render;
+pub mod self_test;
 pub mod server;
+pub mod sniffer;
+pub mod stats;
+pub mod status;
+pub mod sync;
+pub mod templates;
+pub mod text;
+pub mod time;
 pub mod traits;
+pub mod transaction;
+pub mod transport;
+pub mod upload;
+pub mod vectors;
+
+/// Errors encoding or decoding commands and responses, from [deku]
+pub use deku::DekuError as SerializationError;
@@ -0,0 +1,197 @@
+//! Locale-aware formatting of the values displayed on the glasses
+//!
+//! Companion apps can pick the [Locale] matching the phone settings, so numbers, times and dates
+//! sent to the glasses look familiar to the user.
+//! Only characters available in the glasses fonts are used: separators are plain ASCII.
+
+/// 12 or 24 hour clock
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ClockFormat {
+    /// 1:05 PM
+    H12,
+    /// 13:05
+    H24,
+}
+
+/// Order of the date components
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DateOrder {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+/// Formatting conventions of a locale
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Locale {
+    pub decimal_separator: char,
+    /// Separator between groups of 3 digits, if any
+    pub thousands_separator: Option<char>,
+    pub clock: ClockFormat,
+    pub date_order: DateOrder,
+    pub date_separator: char,
+}
+
+impl Locale {
+    pub const EN_US: Locale = Locale {
+        decimal_separator: '.',
+        thousands_separator: Some(','),
+        clock: ClockFormat::H12,
+        date_order: DateOrder::MonthDayYear,
+        date_separator: '/',
+    };
+    pub const EN_GB: Locale = Locale {
+        decimal_separator: '.',
+        thousands_separator: Some(','),
+        clock: ClockFormat::H24,
+        date_order: DateOrder::DayMonthYear,
+        date_separator: '/',
+    };
+    pub const FR_FR: Locale = Locale {
+        decimal_separator: ',',
+        thousands_separator: Some(' '),
+        clock: ClockFormat::H24,
+        date_order: DateOrder::DayMonthYear,
+        date_separator: '/',
+    };
+    pub const DE_DE: Locale = Locale {
+        decimal_separator: ',',
+        thousands_separator: Some('.'),
+        clock: ClockFormat::H24,
+        date_order: DateOrder::DayMonthYear,
+        date_separator: '.',
+    };
+    /// ISO 8601 dates, no grouping
+    pub const ISO: Locale = Locale {
+        decimal_separator: '.',
+        thousands_separator: None,
+        clock: ClockFormat::H24,
+        date_order: DateOrder::YearMonthDay,
+        date_separator: '-',
+    };
+
+    /// Find the locale matching a language tag like `fr-FR`, `en_US` or `de`.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let tag = tag.replace('_', "-").to_ascii_lowercase();
+        let mut parts = tag.split('-');
+        let language = parts.next()?;
+        let region = parts.next();
+        match (language, region) {
+            ("en", Some("us")) | ("en", None) => Some(Self::EN_US),
+            ("en", Some(_)) => Some(Self::EN_GB),
+            ("fr", _) => Some(Self::FR_FR),
+            ("de", _) => Some(Self::DE_DE),
+            _ => None,
+        }
+    }
+
+    /// Format an integer, grouping digits by 3
+    pub fn format_integer(&self, value: i64) -> String {
+        let digits = value.unsigned_abs().to_string();
+        let mut res = String::new();
+        if value < 0 {
+            res.push('-');
+        }
+        res.push_str(&self.group(&digits));
+        res
+    }
+
+    /// Format a number with `decimals` digits after the decimal separator
+    pub fn format_number(&self, value: f32, decimals: usize) -> String {
+        let formatted = format!("{:.*}", decimals, value.abs());
+        let (integer, fraction) = match formatted.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (formatted.as_str(), None),
+        };
+        let mut res = String::new();
+        // Do not display "-0.0"
+        if value < 0.0 && formatted.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            res.push('-');
+        }
+        res.push_str(&self.group(integer));
+        if let Some(fraction) = fraction {
+            res.push(self.decimal_separator);
+            res.push_str(fraction);
+        }
+        res
+    }
+
+    /// Format a time of day
+    pub fn format_time(&self, hour: u8, minute: u8) -> String {
+        match self.clock {
+            ClockFormat::H24 => format!("{:02}:{:02}", hour, minute),
+            ClockFormat::H12 => {
+                let suffix = if hour < 12 { "AM" } else { "PM" };
+                let hour = match hour % 12 {
+                    0 => 12,
+                    h => h,
+                };
+                format!("{}:{:02} {}", hour, minute, suffix)
+            }
+        }
+    }
+
+    /// Format a date
+    pub fn format_date(&self, year: u16, month: u8, day: u8) -> String {
+        let sep = self.date_separator;
+        match self.date_order {
+            DateOrder::DayMonthYear => format!("{:02}{sep}{:02}{sep}{}", day, month, year),
+            DateOrder::MonthDayYear => format!("{:02}{sep}{:02}{sep}{}", month, day, year),
+            DateOrder::YearMonthDay => format!("{}{sep}{:02}{sep}{:02}", year, month, day),
+        }
+    }
+
+    fn group(&self, digits: &str) -> String {
+        let Some(sep) = self.thousands_separator else {
+            return String::from(digits);
+        };
+        let mut res = String::new();
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                res.push(sep);
+            }
+            res.push(c);
+        }
+        res
+    }
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self::EN_US
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!("1,234,567", Locale::EN_US.format_integer(1234567));
+        assert_eq!("-1.234", Locale::DE_DE.format_integer(-1234));
+        assert_eq!("123", Locale::FR_FR.format_integer(123));
+        assert_eq!("1 234,50", Locale::FR_FR.format_number(1234.5, 2));
+        assert_eq!("-3.3", Locale::ISO.format_number(-3.26, 1));
+        assert_eq!("0.0", Locale::EN_US.format_number(-0.01, 1));
+        assert_eq!("12", Locale::EN_US.format_number(12.3, 0));
+    }
+
+    #[test]
+    fn test_time_and_date() {
+        assert_eq!("1:05 PM", Locale::EN_US.format_time(13, 5));
+        assert_eq!("12:00 AM", Locale::EN_US.format_time(0, 0));
+        assert_eq!("13:05", Locale::FR_FR.format_time(13, 5));
+        assert_eq!("07/14/2024", Locale::EN_US.format_date(2024, 7, 14));
+        assert_eq!("14.07.2024", Locale::DE_DE.format_date(2024, 7, 14));
+        assert_eq!("2024-07-14", Locale::ISO.format_date(2024, 7, 14));
+    }
+
+    #[test]
+    fn test_from_tag() {
+        assert_eq!(Some(Locale::FR_FR), Locale::from_tag("fr_CA"));
+        assert_eq!(Some(Locale::EN_GB), Locale::from_tag("en-GB"));
+        assert_eq!(Some(Locale::EN_US), Locale::from_tag("en"));
+        assert_eq!(None, Locale::from_tag("xx"));
+    }
+}
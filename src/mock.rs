@@ -0,0 +1,182 @@
+//! Scriptable glasses for unit tests
+//!
+//! [MockClient] implements [GlassesApi] and checks that the application sends the expected
+//! [Command]s, in order, answering them with scripted [Response]s:
+//!
+//! ```
+//! use activelook_rs::{commands::{Command, Response}, glasses::GlassesApi, mock::MockClient};
+//!
+//! let mut mock = MockClient::new();
+//! mock.expect(Command::Clear);
+//! mock.expect(Command::Battery)
+//!     .reply(Response::Battery { level: 42 });
+//!
+//! mock.clear().unwrap();
+//! assert_eq!(42, mock.battery().unwrap());
+//! mock.verify();
+//! ```
+//!
+//! A scripted [Response::CmdError] is returned as [GlassesError::Command]. Any command which is
+//! not the next expected one panics, and so does dropping the mock while
+//! expectations are left.
+use std::collections::VecDeque;
+
+use crate::{
+    cmd_error::CommandError,
+    commands::{Command, Response},
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// Expected [Command], and how the mock answers it
+#[derive(Debug)]
+pub struct Expectation {
+    cmd: Command,
+    outcome: Option<Result<Response, GlassesError>>,
+}
+
+impl Expectation {
+    /// Answer with `response`. [GlassesApi::send] returns it as
+    /// [GlassesError::UnexpectedResponse], like a [Response::CmdError] from real glasses.
+    pub fn reply(&mut self, response: Response) -> &mut Self {
+        self.outcome = Some(Ok(response));
+        self
+    }
+
+    /// Fail with `error`, like a transport error
+    pub fn fail(&mut self, error: GlassesError) -> &mut Self {
+        self.outcome = Some(Err(error));
+        self
+    }
+}
+
+/// [GlassesApi] following a script of expected commands
+#[derive(Debug, Default)]
+pub struct MockClient {
+    expected: VecDeque<Expectation>,
+    received: Vec<Command>,
+}
+
+impl MockClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect `cmd` after the previous expectations. Without [Expectation::reply], a
+    /// [GlassesApi::send] succeeds and a [GlassesApi::query] fails with
+    /// [GlassesError::Unsupported].
+    pub fn expect(&mut self, cmd: Command) -> &mut Expectation {
+        self.expected.push_back(Expectation { cmd, outcome: None });
+        self.expected.back_mut().expect("Expectation just pushed")
+    }
+
+    /// Commands received so far
+    pub fn received(&self) -> &[Command] {
+        &self.received
+    }
+
+    /// Panics if some expected commands were not received
+    pub fn verify(&self) {
+        let missing: Vec<&Command> = self.expected.iter().map(|e| &e.cmd).collect();
+        assert!(
+            missing.is_empty(),
+            "Expected commands not received: {missing:?}"
+        );
+    }
+
+    fn next(&mut self, cmd: &Command) -> Option<Result<Response, GlassesError>> {
+        let Some(expectation) = self.expected.pop_front() else {
+            panic!("Unexpected command {cmd:?}, no more commands expected");
+        };
+        assert_eq!(&expectation.cmd, cmd, "Unexpected command");
+        self.received.push(cmd.clone());
+        expectation.outcome
+    }
+}
+
+impl GlassesApi for MockClient {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        match self.next(cmd) {
+            None => Ok(()),
+            Some(Ok(response)) => match CommandError::from_response(&response) {
+                Some(error) => Err(GlassesError::Command(error)),
+                None => Err(GlassesError::UnexpectedResponse(response)),
+            },
+            Some(Err(error)) => Err(error),
+        }
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        let response = self.next(cmd).unwrap_or(Err(GlassesError::Unsupported))?;
+        match CommandError::from_response(&response) {
+            Some(error) => Err(GlassesError::Command(error)),
+            None => Ok(response),
+        }
+    }
+}
+
+impl Drop for MockClient {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            self.verify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{CmdError, HoldFlushAction},
+        protocol::ProtocolError,
+    };
+
+    #[test]
+    fn test_scripted_replies() {
+        let mut mock = MockClient::new();
+        mock.expect(Command::HoldFlush {
+            action: HoldFlushAction::Hold,
+        });
+        mock.expect(Command::Clear).reply(Response::CmdError {
+            cmd_id: 0x01,
+            error: CmdError::Generic,
+            sub_error: 0,
+        });
+        mock.expect(Command::HoldFlush {
+            action: HoldFlushAction::ResetFlush,
+        });
+        mock.expect(Command::Battery)
+            .fail(GlassesError::Protocol(ProtocolError::EmbeddedIOError));
+
+        let mut transaction = mock.transaction().unwrap();
+        assert!(matches!(
+            transaction.clear(),
+            Err(GlassesError::Command(CommandError {
+                cmd_id: 0x01,
+                error: CmdError::Generic,
+                ..
+            }))
+        ));
+        drop(transaction);
+        assert_eq!(
+            Err(GlassesError::Protocol(ProtocolError::EmbeddedIOError)),
+            mock.battery()
+        );
+        assert_eq!(4, mock.received().len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected command")]
+    fn test_unexpected_command() {
+        let mut mock = MockClient::new();
+        mock.expect(Command::Clear);
+        let _ = mock.power_display(true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected commands not received")]
+    fn test_missing_command() {
+        let mut mock = MockClient::new();
+        mock.expect(Command::Battery)
+            .reply(Response::Battery { level: 1 });
+    }
+}
@@ -0,0 +1,224 @@
+//! Instrumentation of a client
+//!
+//! A [ClientObserver] is notified of each packet sent, response received and error of an
+//! [crate::client::ActiveLookClient], with a timestamp from the clock given to
+//! [crate::client::ActiveLookClient::set_observer]. Applications profile, log or export
+//! telemetry with it, without patching the crate.
+//!
+//! Like [crate::stats::ClientStats], packets are observed by the sending half and responses by
+//! the whole client only: once split, the receiving half does not notify the observer.
+//!
+//! [LatencyExpectations] is a ready-made observer, reporting the queries answered later than
+//! expected. The client owns its observer: share one with `Arc<Mutex<_>>` to read it back.
+use core::time::Duration;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use log::*;
+
+use crate::{commands::Response, protocol::ProtocolError, time::Clock};
+
+/// Packet written to the glasses
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SentPacket {
+    pub cmd_id: u8,
+    /// query_id of the packet, also counted when [crate::client::ClientConfig::query_id_len]
+    /// is 0
+    pub query_id: u32,
+    /// Bytes of the whole packet
+    pub len: usize,
+}
+
+/// Response received from the glasses
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReceivedResponse<'a> {
+    /// Query answered by the response, `None` for unsolicited responses
+    pub query_id: Option<u32>,
+    /// ID of the command of the query answered
+    pub cmd_id: Option<u8>,
+    /// Time elapsed since the query was sent
+    pub latency: Option<Duration>,
+    pub response: &'a Response,
+}
+
+/// Callbacks notified of the traffic of a client, at `at` on the clock of the observer. Every
+/// callback does nothing by default.
+pub trait ClientObserver {
+    /// A packet was written, including each chunk of split commands
+    fn on_send(&mut self, _at: Duration, _packet: &SentPacket) {}
+
+    /// A response was received
+    fn on_response(&mut self, _at: Duration, _response: &ReceivedResponse) {}
+
+    /// Sending or receiving failed
+    fn on_error(&mut self, _at: Duration, _error: &ProtocolError) {}
+}
+
+impl<O: ClientObserver + ?Sized> ClientObserver for Arc<Mutex<O>> {
+    fn on_send(&mut self, at: Duration, packet: &SentPacket) {
+        if let Ok(mut observer) = self.lock() {
+            observer.on_send(at, packet);
+        }
+    }
+
+    fn on_response(&mut self, at: Duration, response: &ReceivedResponse) {
+        if let Ok(mut observer) = self.lock() {
+            observer.on_response(at, response);
+        }
+    }
+
+    fn on_error(&mut self, at: Duration, error: &ProtocolError) {
+        if let Ok(mut observer) = self.lock() {
+            observer.on_error(at, error);
+        }
+    }
+}
+
+/// Observer attached to a client, timing the queries
+pub(crate) struct Observed {
+    observer: Box<dyn ClientObserver + Send>,
+    clock: Box<dyn Clock + Send>,
+    /// Command ID and send time of the queries waiting for their response
+    in_flight: BTreeMap<u32, (u8, Duration)>,
+}
+
+impl Observed {
+    pub(crate) fn new(
+        observer: impl ClientObserver + Send + 'static,
+        clock: impl Clock + Send + 'static,
+    ) -> Self {
+        Self {
+            observer: Box::new(observer),
+            clock: Box::new(clock),
+            in_flight: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn on_send(&mut self, packet: SentPacket) {
+        self.observer.on_send(self.clock.now(), &packet);
+    }
+
+    pub(crate) fn on_query(&mut self, query_id: u32, cmd_id: u8) {
+        self.in_flight.insert(query_id, (cmd_id, self.clock.now()));
+    }
+
+    /// `response` was received, answering `query_id` if known
+    pub(crate) fn on_response(&mut self, query_id: Option<u32>, response: &Response) {
+        let now = self.clock.now();
+        let sent = query_id.and_then(|query_id| self.in_flight.remove(&query_id));
+        let received = ReceivedResponse {
+            query_id,
+            cmd_id: sent.map(|(cmd_id, _)| cmd_id),
+            latency: sent.map(|(_, sent)| now.saturating_sub(sent)),
+            response,
+        };
+        self.observer.on_response(now, &received);
+    }
+
+    pub(crate) fn on_error(&mut self, error: &ProtocolError) {
+        self.observer.on_error(self.clock.now(), error);
+    }
+}
+
+/// Counts and logs the queries answered later than expected
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LatencyExpectations {
+    /// Maximum latency, by command ID
+    expected: BTreeMap<u8, Duration>,
+    /// Maximum latency of the other commands
+    default: Option<Duration>,
+    /// Late responses, by command ID
+    late: BTreeMap<u8, u64>,
+}
+
+impl LatencyExpectations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Expect the queries of command `cmd_id` to be answered within `max`
+    pub fn expect(mut self, cmd_id: u8, max: Duration) -> Self {
+        self.expected.insert(cmd_id, max);
+        self
+    }
+
+    /// Expect the queries of the commands not given to [LatencyExpectations::expect] to be
+    /// answered within `max`
+    pub fn default_max(mut self, max: Duration) -> Self {
+        self.default = Some(max);
+        self
+    }
+
+    /// Maximum latency expected for command `cmd_id`, if any
+    pub fn expected(&self, cmd_id: u8) -> Option<Duration> {
+        self.expected.get(&cmd_id).copied().or(self.default)
+    }
+
+    /// Number of late responses to command `cmd_id`
+    pub fn late(&self, cmd_id: u8) -> u64 {
+        self.late.get(&cmd_id).copied().unwrap_or_default()
+    }
+
+    /// Number of late responses, to every command
+    pub fn total_late(&self) -> u64 {
+        self.late.values().sum()
+    }
+}
+
+impl ClientObserver for LatencyExpectations {
+    fn on_response(&mut self, _at: Duration, response: &ReceivedResponse) {
+        let (Some(cmd_id), Some(latency)) = (response.cmd_id, response.latency) else {
+            return;
+        };
+        match self.expected(cmd_id) {
+            Some(max) if latency > max => {
+                warn!(
+                    "Command 0x{:02X} answered in {:?}, expected within {:?}",
+                    cmd_id, latency, max
+                );
+                *self.late.entry(cmd_id).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::VirtualClock;
+
+    #[test]
+    fn test_latency_expectations() {
+        let expectations = Arc::new(Mutex::new(
+            LatencyExpectations::new()
+                .expect(0x05, Duration::from_millis(100))
+                .default_max(Duration::from_millis(500)),
+        ));
+        let clock = VirtualClock::new();
+        let mut observed = Observed::new(expectations.clone(), clock.clone());
+        let battery = Response::Battery { level: 42 };
+
+        observed.on_query(1, 0x05);
+        observed.on_query(2, 0xE7);
+        observed.on_query(3, 0x05);
+        clock.advance(Duration::from_millis(50));
+        observed.on_response(Some(1), &battery);
+        clock.advance(Duration::from_millis(100));
+        observed.on_response(Some(2), &battery);
+        observed.on_response(Some(3), &battery);
+        // Unsolicited
+        observed.on_response(None, &battery);
+
+        let expectations = expectations.lock().unwrap();
+        assert_eq!(1, expectations.late(0x05));
+        assert_eq!(0, expectations.late(0xE7));
+        assert_eq!(1, expectations.total_late());
+        assert_eq!(
+            Some(Duration::from_millis(500)),
+            expectations.expected(0x01)
+        );
+    }
+}
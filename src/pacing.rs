@@ -0,0 +1,258 @@
+//! Frame pacing
+//!
+//! The glasses refresh their display a few times per second, and their command queue is small.
+//! An application redrawing its [Screen] in a tight loop floods the queue, which ends with
+//! [crate::protocol::FlowErrorCtrl::MessageQueueOverflow] errors.
+//!
+//! [FramePacer] sends at most one [Screen] per refresh period. Screens submitted in between
+//! replace the pending one: only the most recent content is sent.
+//!
+//! At a lower level, bulk transfers like image uploads write faster than the BLE link can carry:
+//! the writes pile up in the stack of the phone or the glasses. [RateLimiter] paces the writes
+//! of [crate::client::ClientSender::send_bulk] to a number of packets per connection interval.
+use core::time::Duration;
+
+use crate::{
+    design::Screen,
+    glasses::{GlassesApi, GlassesError},
+    time::{Clock, Delay},
+};
+
+/// Connection interval recommended for ActiveLook glasses
+pub const CONNECTION_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Token bucket allowing `packets_per_event` writes per connection interval, waiting with a
+/// [Delay] when the bucket is empty
+pub struct RateLimiter {
+    clock: Box<dyn Clock + Send>,
+    delay: Box<dyn Delay + Send>,
+    interval: Duration,
+    packets_per_event: u32,
+    tokens: u32,
+    /// Start of the current connection event
+    event_start: Duration,
+    /// Total time spent waiting
+    waited: Duration,
+}
+
+impl RateLimiter {
+    /// Allow `packets_per_event` writes every `interval`, at least one
+    pub fn new(
+        clock: impl Clock + Send + 'static,
+        delay: impl Delay + Send + 'static,
+        interval: Duration,
+        packets_per_event: u32,
+    ) -> Self {
+        let packets_per_event = packets_per_event.max(1);
+        Self {
+            event_start: clock.now(),
+            clock: Box::new(clock),
+            delay: Box::new(delay),
+            interval,
+            packets_per_event,
+            tokens: packets_per_event,
+            waited: Duration::ZERO,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn packets_per_event(&self) -> u32 {
+        self.packets_per_event
+    }
+
+    /// Total time spent waiting for the next connection event
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+
+    /// Take a token for one write, waiting for the next connection event if none is left
+    pub fn acquire(&mut self) {
+        let elapsed = self.clock.now().saturating_sub(self.event_start);
+        if elapsed >= self.interval {
+            self.refill();
+        } else if self.tokens == 0 {
+            let wait = self.interval - elapsed;
+            self.delay.delay(wait);
+            self.waited += wait;
+            self.refill();
+        }
+        self.tokens -= 1;
+    }
+
+    fn refill(&mut self) {
+        self.event_start = self.clock.now();
+        self.tokens = self.packets_per_event;
+    }
+}
+
+/// Rate-limits full-screen updates
+pub struct FramePacer<G, C>
+where
+    G: GlassesApi,
+    C: Clock,
+{
+    glasses: G,
+    clock: C,
+    period: Duration,
+    /// Time of the last update sent, if any
+    last_sent: Option<Duration>,
+    pending: Option<Screen>,
+    /// Number of screens replaced before being sent
+    merged: usize,
+}
+
+impl<G, C> FramePacer<G, C>
+where
+    G: GlassesApi,
+    C: Clock,
+{
+    /// Default refresh target, in updates per second
+    pub const DEFAULT_RATE: f32 = 5.0;
+
+    /// Send at most `rate` updates per second
+    pub fn new(glasses: G, clock: C, rate: f32) -> Self {
+        Self {
+            glasses,
+            clock,
+            period: Duration::from_micros((1_000_000.0 / rate).round() as u64),
+            last_sent: None,
+            pending: None,
+            merged: 0,
+        }
+    }
+
+    /// Minimal time between two updates
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns true if a screen is waiting for the next refresh period
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Number of screens replaced by a more recent one before being sent
+    pub fn merged(&self) -> usize {
+        self.merged
+    }
+
+    /// Access the wrapped glasses
+    pub fn glasses(&mut self) -> &mut G {
+        &mut self.glasses
+    }
+
+    /// Submit a new screen content. It is sent immediately if the refresh period elapsed,
+    /// otherwise it replaces the pending screen.
+    /// Returns true if a screen was sent.
+    pub fn submit(&mut self, screen: Screen) -> Result<bool, GlassesError> {
+        if self.pending.replace(screen).is_some() {
+            self.merged += 1;
+        }
+        self.poll()
+    }
+
+    /// Must be called periodically: sends the pending screen once the refresh period elapsed.
+    /// Returns true if a screen was sent.
+    pub fn poll(&mut self) -> Result<bool, GlassesError> {
+        let now = self.clock.now();
+        let ready = match self.last_sent {
+            Some(last_sent) => now.saturating_sub(last_sent) >= self.period,
+            None => true,
+        };
+        if !ready {
+            return Ok(false);
+        }
+        let Some(screen) = self.pending.take() else {
+            return Ok(false);
+        };
+        self.last_sent = Some(now);
+        for cmd in screen.commands() {
+            self.glasses.send(&cmd)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::ClientSender, commands::Command, design::Widget, glasses::Preview,
+        time::VirtualClock, transport::loopback::loopback,
+    };
+
+    fn speed(value: u8) -> Screen {
+        Screen::new(vec![Widget::Gauge { id: 1, value }])
+    }
+
+    #[test]
+    fn test_merge_updates() {
+        let clock = VirtualClock::new();
+        let mut pacer = FramePacer::new(Preview::new(), clock.clone(), 5.0);
+        assert_eq!(Duration::from_millis(200), pacer.period());
+
+        assert_eq!(Ok(true), pacer.submit(speed(1)));
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(Ok(false), pacer.submit(speed(2)));
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(Ok(false), pacer.submit(speed(3)));
+        assert!(pacer.has_pending());
+        assert_eq!(1, pacer.merged());
+
+        clock.advance(Duration::from_millis(100));
+        assert_eq!(Ok(true), pacer.poll());
+        assert!(!pacer.has_pending());
+        assert_eq!(Ok(false), pacer.poll());
+
+        let gauges: Vec<&Command> = pacer
+            .glasses()
+            .displayed()
+            .iter()
+            .filter(|cmd| matches!(cmd, Command::GaugeDisplay { .. }))
+            .collect();
+        assert_eq!(
+            gauges,
+            [
+                &Command::GaugeDisplay { id: 1, value: 1 },
+                &Command::GaugeDisplay { id: 1, value: 3 }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let clock = VirtualClock::new();
+        let mut limiter = RateLimiter::new(clock.clone(), clock.clone(), CONNECTION_INTERVAL, 3);
+        for _ in 0..3 {
+            limiter.acquire();
+        }
+        assert_eq!(Duration::ZERO, clock.now());
+        limiter.acquire();
+        assert_eq!(CONNECTION_INTERVAL, clock.now());
+        // Idle for longer than an interval: the bucket is full again
+        clock.advance(Duration::from_millis(50));
+        for _ in 0..3 {
+            limiter.acquire();
+        }
+        assert_eq!(CONNECTION_INTERVAL, limiter.waited());
+
+        // 7 commands, one write each
+        let (client, _server) = loopback();
+        let mut sender = ClientSender::new(client.rx, client.ctrl);
+        sender.set_rate_limiter(Some(RateLimiter::new(
+            clock.clone(),
+            clock.clone(),
+            CONNECTION_INTERVAL,
+            2,
+        )));
+        let start = clock.now();
+        sender.send_bulk(&vec![Command::Clear; 7]).unwrap();
+        assert_eq!(3 * CONNECTION_INTERVAL, clock.now() - start);
+        // Single commands are not paced
+        sender.send(&Command::Clear).unwrap();
+        assert_eq!(3 * CONNECTION_INTERVAL, clock.now() - start);
+    }
+}
@@ -0,0 +1,213 @@
+//! Pages of layouts
+//!
+//! A page displays several saved layouts at once, each one in a slot with its own position.
+//! [Page] composes the slots, saves them with [Command::PageSave], and keeps the text of each slot
+//! to display them all with a single [Command::PageDisplay].
+//!
+//! The layouts data of [Command::PageSave] and [crate::commands::Response::PageGet] holds, for
+//! each slot, the layout ID followed by its position encoded as a [LayoutPosition].
+
+use thiserror::Error;
+
+use crate::{
+    commands::{Command, LayoutPosition},
+    glasses::{GlassesApi, GlassesError},
+    protocol::PACKET_DATA_MAX_SIZE,
+};
+
+/// Size of a slot in the layouts data: layout ID, then `x` on 16 bits and `y` on 8 bits
+pub const SLOT_LEN: usize = 4;
+
+/// Maximum number of slots of a page. The API documentation gives no limit of its own: the
+/// slots must fit in the data of a single packet, after the page ID.
+pub const MAX_SLOTS: usize = (PACKET_DATA_MAX_SIZE - 1) / SLOT_LEN;
+
+/// Errors building or displaying a page
+#[derive(Debug, Error, PartialEq)]
+pub enum PageError {
+    #[error("A page holds at most {MAX_SLOTS} layouts")]
+    TooManySlots,
+    #[error("Layouts data of {0} bytes is not made of {SLOT_LEN} bytes slots")]
+    InvalidLayouts(usize),
+    #[error("The page has {slots} slots, {values} values given")]
+    ValueCount { slots: usize, values: usize },
+    #[error("Slot {0} does not exist")]
+    NoSlot(usize),
+    #[error(transparent)]
+    Glasses(#[from] GlassesError),
+}
+
+/// Layout displayed by a page, at `pos`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PageSlot {
+    pub layout: u8,
+    pub pos: LayoutPosition,
+}
+
+/// Page `id`, with the text currently displayed in each slot
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Page {
+    id: u8,
+    slots: Vec<PageSlot>,
+    values: Vec<String>,
+}
+
+impl Page {
+    /// Empty page, see [Page::slot]
+    pub fn new(id: u8) -> Self {
+        Self {
+            id,
+            slots: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    /// Page read with [Command::PageGet]
+    pub fn from_layouts(id: u8, layouts: &[u8]) -> Result<Self, PageError> {
+        if !layouts.len().is_multiple_of(SLOT_LEN) {
+            return Err(PageError::InvalidLayouts(layouts.len()));
+        }
+        layouts
+            .chunks(SLOT_LEN)
+            .try_fold(Self::new(id), |page, slot| {
+                let pos = LayoutPosition {
+                    x: u16::from_be_bytes([slot[1], slot[2]]),
+                    y: slot[3],
+                };
+                page.slot(slot[0], pos)
+            })
+    }
+
+    /// Add a slot displaying `layout` at `pos`
+    pub fn slot(mut self, layout: u8, pos: LayoutPosition) -> Result<Self, PageError> {
+        if self.slots.len() == MAX_SLOTS {
+            return Err(PageError::TooManySlots);
+        }
+        self.slots.push(PageSlot { layout, pos });
+        self.values.push(String::new());
+        Ok(self)
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    pub fn slots(&self) -> &[PageSlot] {
+        &self.slots
+    }
+
+    /// Text of each slot, as last displayed
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
+
+    /// Command saving this page
+    pub fn save_command(&self) -> Command {
+        let mut layouts = Vec::with_capacity(self.slots.len() * SLOT_LEN);
+        for slot in &self.slots {
+            layouts.push(slot.layout);
+            layouts.extend(slot.pos.x.to_be_bytes());
+            layouts.push(slot.pos.y);
+        }
+        Command::PageSave {
+            id: self.id,
+            layouts,
+        }
+    }
+
+    /// Command displaying the current values
+    pub fn display_command(&self) -> Command {
+        Command::PageDisplay {
+            id: self.id,
+            strings: self.values.clone(),
+        }
+    }
+
+    /// Display `values`, one for each slot in order
+    pub fn display<G: GlassesApi + ?Sized>(
+        &mut self,
+        glasses: &mut G,
+        values: &[&str],
+    ) -> Result<(), PageError> {
+        if values.len() != self.slots.len() {
+            return Err(PageError::ValueCount {
+                slots: self.slots.len(),
+                values: values.len(),
+            });
+        }
+        self.values = values.iter().map(|value| String::from(*value)).collect();
+        Ok(glasses.send(&self.display_command())?)
+    }
+
+    /// Change the text of slot `index`, and display the whole page again
+    pub fn update<G: GlassesApi + ?Sized>(
+        &mut self,
+        glasses: &mut G,
+        index: usize,
+        value: &str,
+    ) -> Result<(), PageError> {
+        let slot = self.values.get_mut(index).ok_or(PageError::NoSlot(index))?;
+        *slot = String::from(value);
+        Ok(glasses.send(&self.display_command())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mock::MockClient, traits::Serializable};
+
+    #[test]
+    fn test_page() {
+        let page = Page::new(3)
+            .slot(10, LayoutPosition { x: 300, y: 20 })
+            .unwrap()
+            .slot(11, LayoutPosition { x: 0, y: 200 })
+            .unwrap();
+        let save = page.save_command();
+        assert_eq!(
+            vec![3, 10, 0x01, 0x2C, 20, 11, 0, 0, 200],
+            save.data_bytes().unwrap()
+        );
+        let Command::PageSave { layouts, .. } = &save else {
+            unreachable!()
+        };
+        assert_eq!(Ok(page.clone()), Page::from_layouts(3, layouts));
+        assert_eq!(
+            Err(PageError::InvalidLayouts(3)),
+            Page::from_layouts(3, &layouts[..3])
+        );
+
+        let mut page = page;
+        let mut mock = MockClient::new();
+        mock.expect(Command::PageDisplay {
+            id: 3,
+            strings: vec![String::from("12"), String::from("km/h")],
+        });
+        mock.expect(Command::PageDisplay {
+            id: 3,
+            strings: vec![String::from("13"), String::from("km/h")],
+        });
+        page.display(&mut mock, &["12", "km/h"]).unwrap();
+        page.update(&mut mock, 0, "13").unwrap();
+        assert_eq!(["13", "km/h"], page.values());
+        assert_eq!(
+            Err(PageError::ValueCount {
+                slots: 2,
+                values: 1
+            }),
+            page.display(&mut mock, &["14"])
+        );
+        assert_eq!(Err(PageError::NoSlot(2)), page.update(&mut mock, 2, "x"));
+
+        let full = (0..MAX_SLOTS).try_fold(Page::new(1), |page, layout| {
+            page.slot(layout as u8, LayoutPosition { x: 0, y: 0 })
+        });
+        let full = full.unwrap();
+        assert!(full.save_command().data_bytes().unwrap().len() <= PACKET_DATA_MAX_SIZE);
+        assert_eq!(
+            Err(PageError::TooManySlots),
+            full.slot(0, LayoutPosition { x: 0, y: 0 })
+        );
+    }
+}
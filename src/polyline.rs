@@ -0,0 +1,128 @@
+//! Construction of polylines
+//!
+//! [Command::Polyline] carries a reserved field and as many points as fit in one packet.
+//! [Polyline] hides the reserved bytes, and splits longer lines into several commands sharing
+//! their end points, so the line is drawn without gaps.
+
+use thiserror::Error;
+
+use crate::{commands::Command, commands::Point, protocol::PACKET_DATA_MAX_SIZE};
+
+/// Size of the thickness and reserved fields of [Command::Polyline]
+const HEADER_LEN: usize = 3;
+/// Size of a serialized [Point]
+const POINT_LEN: usize = 4;
+
+/// Maximal number of points of one [Command::Polyline]
+pub const MAX_POINTS: usize = (PACKET_DATA_MAX_SIZE - HEADER_LEN) / POINT_LEN;
+
+/// Errors building a polyline
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum PolylineError {
+    #[error("A polyline needs at least 2 points, got {0}")]
+    TooFewPoints(usize),
+}
+
+/// Connected lines between consecutive points
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Polyline {
+    thickness: u8,
+    points: Vec<Point>,
+}
+
+impl Polyline {
+    /// Line of `thickness` pixels going through `points`, in order
+    pub fn new(thickness: u8, points: &[Point]) -> Result<Self, PolylineError> {
+        if points.len() < 2 {
+            return Err(PolylineError::TooFewPoints(points.len()));
+        }
+        Ok(Self {
+            thickness,
+            points: points.to_vec(),
+        })
+    }
+
+    pub fn thickness(&self) -> u8 {
+        self.thickness
+    }
+
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// Commands drawing the line, each one with at most [MAX_POINTS] points.
+    /// Each command starts at the last point of the previous one.
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + MAX_POINTS).min(self.points.len());
+            commands.push(Command::Polyline {
+                thickness: self.thickness,
+                _reserved: 0,
+                points: self.points[start..end].to_vec(),
+            });
+            if end == self.points.len() {
+                return commands;
+            }
+            start = end - 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::*;
+
+    fn points(n: usize) -> Vec<Point> {
+        (0..n)
+            .map(|i| Point {
+                x: i as i16,
+                y: 2 * i as i16,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_polyline() {
+        let line = Polyline::new(2, &points(2)).unwrap();
+        let cmds = line.commands();
+        assert_eq!(1, cmds.len());
+        assert_eq!(
+            &[2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2][..],
+            cmds[0].data_bytes().unwrap()
+        );
+
+        let line = Polyline::new(1, &points(MAX_POINTS)).unwrap();
+        let cmds = line.commands();
+        assert_eq!(1, cmds.len());
+        assert!(cmds[0].data_bytes().unwrap().len() <= PACKET_DATA_MAX_SIZE);
+
+        assert_eq!(
+            Err(PolylineError::TooFewPoints(1)),
+            Polyline::new(1, &points(1))
+        );
+    }
+
+    #[test]
+    fn test_split_polyline() {
+        let all = points(2 * MAX_POINTS);
+        let cmds = Polyline::new(1, &all).unwrap().commands();
+        assert_eq!(3, cmds.len());
+
+        let mut joined: Vec<Point> = Vec::new();
+        for cmd in &cmds {
+            let Command::Polyline { points, .. } = cmd else {
+                panic!("Unexpected command {cmd:?}");
+            };
+            assert!(points.len() <= MAX_POINTS);
+            // Consecutive commands share their end points
+            if let Some(last) = joined.pop() {
+                assert_eq!(last, points[0]);
+            }
+            joined.extend(points);
+        }
+        assert_eq!(all, joined);
+    }
+}
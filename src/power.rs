@@ -0,0 +1,118 @@
+//! Shutdown and reset of the glasses
+//!
+//! The firmware only accepts [Command::Shutdown] on battery, and [Command::Reset] on USB power.
+//! A rejected command is not acknowledged, so [PowerGuard] checks the power supply before sending
+//! either of them, with the documented keys of [Command::shutdown] and [Command::reset].
+//!
+//! No [crate::commands::DeviceInfo] parameter of firmware 4.12.0 reports the power supply: the
+//! application tells it to the guard, e.g. from the charger state shown to the user.
+use thiserror::Error;
+
+use crate::{
+    commands::Command,
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// Power supply of the glasses
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PowerSource {
+    Battery,
+    /// Plugged in through USB
+    Usb,
+}
+
+/// Errors returned by [PowerGuard]
+#[derive(Error, Debug, PartialEq)]
+pub enum PowerError {
+    /// The firmware rejects this command with the current power supply
+    #[error("{cmd} is not allowed on {power:?} power")]
+    NotAllowed {
+        cmd: &'static str,
+        power: PowerSource,
+    },
+    #[error(transparent)]
+    Glasses(#[from] GlassesError),
+}
+
+/// Sends [Command::shutdown] or [Command::reset] only when the firmware accepts them, see
+/// [GlassesApi::power_guard]
+pub struct PowerGuard<'a, G: GlassesApi + ?Sized> {
+    glasses: &'a mut G,
+    power: PowerSource,
+}
+
+impl<'a, G: GlassesApi + ?Sized> PowerGuard<'a, G> {
+    pub fn new(glasses: &'a mut G, power: PowerSource) -> Self {
+        Self { glasses, power }
+    }
+
+    pub fn power(&self) -> PowerSource {
+        self.power
+    }
+
+    /// Returns true if [Command::Shutdown] is accepted with the current power supply
+    pub fn can_shutdown(&self) -> bool {
+        self.power == PowerSource::Battery
+    }
+
+    /// Returns true if [Command::Reset] is accepted with the current power supply
+    pub fn can_reset(&self) -> bool {
+        self.power == PowerSource::Usb
+    }
+
+    /// Shutdown the glasses, only allowed on battery
+    pub fn shutdown(self) -> Result<(), PowerError> {
+        let allowed = self.can_shutdown();
+        self.send(allowed, "shutdown", Command::shutdown())
+    }
+
+    /// Reset the glasses, only allowed on USB power
+    pub fn reset(self) -> Result<(), PowerError> {
+        let allowed = self.can_reset();
+        self.send(allowed, "reset", Command::reset())
+    }
+
+    fn send(self, allowed: bool, cmd: &'static str, command: Command) -> Result<(), PowerError> {
+        if !allowed {
+            return Err(PowerError::NotAllowed {
+                cmd,
+                power: self.power,
+            });
+        }
+        Ok(self.glasses.send(&command)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockClient;
+
+    #[test]
+    fn test_power_guard() {
+        let mut mock = MockClient::new();
+        assert_eq!(
+            Err(PowerError::NotAllowed {
+                cmd: "reset",
+                power: PowerSource::Battery
+            }),
+            mock.power_guard(PowerSource::Battery).reset()
+        );
+        assert_eq!(
+            Err(PowerError::NotAllowed {
+                cmd: "shutdown",
+                power: PowerSource::Usb
+            }),
+            mock.power_guard(PowerSource::Usb).shutdown()
+        );
+
+        mock.expect(Command::Reset {
+            key: [0x5c, 0x1e, 0x2d, 0xe9],
+        });
+        mock.expect(Command::Shutdown {
+            key: [0x6f, 0x7f, 0xc4, 0xee],
+        });
+        mock.power_guard(PowerSource::Usb).reset().unwrap();
+        mock.power_guard(PowerSource::Battery).shutdown().unwrap();
+    }
+}
@@ -0,0 +1,29 @@
+//! Types and traits needed by most applications
+//!
+//! ```
+//! use activelook_rs::prelude::*;
+//!
+//! let cmd = Command::Color { color: Grey::WHITE };
+//! assert_eq!(0x30, cmd.id().unwrap());
+//! ```
+//!
+//! [Serializable] and [Deserializable] are brought into scope, for [Serializable::id] and the
+//! encoding methods of the commands and responses.
+pub use crate::{
+    arc::CircleArc,
+    client::{ActiveLookClient, ClientConfig, ClientReceiver, ClientSender},
+    commands::prelude::*,
+    config::{Config, ConfigBuilder, ConfigSession},
+    emulator::Emulator,
+    gauge::GaugeBuilder,
+    glasses::{Glasses, GlassesApi, GlassesError, NoopGlasses, Preview},
+    image::Image,
+    layout::{LayoutBuilder, LayoutCommand},
+    page::Page,
+    polyline::Polyline,
+    protocol::{CommandPacket, Packet, ProtocolError, ResponsePacket},
+    server::{ActiveLookServer, FaultInjection},
+    text::TextWrap,
+    traits::{Deserializable, Serializable},
+    SerializationError,
+};
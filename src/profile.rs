@@ -0,0 +1,281 @@
+//! Grey rendering of the display models
+//!
+//! Display and lens models do not render the 16 grey levels alike. A [DisplayProfile] describes
+//! how to adapt the content to a model: the gamma and contrast of image conversions, and the
+//! range of grey levels rendered distinctly. [DisplayProfiles] selects the profile of the
+//! glasses from their [DeviceInfo::DisplayModel] and [DeviceInfo::LensModel], once connected.
+//!
+//! The API documentation does not describe the rendering of each model, so no profile is built
+//! in: register the profiles measured on your hardware. Unknown models use
+//! [DisplayProfile::default], which changes nothing.
+//!
+//! [Profiled] clamps the grey levels of the drawing commands sent to the glasses. Images are not
+//! converted again: build them with [DisplayProfile::conversion].
+use std::collections::BTreeMap;
+
+use crate::{
+    commands::{Command, DeviceInfo, Grey, Response},
+    device_info::DeviceInfoValue,
+    glasses::{GlassesApi, GlassesError},
+    image::GreyConversion,
+};
+
+/// Rendering adjustments for a display model, see the [module](self) documentation
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DisplayProfile {
+    gamma: f32,
+    contrast: f32,
+    min_grey: Grey,
+    max_grey: Grey,
+}
+
+impl Default for DisplayProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DisplayProfile {
+    /// Profile changing nothing: no gamma correction, same contrast, every grey level
+    pub fn new() -> Self {
+        Self {
+            gamma: 1.0,
+            contrast: 1.0,
+            min_grey: Grey::BLACK,
+            max_grey: Grey::WHITE,
+        }
+    }
+
+    /// Gamma of the image conversions, see [GreyConversion::gamma]
+    pub fn gamma(mut self, gamma: f32) -> Self {
+        self.gamma = gamma;
+        self
+    }
+
+    /// Contrast of the image conversions, see [GreyConversion::contrast]
+    pub fn contrast(mut self, contrast: f32) -> Self {
+        self.contrast = contrast;
+        self
+    }
+
+    /// Darkest and brightest grey levels rendered distinctly
+    pub fn grey_range(mut self, min: Grey, max: Grey) -> Self {
+        let (min, max) = (min.min(max), max.max(min).min(Grey::WHITE));
+        self.min_grey = min.min(max);
+        self.max_grey = max;
+        self
+    }
+
+    /// `conversion` adjusted for the profile, keeping its dithering
+    pub fn conversion(&self, conversion: GreyConversion) -> GreyConversion {
+        conversion
+            .gamma(self.gamma)
+            .contrast(self.contrast)
+            .grey_range(self.min_grey, self.max_grey)
+    }
+
+    /// `grey` within the range of the profile
+    pub fn clamp(&self, grey: Grey) -> Grey {
+        grey.clamp(self.min_grey, self.max_grey)
+    }
+
+    /// `cmd` with its grey levels clamped, if any changed
+    pub fn adjust(&self, cmd: &Command) -> Option<Command> {
+        let mut adjusted = cmd.clone();
+        let grey = match &mut adjusted {
+            Command::Grey { lvl: grey }
+            | Command::Color { color: grey }
+            | Command::Txt { color: grey, .. } => grey,
+            _ => return None,
+        };
+        let clamped = self.clamp(*grey);
+        if clamped == *grey {
+            return None;
+        }
+        *grey = clamped;
+        Some(adjusted)
+    }
+}
+
+/// Profiles of the known display models
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DisplayProfiles {
+    /// Profile by display model, and optionally lens model
+    profiles: BTreeMap<(String, Option<String>), DisplayProfile>,
+}
+
+impl DisplayProfiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `profile` for `display_model`, with any lens
+    pub fn display(mut self, display_model: &str, profile: DisplayProfile) -> Self {
+        self.profiles
+            .insert((String::from(display_model), None), profile);
+        self
+    }
+
+    /// Use `profile` for `display_model` with `lens_model`, instead of the profile of the
+    /// display with any lens
+    pub fn display_with_lens(
+        mut self,
+        display_model: &str,
+        lens_model: &str,
+        profile: DisplayProfile,
+    ) -> Self {
+        let key = (String::from(display_model), Some(String::from(lens_model)));
+        self.profiles.insert(key, profile);
+        self
+    }
+
+    /// Profile of `display_model` with `lens_model`, the default one if the display is unknown
+    pub fn get(&self, display_model: &str, lens_model: Option<&str>) -> DisplayProfile {
+        let display_model = String::from(display_model);
+        let with_lens = lens_model.and_then(|lens| {
+            self.profiles
+                .get(&(display_model.clone(), Some(String::from(lens))))
+        });
+        with_lens
+            .or_else(|| self.profiles.get(&(display_model, None)))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Read the display and lens models of `glasses`, and return their profile
+    pub fn detect<G: GlassesApi + ?Sized>(
+        &self,
+        glasses: &mut G,
+    ) -> Result<DisplayProfile, GlassesError> {
+        let display_model = model(glasses, DeviceInfo::DisplayModel)?;
+        let lens_model = model(glasses, DeviceInfo::LensModel)?;
+        Ok(match display_model {
+            Some(display_model) => self.get(&display_model, lens_model.as_deref()),
+            None => DisplayProfile::default(),
+        })
+    }
+}
+
+/// Text value of device information `id`, if the glasses give one
+fn model<G: GlassesApi + ?Sized>(
+    glasses: &mut G,
+    id: DeviceInfo,
+) -> Result<Option<String>, GlassesError> {
+    match glasses.device_info(id) {
+        Ok(DeviceInfoValue::Text(text)) => Ok(Some(text)),
+        Ok(_) | Err(GlassesError::Command(_)) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// [GlassesApi] clamping the grey levels of the drawing commands to a [DisplayProfile]
+pub struct Profiled<G: GlassesApi> {
+    glasses: G,
+    profile: DisplayProfile,
+}
+
+impl<G: GlassesApi> Profiled<G> {
+    pub fn new(glasses: G, profile: DisplayProfile) -> Self {
+        Self { glasses, profile }
+    }
+
+    /// Select the profile of `glasses` among `profiles`, see [DisplayProfiles::detect]
+    pub fn detect(mut glasses: G, profiles: &DisplayProfiles) -> Result<Self, GlassesError> {
+        let profile = profiles.detect(&mut glasses)?;
+        Ok(Self::new(glasses, profile))
+    }
+
+    pub fn profile(&self) -> &DisplayProfile {
+        &self.profile
+    }
+
+    pub fn into_inner(self) -> G {
+        self.glasses
+    }
+}
+
+impl<G: GlassesApi> GlassesApi for Profiled<G> {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        match self.profile.adjust(cmd) {
+            Some(adjusted) => self.glasses.send(&adjusted),
+            None => self.glasses.send(cmd),
+        }
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.glasses.query(cmd)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.glasses.send_chunked(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd_error::CommandError,
+        commands::{CmdError, ImgFormat},
+        image::Image,
+        mock::MockClient,
+    };
+
+    fn grey(level: u8) -> Grey {
+        Grey::new(level).unwrap()
+    }
+
+    fn info(text: &str) -> Response {
+        Response::RdDevInfo {
+            parameters: text.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_profiles() {
+        let dim = DisplayProfile::new().grey_range(grey(3), grey(12));
+        let dim_lens = dim.gamma(2.2);
+        let profiles = DisplayProfiles::new()
+            .display("D1", dim)
+            .display_with_lens("D1", "L2", dim_lens);
+        assert_eq!(dim, profiles.get("D1", None));
+        assert_eq!(dim, profiles.get("D1", Some("L1")));
+        assert_eq!(dim_lens, profiles.get("D1", Some("L2")));
+        assert_eq!(DisplayProfile::default(), profiles.get("D2", Some("L2")));
+
+        let mut mock = MockClient::new();
+        mock.expect(Command::Info {
+            id: DeviceInfo::DisplayModel,
+        })
+        .reply(info("D1"));
+        mock.expect(Command::Info {
+            id: DeviceInfo::LensModel,
+        })
+        .fail(GlassesError::Command(
+            CommandError::from_response(&Response::CmdError {
+                cmd_id: 0xE3,
+                error: CmdError::Generic,
+                sub_error: 0,
+            })
+            .unwrap(),
+        ));
+        let mut profiled = Profiled::detect(mock, &profiles).unwrap();
+        assert_eq!(&dim, profiled.profile());
+
+        profiled.glasses.expect(Command::Color { color: grey(12) });
+        profiled.glasses.expect(Command::Grey { lvl: grey(5) });
+        profiled
+            .send(&Command::Color { color: Grey::WHITE })
+            .unwrap();
+        profiled.send(&Command::Grey { lvl: grey(5) }).unwrap();
+        profiled.into_inner().verify();
+    }
+
+    #[test]
+    fn test_conversion() {
+        let profile = DisplayProfile::new().grey_range(grey(3), grey(12));
+        let conversion = profile.conversion(GreyConversion::new());
+        let data = Image::grey_data(&[0, 255], 2, ImgFormat::Img4bpp, &conversion).unwrap();
+        assert_eq!(vec![0xC3], data);
+    }
+}
@@ -23,8 +23,8 @@
 //! | 1B     | 1B         | 1B             | 2B          | nB       | mB             | 1B     |
 //!
 //!
-//! A Packet can be bigger than the BLE max size; we then have to send it in multiple chunks.
-//! This should be handled by the lower protocol layers.
+//! A Packet can be bigger than the BLE max size; we then have to send it in multiple chunks,
+//! see [Packet::to_chunked_frames].
 //!
 //! From ActiveLook official documentation:
 //!
@@ -32,25 +32,18 @@
 //!    The length and presence of a footer are checked to reconstruct the whole command.
 //!
 use crate::{
-    commands::{Command, Response},
+    commands::{Command, ImgFormat, Response},
     traits::*,
+    transport::ATT_HEADER_LEN,
 };
 use deku::prelude::*;
-use embedded_io::{self, Read, Write};
 //use embedded_io::{ReadReady, WriteReady};
-use log::*;
 use thiserror::Error;
 
-/// Min packet size, based on the smallest valid packet
-pub const PACKET_MIN_SIZE: usize = 5;
-/// Max packet size, as defined in ActiveLook documentation 3.1. Rx Server - Length
-pub const PACKET_MAX_SIZE: usize = 533;
-/// Max data size, as defined in ActiveLook documentation 3.1. Rx Server - Length
-pub const PACKET_DATA_MAX_SIZE: usize = 512;
-/// Delimiter at the start of a packet
-const PACKET_START: u8 = 0xFF;
-/// Delimiter at the end of a packet
-const PACKET_END: u8 = 0xAA;
+pub mod consts;
+
+pub use consts::{PACKET_DATA_MAX_SIZE, PACKET_MAX_SIZE, PACKET_MIN_SIZE};
+use consts::{PACKET_END, PACKET_START};
 
 /// Errors returned when dealing with the Protocol.
 #[derive(Error, Debug, PartialEq)]
@@ -73,6 +66,27 @@ pub enum ProtocolError {
     /// Incorrect QueryID
     #[error("QueryID does not correspond to sent Command")]
     IncorrectQueryId,
+    /// The image format is not accepted by [Command::ImgStream]
+    #[error("Image format {0:?} cannot be streamed")]
+    StreamFormat(ImgFormat),
+    /// Error notified by the glasses on the Control characteristic
+    #[error("Flow control error {0:?}")]
+    FlowControl(FlowErrorCtrl),
+    /// The upload was stopped with its [crate::upload::CancelToken]
+    #[error("Upload cancelled")]
+    Cancelled,
+    /// The command ID given to [Packet::from_parts] is not the one of the data
+    #[error("Command ID 0x{cmd_id:02X} does not match the data ID 0x{data_id:02X}")]
+    IdMismatch { cmd_id: u8, data_id: u8 },
+    /// The data of a packet is limited to [PACKET_DATA_MAX_SIZE] bytes
+    #[error("Data of {0} bytes exceeds the {PACKET_DATA_MAX_SIZE} bytes of a packet")]
+    PayloadTooLarge(usize),
+    /// The buffer given to [write_packet] can not hold the packet
+    #[error("Buffer too small for the packet")]
+    BufferTooSmall,
+    /// No response was received within the timeout of the client
+    #[error("No response within {0:?}")]
+    Timeout(core::time::Duration),
     /// Not an error, used to signify there is nothing to read
     #[error("No data")]
     Empty,
@@ -80,27 +94,44 @@ pub enum ProtocolError {
 
 /// Flow Control: used to prevent the Client Device application from overloading the BLE memory
 /// buffer of the ActiveLook device.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 #[repr(u8)]
 pub enum FlowErrorCtrl {
     // Flow control
     /// Client can send data
-    ClientCanSend = 0x01,
+    ClientCanSend = consts::CTRL_CLIENT_CAN_SEND,
     /// Buffer reaches 75%, the client should stop sending data and wait for value return to 0x01
-    ClientShouldWait = 0x02,
+    ClientShouldWait = consts::CTRL_CLIENT_SHOULD_WAIT,
 
     // Error control
     /// The command was incomplete or corrupt, the command is ignored
-    MessageError = 0x03,
+    MessageError = consts::CTRL_MESSAGE_ERROR,
     /// Receive message queue overflow
-    MessageQueueOverflow = 0x04,
-    ReservedError = 0x05,
+    MessageQueueOverflow = consts::CTRL_MESSAGE_QUEUE_OVERFLOW,
+    ReservedError = consts::CTRL_RESERVED_ERROR,
     /// Missing the `cfgWrite` command before configuration modification
-    MissingCfgWrite = 0x06,
+    MissingCfgWrite = consts::CTRL_MISSING_CFG_WRITE,
+}
+
+impl TryFrom<u8> for FlowErrorCtrl {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            consts::CTRL_CLIENT_CAN_SEND => Ok(FlowErrorCtrl::ClientCanSend),
+            consts::CTRL_CLIENT_SHOULD_WAIT => Ok(FlowErrorCtrl::ClientShouldWait),
+            consts::CTRL_MESSAGE_ERROR => Ok(FlowErrorCtrl::MessageError),
+            consts::CTRL_MESSAGE_QUEUE_OVERFLOW => Ok(FlowErrorCtrl::MessageQueueOverflow),
+            consts::CTRL_RESERVED_ERROR => Ok(FlowErrorCtrl::ReservedError),
+            consts::CTRL_MISSING_CFG_WRITE => Ok(FlowErrorCtrl::MissingCfgWrite),
+            _ => Err(value),
+        }
+    }
 }
 
 /// Some packet options
 #[deku_derive(DekuRead, DekuWrite)]
-#[derive(Default)]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub struct CmdFormat {
     #[deku(bits = "3")]
     _reserved: u8,
@@ -116,7 +147,8 @@ pub struct CmdFormat {
 pub struct Packet<T> {
     cmd_id: u8,
     format: CmdFormat,
-    length: i16,
+    /// Total length of the packet, including the delimiters
+    length: u16,
     pub query_id: Option<Vec<u8>>,
     /// Contains the application payload: [Command] or [Response]
     pub data: T,
@@ -134,8 +166,22 @@ pub type CommandPacket = Packet<Command>;
 /// Packet embedding a [Response]
 pub type ResponsePacket = Packet<Response>;
 
+/// Fields of a [Packet], see [Packet::raw_parts]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PacketParts<T> {
+    pub cmd_id: u8,
+    pub format: CmdFormat,
+    /// Total length of the packet, including the delimiters
+    pub length: u16,
+    pub query_id: Option<Vec<u8>>,
+    pub data: T,
+}
+
 impl<'a> RawPacket<'a> {
     /// Construct a Packet from raw bytes
+    ///
+    /// Every field is bounds checked: truncated or inconsistent packets return a
+    /// [ProtocolError], whatever the bytes received.
     pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, ProtocolError> {
         if bytes.len() < PACKET_MIN_SIZE {
             return Err(ProtocolError::PacketLengthTooSmall);
@@ -159,39 +205,39 @@ impl<'a> RawPacket<'a> {
 
         // Length
         // Total length of the packet, including the start and stop delimiters.
-        let length: i16 = if cmd_format.long == 1 {
-            let len = i16::from_be_bytes(bytes[index..index + 1].try_into().unwrap());
+        let length: u16 = if cmd_format.long == 1 {
+            let len = bytes
+                .get(index..index + 2)
+                .ok_or(ProtocolError::PacketLengthTooSmall)?;
             index += 2;
-            len
+            u16::from_be_bytes([len[0], len[1]])
         } else {
             let len = bytes[index];
             index += 1;
-            len as i16
+            len as u16
         };
 
         if bytes.len() != length as usize {
             return Err(ProtocolError::InvalidPacketLength);
         }
 
+        // Everything between the header and the end delimiter is data
+        let data_end = bytes.len() - 1;
+        let header_len = index + cmd_format.query_id_size;
+        if header_len > data_end {
+            return Err(ProtocolError::InvalidPacketLength);
+        }
+
         // QueryID
         let query_id = match cmd_format.query_id_size {
             0 => None,
-            len => Some(Vec::from(&bytes[index..index + len])),
+            _ => Some(Vec::from(&bytes[index..header_len])),
         };
-        index += cmd_format.query_id_size;
 
         // Data
-        let data_len = length as usize
-            -2 // delimiters 
-            -1 // cmd_id
-            -1 // cmd_format
-            -cmd_format.query_id_size
-            -cmd_format.long as usize // -1 if length is on two bytes
-            -1; // length
-
-        let data = match data_len {
-            0 => None,
-            len => Some(&bytes[index..index + len]),
+        let data = match &bytes[header_len..data_end] {
+            [] => None,
+            data => Some(data),
         };
 
         Ok(Packet {
@@ -206,82 +252,324 @@ impl<'a> RawPacket<'a> {
 
 impl CommandPacket {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
-        let raw = RawPacket::from_bytes(bytes)?;
-        Ok(Self::from(raw))
+        Self::try_from(RawPacket::from_bytes(bytes)?)
     }
 }
 
-impl From<RawPacket<'_>> for CommandPacket {
-    fn from(raw: RawPacket) -> Self {
-        Self {
+impl TryFrom<RawPacket<'_>> for CommandPacket {
+    type Error = ProtocolError;
+
+    fn try_from(raw: RawPacket) -> Result<Self, Self::Error> {
+        Ok(Self {
+            data: Command::from_data(raw.cmd_id, raw.data)?,
             cmd_id: raw.cmd_id,
             format: raw.format,
             length: raw.length,
             query_id: raw.query_id,
-            data: Command::from_data(raw.cmd_id, raw.data).expect("Invalid command bytestream"),
-        }
+        })
     }
 }
 
 impl ResponsePacket {
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, ProtocolError> {
-        let raw = RawPacket::from_bytes(bytes)?;
-        Ok(Self::from(raw))
+        Self::try_from(RawPacket::from_bytes(bytes)?)
     }
 }
 
-impl From<RawPacket<'_>> for ResponsePacket {
-    fn from(raw: RawPacket) -> Self {
-        Self {
+impl TryFrom<RawPacket<'_>> for ResponsePacket {
+    type Error = ProtocolError;
+
+    fn try_from(raw: RawPacket) -> Result<Self, Self::Error> {
+        Ok(Self {
+            data: Response::from_data(raw.cmd_id, raw.data)?,
             cmd_id: raw.cmd_id,
             format: raw.format,
             length: raw.length,
             query_id: raw.query_id,
-            data: Response::from_data(raw.cmd_id, raw.data).expect("Invalid response bytestream"),
+        })
+    }
+}
+
+/// Raw data bytes for a given command ID.
+///
+/// Used to send the chunks of a command too big for a single packet, see
+/// [Serializable::as_bytes_chunks], and commands missing from [Command], see [Packet::raw].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RawPayload {
+    pub id: u8,
+    pub data: Vec<u8>,
+}
+
+impl RawPayload {
+    pub fn new(id: u8, data: &[u8]) -> Self {
+        Self {
+            id,
+            data: Vec::from(data),
         }
     }
 }
 
+impl Serializable for RawPayload {
+    fn id(&self) -> Result<u8, DekuError> {
+        Ok(self.id)
+    }
+
+    fn data_bytes(&self) -> Result<Vec<u8>, DekuError> {
+        Ok(self.data.clone())
+    }
+
+    fn as_bytes(&self) -> Result<(u8, Vec<u8>), DekuError> {
+        Ok((self.id, self.data.clone()))
+    }
+
+    fn as_bytes_chunks(&self, chunk_size: usize) -> Result<(u8, Vec<Vec<u8>>), DekuError> {
+        Ok((
+            self.id,
+            self.data.chunks(chunk_size).map(|c| c.to_vec()).collect(),
+        ))
+    }
+}
+
+/// Borrowed [RawPayload], serialized without copying its data before the packet is written.
+///
+/// Used to send the chunks returned by [Serializable::chunks].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PayloadRef<'a> {
+    pub id: u8,
+    pub data: &'a [u8],
+}
+
+impl Serializable for PayloadRef<'_> {
+    fn id(&self) -> Result<u8, DekuError> {
+        Ok(self.id)
+    }
+
+    fn data_bytes(&self) -> Result<Vec<u8>, DekuError> {
+        Ok(self.data.to_vec())
+    }
+
+    fn as_bytes(&self) -> Result<(u8, Vec<u8>), DekuError> {
+        Ok((self.id, self.data.to_vec()))
+    }
+
+    fn as_bytes_chunks(&self, chunk_size: usize) -> Result<(u8, Vec<Vec<u8>>), DekuError> {
+        Ok((
+            self.id,
+            self.data.chunks(chunk_size).map(|c| c.to_vec()).collect(),
+        ))
+    }
+
+    fn write_data_into(&self, buf: &mut [u8]) -> Result<usize, DekuError> {
+        buf.get_mut(..self.data.len())
+            .ok_or(DekuError::Io(std::io::ErrorKind::WriteZero))?
+            .copy_from_slice(self.data);
+        Ok(self.data.len())
+    }
+}
+
+/// Accumulates bytes received in arbitrary chunks until a whole [Packet] is available.
+///
+/// BLE stacks deliver notifications of any size, and serial bridges deliver reads of any size:
+/// a packet may be split across multiple reads, and a read may hold several packets. The length
+/// field and the footer are used to reconstruct each packet, which are returned one at a time.
+///
+/// After a framing error, the assembler resynchronizes on the next [PACKET_START] byte, so the
+/// packets following noise or a corrupted packet are still received.
+#[derive(Debug, Default)]
+pub struct PacketAssembler {
+    buffer: Vec<u8>,
+}
+
+impl PacketAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append received bytes
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Number of bytes waiting for the end of a packet
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Drop all buffered bytes
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Returns the bytes of the next complete packet, or `None` if more bytes are needed.
+    ///
+    /// On a framing error, the bytes before the next [PACKET_START] are dropped: call it again to
+    /// get the following packets.
+    pub fn next_packet(&mut self) -> Result<Option<Vec<u8>>, ProtocolError> {
+        let Some(&start) = self.buffer.first() else {
+            return Ok(None);
+        };
+        if start != PACKET_START {
+            self.resync();
+            return Err(ProtocolError::FrameError);
+        }
+
+        // Start, Command ID, Command Format, and the first length byte
+        if self.buffer.len() < 4 {
+            return Ok(None);
+        }
+        let (_, cmd_format) = CmdFormat::from_bytes((&self.buffer, 2 * 8))?;
+        let length = if cmd_format.long == 1 {
+            match self.buffer.get(3..5) {
+                Some(len) => u16::from_be_bytes([len[0], len[1]]) as usize,
+                None => return Ok(None),
+            }
+        } else {
+            self.buffer[3] as usize
+        };
+
+        if !(PACKET_MIN_SIZE..=PACKET_MAX_SIZE).contains(&length) {
+            self.resync();
+            return Err(ProtocolError::InvalidPacketLength);
+        }
+        if self.buffer.len() < length {
+            return Ok(None);
+        }
+        if self.buffer[length - 1] != PACKET_END {
+            self.resync();
+            return Err(ProtocolError::FrameError);
+        }
+        Ok(Some(self.buffer.drain(..length).collect()))
+    }
+
+    /// Drop the bytes up to the next [PACKET_START], not counting the first byte
+    fn resync(&mut self) {
+        let next = self.buffer[1..]
+            .iter()
+            .position(|&byte| byte == PACKET_START)
+            .map_or(self.buffer.len(), |index| index + 1);
+        self.buffer.drain(..next);
+    }
+}
+
+impl<T> Packet<T> {
+    /// ID of the [Command] or [Response] contained in the packet
+    pub fn cmd_id(&self) -> u8 {
+        self.cmd_id
+    }
+
+    /// Format flags: length field size and query_id size
+    pub fn format(&self) -> CmdFormat {
+        self.format
+    }
+
+    /// Total length of the packet, including the delimiters
+    pub fn length(&self) -> u16 {
+        self.length
+    }
+
+    /// Decompose the packet, to inspect or forward its fields
+    pub fn raw_parts(self) -> PacketParts<T> {
+        PacketParts {
+            cmd_id: self.cmd_id,
+            format: self.format,
+            length: self.length,
+            query_id: self.query_id,
+            data: self.data,
+        }
+    }
+}
+
+impl Packet<RawPayload> {
+    /// Create a packet for any command ID, with raw data bytes.
+    /// Sends undocumented or newer commands, which [Command] does not describe yet.
+    pub fn raw(cmd_id: u8, data: &[u8]) -> Result<Self, ProtocolError> {
+        Self::from_parts(cmd_id, None, RawPayload::new(cmd_id, data))
+    }
+
+    /// Create a packet for any command ID, with raw data bytes and a given query_id
+    pub fn raw_with_query_id(
+        cmd_id: u8,
+        data: &[u8],
+        query_id: &[u8],
+    ) -> Result<Self, ProtocolError> {
+        Self::from_parts(
+            cmd_id,
+            Some(Vec::from(query_id)),
+            RawPayload::new(cmd_id, data),
+        )
+    }
+}
+
 impl<T> Packet<T>
 where
     T: Serializable, // + Deserializable,
 {
-    /// Create a packet from a [Command] or [Response]
-    pub fn new(from: &T) -> Self {
-        let mut cmd_format = CmdFormat::default();
-        let mut length: i16 = from.data_bytes().expect("Should have data").len() as i16 + 5;
-        if length > 255 {
-            cmd_format.long = 1;
-            length += 1;
+    /// Create a packet from a [Command] or [Response].
+    /// Data longer than [PACKET_DATA_MAX_SIZE] must be split, see [Serializable::chunks].
+    pub fn new(from: &T) -> Result<Self, ProtocolError> {
+        Self::from_parts(from.id()?, None, from.clone())
+    }
+
+    /// Create a packet from a [Command] or [Response], with a given query_id
+    pub fn new_with_query_id(from: &T, query_id: &[u8]) -> Result<Self, ProtocolError> {
+        Self::from_parts(from.id()?, Some(Vec::from(query_id)), from.clone())
+    }
+
+    /// Create a packet from its fields. The length and the format flags are computed from
+    /// `query_id` and `data`, whose ID must be `cmd_id`.
+    pub fn from_parts(
+        cmd_id: u8,
+        query_id: Option<Vec<u8>>,
+        data: T,
+    ) -> Result<Self, ProtocolError> {
+        let data_id = data.id()?;
+        if data_id != cmd_id {
+            return Err(ProtocolError::IdMismatch { cmd_id, data_id });
         }
-        Self {
-            cmd_id: from.id().expect("Should be a valid Command"),
-            format: cmd_format,
-            length,
-            query_id: None,
-            data: (*from).clone(),
+        let query_id_len = query_id.as_ref().map_or(0, Vec::len);
+        if query_id_len > consts::QUERY_ID_MAX_LEN {
+            return Err(ProtocolError::InvalidPacketLength);
+        }
+        let data_len = data.data_bytes()?.len();
+        if data_len > PACKET_DATA_MAX_SIZE {
+            return Err(ProtocolError::PayloadTooLarge(data_len));
+        }
+        let length = consts::packet_len(data_len, query_id_len);
+        Ok(Self {
+            cmd_id,
+            format: Self::format_for(length, query_id_len),
+            length: length as u16,
+            query_id,
+            data,
+        })
+    }
+
+    fn format_for(length: usize, query_id_len: usize) -> CmdFormat {
+        CmdFormat {
+            long: (length > consts::SHORT_LENGTH_MAX) as u8,
+            query_id_size: query_id_len,
+            ..Default::default()
         }
     }
 
-    /// Create a packet from a [Command] or [Response], with a given query_id
-    pub fn new_with_query_id(from: &T, query_id: &[u8]) -> Self {
-        let mut packet = Packet::new(from);
-        packet.query_id = Some(Vec::from(query_id));
-        packet.format.query_id_size = query_id.len();
-        packet.length += packet.format.query_id_size as i16;
-        packet
+    /// Write the packet into `buf` without allocating, see [write_packet]
+    pub fn write_into(&self, buf: &mut [u8]) -> Result<usize, ProtocolError> {
+        write_packet(
+            &self.data,
+            self.query_id.as_deref().unwrap_or_default(),
+            buf,
+        )
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut res: Vec<u8> = Vec::new();
-        res.push(0xFF);
+        let mut res: Vec<u8> = Vec::with_capacity(self.length as usize);
+        res.push(PACKET_START);
         res.push(self.cmd_id);
         res.extend(self.format.to_bytes().unwrap());
 
-        if self.length > 255 {
-            res.extend(self.length.to_be_bytes());
-        } else {
-            res.push(self.length as u8);
+        match self.format.long {
+            1 => res.extend(self.length.to_be_bytes()),
+            _ => res.push(self.length as u8),
         }
 
         if let Some(query) = &self.query_id {
@@ -289,14 +577,81 @@ where
         }
 
         res.extend(self.data.data_bytes().expect("Should be able to unwrap"));
-        res.push(0xAA);
+        res.push(PACKET_END);
         res
     }
+
+    /// Bytes of the packet, split in BLE writes of the ATT `mtu`.
+    /// The glasses rebuild the packet from its length and footer.
+    pub fn to_chunked_frames(&self, mtu: usize) -> impl Iterator<Item = Vec<u8>> {
+        let bytes = self.to_bytes();
+        let write_len = mtu.saturating_sub(ATT_HEADER_LEN).max(1);
+        (0..bytes.len())
+            .step_by(write_len)
+            .map(move |start| bytes[start..bytes.len().min(start + write_len)].to_vec())
+    }
+}
+
+/// Write the packet containing `item` into `buf`, returning its length.
+///
+/// Unlike [Packet::to_bytes], nothing is allocated: use it on embedded centrals with a buffer of
+/// [PACKET_MAX_SIZE] bytes. An empty `query_id` means no query ID.
+/// Data longer than [PACKET_DATA_MAX_SIZE] is rejected.
+pub fn write_packet<T: Serializable>(
+    item: &T,
+    query_id: &[u8],
+    buf: &mut [u8],
+) -> Result<usize, ProtocolError> {
+    if query_id.len() > consts::QUERY_ID_MAX_LEN {
+        return Err(ProtocolError::InvalidPacketLength);
+    }
+    // The data is written after the longest header, then moved if the length fits in one byte
+    let data_start = consts::HEADER_LEN_LONG + query_id.len();
+    let data_end = buf
+        .len()
+        .checked_sub(consts::FOOTER_LEN)
+        .filter(|end| *end >= data_start)
+        .ok_or(ProtocolError::BufferTooSmall)?;
+    let data_len = item
+        .write_data_into(&mut buf[data_start..data_end])
+        .map_err(|error| match error {
+            // The data does not fit in any packet
+            DekuError::Io(_) if buf.len() >= PACKET_MAX_SIZE => match item.data_bytes() {
+                Ok(data) => ProtocolError::PayloadTooLarge(data.len()),
+                Err(error) => ProtocolError::ParseError(error),
+            },
+            DekuError::Io(_) => ProtocolError::BufferTooSmall,
+            other => ProtocolError::ParseError(other),
+        })?;
+    if data_len > PACKET_DATA_MAX_SIZE {
+        return Err(ProtocolError::PayloadTooLarge(data_len));
+    }
+
+    let length = consts::packet_len(data_len, query_id.len());
+    let long = length > consts::SHORT_LENGTH_MAX;
+    let mut index = 0;
+    buf[index] = PACKET_START;
+    buf[index + 1] = item.id()?;
+    buf[index + 2] = consts::command_format(query_id.len(), long);
+    index += 3;
+    if long {
+        buf[index..index + 2].copy_from_slice(&(length as u16).to_be_bytes());
+        index += 2;
+    } else {
+        buf.copy_within(data_start..data_start + data_len, data_start - 1);
+        buf[index] = length as u8;
+        index += 1;
+    }
+    buf[index..index + query_id.len()].copy_from_slice(query_id);
+    buf[length - 1] = PACKET_END;
+    Ok(length)
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use crate::commands::{Point, StreamImgFormat};
+    use consts::{FORMAT_LONG_LENGTH, QUERY_ID_MAX_LEN, SHORT_LENGTH_MAX};
 
     #[test]
     fn test_packet_too_small() {
@@ -335,14 +690,14 @@ pub mod tests {
             data: None,
         };
 
-        let packet = CommandPacket::from(raw);
+        let packet = CommandPacket::try_from(raw).unwrap();
         assert_eq!(packet.cmd_id, 0x01);
         assert_eq!(packet.data, cmd);
     }
 
     #[test]
     fn test_raw_to_command_conversion_with_data() {
-        let cmd = Command::PowerDisplay { en: 1 };
+        let cmd = Command::PowerDisplay { en: true };
         let raw = RawPacket {
             cmd_id: cmd.id().unwrap(),
             format: CmdFormat::default(),
@@ -351,24 +706,46 @@ pub mod tests {
             data: Some(&[0x01]),
         };
 
-        let packet = CommandPacket::from(raw);
+        let packet = CommandPacket::try_from(raw).unwrap();
         assert_eq!(packet.cmd_id, 0x00);
         assert_eq!(packet.data, cmd);
     }
 
+    #[test]
+    fn test_raw_packet() {
+        // Same bytes as the documented command
+        let cmd = Command::PowerDisplay { en: true };
+        assert_eq!(
+            Packet::new_with_query_id(&cmd, &[7]).unwrap().to_bytes(),
+            Packet::raw_with_query_id(0x00, &[0x01], &[7])
+                .unwrap()
+                .to_bytes()
+        );
+        // Unknown command IDs
+        let bytes = Packet::raw(0xF0, &[1, 2, 3]).unwrap().to_bytes();
+        assert_eq!(vec![0xFF, 0xF0, 0x00, 0x08, 1, 2, 3, 0xAA], bytes);
+        let raw = RawPacket::from_bytes(&bytes).unwrap();
+        assert_eq!((0xF0, Some(&[1, 2, 3][..])), (raw.cmd_id(), raw.data));
+        assert!(CommandPacket::try_from(raw).is_err());
+        assert_eq!(
+            Some(ProtocolError::PayloadTooLarge(PACKET_DATA_MAX_SIZE + 1)),
+            Packet::raw(0xF0, &[0; PACKET_DATA_MAX_SIZE + 1]).err()
+        );
+    }
+
     #[test]
     fn test_packet_creation() {
-        let cmd = Command::PowerDisplay { en: 1 };
-        let packet = Packet::new(&cmd);
+        let cmd = Command::PowerDisplay { en: true };
+        let packet = Packet::new(&cmd).unwrap();
         assert_eq!(packet.cmd_id, 0x00);
     }
 
     #[test]
     fn test_packet_serialization() {
         let expected = [0xFF, 0x00, 0x00, 0x06, 0x01, 0xAA];
-        let expected_cmd = Command::PowerDisplay { en: 1 };
-        let cmd = Command::PowerDisplay { en: 1 };
-        let packet = Packet::new(&cmd);
+        let expected_cmd = Command::PowerDisplay { en: true };
+        let cmd = Command::PowerDisplay { en: true };
+        let packet = Packet::new(&cmd).unwrap();
         // Serialization
         let bytes = packet.to_bytes();
         assert_eq!(expected, bytes[..]);
@@ -377,4 +754,406 @@ pub mod tests {
         let newpkt = CommandPacket::from_bytes(&bytes).expect("Should be able to deserialize");
         assert_eq!(expected_cmd, newpkt.data);
     }
+
+    #[test]
+    fn test_long_packet() {
+        let cmd = Command::LayoutDisplay {
+            id: 1,
+            text: String::from_utf8(vec![b'a'; 250]).unwrap(),
+        };
+        // The query_id makes the packet longer than 255 bytes
+        let bytes = Packet::new_with_query_id(&cmd, &[0; 4]).unwrap().to_bytes();
+        assert_eq!(0x14, bytes[2]);
+        assert_eq!(262, u16::from_be_bytes([bytes[3], bytes[4]]));
+        assert_eq!(262, bytes.len());
+
+        let raw = RawPacket::from_bytes(&bytes).unwrap();
+        assert_eq!(0x62, raw.cmd_id());
+        assert_eq!(Some(vec![0; 4]), raw.query_id);
+        assert_eq!(cmd, CommandPacket::try_from(raw).unwrap().data);
+    }
+
+    #[test]
+    fn test_length_boundary_roundtrip() {
+        for data_len in 240..270 {
+            for query_len in [0, 1, 4, QUERY_ID_MAX_LEN] {
+                let payload = RawPayload {
+                    id: 0x41,
+                    data: vec![0x55; data_len],
+                };
+                let packet = Packet::new_with_query_id(&payload, &vec![7; query_len]).unwrap();
+                let bytes = packet.to_bytes();
+                let long = bytes.len() > SHORT_LENGTH_MAX;
+                assert_eq!(bytes.len(), packet.length() as usize);
+                assert_eq!(long, bytes[2] & FORMAT_LONG_LENGTH != 0);
+                let length = match long {
+                    true => u16::from_be_bytes([bytes[3], bytes[4]]) as usize,
+                    false => bytes[3] as usize,
+                };
+                assert_eq!(bytes.len(), length);
+
+                let raw = RawPacket::from_bytes(&bytes).unwrap();
+                assert_eq!(packet.length(), raw.length());
+                assert_eq!(Some(&payload.data[..]), raw.data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_img_stream_packet() {
+        let data = vec![0xA5; 480];
+        let cmd = Command::ImgStream {
+            size: data.len() as u32,
+            width: 64,
+            coord: Point { x: 0, y: 0 },
+            format: StreamImgFormat::Img1bpp,
+            data,
+        };
+        let bytes = Packet::new(&cmd).unwrap().to_bytes();
+        assert!(bytes.len() > SHORT_LENGTH_MAX);
+        assert_eq!(cmd, CommandPacket::from_bytes(&bytes).unwrap().data);
+    }
+
+    #[test]
+    fn test_chunked_frames() {
+        let cmd = Command::Polyline {
+            thickness: 1,
+            _reserved: 0,
+            points: vec![Point { x: 1, y: 2 }; 100],
+        };
+        let packet = Packet::new(&cmd).unwrap();
+        let frames: Vec<Vec<u8>> = packet.to_chunked_frames(185).collect();
+        assert_eq!(3, frames.len());
+        assert!(frames.iter().all(|frame| frame.len() <= 182));
+        assert_eq!(packet.to_bytes(), frames.concat());
+
+        let mut assembler = PacketAssembler::new();
+        for frame in &frames[..2] {
+            assembler.push(frame);
+            assert_eq!(Ok(None), assembler.next_packet());
+        }
+        assembler.push(&frames[2]);
+        let bytes = assembler.next_packet().unwrap().unwrap();
+        assert_eq!(cmd, CommandPacket::from_bytes(&bytes).unwrap().data);
+
+        assert_eq!(
+            1,
+            Packet::new(&Command::Clear)
+                .unwrap()
+                .to_chunked_frames(512)
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_write_packet() {
+        let mut buf = [0; PACKET_MAX_SIZE];
+        for cmd in crate::vectors::sample_commands() {
+            for query_id in [&[][..], &[1, 2, 3, 4]] {
+                let len = write_packet(&cmd, query_id, &mut buf).unwrap();
+                let packet = match query_id.len() {
+                    0 => Packet::new(&cmd).unwrap(),
+                    _ => Packet::new_with_query_id(&cmd, query_id).unwrap(),
+                };
+                assert_eq!(packet.to_bytes(), buf[..len], "{:?}", cmd);
+                assert_eq!(Ok(len), packet.write_into(&mut buf));
+            }
+        }
+        for response in crate::vectors::sample_responses() {
+            let len = write_packet(&response, &[], &mut buf).unwrap();
+            assert_eq!(Packet::new(&response).unwrap().to_bytes(), buf[..len]);
+        }
+
+        let cmd = Command::LayoutDisplay {
+            id: 1,
+            text: String::from_utf8(vec![b'a'; 250]).unwrap(),
+        };
+        let len = write_packet(&cmd, &[0; 4], &mut buf).unwrap();
+        assert_eq!(
+            Packet::new_with_query_id(&cmd, &[0; 4]).unwrap().to_bytes(),
+            buf[..len]
+        );
+        assert_eq!(
+            Err(ProtocolError::BufferTooSmall),
+            write_packet(&cmd, &[], &mut buf[..100])
+        );
+        assert_eq!(
+            Err(ProtocolError::BufferTooSmall),
+            write_packet(&Command::Clear, &[], &mut buf[..4])
+        );
+    }
+
+    #[test]
+    fn test_packet_parts() {
+        let cmd = Command::Battery;
+        let parts = Packet::new_with_query_id(&cmd, &[1, 2])
+            .unwrap()
+            .raw_parts();
+        assert_eq!(0x05, parts.cmd_id);
+        assert_eq!(2, parts.format.query_id_size);
+        assert_eq!(0, parts.format.long);
+        assert_eq!(7, parts.length);
+
+        let packet = Packet::from_parts(parts.cmd_id, parts.query_id, parts.data).unwrap();
+        assert_eq!(
+            Packet::new_with_query_id(&cmd, &[1, 2]).unwrap().to_bytes(),
+            packet.to_bytes()
+        );
+        // Forwarded raw data gets its length recomputed
+        let payload = RawPayload {
+            id: 0x41,
+            data: vec![0; 300],
+        };
+        let packet = Packet::from_parts(0x41, None, payload.clone()).unwrap();
+        assert_eq!(1, packet.format().long);
+        assert_eq!(306, packet.length());
+        assert_eq!(
+            Some(ProtocolError::IdMismatch {
+                cmd_id: 0x42,
+                data_id: 0x41
+            }),
+            Packet::from_parts(0x42, None, payload).err()
+        );
+        assert_eq!(
+            Some(ProtocolError::InvalidPacketLength),
+            Packet::from_parts(0x05, Some(vec![0; QUERY_ID_MAX_LEN + 1]), cmd).err()
+        );
+    }
+
+    #[test]
+    fn test_payload_too_large() {
+        let polyline = Command::Polyline {
+            thickness: 1,
+            _reserved: 0,
+            points: vec![Point { x: 0, y: 0 }; 200],
+        };
+        // Thickness, reserved field and the points
+        assert_eq!(Ok(803), polyline.wire_size());
+        assert_eq!(
+            Some(ProtocolError::PayloadTooLarge(803)),
+            Packet::new(&polyline).err()
+        );
+        let mut buf = [0; PACKET_MAX_SIZE];
+        assert_eq!(
+            Err(ProtocolError::PayloadTooLarge(803)),
+            write_packet(&polyline, &[], &mut buf)
+        );
+
+        // Fits in the buffer, but not in a packet
+        let payload = |len| RawPayload {
+            id: 0x41,
+            data: vec![0; len],
+        };
+        assert_eq!(
+            Err(ProtocolError::PayloadTooLarge(520)),
+            write_packet(&payload(520), &[], &mut buf)
+        );
+        let full =
+            Packet::new_with_query_id(&payload(PACKET_DATA_MAX_SIZE), &[0; QUERY_ID_MAX_LEN])
+                .unwrap();
+        assert_eq!(PACKET_MAX_SIZE, full.length() as usize);
+        assert_eq!(
+            Ok(PACKET_MAX_SIZE),
+            write_packet(&full.data, &[0; QUERY_ID_MAX_LEN], &mut buf)
+        );
+    }
+
+    #[test]
+    fn test_assembler_one_byte_at_a_time() {
+        let bytes = Packet::new_with_query_id(&Command::Grey { lvl: 3.into() }, &[1, 2])
+            .unwrap()
+            .to_bytes();
+        let mut assembler = PacketAssembler::new();
+        for (i, byte) in bytes.iter().enumerate() {
+            assert_eq!(Ok(None), assembler.next_packet(), "byte {}", i);
+            assembler.push(&[*byte]);
+        }
+        assert_eq!(Ok(Some(bytes)), assembler.next_packet());
+        assert_eq!(0, assembler.pending());
+    }
+
+    #[test]
+    fn test_assembler_split_and_merged_chunks() {
+        let first = Packet::new(&Command::Clear).unwrap().to_bytes();
+        let second = Packet::new(&Command::Luma { level: 8.into() })
+            .unwrap()
+            .to_bytes();
+        let stream: Vec<u8> = first.iter().chain(second.iter()).copied().collect();
+
+        // First notification ends in the middle of the second packet
+        let split = first.len() + 2;
+        let mut assembler = PacketAssembler::new();
+        assembler.push(&stream[..split]);
+        assert_eq!(Ok(Some(first)), assembler.next_packet());
+        assert_eq!(Ok(None), assembler.next_packet());
+        assembler.push(&stream[split..]);
+        assert_eq!(Ok(Some(second)), assembler.next_packet());
+    }
+
+    #[test]
+    fn test_assembler_frame_error() {
+        let mut assembler = PacketAssembler::new();
+        assembler.push(&[0x00, 0x01, 0x00, 0x05, 0xAA]);
+        assert_eq!(Err(ProtocolError::FrameError), assembler.next_packet());
+        assert_eq!(0, assembler.pending());
+    }
+
+    #[test]
+    fn test_assembler_resync() {
+        let first = Packet::new(&Command::Clear).unwrap().to_bytes();
+        let second = Packet::new(&Command::Luma { level: 8.into() })
+            .unwrap()
+            .to_bytes();
+        let mut corrupted = first.clone();
+        *corrupted.last_mut().unwrap() = 0x00;
+
+        // Noise, a corrupted packet, then two valid packets in a single read
+        let mut stream = vec![0x12, 0x34];
+        stream.extend(&corrupted);
+        stream.extend(&first);
+        stream.extend(&second);
+        let mut assembler = PacketAssembler::new();
+        assembler.push(&stream);
+        assert_eq!(Err(ProtocolError::FrameError), assembler.next_packet());
+        assert_eq!(Err(ProtocolError::FrameError), assembler.next_packet());
+        assert_eq!(Ok(Some(first)), assembler.next_packet());
+        assert_eq!(Ok(Some(second)), assembler.next_packet());
+        assert_eq!(Ok(None), assembler.next_packet());
+
+        // A length over the maximum does not wait for the missing bytes
+        assembler.push(&[PACKET_START, 0x01, 0x10, 0xFF, 0xFF]);
+        assert_eq!(
+            Err(ProtocolError::InvalidPacketLength),
+            assembler.next_packet()
+        );
+    }
+
+    mod proptests {
+        use super::*;
+        use crate::commands::*;
+        use proptest::prelude::*;
+
+        /// Commands decoded from the data of [crate::vectors::sample_commands], with a few bytes
+        /// changed: every variant, with varied field values
+        fn command() -> impl Strategy<Value = Command> {
+            (
+                prop::sample::select(crate::vectors::sample_commands()),
+                prop::collection::vec((any::<prop::sample::Index>(), any::<u8>()), 0..4),
+            )
+                .prop_filter_map("Invalid field values", |(sample, changes)| {
+                    let mut data = sample.data_bytes().unwrap();
+                    if !data.is_empty() {
+                        for (index, byte) in changes {
+                            let index = index.index(data.len());
+                            data[index] = byte;
+                        }
+                    }
+                    let data = (!data.is_empty()).then_some(&data[..]);
+                    let cmd = Command::from_data(sample.id().unwrap(), data).ok()?;
+                    Packet::new(&cmd).is_ok().then_some(cmd)
+                })
+        }
+
+        /// Bytes between the delimiters of a packet
+        fn framed(inner: Vec<u8>) -> Vec<u8> {
+            let mut bytes = vec![PACKET_START];
+            bytes.extend(inner);
+            bytes.push(PACKET_END);
+            bytes
+        }
+
+        proptest! {
+            #[test]
+            fn test_random_bytes_do_not_panic(bytes in prop::collection::vec(any::<u8>(), 0..600)) {
+                let _ = RawPacket::from_bytes(&bytes);
+                let _ = CommandPacket::from_bytes(&bytes);
+                let _ = ResponsePacket::from_bytes(&bytes);
+            }
+
+            #[test]
+            fn test_random_frames_do_not_panic(inner in prop::collection::vec(any::<u8>(), 3..600)) {
+                let bytes = framed(inner);
+                let _ = RawPacket::from_bytes(&bytes);
+                let _ = CommandPacket::from_bytes(&bytes);
+                let _ = ResponsePacket::from_bytes(&bytes);
+            }
+
+            #[test]
+            fn test_random_header_with_valid_length(
+                cmd_id in any::<u8>(),
+                format in any::<u8>(),
+                data in prop::collection::vec(any::<u8>(), 0..40),
+            ) {
+                // Consistent length, but the format may announce a query ID longer than the data
+                let mut inner = vec![cmd_id, format];
+                let long = format & 0x10 != 0;
+                let length = data.len() + 4 + if long { 2 } else { 1 };
+                match long {
+                    true => inner.extend((length as u16).to_be_bytes()),
+                    false => inner.push(length as u8),
+                }
+                inner.extend(&data);
+                let bytes = framed(inner);
+                let _ = CommandPacket::from_bytes(&bytes);
+                let _ = ResponsePacket::from_bytes(&bytes);
+                if let Ok(raw) = RawPacket::from_bytes(&bytes) {
+                    let query_len = raw.query_id.as_ref().map_or(0, |q| q.len());
+                    let data_len = raw.data.map_or(0, |d| d.len());
+                    prop_assert_eq!(data.len(), query_len + data_len);
+                }
+            }
+
+            #[test]
+            fn test_assembler_random_chunks(
+                chunks in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..64), 0..16),
+            ) {
+                let mut assembler = PacketAssembler::new();
+                for chunk in chunks {
+                    assembler.push(&chunk);
+                    while let Ok(Some(packet)) = assembler.next_packet() {
+                        let _ = RawPacket::from_bytes(&packet);
+                    }
+                }
+            }
+
+            #[test]
+            fn test_command_packet_roundtrip(
+                cmd in command(),
+                query_id in prop::collection::vec(any::<u8>(), 0..=QUERY_ID_MAX_LEN),
+            ) {
+                let packet = Packet::new_with_query_id(&cmd, &query_id).unwrap();
+                let bytes = packet.to_bytes();
+                prop_assert_eq!(bytes.len(), packet.length() as usize);
+
+                let parsed = CommandPacket::from_bytes(&bytes).unwrap();
+                prop_assert_eq!(cmd.id().unwrap(), parsed.cmd_id);
+                prop_assert_eq!(&cmd, &parsed.data);
+                prop_assert_eq!(bytes, parsed.to_bytes());
+            }
+
+            #[test]
+            fn test_valid_packet_roundtrip(
+                id in any::<u8>(),
+                data in prop::collection::vec(any::<u8>(), 0..=PACKET_DATA_MAX_SIZE),
+                query_id in prop::collection::vec(any::<u8>(), 0..=QUERY_ID_MAX_LEN),
+                split in any::<prop::sample::Index>(),
+            ) {
+                let payload = RawPayload { id, data: data.clone() };
+                let bytes = Packet::new_with_query_id(&payload, &query_id).unwrap().to_bytes();
+
+                let raw = RawPacket::from_bytes(&bytes).unwrap();
+                prop_assert_eq!(id, raw.cmd_id());
+                prop_assert_eq!(query_id.len(), raw.query_id.as_ref().map_or(0, |q| q.len()));
+                prop_assert_eq!(&data[..], raw.data.unwrap_or_default());
+
+                // Received in two notifications
+                let split = split.index(bytes.len());
+                let mut assembler = PacketAssembler::new();
+                assembler.push(&bytes[..split]);
+                prop_assert_eq!(Ok(None), assembler.next_packet());
+                assembler.push(&bytes[split..]);
+                prop_assert_eq!(Ok(Some(bytes)), assembler.next_packet());
+            }
+        }
+    }
 }
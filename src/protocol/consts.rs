@@ -0,0 +1,100 @@
+//! Protocol constants
+//!
+//! Single source of truth for the framing of ActiveLook packets and the values of the Control
+//! characteristic, for implementations in other languages generated from this crate.
+
+/// Delimiter at the start of a packet
+pub const PACKET_START: u8 = 0xFF;
+/// Delimiter at the end of a packet
+pub const PACKET_END: u8 = 0xAA;
+
+/// Min packet size, based on the smallest valid packet
+pub const PACKET_MIN_SIZE: usize = 5;
+/// Max packet size, as defined in ActiveLook documentation 3.1. Rx Server - Length
+pub const PACKET_MAX_SIZE: usize = 533;
+/// Max data size, as defined in ActiveLook documentation 3.1. Rx Server - Length
+pub const PACKET_DATA_MAX_SIZE: usize = 512;
+
+/// Start, Command ID, Command Format and a 1 byte length
+pub const HEADER_LEN_SHORT: usize = 4;
+/// Start, Command ID, Command Format and a 2 bytes length
+pub const HEADER_LEN_LONG: usize = 5;
+/// End delimiter
+pub const FOOTER_LEN: usize = 1;
+/// Packets longer than this need a 2 bytes length field
+pub const SHORT_LENGTH_MAX: usize = 255;
+
+/// Bit of the Command Format set when the length field is on two bytes
+pub const FORMAT_LONG_LENGTH: u8 = 0x10;
+/// Bits of the Command Format containing the query ID length
+pub const FORMAT_QUERY_ID_MASK: u8 = 0x0F;
+/// Max length of the query ID, limited by [FORMAT_QUERY_ID_MASK]
+pub const QUERY_ID_MAX_LEN: usize = FORMAT_QUERY_ID_MASK as usize;
+
+// Control characteristic values
+/// Client can send data
+pub const CTRL_CLIENT_CAN_SEND: u8 = 0x01;
+/// Buffer reaches 75%, the client should stop sending data
+pub const CTRL_CLIENT_SHOULD_WAIT: u8 = 0x02;
+/// The command was incomplete or corrupt, the command is ignored
+pub const CTRL_MESSAGE_ERROR: u8 = 0x03;
+/// Receive message queue overflow
+pub const CTRL_MESSAGE_QUEUE_OVERFLOW: u8 = 0x04;
+pub const CTRL_RESERVED_ERROR: u8 = 0x05;
+/// Missing the `cfgWrite` command before configuration modification
+pub const CTRL_MISSING_CFG_WRITE: u8 = 0x06;
+
+/// Number of protocol bytes around the data of a packet
+pub const fn header_overhead(query_id_len: usize, long: bool) -> usize {
+    let header = if long {
+        HEADER_LEN_LONG
+    } else {
+        HEADER_LEN_SHORT
+    };
+    header + query_id_len + FOOTER_LEN
+}
+
+/// Total length of a packet containing `data_len` bytes, the length field being on two bytes
+/// when needed
+pub const fn packet_len(data_len: usize, query_id_len: usize) -> usize {
+    let short = data_len + header_overhead(query_id_len, false);
+    if short > SHORT_LENGTH_MAX {
+        data_len + header_overhead(query_id_len, true)
+    } else {
+        short
+    }
+}
+
+/// Command Format byte
+pub const fn command_format(query_id_len: usize, long: bool) -> u8 {
+    let long = if long { FORMAT_LONG_LENGTH } else { 0 };
+    long | (query_id_len as u8 & FORMAT_QUERY_ID_MASK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::Command, protocol::Packet, traits::Serializable};
+
+    #[test]
+    fn test_packet_len() {
+        assert_eq!(PACKET_MIN_SIZE, header_overhead(0, false));
+        for len in [0, 10, 245, 246, 247, 300] {
+            let cmd = Command::LayoutDisplay {
+                id: 1,
+                text: String::from_utf8(vec![b'a'; len]).unwrap(),
+            };
+            let data_len = cmd.data_bytes().unwrap().len();
+            let bytes = Packet::new_with_query_id(&cmd, &[0; 4]).unwrap().to_bytes();
+            assert_eq!(
+                packet_len(data_len, 4),
+                bytes.len(),
+                "text of {} bytes",
+                len
+            );
+            let long = bytes.len() > SHORT_LENGTH_MAX;
+            assert_eq!(command_format(4, long), bytes[2]);
+        }
+        assert!(packet_len(PACKET_DATA_MAX_SIZE, QUERY_ID_MAX_LEN) <= PACKET_MAX_SIZE);
+    }
+}
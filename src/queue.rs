@@ -0,0 +1,296 @@
+//! Bounded command queue
+//!
+//! When the link stalls under flow control, an application drawing faster than the glasses
+//! accept commands accumulates them. [CommandQueue] bounds the number of pending commands, and
+//! applies an [OverflowPolicy] when full, so small-RAM hosts do not grow without limit.
+use std::collections::VecDeque;
+
+use crate::{
+    commands::{Command, Response},
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// What to do when pushing a command to a full [CommandQueue]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest command superseded by a more recent one, like a previous value of the
+    /// same layout or gauge. Only other coalescible commands may be queued between the two, a
+    /// command like [Command::Clear] or [Command::LayoutPosition] keeps the older one. The new
+    /// command is rejected if no command can be dropped.
+    DropOldestCoalescible,
+    /// Reject the new command
+    RejectNew,
+    /// Send the oldest command to make room, blocking until the glasses accept it
+    Block,
+}
+
+/// Queue statistics
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct QueueMetrics {
+    /// Commands accepted in the queue
+    pub enqueued: usize,
+    /// Commands sent to the glasses
+    pub sent: usize,
+    /// Commands dropped because a more recent one superseded them
+    pub dropped: usize,
+    /// Commands rejected because the queue was full
+    pub rejected: usize,
+    /// Maximum number of commands queued at once
+    pub high_watermark: usize,
+}
+
+/// Commands replacing the effect of a previous command with the same key, and whether they
+/// clear what the previous command drew
+fn coalesce_key(cmd: &Command) -> Option<((u8, u8), bool)> {
+    match cmd {
+        Command::LayoutDisplay { id, .. } => Some(((0x62, *id), false)),
+        Command::LayoutClearAndDisplay { id, .. } => Some(((0x62, *id), true)),
+        Command::GaugeDisplay { id, .. } => Some(((0x70, *id), false)),
+        Command::Luma { .. } => Some(((0x10, 0), false)),
+        _ => None,
+    }
+}
+
+/// Whether `newer` replaces the effect of `older`: a command which does not clear can not
+/// replace one which does, or the clear would be lost
+fn supersedes(newer: &Command, older: &Command) -> bool {
+    match (coalesce_key(newer), coalesce_key(older)) {
+        (Some((key, clears)), Some((older_key, older_clears))) => {
+            key == older_key && (clears || !older_clears)
+        }
+        _ => false,
+    }
+}
+
+/// Bounded queue in front of a [GlassesApi].
+///
+/// Commands are queued by [GlassesApi::send], and sent by [CommandQueue::flush] or
+/// [CommandQueue::send_next]. Queries and chunked uploads flush the queue first, to keep the
+/// commands in order.
+pub struct CommandQueue<G: GlassesApi> {
+    glasses: G,
+    queue: VecDeque<Command>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    metrics: QueueMetrics,
+}
+
+impl<G: GlassesApi> CommandQueue<G> {
+    pub fn new(glasses: G, capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            glasses,
+            queue: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+            policy,
+            metrics: QueueMetrics::default(),
+        }
+    }
+
+    /// Number of commands waiting to be sent
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn metrics(&self) -> &QueueMetrics {
+        &self.metrics
+    }
+
+    /// Access the wrapped glasses
+    pub fn glasses(&mut self) -> &mut G {
+        &mut self.glasses
+    }
+
+    /// Queue a command, applying the [OverflowPolicy] if the queue is full
+    pub fn push(&mut self, cmd: Command) -> Result<(), GlassesError> {
+        if self.queue.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::DropOldestCoalescible => {
+                    let Some(index) = self.superseded(&cmd) else {
+                        self.metrics.rejected += 1;
+                        return Err(GlassesError::QueueFull);
+                    };
+                    self.queue.remove(index);
+                    self.metrics.dropped += 1;
+                }
+                OverflowPolicy::RejectNew => {
+                    self.metrics.rejected += 1;
+                    return Err(GlassesError::QueueFull);
+                }
+                OverflowPolicy::Block => {
+                    self.send_next()?;
+                }
+            }
+        }
+        self.queue.push_back(cmd);
+        self.metrics.enqueued += 1;
+        self.metrics.high_watermark = self.metrics.high_watermark.max(self.queue.len());
+        Ok(())
+    }
+
+    /// Index of the oldest queued command superseded by a more recent one, or by `new`, with
+    /// only coalescible commands between them
+    fn superseded(&self, new: &Command) -> Option<usize> {
+        self.queue.iter().enumerate().find_map(|(index, cmd)| {
+            for other in self
+                .queue
+                .iter()
+                .skip(index + 1)
+                .chain(core::iter::once(new))
+            {
+                if supersedes(other, cmd) {
+                    return Some(index);
+                }
+                // Stop at a command which is not coalescible
+                coalesce_key(other)?;
+            }
+            None
+        })
+    }
+
+    /// Send the oldest queued command. Returns false if the queue is empty.
+    pub fn send_next(&mut self) -> Result<bool, GlassesError> {
+        let Some(cmd) = self.queue.pop_front() else {
+            return Ok(false);
+        };
+        self.glasses.send(&cmd)?;
+        self.metrics.sent += 1;
+        Ok(true)
+    }
+
+    /// Send all queued commands
+    pub fn flush(&mut self) -> Result<(), GlassesError> {
+        while self.send_next()? {}
+        Ok(())
+    }
+}
+
+impl<G: GlassesApi> GlassesApi for CommandQueue<G> {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.push(cmd.clone())
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.flush()?;
+        self.glasses.query(cmd)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.flush()?;
+        self.glasses.send_chunked(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::LayoutPosition, glasses::Preview};
+
+    fn gauge(value: u8) -> Command {
+        Command::GaugeDisplay { id: 1, value }
+    }
+
+    #[test]
+    fn test_drop_oldest_coalescible() {
+        let mut queue = CommandQueue::new(Preview::new(), 3, OverflowPolicy::DropOldestCoalescible);
+        queue.send(&Command::Clear).unwrap();
+        queue.send(&gauge(1)).unwrap();
+        queue.send(&Command::Luma { level: 3.into() }).unwrap();
+        // gauge(1) is superseded by gauge(2)
+        queue.send(&gauge(2)).unwrap();
+        // Nothing can be dropped
+        assert_eq!(
+            Err(GlassesError::QueueFull),
+            queue.send(&Command::PowerDisplay { en: true })
+        );
+        queue.flush().unwrap();
+
+        assert_eq!(
+            &[Command::Luma { level: 3.into() }, gauge(2)],
+            queue.glasses().displayed()
+        );
+        assert_eq!(
+            &QueueMetrics {
+                enqueued: 4,
+                sent: 3,
+                dropped: 1,
+                rejected: 1,
+                high_watermark: 3,
+            },
+            queue.metrics()
+        );
+    }
+
+    #[test]
+    fn test_coalesce_layout_clear() {
+        let display = |text: &str| Command::LayoutDisplay {
+            id: 2,
+            text: String::from(text),
+        };
+        let clear_and_display = |text: &str| Command::LayoutClearAndDisplay {
+            id: 2,
+            text: String::from(text),
+        };
+        let mut queue = CommandQueue::new(Preview::new(), 2, OverflowPolicy::DropOldestCoalescible);
+        queue.send(&clear_and_display("a")).unwrap();
+        queue.send(&gauge(1)).unwrap();
+        // Drawing over the layout does not replace its clear
+        assert_eq!(Err(GlassesError::QueueFull), queue.send(&display("b")));
+        // Clearing it again does
+        queue.send(&clear_and_display("c")).unwrap();
+        assert_eq!(1, queue.metrics().dropped);
+        assert_eq!(1, queue.metrics().rejected);
+
+        let mut queue = CommandQueue::new(Preview::new(), 1, OverflowPolicy::DropOldestCoalescible);
+        queue.send(&display("a")).unwrap();
+        queue.send(&clear_and_display("b")).unwrap();
+        assert_eq!(1, queue.metrics().dropped);
+    }
+
+    #[test]
+    fn test_coalesce_across_state_change() {
+        let display = |text: &str| Command::LayoutDisplay {
+            id: 2,
+            text: String::from(text),
+        };
+        let position = Command::LayoutPosition {
+            id: 2,
+            pos: LayoutPosition { x: 10, y: 20 },
+        };
+        let mut queue = CommandQueue::new(Preview::new(), 3, OverflowPolicy::DropOldestCoalescible);
+        queue.send(&display("a")).unwrap();
+        queue.send(&position).unwrap();
+        queue.send(&display("b")).unwrap();
+        // display("b") is adjacent to display("c"), display("a") is drawn at the previous position
+        queue.send(&display("c")).unwrap();
+        queue.flush().unwrap();
+        assert_eq!(
+            &[display("a"), position, display("c")],
+            queue.glasses().displayed()
+        );
+        assert_eq!(1, queue.metrics().dropped);
+
+        let mut queue = CommandQueue::new(Preview::new(), 3, OverflowPolicy::DropOldestCoalescible);
+        queue.send(&display("a")).unwrap();
+        queue.send(&Command::Clear).unwrap();
+        queue.send(&gauge(1)).unwrap();
+        // The clear is between display("a") and display("b")
+        assert_eq!(Err(GlassesError::QueueFull), queue.send(&display("b")));
+    }
+
+    #[test]
+    fn test_reject_and_block() {
+        let mut queue = CommandQueue::new(Preview::new(), 1, OverflowPolicy::RejectNew);
+        queue.send(&gauge(1)).unwrap();
+        assert_eq!(Err(GlassesError::QueueFull), queue.send(&gauge(2)));
+
+        let mut queue = CommandQueue::new(Preview::new(), 1, OverflowPolicy::Block);
+        queue.send(&gauge(1)).unwrap();
+        queue.send(&gauge(2)).unwrap();
+        assert_eq!(&[gauge(1)], queue.glasses().displayed());
+        assert_eq!(1, queue.len());
+    }
+}
@@ -0,0 +1,113 @@
+//! Workarounds for known firmware quirks
+//!
+//! Some firmware versions behave differently from the documentation. [Quirks] lists the
+//! workarounds needed for the connected glasses; [crate::glasses::Glasses] applies them
+//! transparently.
+use crate::firmware::FirmwareVersion;
+
+/// A behaviour requiring a workaround
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Quirk {
+    /// [crate::commands::Command::LayoutDisplay] draws over the previous text: the layout area
+    /// must be cleared first with [crate::commands::Command::LayoutClear].
+    LayoutDisplayNeedsClear,
+}
+
+/// Entry of the [QUIRKS] table
+#[derive(Copy, Clone, Debug)]
+pub struct QuirkEntry {
+    /// First affected version
+    pub from: FirmwareVersion,
+    /// First fixed version, if any
+    pub until: Option<FirmwareVersion>,
+    /// Affected display model, as read with [crate::commands::DeviceInfo::DisplayModel], or all
+    /// models if `None`
+    pub model: Option<&'static str>,
+    pub quirk: Quirk,
+}
+
+impl QuirkEntry {
+    fn applies(&self, version: FirmwareVersion, model: Option<&str>) -> bool {
+        version >= self.from
+            && self.until.is_none_or(|until| version < until)
+            && self.model.is_none_or(|m| Some(m) == model)
+    }
+}
+
+/// Known quirks
+pub const QUIRKS: &[QuirkEntry] = &[
+    // Firmware 3.x has no LayoutClearAndDisplay and does not clear the layout area
+    QuirkEntry {
+        from: FirmwareVersion::new(0, 0, 0),
+        until: Some(FirmwareVersion::new(4, 0, 0)),
+        model: None,
+        quirk: Quirk::LayoutDisplayNeedsClear,
+    },
+];
+
+/// Set of active workarounds
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Quirks {
+    active: Vec<Quirk>,
+}
+
+impl Quirks {
+    /// No workaround
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Workarounds needed by `version` of the firmware, running on display `model`, according to
+    /// the [QUIRKS] table
+    pub fn for_device(version: FirmwareVersion, model: Option<&str>) -> Self {
+        let mut quirks = Self::none();
+        for entry in QUIRKS.iter().filter(|entry| entry.applies(version, model)) {
+            quirks.insert(entry.quirk);
+        }
+        quirks
+    }
+
+    /// Manually enable a workaround
+    pub fn insert(&mut self, quirk: Quirk) {
+        if !self.contains(quirk) {
+            self.active.push(quirk);
+        }
+    }
+
+    pub fn contains(&self, quirk: Quirk) -> bool {
+        self.active.contains(&quirk)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Quirk> {
+        self.active.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_device() {
+        let old = Quirks::for_device(FirmwareVersion::new(3, 7, 4), None);
+        assert!(old.contains(Quirk::LayoutDisplayNeedsClear));
+
+        let recent = Quirks::for_device(FirmwareVersion::new(4, 12, 0), Some("ENGO"));
+        assert_eq!(Quirks::none(), recent);
+    }
+
+    #[test]
+    fn test_model_entry() {
+        let entry = QuirkEntry {
+            from: FirmwareVersion::new(4, 0, 0),
+            until: None,
+            model: Some("D1"),
+            quirk: Quirk::LayoutDisplayNeedsClear,
+        };
+        let version = FirmwareVersion::new(4, 12, 0);
+        assert!(entry.applies(version, Some("D1")));
+        assert!(!entry.applies(version, Some("D2")));
+        assert!(!entry.applies(version, None));
+        assert!(!entry.applies(FirmwareVersion::new(3, 7, 0), Some("D1")));
+    }
+}
@@ -0,0 +1,366 @@
+//! Recording and replay of command sequences
+//!
+//! [Recorder] wraps any [GlassesApi] and logs every sent [Command] and received [Response] with
+//! its timestamp. The resulting [Trace] is saved as text, attached to bug reports, and fed back
+//! to an [Emulator] with [Replayer] to reproduce the issue in a test.
+//!
+//! A trace line contains the timestamp in microseconds, the direction (`>` sent, `<` received),
+//! the command ID and the data bytes, separated by tabs. Bytes are in hexadecimal, `-` if empty.
+use core::time::Duration;
+
+use thiserror::Error;
+
+use crate::{
+    commands::{Command, Response},
+    emulator::Emulator,
+    glasses::{GlassesApi, GlassesError},
+    redact::{Redact, RedactionPolicy},
+    time::Clock,
+    traits::*,
+    vectors::to_hex,
+};
+
+/// Header line describing the columns of [Trace::to_text]
+pub const HEADER: &str = "# time_us\tdirection\tid\tdata";
+
+/// Errors parsing a [Trace]
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum TraceError {
+    #[error("Line {0}: expected 4 columns")]
+    Columns(usize),
+    #[error("Line {0}: invalid number")]
+    Number(usize),
+    #[error("Line {0}: invalid direction")]
+    Direction(usize),
+    #[error("Line {0}: cannot decode the command or response")]
+    Decode(usize),
+}
+
+/// One recorded message
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    Sent(Command),
+    Received(Response),
+}
+
+/// [Event] with its timestamp
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceEntry {
+    /// Time since the start of the recording
+    pub time: Duration,
+    pub event: Event,
+}
+
+impl TraceEntry {
+    fn to_line(&self) -> String {
+        let (direction, bytes) = match &self.event {
+            Event::Sent(cmd) => ('>', cmd.as_bytes()),
+            Event::Received(response) => ('<', response.as_bytes()),
+        };
+        let (id, data) = bytes.expect("Recorded messages are serializable");
+        format!(
+            "{}\t{}\t{:02x}\t{}",
+            self.time.as_micros(),
+            direction,
+            id,
+            to_hex(&data)
+        )
+    }
+
+    fn from_line(index: usize, line: &str) -> Result<Self, TraceError> {
+        let line_nb = index + 1;
+        let columns: Vec<&str> = line.split('\t').collect();
+        let [time, direction, id, data] = columns[..] else {
+            return Err(TraceError::Columns(line_nb));
+        };
+        let time = time.parse().map_err(|_| TraceError::Number(line_nb))?;
+        let id = u8::from_str_radix(id, 16).map_err(|_| TraceError::Number(line_nb))?;
+        let data = from_hex(data).ok_or(TraceError::Number(line_nb))?;
+        let data = (!data.is_empty()).then_some(&data[..]);
+        let event = match direction {
+            ">" => Command::from_data(id, data).map(Event::Sent),
+            "<" => Response::from_data(id, data).map(Event::Received),
+            _ => return Err(TraceError::Direction(line_nb)),
+        }
+        .map_err(|_| TraceError::Decode(line_nb))?;
+        Ok(Self {
+            time: Duration::from_micros(time),
+            event,
+        })
+    }
+}
+
+/// Parse the output of [to_hex]
+pub(crate) fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if s == "-" {
+        return Some(Vec::new());
+    }
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Recorded messages, in order
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Trace {
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    /// Text format, starting with [HEADER]
+    pub fn to_text(&self) -> String {
+        let mut text = String::from(HEADER);
+        for entry in &self.entries {
+            text.push('\n');
+            text.push_str(&entry.to_line());
+        }
+        text.push('\n');
+        text
+    }
+
+    /// Parse the output of [Trace::to_text]. Empty lines and lines starting with `#` are ignored.
+    pub fn from_text(text: &str) -> Result<Self, TraceError> {
+        let entries = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|(index, line)| TraceEntry::from_line(index, line.trim_end()))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { entries })
+    }
+}
+
+/// [GlassesApi] recording every message exchanged with the wrapped glasses
+pub struct Recorder<G: GlassesApi, C: Clock> {
+    glasses: G,
+    clock: C,
+    origin: Duration,
+    policy: RedactionPolicy,
+    trace: Trace,
+}
+
+impl<G: GlassesApi, C: Clock> Recorder<G, C> {
+    /// Record the messages exchanged with `glasses`, timestamped with `clock`.
+    /// Passwords are masked, see [Recorder::redaction].
+    pub fn new(glasses: G, clock: C) -> Self {
+        Self {
+            glasses,
+            origin: clock.now(),
+            clock,
+            policy: RedactionPolicy::PASSWORDS,
+            trace: Trace::default(),
+        }
+    }
+
+    /// Mask private data in the recorded messages.
+    /// Replaying a redacted [Command::CfgWrite] fails if the configuration has a password.
+    pub fn redaction(mut self, policy: RedactionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn trace(&self) -> &Trace {
+        &self.trace
+    }
+
+    /// Stop recording, returning the wrapped glasses and the trace
+    pub fn into_inner(self) -> (G, Trace) {
+        (self.glasses, self.trace)
+    }
+
+    fn record(&mut self, event: Event) {
+        let event = match event {
+            Event::Sent(cmd) => Event::Sent(cmd.redacted(&self.policy)),
+            Event::Received(response) => Event::Received(response.redacted(&self.policy)),
+        };
+        self.trace.entries.push(TraceEntry {
+            time: self.clock.now().saturating_sub(self.origin),
+            event,
+        });
+    }
+
+    /// Errors reported by the glasses are part of the exchange
+    fn record_result<T>(&mut self, res: &Result<T, GlassesError>) {
+        if let Err(GlassesError::UnexpectedResponse(response)) = res {
+            self.record(Event::Received(response.clone()));
+        }
+    }
+}
+
+impl<G: GlassesApi, C: Clock> GlassesApi for Recorder<G, C> {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.record(Event::Sent(cmd.clone()));
+        let res = self.glasses.send(cmd);
+        self.record_result(&res);
+        res
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.record(Event::Sent(cmd.clone()));
+        let res = self.glasses.query(cmd);
+        if let Ok(response) = &res {
+            self.record(Event::Received(response.clone()));
+        }
+        self.record_result(&res);
+        res
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.record(Event::Sent(cmd.clone()));
+        let res = self.glasses.send_chunked(cmd);
+        self.record_result(&res);
+        res
+    }
+}
+
+/// Response of the emulator differing from the trace
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    /// Index of the [Event::Sent] entry in the trace
+    pub index: usize,
+    pub recorded: Option<Response>,
+    pub replayed: Option<Response>,
+}
+
+/// Feeds a [Trace] to an [Emulator]
+pub struct Replayer<'a> {
+    trace: &'a Trace,
+}
+
+impl<'a> Replayer<'a> {
+    pub fn new(trace: &'a Trace) -> Self {
+        Self { trace }
+    }
+
+    /// Send every recorded command to `emulator`, advancing its clock to the recorded
+    /// timestamps. Returns the commands answered differently than during the recording.
+    pub fn replay(&self, emulator: &mut Emulator) -> Vec<Mismatch> {
+        let start = emulator.clock().now();
+        let mut mismatches = Vec::new();
+        let entries = &self.trace.entries;
+        for (index, entry) in entries.iter().enumerate() {
+            let Event::Sent(cmd) = &entry.event else {
+                continue;
+            };
+            let elapsed = emulator.clock().now().saturating_sub(start);
+            emulator.clock().advance(entry.time.saturating_sub(elapsed));
+
+            let replayed = emulator.handle(cmd);
+            let recorded = match entries.get(index + 1).map(|next| &next.event) {
+                Some(Event::Received(response)) => Some(response.clone()),
+                _ => None,
+            };
+            if replayed != recorded {
+                mismatches.push(Mismatch {
+                    index,
+                    recorded,
+                    replayed,
+                });
+            }
+        }
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{CmdError, Selector},
+        time::VirtualClock,
+    };
+
+    /// Emulated glasses, returning errors as unexpected responses
+    struct Emulated(Emulator);
+
+    impl GlassesApi for Emulated {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            match self.0.handle(cmd) {
+                Some(response) => Err(GlassesError::UnexpectedResponse(response)),
+                None => Ok(()),
+            }
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            self.0.handle(cmd).ok_or(GlassesError::Unsupported)
+        }
+    }
+
+    fn cfg_write(password: u32) -> Command {
+        Command::CfgWrite {
+            name: String::from("trace"),
+            version: 1,
+            password,
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let clock = VirtualClock::new();
+        let mut recorder = Recorder::new(Emulated(Emulator::new()), clock.clone());
+        recorder.battery().unwrap();
+        clock.advance(Duration::from_millis(5));
+        // No configuration written yet
+        assert!(recorder
+            .send(&Command::FontDelete {
+                id: Selector::one(3).unwrap(),
+            })
+            .is_err());
+        recorder.send(&cfg_write(0)).unwrap();
+        recorder.list(crate::config::ElementKind::Font).unwrap();
+
+        let (_, trace) = recorder.into_inner();
+        assert_eq!(7, trace.entries.len());
+        assert_eq!(Duration::from_millis(5), trace.entries[2].time);
+        assert_eq!(
+            Event::Received(Response::CmdError {
+                cmd_id: 0x53,
+                error: CmdError::MissingCfgWrite,
+                sub_error: 0,
+            }),
+            trace.entries[3].event
+        );
+
+        let text = trace.to_text();
+        assert!(text.starts_with(HEADER));
+        assert!(text.contains("5000\t>\t53\t03\n"));
+        let parsed = Trace::from_text(&text).unwrap();
+        assert_eq!(trace, parsed);
+
+        assert!(Replayer::new(&parsed)
+            .replay(&mut Emulator::new())
+            .is_empty());
+
+        // Glasses with a smaller battery level answer differently
+        let mut emulator = Emulator::new();
+        emulator.battery = 10;
+        let mismatches = Replayer::new(&parsed).replay(&mut emulator);
+        assert_eq!(1, mismatches.len());
+        assert_eq!(0, mismatches[0].index);
+        assert_eq!(
+            Some(Response::Battery { level: 10 }),
+            mismatches[0].replayed
+        );
+    }
+
+    #[test]
+    fn test_redaction_and_errors() {
+        let mut recorder = Recorder::new(Emulated(Emulator::new()), VirtualClock::new());
+        recorder.send(&cfg_write(1234)).unwrap();
+        assert_eq!(Event::Sent(cfg_write(0)), recorder.trace().entries[0].event);
+
+        assert_eq!(
+            Err(TraceError::Columns(2)),
+            Trace::from_text("# header\n0\t>\n")
+        );
+        assert_eq!(
+            Err(TraceError::Direction(1)),
+            Trace::from_text("0\t=\t01\t-")
+        );
+        assert_eq!(Err(TraceError::Number(1)), Trace::from_text("0\t>\t01\t0"));
+    }
+}
@@ -0,0 +1,235 @@
+//! Redaction of user content before logging or recording
+//!
+//! Text displayed on the glasses and configuration passwords may be personal data. A
+//! [RedactionPolicy] masks them while keeping the structure of commands: the command kind, ids,
+//! positions and the length of the text stay visible, which is what protocol debugging needs.
+//!
+//! The crate logs go through [Redacted], using the policy set with [set_log_policy].
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::commands::{Command, Response};
+
+/// Character replacing each character of redacted strings
+pub const MASK: char = '*';
+
+/// Which fields to redact
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RedactionPolicy {
+    /// Text displayed on the glasses: [Command::Txt] and the layout display commands
+    pub text: bool,
+    /// Configuration names, in commands and [Response::CfgList]
+    pub config_names: bool,
+    /// Configuration passwords, replaced by 0
+    pub passwords: bool,
+}
+
+impl RedactionPolicy {
+    /// Keep everything
+    pub const NONE: RedactionPolicy = RedactionPolicy {
+        text: false,
+        config_names: false,
+        passwords: false,
+    };
+    /// Only keep structural information
+    pub const ALL: RedactionPolicy = RedactionPolicy {
+        text: true,
+        config_names: true,
+        passwords: true,
+    };
+    /// Default policy: passwords are never worth logging
+    pub const PASSWORDS: RedactionPolicy = RedactionPolicy {
+        passwords: true,
+        ..Self::NONE
+    };
+
+    const fn to_bits(self) -> u8 {
+        self.text as u8 | (self.config_names as u8) << 1 | (self.passwords as u8) << 2
+    }
+
+    const fn from_bits(bits: u8) -> Self {
+        Self {
+            text: bits & 1 != 0,
+            config_names: bits & 2 != 0,
+            passwords: bits & 4 != 0,
+        }
+    }
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        Self::PASSWORDS
+    }
+}
+
+static LOG_POLICY: AtomicU8 = AtomicU8::new(RedactionPolicy::PASSWORDS.to_bits());
+
+/// Set the policy applied to the logs of this crate
+pub fn set_log_policy(policy: RedactionPolicy) {
+    LOG_POLICY.store(policy.to_bits(), Ordering::Relaxed);
+}
+
+/// Policy applied to the logs of this crate
+pub fn log_policy() -> RedactionPolicy {
+    RedactionPolicy::from_bits(LOG_POLICY.load(Ordering::Relaxed))
+}
+
+/// Replace each character of `s` with [MASK]
+pub fn mask(s: &mut String) {
+    *s = MASK.to_string().repeat(s.chars().count());
+}
+
+/// Types containing fields to redact
+pub trait Redact {
+    /// Redact the fields selected by `policy` in place
+    fn redact(&mut self, policy: &RedactionPolicy);
+
+    /// Return a redacted copy
+    fn redacted(&self, policy: &RedactionPolicy) -> Self
+    where
+        Self: Clone,
+    {
+        let mut res = self.clone();
+        res.redact(policy);
+        res
+    }
+}
+
+impl Redact for Command {
+    fn redact(&mut self, policy: &RedactionPolicy) {
+        match self {
+            Command::Txt { string: text, .. }
+            | Command::LayoutDisplay { text, .. }
+            | Command::LayoutDisplayExtended { text, .. }
+            | Command::LayoutClearAndDisplay { text, .. }
+            | Command::LayoutClearAndDisplayExtended { text, .. }
+                if policy.text =>
+            {
+                mask(text)
+            }
+            Command::PageDisplay { strings, .. } | Command::PageClearAndDisplay { strings, .. }
+                if policy.text =>
+            {
+                strings.iter_mut().for_each(mask)
+            }
+            Command::CfgWrite { name, password, .. } => {
+                if policy.config_names {
+                    mask(name);
+                }
+                if policy.passwords {
+                    *password = 0;
+                }
+            }
+            Command::CfgRename { old, new, password } => {
+                if policy.config_names {
+                    mask(old);
+                    mask(new);
+                }
+                if policy.passwords {
+                    *password = 0;
+                }
+            }
+            Command::CfgRead { name } | Command::CfgSet { name } | Command::CfgDelete { name }
+                if policy.config_names =>
+            {
+                mask(name)
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Redact for Response {
+    fn redact(&mut self, policy: &RedactionPolicy) {
+        if let Response::CfgList { list } = self {
+            if policy.config_names {
+                list.iter_mut().for_each(|item| mask(&mut item.name));
+            }
+        }
+    }
+}
+
+/// Debug formatting of a value, redacted with the [log_policy]
+pub struct Redacted<'a, T>(pub &'a T);
+
+impl<T: Redact + Clone + fmt::Debug> fmt::Debug for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let policy = log_policy();
+        if policy == RedactionPolicy::NONE {
+            self.0.fmt(f)
+        } else {
+            self.0.redacted(&policy).fmt(f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{CfgItem, Grey, Point, TextRotation};
+
+    #[test]
+    fn test_redact_text() {
+        let cmd = Command::Txt {
+            pos: Point { x: 10, y: 20 },
+            rotation: TextRotation::TOP_LR,
+            font_size: 1,
+            color: Grey::WHITE,
+            string: String::from("Héllo"),
+        };
+        let Command::Txt { pos, string, .. } = cmd.redacted(&RedactionPolicy::ALL) else {
+            panic!("Not a Txt");
+        };
+        assert_eq!(Point { x: 10, y: 20 }, pos);
+        assert_eq!("*****", string);
+        assert_eq!(cmd, cmd.redacted(&RedactionPolicy::default()));
+    }
+
+    #[test]
+    fn test_redact_config() {
+        let cmd = Command::CfgWrite {
+            name: String::from("secret"),
+            version: 3,
+            password: 0xDEADBEEF,
+        };
+        let expected = Command::CfgWrite {
+            name: String::from("secret"),
+            version: 3,
+            password: 0,
+        };
+        assert_eq!(expected, cmd.redacted(&RedactionPolicy::default()));
+        assert_eq!(cmd, cmd.redacted(&RedactionPolicy::NONE));
+
+        let mut response = Response::CfgList {
+            list: vec![CfgItem {
+                name: String::from("ab"),
+                size: 1,
+                version: 2,
+                usage_counter: 3,
+                install_counter: 4,
+                is_system: false,
+            }],
+        };
+        response.redact(&RedactionPolicy::ALL);
+        let Response::CfgList { list } = response else {
+            panic!("Not a CfgList");
+        };
+        assert_eq!("**", list[0].name);
+        assert_eq!(2, list[0].version);
+    }
+
+    #[test]
+    fn test_redacted_debug() {
+        let cmd = Command::LayoutDisplay {
+            id: 7,
+            text: String::from("42"),
+        };
+        set_log_policy(RedactionPolicy::ALL);
+        let logged = format!("{:?}", Redacted(&cmd));
+        set_log_policy(RedactionPolicy::default());
+        assert!(logged.contains("id: 7"));
+        assert!(logged.contains("\"**\""));
+        assert!(!logged.contains("42"));
+    }
+}
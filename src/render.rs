@@ -0,0 +1,240 @@
+//! Double-buffered screen rendering
+//!
+//! The application describes the whole [Screen] it wants on each frame. [ScreenRenderer] keeps
+//! the last frame sent, and only sends what changed, between [HoldFlushAction::Hold] and
+//! [HoldFlushAction::Flush] so the update appears at once:
+//! - a free text which changed is erased by drawing the previous text in black, then drawn
+//! - a layout is updated with [Command::LayoutClearAndDisplay], or cleared when removed
+//! - a gauge is drawn again with its new value
+//! - an image is drawn when it appears
+//!
+//! The firmware can not erase a gauge or an image alone: removing or moving one clears the whole
+//! display and draws every widget again. Widgets are assumed not to overlap, since erasing one
+//! would erase the other.
+use crate::{
+    commands::{Command, Grey, HoldFlushAction},
+    design::{Screen, Widget},
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// Returns true if `screen` has a widget of the same kind and ID as `widget`, not counting free
+/// texts and images which have no ID
+fn has_same_id(screen: &Screen, widget: &Widget) -> bool {
+    screen.widgets.iter().any(|other| match (widget, other) {
+        (Widget::Layout { id, .. }, Widget::Layout { id: other, .. }) => id == other,
+        (Widget::Gauge { id, .. }, Widget::Gauge { id: other, .. }) => id == other,
+        _ => false,
+    })
+}
+
+/// Clear the display and draw every widget of `screen`
+fn redraw(screen: &Screen) -> Vec<Command> {
+    let mut commands = screen.commands();
+    commands.insert(1, Command::Clear);
+    commands
+}
+
+/// Commands turning the display from `previous` into `next`.
+/// Without a previous frame, the display is cleared and every widget drawn.
+pub fn diff(previous: Option<&Screen>, next: &Screen) -> Vec<Command> {
+    let Some(previous) = previous else {
+        return redraw(next);
+    };
+    let mut commands = vec![Command::HoldFlush {
+        action: HoldFlushAction::Hold,
+    }];
+    for old in previous
+        .widgets
+        .iter()
+        .filter(|w| !next.widgets.contains(w))
+    {
+        match old {
+            Widget::Text {
+                pos,
+                rotation,
+                font_size,
+                text,
+                ..
+            } => commands.push(Command::Txt {
+                pos: *pos,
+                rotation: *rotation,
+                font_size: *font_size,
+                color: Grey::BLACK,
+                string: text.clone(),
+            }),
+            Widget::Layout { id, .. } if !has_same_id(next, old) => {
+                commands.push(Command::LayoutClear { id: *id })
+            }
+            Widget::Layout { .. } => {}
+            Widget::Gauge { .. } if has_same_id(next, old) => {}
+            Widget::Gauge { .. } | Widget::Image { .. } => return redraw(next),
+        }
+    }
+    for new in next
+        .widgets
+        .iter()
+        .filter(|w| !previous.widgets.contains(w))
+    {
+        match new {
+            Widget::Layout { id, text } if has_same_id(previous, new) => {
+                commands.push(Command::LayoutClearAndDisplay {
+                    id: *id,
+                    text: text.clone(),
+                })
+            }
+            _ => commands.extend(new.commands()),
+        }
+    }
+    if commands.len() == 1 {
+        return Vec::new();
+    }
+    commands.push(Command::HoldFlush {
+        action: HoldFlushAction::Flush,
+    });
+    commands
+}
+
+/// Sends each [Screen] as the difference with the previous one, see [diff].
+///
+/// Commands drawing on the display without the renderer make its previous frame wrong: call
+/// [ScreenRenderer::invalidate] after them.
+pub struct ScreenRenderer<G: GlassesApi> {
+    glasses: G,
+    previous: Option<Screen>,
+}
+
+impl<G: GlassesApi> ScreenRenderer<G> {
+    /// The first frame clears the display
+    pub fn new(glasses: G) -> Self {
+        Self {
+            glasses,
+            previous: None,
+        }
+    }
+
+    /// Update the display to `screen`.
+    /// On error, the display content is unknown: the next frame is drawn from scratch.
+    pub fn render(&mut self, screen: Screen) -> Result<(), GlassesError> {
+        let commands = diff(self.previous.as_ref(), &screen);
+        self.previous = None;
+        for cmd in &commands {
+            self.glasses.send(cmd)?;
+        }
+        self.previous = Some(screen);
+        Ok(())
+    }
+
+    /// Draw the next frame from scratch, after the display was modified or reset
+    pub fn invalidate(&mut self) {
+        self.previous = None;
+    }
+
+    /// Last frame sent, if known
+    pub fn previous(&self) -> Option<&Screen> {
+        self.previous.as_ref()
+    }
+
+    /// Access the wrapped glasses
+    pub fn glasses(&mut self) -> &mut G {
+        &mut self.glasses
+    }
+
+    /// Stop rendering, returning the wrapped glasses
+    pub fn into_inner(self) -> G {
+        self.glasses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{Point, TextRotation},
+        glasses::Preview,
+    };
+
+    fn text(x: i16, text: &str) -> Widget {
+        Widget::Text {
+            pos: Point { x, y: 100 },
+            rotation: TextRotation::TOP_LR,
+            font_size: 1,
+            color: Grey::WHITE,
+            text: String::from(text),
+        }
+    }
+
+    fn layout(id: u8, text: &str) -> Widget {
+        Widget::Layout {
+            id,
+            text: String::from(text),
+        }
+    }
+
+    #[test]
+    fn test_screen_renderer() {
+        let hold = Command::HoldFlush {
+            action: HoldFlushAction::Hold,
+        };
+        let flush = Command::HoldFlush {
+            action: HoldFlushAction::Flush,
+        };
+        let first = Screen::new(vec![
+            text(10, "12:00"),
+            layout(1, "42"),
+            layout(2, "km/h"),
+            Widget::Gauge { id: 1, value: 50 },
+        ]);
+        let mut renderer = ScreenRenderer::new(Preview::new());
+        renderer.render(first.clone()).unwrap();
+        // Hold and Clear, then the widgets and Flush
+        assert_eq!(&redraw(&first)[2..], renderer.glasses().displayed());
+
+        let second = Screen::new(vec![
+            text(10, "12:01"),
+            layout(1, "43"),
+            Widget::Gauge { id: 1, value: 60 },
+        ]);
+        assert_eq!(
+            vec![
+                hold.clone(),
+                Command::Txt {
+                    pos: Point { x: 10, y: 100 },
+                    rotation: TextRotation::TOP_LR,
+                    font_size: 1,
+                    color: Grey::BLACK,
+                    string: String::from("12:00"),
+                },
+                Command::LayoutClear { id: 2 },
+                text(10, "12:01").commands()[0].clone(),
+                Command::LayoutClearAndDisplay {
+                    id: 1,
+                    text: String::from("43"),
+                },
+                Command::GaugeDisplay { id: 1, value: 60 },
+                flush.clone(),
+            ],
+            diff(Some(&first), &second)
+        );
+        assert!(diff(Some(&second), &second).is_empty());
+
+        // Removing the gauge redraws everything
+        let third = Screen::new(vec![layout(1, "43")]);
+        assert_eq!(
+            vec![
+                hold,
+                Command::Clear,
+                Command::LayoutDisplay {
+                    id: 1,
+                    text: String::from("43"),
+                },
+                flush,
+            ],
+            diff(Some(&second), &third)
+        );
+
+        renderer.render(second.clone()).unwrap();
+        assert_eq!(Some(&second), renderer.previous());
+        renderer.invalidate();
+        assert_eq!(redraw(&second), diff(renderer.previous(), &second));
+    }
+}
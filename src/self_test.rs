@@ -0,0 +1,180 @@
+//! Self-test against live glasses
+//!
+//! [run] exercises the main capabilities of the glasses with commands which do not modify the
+//! stored configurations: queries, drawing on a cleared screen, and streaming a small image which
+//! is not saved. It is meant for support diagnostics, and for validating a new transport on real
+//! hardware.
+//!
+//! Commands without response can only fail asynchronously, so each drawing check ends with a
+//! query confirming the glasses are still answering.
+
+use crate::{
+    commands::{Command, Point, Response, StreamImgFormat},
+    config::ElementKind,
+    firmware::FirmwareVersion,
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// Capability checked by the self-test
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Capability {
+    /// [Command::Version] answers with a firmware version
+    Version,
+    /// [Command::Settings] answers
+    Settings,
+    /// [Command::Battery] answers with a level up to 100%
+    Battery,
+    /// Drawing commands and [Command::Clear] are accepted
+    Drawing,
+    /// [Command::ImgStream] is accepted
+    ImageStream,
+    /// Every list query answers
+    Lists,
+}
+
+/// Outcome of one check
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckResult {
+    pub capability: Capability,
+    /// Why the check failed
+    pub error: Option<String>,
+}
+
+/// Result of the self-test
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SelfTestReport {
+    /// Firmware version, if [Capability::Version] passed
+    pub version: Option<FirmwareVersion>,
+    pub checks: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    /// Every check passed
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.error.is_none())
+    }
+
+    /// Capabilities which failed
+    pub fn failures(&self) -> Vec<Capability> {
+        self.checks
+            .iter()
+            .filter(|check| check.error.is_some())
+            .map(|check| check.capability)
+            .collect()
+    }
+
+    fn record(&mut self, capability: Capability, result: Result<(), GlassesError>) {
+        self.checks.push(CheckResult {
+            capability,
+            error: result.err().map(|error| error.to_string()),
+        });
+    }
+}
+
+/// Run every check, in order. A failing check does not stop the following ones.
+pub fn run<G: GlassesApi + ?Sized>(glasses: &mut G) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+
+    let version = glasses.query(&Command::Version).and_then(|response| {
+        FirmwareVersion::from_response(&response).ok_or(GlassesError::UnexpectedResponse(response))
+    });
+    report.version = version.as_ref().ok().copied();
+    report.record(Capability::Version, version.map(|_| ()));
+
+    let settings = match glasses.query(&Command::Settings) {
+        Ok(Response::Settings { .. }) => Ok(()),
+        Ok(other) => Err(GlassesError::UnexpectedResponse(other)),
+        Err(error) => Err(error),
+    };
+    report.record(Capability::Settings, settings);
+
+    let battery = match glasses.battery() {
+        Ok(level) if level > 100 => Err(GlassesError::UnexpectedResponse(Response::Battery {
+            level,
+        })),
+        other => other.map(|_| ()),
+    };
+    report.record(Capability::Battery, battery);
+
+    let drawing = [
+        Command::Clear,
+        Command::Rect {
+            from: Point { x: 10, y: 10 },
+            to: Point { x: 50, y: 50 },
+        },
+        Command::Clear,
+    ];
+    let result = send_all(glasses, &drawing);
+    report.record(Capability::Drawing, result);
+
+    // 16 x 2 pixels, in 1bpp
+    let data = vec![0xFF; 4];
+    let stream = [
+        Command::ImgStream {
+            size: data.len() as u32,
+            width: 16,
+            coord: Point { x: 10, y: 10 },
+            format: StreamImgFormat::Img1bpp,
+            data,
+        },
+        Command::Clear,
+    ];
+    let result = send_all(glasses, &stream);
+    report.record(Capability::ImageStream, result);
+
+    let lists = [
+        ElementKind::Image,
+        ElementKind::Font,
+        ElementKind::Layout,
+        ElementKind::Gauge,
+        ElementKind::Page,
+        ElementKind::Animation,
+    ]
+    .into_iter()
+    .try_for_each(|kind| glasses.list(kind).map(|_| ()));
+    report.record(Capability::Lists, lists);
+
+    report
+}
+
+/// Send `cmds`, then check the glasses still answer
+fn send_all<G: GlassesApi + ?Sized>(glasses: &mut G, cmds: &[Command]) -> Result<(), GlassesError> {
+    for cmd in cmds {
+        glasses.send(cmd)?;
+    }
+    glasses.battery().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    /// Emulated glasses without gauge support
+    struct NoGauges(Emulator);
+
+    impl GlassesApi for NoGauges {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            self.0.handle(cmd);
+            Ok(())
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            if let Command::GaugeList = cmd {
+                return Err(GlassesError::Unsupported);
+            }
+            self.0.handle(cmd).ok_or(GlassesError::Unsupported)
+        }
+    }
+
+    #[test]
+    fn test_self_test() {
+        let mut glasses = NoGauges(Emulator::new());
+        let report = run(&mut glasses);
+        assert_eq!(Some(FirmwareVersion::new(4, 12, 0)), report.version);
+        assert_eq!(6, report.checks.len());
+        assert!(!report.passed());
+        assert_eq!(vec![Capability::Lists], report.failures());
+        assert!(glasses.0.configs().is_empty());
+    }
+}
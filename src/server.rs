@@ -1,18 +1,83 @@
 //! ActiveLook server
 //!
 //! This is used in the ActiveLook emulator, to simulate the behaviour of ActiveLook glasses and
-//! accelerate development.
+//! accelerate development. `transport::embassy` connects it to a BLE peripheral stack.
+//!
+//! [FaultInjection] makes the server misbehave on purpose, to test the retries and the flow
+//! control of a client against faulty firmware or a lossy link.
+
+use core::time::Duration;
 
 use embedded_io::{Read, Write};
 use log::*;
-use thiserror::Error;
 
 use crate::{
-    commands::{Command, Response},
-    protocol::{CommandPacket, Packet, ProtocolError, ResponsePacket, PACKET_MAX_SIZE},
-    traits::*,
+    commands::{CmdError, Response},
+    emulator::Emulator,
+    protocol::{
+        CommandPacket, FlowErrorCtrl, Packet, PacketAssembler, ProtocolError, RawPacket,
+        ResponsePacket, PACKET_MAX_SIZE,
+    },
+    time::Delay,
 };
 
+/// Faults injected by an [ActiveLookServer]. Periods count packets from the creation of the
+/// server, `n` applies to the nth, 2nth, ... packet. None by default.
+#[derive(Default)]
+pub struct FaultInjection {
+    drop_every: Option<usize>,
+    corrupt_every: Option<usize>,
+    error_every: Option<usize>,
+    wait_every: Option<usize>,
+    delay: Option<(Duration, Box<dyn Delay + Send>)>,
+}
+
+impl FaultInjection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ignore every `n`th received packet, as if lost
+    pub fn drop_every(mut self, n: usize) -> Self {
+        self.drop_every = Some(n);
+        self
+    }
+
+    /// Corrupt the end delimiter of every `n`th sent response
+    pub fn corrupt_every(mut self, n: usize) -> Self {
+        self.corrupt_every = Some(n);
+        self
+    }
+
+    /// Answer every `n`th received packet with a [Response::CmdError] before handling it
+    pub fn error_every(mut self, n: usize) -> Self {
+        self.error_every = Some(n);
+        self
+    }
+
+    /// Notify [FlowErrorCtrl::ClientShouldWait] after every `n`th received packet, then
+    /// [FlowErrorCtrl::ClientCanSend] on the next read of the server
+    pub fn wait_every(mut self, n: usize) -> Self {
+        self.wait_every = Some(n);
+        self
+    }
+
+    /// Wait `duration` with `delay` before sending each response
+    pub fn delay_responses(
+        mut self,
+        duration: Duration,
+        delay: impl Delay + Send + 'static,
+    ) -> Self {
+        self.delay = Some((duration, Box::new(delay)));
+        self
+    }
+}
+
+/// Returns true if the `count`th packet is concerned by a fault of period `every`
+fn every(every: Option<usize>, count: usize) -> bool {
+    matches!(every, Some(n) if n > 0 && count.is_multiple_of(n))
+}
+
 /// Server which uses:
 /// - Connection to Tx Activelook Server (Write)
 /// - Connection to Rx Activelook Server (Notify)
@@ -28,6 +93,14 @@ where
     /// Server Tx is connected to ActiveLook Tx
     tx: TxActiveLook,
     ctrl: Ctrl,
+    /// Reconstructs packets split across multiple writes
+    assembler: PacketAssembler,
+    faults: FaultInjection,
+    /// Packets received and responses sent, to apply the faults
+    received: usize,
+    sent: usize,
+    /// [FlowErrorCtrl::ClientShouldWait] was notified by fault injection
+    paused: bool,
 }
 
 /// Protocol implementation
@@ -39,21 +112,272 @@ where
     Ctrl: Write,
 {
     pub fn new(rx: RxActiveLook, tx: TxActiveLook, ctrl: Ctrl) -> Self {
-        Self { rx, tx, ctrl }
+        Self {
+            rx,
+            tx,
+            ctrl,
+            assembler: PacketAssembler::new(),
+            faults: FaultInjection::default(),
+            received: 0,
+            sent: 0,
+            paused: false,
+        }
+    }
+
+    /// Inject `faults` from now on
+    pub fn set_faults(&mut self, faults: FaultInjection) {
+        self.faults = faults;
     }
 
+    /// Read from the Rx characteristic until a whole command packet is received.
+    /// A command can be split across multiple BLE writes.
     pub fn read_data(&mut self) -> Result<CommandPacket, ProtocolError> {
+        let bytes = self.receive()?;
+        CommandPacket::from_bytes(&bytes)
+    }
+
+    /// Read one packet, and answer it with `emulator`
+    pub fn serve(&mut self, emulator: &mut Emulator) -> Result<(), ProtocolError> {
+        let bytes = self.receive()?;
+        let packet = RawPacket::from_bytes(&bytes)?;
+        if let Some(response) = emulator.handle_packet(&packet) {
+            self.send_response(response);
+        }
+        Ok(())
+    }
+
+    /// Read the next packet which is not dropped, injecting the faults
+    fn receive(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        if self.paused {
+            self.paused = false;
+            self.notify(FlowErrorCtrl::ClientCanSend);
+        }
+        loop {
+            let bytes = self.read_packet()?;
+            self.received += 1;
+            if every(self.faults.drop_every, self.received) {
+                warn!("Fault injection: dropping packet {}", self.received);
+                continue;
+            }
+            if every(self.faults.wait_every, self.received) {
+                self.paused = true;
+                self.notify(FlowErrorCtrl::ClientShouldWait);
+            }
+            if every(self.faults.error_every, self.received) {
+                let cmd_id = RawPacket::from_bytes(&bytes)?.cmd_id();
+                warn!("Fault injection: error for command 0x{:02X}", cmd_id);
+                self.send(
+                    None,
+                    &Response::CmdError {
+                        cmd_id,
+                        error: CmdError::Generic,
+                        sub_error: 0,
+                    },
+                );
+            }
+            return Ok(bytes);
+        }
+    }
+
+    fn notify(&mut self, value: FlowErrorCtrl) {
+        if let Err(error) = self.ctrl.write_all(&[value as u8]) {
+            error!("{:?}", error);
+        }
+    }
+
+    fn read_packet(&mut self) -> Result<Vec<u8>, ProtocolError> {
         let mut rxbuf = [0; PACKET_MAX_SIZE];
-        if let Ok(len) = self.rx.read(&mut rxbuf) {
-            CommandPacket::from_bytes(&rxbuf[..len])
-        } else {
-            //trace!("No data to read");
-            Err(ProtocolError::Empty)
+        loop {
+            if let Some(bytes) = self.assembler.next_packet()? {
+                return Ok(bytes);
+            }
+            match self.rx.read(&mut rxbuf) {
+                Ok(0) => {
+                    //trace!("No data to read");
+                    return Err(ProtocolError::Empty);
+                }
+                Ok(len) => self.assembler.push(&rxbuf[..len]),
+                Err(_) => return Err(ProtocolError::EmbeddedIOError),
+            }
+        }
+    }
+
+    /// Answer `incoming` with `response`, echoing its query_id as the client expects.
+    /// A list too long for a single packet is sent in several, see [Response::split_list].
+    pub fn reply(&mut self, incoming: &CommandPacket, response: &Response) {
+        for part in response.clone().split_list() {
+            self.send(incoming.query_id.as_deref(), &part);
+        }
+    }
+
+    /// Send `response` in a packet, with `query_id` if given
+    fn send(&mut self, query_id: Option<&[u8]>, response: &Response) {
+        let packet = match query_id {
+            Some(query_id) => Packet::new_with_query_id(response, query_id),
+            None => Packet::new(response),
+        };
+        match packet {
+            Ok(packet) => self.send_response(packet),
+            Err(error) => error!("Can not send {:?}: {}", response, error),
         }
     }
 
     pub fn send_response(&mut self, response: ResponsePacket) {
-        let bytes = response.to_bytes();
-        self.tx.write(&bytes);
+        let mut bytes = response.to_bytes();
+        self.sent += 1;
+        if let Some((duration, delay)) = &mut self.faults.delay {
+            delay.delay(*duration);
+        }
+        if every(self.faults.corrupt_every, self.sent) {
+            warn!("Fault injection: corrupting response {}", self.sent);
+            if let Some(end) = bytes.last_mut() {
+                *end = !*end;
+            }
+        }
+        if let Err(error) = self.tx.write_all(&bytes) {
+            error!("{:?}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{ActiveLookClient, ClientConfig},
+        commands::Command,
+        time::{Clock, VirtualClock},
+        transport::loopback::loopback,
+    };
+    use core::convert::Infallible;
+    use embedded_io::ErrorType;
+
+    /// Reader returning data in chunks of at most `chunk` bytes, like BLE writes
+    struct ChunkReader {
+        data: Vec<u8>,
+        index: usize,
+        chunk: usize,
+    }
+
+    impl ErrorType for ChunkReader {
+        type Error = Infallible;
+    }
+
+    impl Read for ChunkReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = self.chunk.min(buf.len()).min(self.data.len() - self.index);
+            buf[..len].copy_from_slice(&self.data[self.index..self.index + len]);
+            self.index += len;
+            Ok(len)
+        }
+    }
+
+    /// Discards everything
+    struct Sink;
+
+    impl ErrorType for Sink {
+        type Error = Infallible;
+    }
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_read_split_command() {
+        let cmd = Command::LayoutDisplay {
+            id: 1,
+            text: String::from("A text longer than a BLE write"),
+        };
+        let mut data = Packet::new(&cmd).unwrap().to_bytes();
+        data.extend(Packet::new(&Command::Clear).unwrap().to_bytes());
+        let rx = ChunkReader {
+            data,
+            index: 0,
+            chunk: 20,
+        };
+        let mut server = ActiveLookServer::new(rx, Sink, Sink);
+
+        let packet = server.read_data().unwrap();
+        assert_eq!(cmd, packet.data);
+        let packet = server.read_data().unwrap();
+        assert_eq!(Command::Clear, packet.data);
+        assert!(matches!(server.read_data(), Err(ProtocolError::Empty)));
+    }
+
+    #[test]
+    fn test_reply_to_client() {
+        let (client, server) = loopback();
+        let mut client = ActiveLookClient::new(client.tx, client.rx, client.ctrl);
+        let mut server = ActiveLookServer::new(server.rx, server.tx, server.ctrl);
+        let battery = Response::Battery { level: 42 };
+
+        for query_id_len in [4, 1, 0] {
+            client.set_config(ClientConfig { query_id_len });
+            let query_id = client.send_query(&Command::Battery).unwrap();
+            let incoming = server.read_data().unwrap();
+            assert_eq!(query_id_len, incoming.query_id.as_ref().map_or(0, Vec::len));
+            server.reply(&incoming, &battery);
+            assert_eq!(Ok(battery.clone()), client.wait_response(query_id));
+        }
+    }
+
+    #[test]
+    fn test_fault_injection() {
+        let (client, server) = loopback();
+        let mut client = ActiveLookClient::new(client.tx, client.rx, client.ctrl);
+        let mut server = ActiveLookServer::new(server.rx, server.tx, server.ctrl);
+        let clock = VirtualClock::new();
+        server.set_faults(
+            FaultInjection::new()
+                .drop_every(2)
+                .error_every(3)
+                .wait_every(3)
+                .corrupt_every(2)
+                .delay_responses(Duration::from_millis(50), clock.clone()),
+        );
+        for _ in 0..3 {
+            client.send(&Command::Clear).unwrap();
+        }
+        client.send(&Command::Battery).unwrap();
+        client.send(&Command::Battery).unwrap();
+
+        // The second packet is lost
+        assert_eq!(Command::Clear, server.read_data().unwrap().data);
+        assert_eq!(Command::Clear, server.read_data().unwrap().data);
+
+        // Spurious error and pause for the third packet
+        client.poll_ctrl().unwrap();
+        assert!(!client.can_send());
+        assert_eq!(
+            Response::CmdError {
+                cmd_id: 0x01,
+                error: CmdError::Generic,
+                sub_error: 0,
+            },
+            client.read_tx_char().unwrap().data
+        );
+        assert_eq!(Duration::from_millis(50), clock.now());
+
+        // The pause ends with the next read of the server, the fourth packet is lost
+        let incoming = server.read_data().unwrap();
+        assert_eq!(Command::Battery, incoming.data);
+        assert!(matches!(server.read_data(), Err(ProtocolError::Empty)));
+        client.poll_ctrl().unwrap();
+        assert!(client.can_send());
+
+        // The second response is corrupted
+        server.reply(&incoming, &Response::Battery { level: 42 });
+        assert_eq!(
+            Err(ProtocolError::FrameError),
+            client.read_tx_char().map(|packet| packet.data)
+        );
+        assert_eq!(Duration::from_millis(100), clock.now());
     }
 }
@@ -0,0 +1,348 @@
+//! Decoding of captured BLE traffic
+//!
+//! [Decoder] reassembles the values written to the Rx characteristic and notified on the Tx
+//! characteristic, and decodes them as [Command]s and [Response]s. Captures are read from:
+//! - hex dumps, see [parse_hex_dump],
+//! - btsnoop files, as written by the Android Bluetooth HCI snoop log, see [parse_btsnoop].
+//!
+//! ATT values which neither start nor continue a packet are ignored: they belong to other
+//! characteristics, like the battery level or the flow control.
+use core::time::Duration;
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::{
+    commands::{Command, Response},
+    protocol::{consts::PACKET_START, PacketAssembler, ProtocolError, RawPacket},
+    recorder::{from_hex, Event},
+    traits::*,
+};
+
+/// First bytes of a btsnoop file
+pub const BTSNOOP_MAGIC: &[u8] = b"btsnoop\0";
+
+/// HCI packets without H4 packet type
+const DATALINK_HCI_UNENCAPSULATED: u32 = 1001;
+/// HCI packets prefixed with their H4 packet type
+const DATALINK_HCI_UART: u32 = 1002;
+const FILE_HEADER_LEN: usize = 16;
+const RECORD_HEADER_LEN: usize = 24;
+const H4_ACL: u8 = 0x02;
+/// Record flag: packet received by the host
+const FLAG_RECEIVED: u32 = 0x01;
+/// Record flag: HCI command or event, not data
+const FLAG_COMMAND_EVENT: u32 = 0x02;
+/// ACL packet boundary flag of a continuing fragment
+const ACL_CONTINUATION: u16 = 0b01;
+const L2CAP_HEADER_LEN: usize = 4;
+const L2CAP_CID_ATT: u16 = 0x0004;
+const ATT_WRITE_REQUEST: u8 = 0x12;
+const ATT_WRITE_COMMAND: u8 = 0x52;
+const ATT_NOTIFICATION: u8 = 0x1B;
+const ATT_INDICATION: u8 = 0x1D;
+
+/// Errors reading a capture
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum SnifferError {
+    #[error("Line {0}: expected `>` or `<`")]
+    Direction(usize),
+    #[error("Line {0}: invalid hexadecimal")]
+    Hex(usize),
+    #[error("Not a btsnoop capture")]
+    NotBtsnoop,
+    #[error("Unsupported btsnoop datalink type {0}")]
+    Datalink(u32),
+    #[error("Truncated btsnoop record at offset {0}")]
+    Truncated(usize),
+}
+
+/// Direction of the captured bytes
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// Written to the Rx characteristic: [Command]s
+    ToGlasses,
+    /// Notified on the Tx characteristic: [Response]s
+    FromGlasses,
+}
+
+/// One decoded packet
+#[derive(Debug, PartialEq)]
+pub struct Decoded {
+    /// Time since the start of the capture, if known
+    pub time: Option<Duration>,
+    pub direction: Direction,
+    pub query_id: Option<Vec<u8>>,
+    pub message: Result<Event, ProtocolError>,
+}
+
+/// Reassembles and decodes the packets of both directions
+#[derive(Default)]
+pub struct Decoder {
+    commands: PacketAssembler,
+    responses: PacketAssembler,
+    decoded: Vec<Decoded>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode the packets completed by `bytes`
+    pub fn push(&mut self, time: Option<Duration>, direction: Direction, bytes: &[u8]) {
+        let assembler = match direction {
+            Direction::ToGlasses => &mut self.commands,
+            Direction::FromGlasses => &mut self.responses,
+        };
+        if assembler.pending() == 0 && bytes.first() != Some(&PACKET_START) {
+            return;
+        }
+        assembler.push(bytes);
+        loop {
+            let (query_id, message) = match assembler.next_packet() {
+                Ok(None) => break,
+                Ok(Some(packet)) => decode_packet(direction, &packet),
+                Err(error) => (None, Err(error)),
+            };
+            self.decoded.push(Decoded {
+                time,
+                direction,
+                query_id,
+                message,
+            });
+        }
+    }
+
+    /// Decoded packets, in order. Incomplete packets are dropped.
+    pub fn finish(self) -> Vec<Decoded> {
+        self.decoded
+    }
+}
+
+fn decode_packet(
+    direction: Direction,
+    bytes: &[u8],
+) -> (Option<Vec<u8>>, Result<Event, ProtocolError>) {
+    let raw = match RawPacket::from_bytes(bytes) {
+        Ok(raw) => raw,
+        Err(error) => return (None, Err(error)),
+    };
+    let (id, data) = (raw.cmd_id(), raw.data);
+    let message = match direction {
+        Direction::ToGlasses => Command::from_data(id, data).map(Event::Sent),
+        Direction::FromGlasses => Response::from_data(id, data).map(Event::Received),
+    };
+    (raw.query_id, message.map_err(ProtocolError::from))
+}
+
+/// Decode a hex dump.
+///
+/// Each line starts with the direction, `>` for [Direction::ToGlasses] and `<` for
+/// [Direction::FromGlasses], followed by the bytes in hexadecimal. Bytes may be separated by
+/// spaces or colons. Empty lines and lines starting with `#` are ignored.
+pub fn parse_hex_dump(text: &str) -> Result<Vec<Decoded>, SnifferError> {
+    let mut decoder = Decoder::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_nb = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (direction, hex) = if let Some(hex) = line.strip_prefix('>') {
+            (Direction::ToGlasses, hex)
+        } else if let Some(hex) = line.strip_prefix('<') {
+            (Direction::FromGlasses, hex)
+        } else {
+            return Err(SnifferError::Direction(line_nb));
+        };
+        let hex: String = hex
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != ':')
+            .collect();
+        let bytes = from_hex(&hex)
+            .filter(|bytes| !bytes.is_empty())
+            .ok_or(SnifferError::Hex(line_nb))?;
+        decoder.push(None, direction, &bytes);
+    }
+    Ok(decoder.finish())
+}
+
+fn be_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn le_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    let bytes = bytes.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+/// Decode a btsnoop capture.
+///
+/// Only ATT writes and notifications are considered. L2CAP frames fragmented over several ACL
+/// packets are reassembled.
+pub fn parse_btsnoop(bytes: &[u8]) -> Result<Vec<Decoded>, SnifferError> {
+    if !bytes.starts_with(BTSNOOP_MAGIC) || bytes.len() < FILE_HEADER_LEN {
+        return Err(SnifferError::NotBtsnoop);
+    }
+    let datalink = be_u32(bytes, 12).ok_or(SnifferError::NotBtsnoop)?;
+    if datalink != DATALINK_HCI_UNENCAPSULATED && datalink != DATALINK_HCI_UART {
+        return Err(SnifferError::Datalink(datalink));
+    }
+
+    let mut decoder = Decoder::new();
+    // L2CAP frames being reassembled, by direction and connection handle
+    let mut fragments: BTreeMap<(bool, u16), Vec<u8>> = BTreeMap::new();
+    let mut origin = None;
+    let mut offset = FILE_HEADER_LEN;
+    while offset < bytes.len() {
+        let header = bytes
+            .get(offset..offset + RECORD_HEADER_LEN)
+            .ok_or(SnifferError::Truncated(offset))?;
+        let included = be_u32(header, 4).unwrap_or_default() as usize;
+        let flags = be_u32(header, 8).unwrap_or_default();
+        let timestamp = u64::from_be_bytes(header[16..24].try_into().unwrap_or_default());
+        let start = offset + RECORD_HEADER_LEN;
+        let record = bytes
+            .get(start..start + included)
+            .ok_or(SnifferError::Truncated(offset))?;
+        offset = start + included;
+        let origin = *origin.get_or_insert(timestamp);
+
+        let acl = match datalink {
+            DATALINK_HCI_UART => match record.split_first() {
+                Some((&H4_ACL, acl)) => acl,
+                _ => continue,
+            },
+            _ if flags & FLAG_COMMAND_EVENT != 0 => continue,
+            _ => record,
+        };
+        let (Some(handle), Some(acl_data)) = (le_u16(acl, 0), acl.get(4..)) else {
+            continue;
+        };
+        let received = flags & FLAG_RECEIVED != 0;
+        let key = (received, handle & 0x0FFF);
+        let frame = fragments.entry(key).or_default();
+        if (handle >> 12) & 0b11 != ACL_CONTINUATION {
+            frame.clear();
+        }
+        frame.extend_from_slice(acl_data);
+        let Some(l2cap_len) = le_u16(frame, 0) else {
+            continue;
+        };
+        if frame.len() < L2CAP_HEADER_LEN + l2cap_len as usize {
+            continue;
+        }
+        let frame = fragments.remove(&key).unwrap_or_default();
+        if le_u16(&frame, 2) != Some(L2CAP_CID_ATT) {
+            continue;
+        }
+
+        let att = &frame[L2CAP_HEADER_LEN..L2CAP_HEADER_LEN + l2cap_len as usize];
+        let direction = match att.first() {
+            Some(&ATT_WRITE_REQUEST | &ATT_WRITE_COMMAND) => Direction::ToGlasses,
+            Some(&ATT_NOTIFICATION | &ATT_INDICATION) => Direction::FromGlasses,
+            _ => continue,
+        };
+        // Opcode and attribute handle
+        let Some(value) = att.get(3..) else {
+            continue;
+        };
+        let time = Duration::from_micros(timestamp.saturating_sub(origin));
+        decoder.push(Some(time), direction, value);
+    }
+    Ok(decoder.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::CommandPacket;
+
+    fn packet(cmd: &Command) -> Vec<u8> {
+        CommandPacket::new(cmd).unwrap().to_bytes()
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let clear = packet(&Command::Clear);
+        let (first, last) = clear.split_at(3);
+        let text = format!(
+            "# capture\n> {}\n>{}\n< FF:05:00:06:64:AA\n< 01\n",
+            crate::vectors::to_hex(first),
+            crate::vectors::to_hex(last)
+        );
+        let decoded = parse_hex_dump(&text).unwrap();
+        assert_eq!(2, decoded.len());
+        assert_eq!(Ok(Event::Sent(Command::Clear)), decoded[0].message);
+        assert_eq!(Direction::FromGlasses, decoded[1].direction);
+        assert_eq!(
+            Ok(Event::Received(Response::Battery { level: 100 })),
+            decoded[1].message
+        );
+
+        assert_eq!(Err(SnifferError::Direction(1)), parse_hex_dump("ff"));
+        assert_eq!(Err(SnifferError::Hex(2)), parse_hex_dump("> ff\n< f"));
+    }
+
+    /// Synthesized btsnoop record carrying one ATT PDU in H4 framing
+    fn record(received: bool, timestamp: u64, pb: u16, acl_data: &[u8]) -> Vec<u8> {
+        let mut acl = vec![H4_ACL];
+        acl.extend_from_slice(&(0x0040 | (pb << 12)).to_le_bytes());
+        acl.extend_from_slice(&(acl_data.len() as u16).to_le_bytes());
+        acl.extend_from_slice(acl_data);
+        let mut record = Vec::new();
+        record.extend_from_slice(&(acl.len() as u32).to_be_bytes());
+        record.extend_from_slice(&(acl.len() as u32).to_be_bytes());
+        record.extend_from_slice(&(received as u32).to_be_bytes());
+        record.extend_from_slice(&0u32.to_be_bytes());
+        record.extend_from_slice(&timestamp.to_be_bytes());
+        record.extend_from_slice(&acl);
+        record
+    }
+
+    fn l2cap_att(opcode: u8, value: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&(3 + value.len() as u16).to_le_bytes());
+        frame.extend_from_slice(&L2CAP_CID_ATT.to_le_bytes());
+        frame.push(opcode);
+        frame.extend_from_slice(&0x0011u16.to_le_bytes());
+        frame.extend_from_slice(value);
+        frame
+    }
+
+    #[test]
+    fn test_btsnoop() {
+        let mut capture = BTSNOOP_MAGIC.to_vec();
+        capture.extend_from_slice(&1u32.to_be_bytes());
+        capture.extend_from_slice(&DATALINK_HCI_UART.to_be_bytes());
+
+        let write = l2cap_att(ATT_WRITE_COMMAND, &packet(&Command::Battery));
+        let (first, last) = write.split_at(6);
+        capture.extend(record(false, 1_000, 0b10, first));
+        capture.extend(record(false, 1_500, ACL_CONTINUATION, last));
+        // Battery level characteristic
+        let notify = l2cap_att(ATT_NOTIFICATION, &[87]);
+        capture.extend(record(true, 2_000, 0b10, &notify));
+        let notify = l2cap_att(ATT_NOTIFICATION, &[0xFF, 0x05, 0x00, 0x06, 0x57, 0xAA]);
+        capture.extend(record(true, 3_000, 0b10, &notify));
+
+        let decoded = parse_btsnoop(&capture).unwrap();
+        assert_eq!(2, decoded.len());
+        assert_eq!(Some(Duration::from_micros(500)), decoded[0].time);
+        assert_eq!(Ok(Event::Sent(Command::Battery)), decoded[0].message);
+        assert_eq!(Some(Duration::from_micros(2_000)), decoded[1].time);
+        assert_eq!(
+            Ok(Event::Received(Response::Battery { level: 87 })),
+            decoded[1].message
+        );
+
+        assert_eq!(Err(SnifferError::NotBtsnoop), parse_btsnoop(b"> ff"));
+        capture.pop();
+        assert!(matches!(
+            parse_btsnoop(&capture),
+            Err(SnifferError::Truncated(_))
+        ));
+    }
+}
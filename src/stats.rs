@@ -0,0 +1,151 @@
+//! Throughput statistics of a client
+//!
+//! [ClientStats] counts what an [crate::client::ActiveLookClient] sends and receives, to size
+//! image updates and tune the BLE connection interval. It is disabled by default, see
+//! [crate::client::ActiveLookClient::enable_stats].
+//!
+//! Commands, bytes and flow control stalls are counted by the sending half. Responses are
+//! counted by the whole client only: once split, the receiving half does not update them.
+use core::time::Duration;
+use std::collections::BTreeMap;
+
+use crate::time::Clock;
+
+/// Round-trip latency of the queries of a command
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Latency {
+    /// Number of responses received
+    pub count: u64,
+    pub min: Duration,
+    pub max: Duration,
+    /// Sum of all latencies
+    pub total: Duration,
+}
+
+impl Latency {
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total / count as u32,
+        }
+    }
+
+    fn add(&mut self, latency: Duration) {
+        if self.count == 0 || latency < self.min {
+            self.min = latency;
+        }
+        self.max = self.max.max(latency);
+        self.total += latency;
+        self.count += 1;
+    }
+}
+
+/// Statistics collected since the collector was created or [ClientStats::reset]
+pub struct ClientStats {
+    clock: Box<dyn Clock + Send>,
+    start: Duration,
+    commands_sent: u64,
+    bytes_written: u64,
+    stalls: u64,
+    error_responses: u64,
+    latencies: BTreeMap<u8, Latency>,
+    /// Command ID and send time of the queries waiting for their response
+    in_flight: BTreeMap<u32, (u8, Duration)>,
+}
+
+impl ClientStats {
+    pub fn new(clock: impl Clock + Send + 'static) -> Self {
+        let start = clock.now();
+        Self {
+            clock: Box::new(clock),
+            start,
+            commands_sent: 0,
+            bytes_written: 0,
+            stalls: 0,
+            error_responses: 0,
+            latencies: BTreeMap::new(),
+            in_flight: BTreeMap::new(),
+        }
+    }
+
+    /// Forget everything, and start measuring again from now
+    pub fn reset(&mut self) {
+        self.start = self.clock.now();
+        self.commands_sent = 0;
+        self.bytes_written = 0;
+        self.stalls = 0;
+        self.error_responses = 0;
+        self.latencies.clear();
+        self.in_flight.clear();
+    }
+
+    /// Time elapsed since the statistics started
+    pub fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_sub(self.start)
+    }
+
+    /// Packets sent, including each chunk of split commands
+    pub fn commands_sent(&self) -> u64 {
+        self.commands_sent
+    }
+
+    /// Bytes written on the Rx characteristic, packet headers included
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Mean throughput since the statistics started
+    pub fn bytes_per_second(&self) -> f64 {
+        let elapsed = self.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.bytes_written as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Number of times the glasses asked to wait before sending more data
+    pub fn stalls(&self) -> u64 {
+        self.stalls
+    }
+
+    /// Number of [crate::commands::Response::CmdError] received
+    pub fn error_responses(&self) -> u64 {
+        self.error_responses
+    }
+
+    /// Latency of the queries of command `cmd_id`, if any was answered
+    pub fn latency(&self, cmd_id: u8) -> Option<&Latency> {
+        self.latencies.get(&cmd_id)
+    }
+
+    /// Latency of each command answered, by command ID
+    pub fn latencies(&self) -> impl Iterator<Item = (u8, &Latency)> {
+        self.latencies.iter().map(|(id, latency)| (*id, latency))
+    }
+
+    pub(crate) fn on_packet(&mut self, len: usize) {
+        self.commands_sent += 1;
+        self.bytes_written += len as u64;
+    }
+
+    pub(crate) fn on_stall(&mut self) {
+        self.stalls += 1;
+    }
+
+    pub(crate) fn on_query(&mut self, query_id: u32, cmd_id: u8) {
+        self.in_flight.insert(query_id, (cmd_id, self.clock.now()));
+    }
+
+    /// A response was received, answering `query_id` if known
+    pub(crate) fn on_response(&mut self, query_id: Option<u32>, is_error: bool) {
+        if is_error {
+            self.error_responses += 1;
+        }
+        let sent = query_id.and_then(|query_id| self.in_flight.remove(&query_id));
+        if let Some((cmd_id, sent)) = sent {
+            let latency = self.clock.now().saturating_sub(sent);
+            self.latencies.entry(cmd_id).or_default().add(latency);
+        }
+    }
+}
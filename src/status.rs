@@ -0,0 +1,251 @@
+//! Battery and settings polling
+//!
+//! [StatusMonitor] wraps a [GlassesApi] and queries the battery level and the settings every
+//! interval, when the glasses accept data. Application commands go through the monitor, which
+//! interleaves its queries with them. The latest values are cached, and callbacks are called
+//! when they change.
+use core::time::Duration;
+
+use crate::{
+    commands::{Command, Luma, Response},
+    glasses::{GlassesApi, GlassesError},
+    time::Clock,
+};
+
+/// Decoded [Response::Settings]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Settings {
+    /// Shift of the display
+    pub x: i8,
+    pub y: i8,
+    pub luma: Luma,
+    pub als_enable: bool,
+    pub gesture_enable: bool,
+}
+
+impl Settings {
+    pub fn from_response(response: &Response) -> Option<Self> {
+        match *response {
+            Response::Settings {
+                x,
+                y,
+                luma,
+                als_enable,
+                gesture_enable,
+            } => Some(Self {
+                x,
+                y,
+                luma,
+                als_enable,
+                gesture_enable,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Luminance, in % of [Luma::MAX]
+    pub fn luma_percent(&self) -> u8 {
+        (self.luma.value() as u16 * 100 / Luma::MAX.value() as u16) as u8
+    }
+}
+
+/// Callback given the new battery level
+pub type BatteryHandler = Box<dyn FnMut(u8) + Send>;
+/// Callback given the new settings
+pub type SettingsHandler = Box<dyn FnMut(&Settings) + Send>;
+
+/// Polls the battery level and the settings of the glasses
+pub struct StatusMonitor<G, C>
+where
+    G: GlassesApi,
+    C: Clock,
+{
+    glasses: G,
+    clock: C,
+    interval: Duration,
+    /// Time of the last poll, if any
+    last_poll: Option<Duration>,
+    battery: Option<u8>,
+    settings: Option<Settings>,
+    on_battery: Vec<BatteryHandler>,
+    /// Thresholds and their callbacks
+    on_battery_low: Vec<(u8, BatteryHandler)>,
+    on_settings: Vec<SettingsHandler>,
+}
+
+impl<G, C> StatusMonitor<G, C>
+where
+    G: GlassesApi,
+    C: Clock,
+{
+    /// Default polling interval
+    pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+    /// Poll every `interval`, starting with the first call to [StatusMonitor::poll]
+    pub fn new(glasses: G, clock: C, interval: Duration) -> Self {
+        Self {
+            glasses,
+            clock,
+            interval,
+            last_poll: None,
+            battery: None,
+            settings: None,
+            on_battery: Vec::new(),
+            on_battery_low: Vec::new(),
+            on_settings: Vec::new(),
+        }
+    }
+
+    /// Latest battery level in %, if polled
+    pub fn battery(&self) -> Option<u8> {
+        self.battery
+    }
+
+    /// Latest settings, if polled
+    pub fn settings(&self) -> Option<&Settings> {
+        self.settings.as_ref()
+    }
+
+    /// Access the wrapped glasses
+    pub fn glasses(&mut self) -> &mut G {
+        &mut self.glasses
+    }
+
+    /// Call `handler` when the battery level changes, and on the first poll
+    pub fn on_battery_change(&mut self, handler: impl FnMut(u8) + Send + 'static) {
+        self.on_battery.push(Box::new(handler));
+    }
+
+    /// Call `handler` when the battery level drops below `threshold` %, or is below it on the
+    /// first poll. It is called again only after the level went back above the threshold.
+    pub fn on_battery_drop_below(
+        &mut self,
+        threshold: u8,
+        handler: impl FnMut(u8) + Send + 'static,
+    ) {
+        self.on_battery_low.push((threshold, Box::new(handler)));
+    }
+
+    /// Call `handler` when the settings change, and on the first poll
+    pub fn on_settings_change(&mut self, handler: impl FnMut(&Settings) + Send + 'static) {
+        self.on_settings.push(Box::new(handler));
+    }
+
+    /// Must be called periodically: queries the status once the interval elapsed, unless the
+    /// glasses asked to wait. Returns true if the status was queried.
+    pub fn poll(&mut self) -> Result<bool, GlassesError> {
+        let now = self.clock.now();
+        let due = match self.last_poll {
+            Some(last_poll) => now.saturating_sub(last_poll) >= self.interval,
+            None => true,
+        };
+        if !due || !self.glasses.can_send() {
+            return Ok(false);
+        }
+        self.last_poll = Some(now);
+        self.refresh()?;
+        Ok(true)
+    }
+
+    /// Query the status now
+    pub fn refresh(&mut self) -> Result<(), GlassesError> {
+        let level = self.glasses.battery()?;
+        let settings = self.glasses.settings()?;
+        self.update_battery(level);
+        if self.settings != Some(settings) {
+            self.settings = Some(settings);
+            self.on_settings
+                .iter_mut()
+                .for_each(|handler| handler(&settings));
+        }
+        Ok(())
+    }
+
+    fn update_battery(&mut self, level: u8) {
+        let previous = self.battery.replace(level);
+        if previous == Some(level) {
+            return;
+        }
+        self.on_battery
+            .iter_mut()
+            .for_each(|handler| handler(level));
+        for (threshold, handler) in &mut self.on_battery_low {
+            let was_above = previous.is_none_or(|previous| previous >= *threshold);
+            if was_above && level < *threshold {
+                handler(level);
+            }
+        }
+    }
+}
+
+impl<G, C> GlassesApi for StatusMonitor<G, C>
+where
+    G: GlassesApi,
+    C: Clock,
+{
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.glasses.send(cmd)
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        self.glasses.query(cmd)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        self.glasses.send_chunked(cmd)
+    }
+
+    fn can_send(&mut self) -> bool {
+        self.glasses.can_send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{mock::MockClient, time::VirtualClock};
+
+    fn settings(luma: u8) -> Response {
+        Response::Settings {
+            x: 0,
+            y: 0,
+            luma: Luma::from(luma),
+            als_enable: true,
+            gesture_enable: true,
+        }
+    }
+
+    #[test]
+    fn test_status_monitor() {
+        let mut mock = MockClient::new();
+        for (level, luma) in [(25, 15), (19, 15), (18, 10)] {
+            mock.expect(Command::Battery)
+                .reply(Response::Battery { level });
+            mock.expect(Command::Settings).reply(settings(luma));
+        }
+        let clock = VirtualClock::new();
+        let mut monitor = StatusMonitor::new(mock, clock.clone(), Duration::from_secs(60));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let low = events.clone();
+        monitor.on_battery_drop_below(20, move |level| low.lock().unwrap().push(level));
+        let changed = events.clone();
+        monitor.on_settings_change(move |settings| {
+            changed.lock().unwrap().push(settings.luma.value())
+        });
+
+        assert_eq!(Ok(true), monitor.poll());
+        assert_eq!(Some(25), monitor.battery());
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Ok(false), monitor.poll());
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(Ok(true), monitor.poll());
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(Ok(true), monitor.poll());
+        assert_eq!(Some(18), monitor.battery());
+        assert_eq!(Luma::from(10), monitor.settings().unwrap().luma);
+        // Settings on the first poll, crossing 20 % once, then the new luminance
+        assert_eq!(vec![15, 19, 10], *events.lock().unwrap());
+    }
+}
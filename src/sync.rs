@@ -0,0 +1,519 @@
+//! Incremental upload of a configuration
+//!
+//! Uploading a whole [Config] takes a while over BLE, mostly because of the images and fonts.
+//! [ConfigSync] compares a configuration with the one stored in the glasses, and uploads only the
+//! elements which are missing or changed.
+//!
+//! The glasses give back the parameters of layouts, gauges and pages, which are compared exactly.
+//! Images and fonts can not be read back: an image is changed when its size differs, and a font
+//! or an animation is only checked for presence. Bump the version of the configuration and use
+//! [Config::upload] to force a full upload.
+//!
+//! The elements can only be listed in the configuration selected in the glasses.
+//! [ConfigSync::plan] does not change the glasses: it only tells whether the configuration is
+//! stored and in which version. [ConfigSync::apply] selects the stored configuration with
+//! [Command::CfgSet] before comparing its elements, and writes it whenever the stored version
+//! differs, even if no element changed.
+use crate::{
+    commands::{Command, ImgFormat, ImgListItem, Response, Selector},
+    config::{
+        Config, ConfigElement, ConfigSession, ElementKind, ElementRef, SessionError, UploadProgress,
+    },
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// State of an element of the configuration in the glasses
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ElementState {
+    /// Not stored in the glasses
+    Missing,
+    /// Stored with different content
+    Changed,
+    /// Stored as in the configuration, as far as the glasses tell
+    Unchanged,
+    /// Not compared yet, the stored configuration is not selected, see [ConfigSync::apply]
+    Unread,
+}
+
+/// Result of the comparison of a configuration with the glasses, see [ConfigSync::plan]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyncPlan {
+    /// Version of the configuration stored in the glasses, `None` if it does not exist
+    pub device_version: Option<u32>,
+    /// Version of the configuration to store
+    pub version: u32,
+    /// State of each element of the configuration, in upload order
+    pub elements: Vec<(ElementRef, ElementState)>,
+    /// Elements stored in the glasses which are not part of the configuration. They are kept.
+    pub extra: Vec<ElementRef>,
+}
+
+impl SyncPlan {
+    /// Elements to upload, in upload order
+    pub fn to_upload(&self) -> impl Iterator<Item = (ElementRef, ElementState)> + '_ {
+        self.elements
+            .iter()
+            .copied()
+            .filter(|(_, state)| *state != ElementState::Unchanged)
+    }
+
+    /// Returns true if the glasses store this version of the configuration and nothing needs to
+    /// be uploaded
+    pub fn is_up_to_date(&self) -> bool {
+        self.device_version == Some(self.version) && self.to_upload().next().is_none()
+    }
+
+    /// Returns true if every element was compared with the glasses
+    pub fn is_read(&self) -> bool {
+        self.elements
+            .iter()
+            .all(|(_, state)| *state != ElementState::Unread)
+    }
+}
+
+/// Elements stored in the current configuration of the glasses
+struct DeviceElements {
+    images: Vec<ImgListItem>,
+    ids: Vec<ElementRef>,
+}
+
+/// Uploads the missing and changed elements of a configuration
+pub struct ConfigSync<'c> {
+    config: &'c Config,
+}
+
+impl<'c> ConfigSync<'c> {
+    pub fn new(config: &'c Config) -> Self {
+        Self { config }
+    }
+
+    /// Look for the configuration in the glasses, without selecting it.
+    ///
+    /// The elements of a stored configuration are [ElementState::Unread], they are compared by
+    /// [ConfigSync::apply].
+    pub fn plan<G: GlassesApi + ?Sized>(&self, glasses: &mut G) -> Result<SyncPlan, GlassesError> {
+        let device_version = match glasses.query(&Command::CfgList)? {
+            Response::CfgList { list } => list
+                .into_iter()
+                .find(|item| item.name == self.config.name)
+                .map(|item| item.version),
+            other => return Err(GlassesError::UnexpectedResponse(other)),
+        };
+        let state = match device_version {
+            Some(_) => ElementState::Unread,
+            None => ElementState::Missing,
+        };
+        Ok(SyncPlan {
+            device_version,
+            version: self.config.version,
+            elements: self
+                .config
+                .elements()
+                .iter()
+                .map(|element| (element.element, state))
+                .collect(),
+            extra: Vec::new(),
+        })
+    }
+
+    /// Compare the configuration with the elements of the configuration selected in the glasses
+    fn read<G: GlassesApi + ?Sized>(
+        &self,
+        glasses: &mut G,
+        device_version: Option<u32>,
+    ) -> Result<SyncPlan, GlassesError> {
+        let device = Self::device_elements(glasses)?;
+        let mut elements = Vec::with_capacity(self.config.elements().len());
+        for element in self.config.elements() {
+            let state = if device.ids.contains(&element.element) {
+                Self::compare(glasses, element, &device)?
+            } else {
+                ElementState::Missing
+            };
+            elements.push((element.element, state));
+        }
+        let extra = device
+            .ids
+            .into_iter()
+            .filter(|id| {
+                !id.is_builtin()
+                    && !self
+                        .config
+                        .elements()
+                        .iter()
+                        .any(|element| element.element == *id)
+            })
+            .collect();
+        Ok(SyncPlan {
+            device_version,
+            version: self.config.version,
+            elements,
+            extra,
+        })
+    }
+
+    /// Upload the elements of `plan` inside a [ConfigSession], calling `progress` after each
+    /// command, and return the plan applied. Changed elements are deleted before being saved
+    /// again.
+    ///
+    /// If `plan` has [ElementState::Unread] elements, the stored configuration is selected with
+    /// [Command::CfgSet] and compared first. The session is opened if an element must be
+    /// uploaded or if the stored version differs, to write the version of the configuration.
+    pub fn apply<G: GlassesApi + ?Sized>(
+        &self,
+        glasses: &mut G,
+        plan: &SyncPlan,
+        mut progress: impl FnMut(UploadProgress),
+    ) -> Result<SyncPlan, SessionError> {
+        let plan = if plan.is_read() {
+            plan.clone()
+        } else {
+            glasses.send(&Command::CfgSet {
+                name: self.config.name.clone(),
+            })?;
+            self.read(glasses, plan.device_version)?
+        };
+        if plan.is_up_to_date() {
+            return Ok(plan);
+        }
+
+        let mut commands = Vec::new();
+        for (element, state) in plan.to_upload() {
+            // No element is stored as ALL, which would delete them all
+            if let (ElementState::Changed, Some(id)) = (state, Selector::one(element.id)) {
+                commands.push((element, element.kind.delete_command(id)));
+            }
+            let Some(config_element) = self
+                .config
+                .elements()
+                .iter()
+                .find(|config_element| config_element.element == element)
+            else {
+                continue;
+            };
+            commands.extend(
+                config_element
+                    .commands
+                    .iter()
+                    .map(|cmd| (element, cmd.clone())),
+            );
+        }
+
+        let mut session = ConfigSession::open(
+            glasses,
+            &self.config.name,
+            self.config.version,
+            self.config.password,
+        )?;
+        let total = commands.len();
+        for (index, (element, cmd)) in commands.into_iter().enumerate() {
+            match cmd.chunk_policy() {
+                Some(_) => session.send_chunked(&cmd)?,
+                None => session.send(&cmd)?,
+            }
+            progress(UploadProgress {
+                element: Some(element),
+                sent: index + 1,
+                total,
+            });
+        }
+        Ok(plan)
+    }
+
+    /// [ConfigSync::plan] then [ConfigSync::apply], returning the plan applied
+    pub fn sync<G: GlassesApi + ?Sized>(
+        &self,
+        glasses: &mut G,
+        progress: impl FnMut(UploadProgress),
+    ) -> Result<SyncPlan, SessionError> {
+        let plan = self.plan(glasses)?;
+        self.apply(glasses, &plan, progress)
+    }
+
+    fn device_elements<G: GlassesApi + ?Sized>(
+        glasses: &mut G,
+    ) -> Result<DeviceElements, GlassesError> {
+        let images = match glasses.query(&Command::ImgList)? {
+            Response::ImgList { list } => list,
+            other => return Err(GlassesError::UnexpectedResponse(other)),
+        };
+        let mut device = DeviceElements {
+            ids: images
+                .iter()
+                .map(|item| ElementRef::new(ElementKind::Image, item.id))
+                .collect(),
+            images,
+        };
+        for kind in [
+            ElementKind::Font,
+            ElementKind::Layout,
+            ElementKind::Gauge,
+            ElementKind::Page,
+            ElementKind::Animation,
+        ] {
+            let ids = glasses.list(kind)?;
+            device
+                .ids
+                .extend(ids.into_iter().map(|id| ElementRef::new(kind, id)));
+        }
+        Ok(device)
+    }
+
+    /// Compare an element stored in the glasses with its upload commands
+    fn compare<G: GlassesApi + ?Sized>(
+        glasses: &mut G,
+        element: &ConfigElement,
+        device: &DeviceElements,
+    ) -> Result<ElementState, GlassesError> {
+        let id = element.element.id;
+        let get = match element.element.kind {
+            ElementKind::Image => {
+                let stored = device.images.iter().find(|item| item.id == id);
+                let same = element.commands.iter().any(|cmd| match (cmd, stored) {
+                    (
+                        Command::ImgSave {
+                            width,
+                            format,
+                            data,
+                            ..
+                        },
+                        Some(stored),
+                    ) => {
+                        stored.width == *width
+                            && image_height(*format, *width, data)
+                                .is_none_or(|height| height == stored.height)
+                    }
+                    _ => false,
+                });
+                return Ok(if same {
+                    ElementState::Unchanged
+                } else {
+                    ElementState::Changed
+                });
+            }
+            ElementKind::Layout => Command::LayoutGet { id },
+            ElementKind::Gauge => Command::GaugeGet { id },
+            ElementKind::Page => Command::PageGet { id },
+            ElementKind::Font | ElementKind::Animation => return Ok(ElementState::Unchanged),
+        };
+        let stored = glasses.query(&get)?.to_save_command(id);
+        Ok(
+            if stored.is_some_and(|cmd| element.commands.contains(&cmd)) {
+                ElementState::Unchanged
+            } else {
+                ElementState::Changed
+            },
+        )
+    }
+}
+
+/// Height of an uncompressed image, the firmware does not tell the height of compressed images
+fn image_height(format: ImgFormat, width: u16, data: &[u8]) -> Option<u16> {
+    match format {
+        ImgFormat::Img4bpp | ImgFormat::Img1bpp | ImgFormat::Img8bpp => {
+            let line = format.nb_of_bytes(width as usize).max(1);
+            Some((data.len() / line) as u16)
+        }
+        ImgFormat::Img4bppDecompressBeforeSaving | ImgFormat::Img4bppDecompressBeforeDisplaying => {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::{LayoutParameters, LayoutPosition},
+        config::ConfigBuilder,
+        emulator::Emulator,
+        layout::LayoutBuilder,
+    };
+
+    /// Emulated glasses, recording the commands sent
+    struct Emulated {
+        emulator: Emulator,
+        sent: Vec<Command>,
+    }
+
+    impl GlassesApi for Emulated {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            self.sent.push(cmd.clone());
+            match self.emulator.handle(cmd) {
+                Some(response) => Err(GlassesError::UnexpectedResponse(response)),
+                None => Ok(()),
+            }
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            self.emulator.handle(cmd).ok_or(GlassesError::Unsupported)
+        }
+    }
+
+    fn image(id: u8, width: u16, lines: usize) -> Command {
+        let data = vec![0x11; lines * width.div_ceil(2) as usize];
+        Command::ImgSave {
+            id,
+            size: data.len() as u32,
+            width,
+            format: ImgFormat::Img4bpp,
+            data,
+        }
+    }
+
+    fn layout(x: u16) -> LayoutParameters {
+        LayoutBuilder::new(LayoutPosition { x, y: 10 }, 100, 30)
+            .build()
+            .unwrap()
+    }
+
+    fn config(layout_x: u16, image_lines: usize) -> Config {
+        let params = layout(layout_x);
+        ConfigBuilder::new("sync", 2, 0)
+            .element(ConfigElement {
+                element: ElementRef::new(ElementKind::Image, 1),
+                commands: vec![image(1, 4, image_lines)],
+                depends_on: Vec::new(),
+            })
+            .layout(10, params.clone())
+            .unwrap()
+            .layout(11, params)
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_config_sync() {
+        let mut glasses = Emulated {
+            emulator: Emulator::new(),
+            sent: Vec::new(),
+        };
+        let first = config(0, 2);
+        let plan = ConfigSync::new(&first).sync(&mut glasses, |_| {}).unwrap();
+        assert_eq!(None, plan.device_version);
+        assert_eq!(3, plan.to_upload().count());
+
+        // Nothing to upload the second time
+        glasses.sent.clear();
+        let plan = ConfigSync::new(&first).sync(&mut glasses, |_| {}).unwrap();
+        assert_eq!(Some(2), plan.device_version);
+        assert!(plan.is_up_to_date());
+        assert_eq!(
+            vec![Command::CfgSet {
+                name: String::from("sync")
+            }],
+            glasses.sent
+        );
+
+        // Only the changed elements are uploaded
+        let second = config(20, 3);
+        glasses.sent.clear();
+        let mut progress = Vec::new();
+        let plan = ConfigSync::new(&second)
+            .sync(&mut glasses, |p| progress.push(p.sent))
+            .unwrap();
+        assert_eq!(
+            vec![
+                (
+                    ElementRef::new(ElementKind::Image, 1),
+                    ElementState::Changed
+                ),
+                (
+                    ElementRef::new(ElementKind::Layout, 10),
+                    ElementState::Changed
+                ),
+                (
+                    ElementRef::new(ElementKind::Layout, 11),
+                    ElementState::Changed
+                ),
+            ],
+            plan.elements
+        );
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], progress);
+        assert!(ConfigSync::new(&second)
+            .sync(&mut glasses, |_| {})
+            .unwrap()
+            .is_up_to_date());
+
+        // Elements missing from the configuration are reported, not deleted. The new version is
+        // written even if no element changed.
+        let third = ConfigBuilder::new("sync", 3, 0)
+            .layout(10, layout(20))
+            .unwrap()
+            .build()
+            .unwrap();
+        glasses.sent.clear();
+        let plan = ConfigSync::new(&third).sync(&mut glasses, |_| {}).unwrap();
+        assert_eq!(Some(2), plan.device_version);
+        assert_eq!(0, plan.to_upload().count());
+        assert!(!plan.is_up_to_date());
+        assert_eq!(
+            vec![
+                ElementRef::new(ElementKind::Image, 1),
+                ElementRef::new(ElementKind::Layout, 11)
+            ],
+            plan.extra
+        );
+        assert_eq!(
+            vec![
+                Command::CfgSet {
+                    name: String::from("sync")
+                },
+                Command::CfgWrite {
+                    name: String::from("sync"),
+                    version: 3,
+                    password: 0
+                }
+            ],
+            glasses.sent
+        );
+        assert!(ConfigSync::new(&third)
+            .sync(&mut glasses, |_| {})
+            .unwrap()
+            .is_up_to_date());
+    }
+
+    #[test]
+    fn test_plan_does_not_select() {
+        let mut glasses = Emulated {
+            emulator: Emulator::new(),
+            sent: Vec::new(),
+        };
+        let first = config(0, 2);
+        ConfigSync::new(&first).sync(&mut glasses, |_| {}).unwrap();
+        glasses
+            .send(&Command::CfgWrite {
+                name: String::from("other"),
+                version: 1,
+                password: 0,
+            })
+            .unwrap();
+
+        glasses.sent.clear();
+        let plan = ConfigSync::new(&first).plan(&mut glasses).unwrap();
+        assert!(glasses.sent.is_empty());
+        assert_eq!(
+            Some("other"),
+            glasses
+                .emulator
+                .current_config()
+                .map(|config| config.name.as_str())
+        );
+        assert_eq!(Some(2), plan.device_version);
+        assert!(!plan.is_read());
+        assert!(!plan.is_up_to_date());
+
+        // The configuration is selected to be compared
+        let plan = ConfigSync::new(&first)
+            .apply(&mut glasses, &plan, |_| {})
+            .unwrap();
+        assert!(plan.is_up_to_date());
+        assert_eq!(
+            vec![Command::CfgSet {
+                name: String::from("sync")
+            }],
+            glasses.sent
+        );
+    }
+}
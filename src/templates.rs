@@ -0,0 +1,386 @@
+//! Ready-made layouts for common applications
+//!
+//! Each template is a set of layouts with fixed IDs, from [FIRST_LAYOUT_ID], drawn with the
+//! built-in fonts. Install them once in a configuration, with [add_to] or through a
+//! [crate::config::ConfigSession] with [install], then drive them with typed calls:
+//! - [Navigation]: a direction arrow and the distance to the next turn
+//! - [HeartRate]: the heart rate and its training zone
+//! - [Notification]: a phone notification, with its title and a wrapped body
+//!
+//! Templates cover the whole 304x256 display and are not meant to be shown together.
+//! Application layouts should use IDs below [FIRST_LAYOUT_ID].
+use crate::{
+    commands::{
+        Command, DefaultFont, FontItem, HoldFlushAction, LayoutParameters, LayoutPosition, Point,
+    },
+    config::{ConfigBuilder, ConfigError},
+    glasses::{GlassesApi, GlassesError},
+    layout::{LayoutBuilder, LayoutCommand},
+    text::TextWrap,
+};
+
+/// First layout ID used by the templates
+pub const FIRST_LAYOUT_ID: u8 = 200;
+
+/// Layouts of every template, with their IDs
+pub fn layouts() -> Vec<(u8, LayoutParameters)> {
+    let mut layouts = Navigation::layouts();
+    layouts.extend(HeartRate::layouts());
+    layouts.extend(Notification::layouts());
+    layouts
+}
+
+/// Add the layouts of every template to a configuration
+pub fn add_to(builder: ConfigBuilder) -> Result<ConfigBuilder, ConfigError> {
+    layouts()
+        .into_iter()
+        .try_fold(builder, |builder, (id, params)| builder.layout(id, params))
+}
+
+/// Save the layouts of every template, through a [crate::config::ConfigSession]
+pub fn install<G: GlassesApi + ?Sized>(glasses: &mut G) -> Result<(), GlassesError> {
+    for (id, params) in layouts() {
+        glasses.send(&Command::LayoutSave { id, params })?;
+    }
+    Ok(())
+}
+
+/// Parameters of a template layout, checked by the tests
+fn build(builder: LayoutBuilder) -> LayoutParameters {
+    builder.build().expect("Invalid template layout")
+}
+
+/// Send `commands` between [HoldFlushAction::Hold] and [HoldFlushAction::Flush]
+fn send_held<G: GlassesApi + ?Sized>(
+    glasses: &mut G,
+    commands: impl IntoIterator<Item = Command>,
+) -> Result<(), GlassesError> {
+    glasses.send(&Command::HoldFlush {
+        action: HoldFlushAction::Hold,
+    })?;
+    for cmd in commands {
+        glasses.send(&cmd)?;
+    }
+    glasses.send(&Command::HoldFlush {
+        action: HoldFlushAction::Flush,
+    })
+}
+
+/// Direction of the next turn
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction {
+    Left,
+    Right,
+    Straight,
+}
+
+/// Direction arrow above the distance to the next turn
+pub struct Navigation;
+
+impl Navigation {
+    /// Arrow layouts, one per [Direction], sharing the same region
+    pub const LEFT_ID: u8 = FIRST_LAYOUT_ID;
+    pub const RIGHT_ID: u8 = FIRST_LAYOUT_ID + 1;
+    pub const STRAIGHT_ID: u8 = FIRST_LAYOUT_ID + 2;
+    /// Distance, in the large font
+    pub const DISTANCE_ID: u8 = FIRST_LAYOUT_ID + 3;
+
+    /// Size of the square region of the arrows
+    const ARROW_SIZE: u16 = 120;
+
+    pub fn layouts() -> Vec<(u8, LayoutParameters)> {
+        let arrow = |lines: [(Point, Point); 3]| {
+            let builder = LayoutBuilder::new(
+                LayoutPosition { x: 92, y: 20 },
+                Self::ARROW_SIZE,
+                Self::ARROW_SIZE as u8,
+            );
+            build(
+                lines
+                    .into_iter()
+                    .fold(builder, |builder, (p1, p2)| builder.line(p1, p2)),
+            )
+        };
+        let p = |x, y| Point { x, y };
+        vec![
+            (
+                Self::LEFT_ID,
+                arrow([
+                    (p(100, 60), p(20, 60)),
+                    (p(20, 60), p(50, 30)),
+                    (p(20, 60), p(50, 90)),
+                ]),
+            ),
+            (
+                Self::RIGHT_ID,
+                arrow([
+                    (p(20, 60), p(100, 60)),
+                    (p(100, 60), p(70, 30)),
+                    (p(100, 60), p(70, 90)),
+                ]),
+            ),
+            (
+                Self::STRAIGHT_ID,
+                arrow([
+                    (p(60, 110), p(60, 10)),
+                    (p(60, 10), p(30, 40)),
+                    (p(60, 10), p(90, 40)),
+                ]),
+            ),
+            (
+                Self::DISTANCE_ID,
+                build(
+                    LayoutBuilder::new(LayoutPosition { x: 0, y: 160 }, 304, 60)
+                        .font(DefaultFont::ComputerModernSansSerif49.into())
+                        .text_at(LayoutPosition { x: 40, y: 5 }),
+                ),
+            ),
+        ]
+    }
+
+    /// Distance as displayed: meters below 1 km, then kilometers
+    pub fn distance_text(meters: u32) -> String {
+        match meters {
+            0..1000 => format!("{} m", meters),
+            1000..10_000 => format!("{}.{} km", meters / 1000, meters % 1000 / 100),
+            _ => format!("{} km", meters / 1000),
+        }
+    }
+
+    /// Display `direction` and the distance to the turn, in meters
+    pub fn show<G: GlassesApi + ?Sized>(
+        glasses: &mut G,
+        direction: Direction,
+        meters: u32,
+    ) -> Result<(), GlassesError> {
+        let arrow = match direction {
+            Direction::Left => Self::LEFT_ID,
+            Direction::Right => Self::RIGHT_ID,
+            Direction::Straight => Self::STRAIGHT_ID,
+        };
+        send_held(
+            glasses,
+            [
+                Command::LayoutClearAndDisplay {
+                    id: arrow,
+                    text: String::new(),
+                },
+                Command::LayoutClearAndDisplay {
+                    id: Self::DISTANCE_ID,
+                    text: Self::distance_text(meters),
+                },
+            ],
+        )
+    }
+}
+
+/// Heart rate, with its training zone below
+pub struct HeartRate;
+
+impl HeartRate {
+    /// Beats per minute, in the large font, followed by a fixed "bpm"
+    pub const BPM_ID: u8 = FIRST_LAYOUT_ID + 4;
+    /// Training zone
+    pub const ZONE_ID: u8 = FIRST_LAYOUT_ID + 5;
+
+    pub fn layouts() -> Vec<(u8, LayoutParameters)> {
+        vec![
+            (
+                Self::BPM_ID,
+                build(
+                    LayoutBuilder::new(LayoutPosition { x: 0, y: 60 }, 304, 60)
+                        .font(DefaultFont::ComputerModernSansSerif49.into())
+                        .text_at(LayoutPosition { x: 60, y: 5 })
+                        .command(LayoutCommand::Font {
+                            id: DefaultFont::ComputerModernSansSerif24.into(),
+                        })
+                        .text(Point { x: 200, y: 25 }, "bpm"),
+                ),
+            ),
+            (
+                Self::ZONE_ID,
+                build(
+                    LayoutBuilder::new(LayoutPosition { x: 0, y: 140 }, 304, 40)
+                        .font(DefaultFont::ComputerModernSansSerif35.into())
+                        .text_at(LayoutPosition { x: 60, y: 2 }),
+                ),
+            ),
+        ]
+    }
+
+    /// Display `bpm` and training `zone`
+    pub fn show<G: GlassesApi + ?Sized>(
+        glasses: &mut G,
+        bpm: u8,
+        zone: u8,
+    ) -> Result<(), GlassesError> {
+        send_held(
+            glasses,
+            [
+                Command::LayoutClearAndDisplay {
+                    id: Self::BPM_ID,
+                    text: bpm.to_string(),
+                },
+                Command::LayoutClearAndDisplay {
+                    id: Self::ZONE_ID,
+                    text: format!("Zone {}", zone),
+                },
+            ],
+        )
+    }
+}
+
+/// Phone notification: a title above a separator, then the body wrapped on several lines
+pub struct Notification;
+
+impl Notification {
+    /// Whole notification area, cleared before each notification, drawing the separator
+    pub const FRAME_ID: u8 = FIRST_LAYOUT_ID + 6;
+    /// Title, in the medium font
+    pub const TITLE_ID: u8 = FIRST_LAYOUT_ID + 7;
+    /// One line of the body, moved down for each line
+    pub const BODY_ID: u8 = FIRST_LAYOUT_ID + 8;
+
+    const LEFT: u16 = 10;
+    const WIDTH: u16 = 284;
+    const TOP: u8 = 20;
+    const BODY_TOP: u8 = Self::TOP + 50;
+    /// Lines of the body fitting below the title
+    pub const MAX_BODY_LINES: usize = 6;
+
+    fn body_font() -> FontItem {
+        FontItem {
+            id: DefaultFont::ComputerModernSansSerif24.into(),
+            height: 24,
+        }
+    }
+
+    pub fn layouts() -> Vec<(u8, LayoutParameters)> {
+        vec![
+            (
+                Self::FRAME_ID,
+                build(
+                    LayoutBuilder::new(
+                        LayoutPosition {
+                            x: Self::LEFT,
+                            y: Self::TOP,
+                        },
+                        Self::WIDTH,
+                        216,
+                    )
+                    .line(Point { x: 0, y: 44 }, Point { x: 283, y: 44 }),
+                ),
+            ),
+            (
+                Self::TITLE_ID,
+                build(
+                    LayoutBuilder::new(
+                        LayoutPosition {
+                            x: Self::LEFT,
+                            y: Self::TOP,
+                        },
+                        Self::WIDTH,
+                        40,
+                    )
+                    .font(DefaultFont::ComputerModernSansSerif35.into())
+                    .text_at(LayoutPosition { x: 0, y: 2 }),
+                ),
+            ),
+            (
+                Self::BODY_ID,
+                build(
+                    LayoutBuilder::new(
+                        LayoutPosition {
+                            x: Self::LEFT,
+                            y: Self::BODY_TOP,
+                        },
+                        Self::WIDTH,
+                        Self::body_font().height,
+                    )
+                    .font(Self::body_font().id)
+                    .text_at(LayoutPosition { x: 0, y: 0 }),
+                ),
+            ),
+        ]
+    }
+
+    /// Display a notification. The body is wrapped, and cut after [Self::MAX_BODY_LINES].
+    pub fn show<G: GlassesApi + ?Sized>(
+        glasses: &mut G,
+        title: &str,
+        body: &str,
+    ) -> Result<(), GlassesError> {
+        let body_pos = LayoutPosition {
+            x: Self::LEFT,
+            y: Self::BODY_TOP,
+        };
+        let lines = TextWrap::new(Self::body_font(), Self::WIDTH)
+            .line_spacing(4)
+            .layout_commands(Self::BODY_ID, body_pos, body);
+        let commands = [
+            Command::LayoutClear { id: Self::FRAME_ID },
+            Command::LayoutDisplay {
+                id: Self::FRAME_ID,
+                text: String::new(),
+            },
+            Command::LayoutDisplay {
+                id: Self::TITLE_ID,
+                text: String::from(title),
+            },
+        ];
+        send_held(
+            glasses,
+            commands
+                .into_iter()
+                .chain(lines.into_iter().take(Self::MAX_BODY_LINES)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{glasses::Preview, mock::MockClient};
+
+    #[test]
+    fn test_templates() {
+        let layouts = layouts();
+        let ids: Vec<u8> = layouts.iter().map(|(id, _)| *id).collect();
+        assert_eq!(
+            (FIRST_LAYOUT_ID..FIRST_LAYOUT_ID + 9).collect::<Vec<_>>(),
+            ids
+        );
+        let config = add_to(ConfigBuilder::new("templates", 1, 0))
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(9, config.elements().len());
+
+        let mut mock = MockClient::new();
+        for (id, params) in layouts {
+            mock.expect(Command::LayoutSave { id, params });
+        }
+        install(&mut mock).unwrap();
+        mock.verify();
+
+        assert_eq!("250 m", Navigation::distance_text(250));
+        assert_eq!("1.2 km", Navigation::distance_text(1250));
+        assert_eq!("12 km", Navigation::distance_text(12_500));
+
+        let mut preview = Preview::new();
+        Navigation::show(&mut preview, Direction::Left, 250).unwrap();
+        assert_eq!(
+            Command::LayoutClearAndDisplay {
+                id: Navigation::DISTANCE_ID,
+                text: String::from("250 m"),
+            },
+            preview.displayed()[2]
+        );
+
+        let mut preview = Preview::new();
+        let body = "word ".repeat(200);
+        Notification::show(&mut preview, "Message", &body).unwrap();
+        // Hold, frame, title, body lines and Flush
+        assert_eq!(5 + Notification::MAX_BODY_LINES, preview.displayed().len());
+    }
+}
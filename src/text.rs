@@ -0,0 +1,236 @@
+//! Text wrapping
+//!
+//! The firmware draws a single line per [Command::Txt] or layout display. [TextWrap] splits a
+//! paragraph in lines fitting a clipping width, and positions one command per line.
+//!
+//! The firmware only reports the height of its fonts, in [Response::FontList]: the width of the
+//! glyphs is estimated to half of the height, like the [crate::framebuffer::Framebuffer]
+//! rendering. Set the actual advance of a monospace font with [TextWrap::char_width].
+//!
+//! Lines are stacked towards increasing y, for the usual [TextRotation::TOP_LR] direction, and the
+//! lines past the bottom of the display are dropped. With other rotations, position the
+//! [TextWrap::lines] yourself.
+//!
+//! [Response::FontList]: crate::commands::Response::FontList
+use crate::{
+    commands::{Command, FontItem, Grey, LayoutPosition, Point, TextRotation},
+    display,
+};
+
+/// Splits text in lines of a given width
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TextWrap {
+    font: u8,
+    font_height: u8,
+    width: u16,
+    char_width: u16,
+    line_spacing: u8,
+    color: Grey,
+}
+
+impl TextWrap {
+    /// Lines of at most `width` pixels, drawn with `font` as listed by
+    /// [crate::commands::Response::FontList].
+    /// By default, lines are separated by 2 pixels, and drawn in white.
+    pub fn new(font: FontItem, width: u16) -> Self {
+        Self {
+            font: font.id,
+            font_height: font.height,
+            width,
+            char_width: (font.height as u16 / 2).max(1),
+            line_spacing: 2,
+            color: Grey::WHITE,
+        }
+    }
+
+    /// Width of a character, in pixels
+    pub fn char_width(mut self, width: u16) -> Self {
+        self.char_width = width.max(1);
+        self
+    }
+
+    /// Pixels between two lines
+    pub fn line_spacing(mut self, spacing: u8) -> Self {
+        self.line_spacing = spacing;
+        self
+    }
+
+    /// Color of the [Command::Txt] commands
+    pub fn color(mut self, color: Grey) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Distance between the tops of two consecutive lines
+    pub fn line_height(&self) -> u16 {
+        self.font_height as u16 + self.line_spacing as u16
+    }
+
+    /// Number of characters fitting in a line, at least one
+    pub fn max_chars(&self) -> usize {
+        (self.width / self.char_width).max(1) as usize
+    }
+
+    /// Split `text` in lines, breaking between words. Words longer than a line are cut.
+    /// Line feeds start a new line, and empty lines are kept.
+    pub fn lines(&self, text: &str) -> Vec<String> {
+        let max_chars = self.max_chars();
+        let mut lines = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut line = String::new();
+            let mut line_len = 0;
+            for word in paragraph.split_whitespace() {
+                let chars: Vec<char> = word.chars().collect();
+                if line_len > 0 && line_len + 1 + chars.len() <= max_chars {
+                    line.push(' ');
+                    line.push_str(word);
+                    line_len += 1 + chars.len();
+                    continue;
+                }
+                if line_len > 0 {
+                    lines.push(core::mem::take(&mut line));
+                }
+                let mut rest = &chars[..];
+                while rest.len() > max_chars {
+                    lines.push(rest[..max_chars].iter().collect());
+                    rest = &rest[max_chars..];
+                }
+                line = rest.iter().collect();
+                line_len = rest.len();
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    /// Offset of line `index` from the first line, if it fits the coordinates
+    fn line_offset(&self, index: usize) -> Option<i16> {
+        let offset = u16::try_from(index).ok()?.checked_mul(self.line_height())?;
+        i16::try_from(offset).ok()
+    }
+
+    /// [Command::Txt] for each line of `text`, the first one at `pos`, until the bottom of the
+    /// display
+    pub fn txt_commands(&self, pos: Point, text: &str) -> Vec<Command> {
+        self.lines(text)
+            .into_iter()
+            .enumerate()
+            .map_while(|(i, line)| {
+                let y = pos.y.checked_add(self.line_offset(i)?)?;
+                (y < display::HEIGHT).then_some((y, line))
+            })
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(y, string)| Command::Txt {
+                pos: Point { x: pos.x, y },
+                rotation: TextRotation::TOP_LR,
+                font_size: self.font,
+                color: self.color,
+                string,
+            })
+            .collect()
+    }
+
+    /// [Command::LayoutDisplayExtended] of layout `id` for each line of `text`, the first one at
+    /// `pos`, until the bottom of the display. The layout should use the same font, and a
+    /// clipping region of the same width.
+    pub fn layout_commands(&self, id: u8, pos: LayoutPosition, text: &str) -> Vec<Command> {
+        self.lines(text)
+            .into_iter()
+            .enumerate()
+            .map_while(|(i, line)| {
+                let y = (pos.y as i16).checked_add(self.line_offset(i)?)?;
+                Some((u8::try_from(y).ok()?, line))
+            })
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(y, text)| Command::LayoutDisplayExtended {
+                id,
+                pos: LayoutPosition { x: pos.x, y },
+                text,
+                extra_cmd: Vec::new(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        // 10 characters per line
+        let wrap = TextWrap::new(FontItem { id: 1, height: 24 }, 120);
+        assert_eq!(10, wrap.max_chars());
+        assert_eq!(
+            vec![
+                "Turn left",
+                "in 200 m",
+                "",
+                "Roundabout",
+                "xit_number",
+                "_2"
+            ],
+            wrap.lines("Turn left in 200 m\n\nRoundabout xit_number_2")
+        );
+
+        let cmds = wrap
+            .clone()
+            .color(Grey::from(8))
+            .txt_commands(Point { x: 10, y: 20 }, "Turn left\n\nnow");
+        assert_eq!(
+            vec![
+                Command::Txt {
+                    pos: Point { x: 10, y: 20 },
+                    rotation: TextRotation::TOP_LR,
+                    font_size: 1,
+                    color: Grey::from(8),
+                    string: String::from("Turn left"),
+                },
+                Command::Txt {
+                    pos: Point { x: 10, y: 72 },
+                    rotation: TextRotation::TOP_LR,
+                    font_size: 1,
+                    color: Grey::from(8),
+                    string: String::from("now"),
+                },
+            ],
+            cmds
+        );
+
+        let cmds = wrap.char_width(20).line_spacing(0).layout_commands(
+            3,
+            LayoutPosition { x: 0, y: 240 },
+            "a b c d e f g",
+        );
+        // The next lines are past the bottom of the display
+        assert_eq!(
+            vec![Command::LayoutDisplayExtended {
+                id: 3,
+                pos: LayoutPosition { x: 0, y: 240 },
+                text: String::from("a b c"),
+                extra_cmd: Vec::new(),
+            }],
+            cmds
+        );
+    }
+
+    #[test]
+    fn test_tall_font() {
+        let wrap = TextWrap::new(FontItem { id: 1, height: 200 }, 304).line_spacing(200);
+        let text = vec!["a"; 200].join("\n");
+        let cmds = wrap.txt_commands(Point { x: 0, y: 10 }, &text);
+        assert_eq!(1, cmds.len());
+        let cmds = wrap.txt_commands(Point { x: 0, y: -390 }, &text);
+        assert_eq!(
+            vec![Point { x: 0, y: -390 }, Point { x: 0, y: 10 }],
+            cmds.iter()
+                .map(|cmd| match cmd {
+                    Command::Txt { pos, .. } => *pos,
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        );
+        let cmds = wrap.layout_commands(3, LayoutPosition { x: 0, y: 10 }, &text);
+        assert_eq!(1, cmds.len());
+    }
+}
@@ -0,0 +1,88 @@
+//! Time sources
+//!
+//! Helpers depending on time take a [Clock], so they work on std and embedded targets, and can be
+//! tested with a [VirtualClock]. Helpers which wait take a [Delay].
+use core::time::Duration;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Instant;
+
+/// Monotonic time source
+pub trait Clock {
+    /// Time elapsed since an arbitrary origin
+    fn now(&self) -> Duration;
+}
+
+/// Blocking wait. Implement it with the delay of your HAL or executor on embedded targets.
+pub trait Delay {
+    fn delay(&mut self, duration: Duration);
+}
+
+/// [Delay] sleeping the current thread
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StdDelay;
+
+impl Delay for StdDelay {
+    fn delay(&mut self, duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// [Clock] based on [std::time::Instant]
+#[derive(Copy, Clone, Debug)]
+pub struct StdClock {
+    origin: Instant,
+}
+
+impl StdClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for StdClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// [Clock] advanced manually. Clones share the same time.
+#[derive(Clone, Debug, Default)]
+pub struct VirtualClock {
+    micros: Arc<AtomicU64>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the time forward
+    pub fn advance(&self, duration: Duration) {
+        self.micros
+            .fetch_add(duration.as_micros() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        Duration::from_micros(self.micros.load(Ordering::SeqCst))
+    }
+}
+
+/// Waiting moves the time forward, without sleeping
+impl Delay for VirtualClock {
+    fn delay(&mut self, duration: Duration) {
+        self.advance(duration)
+    }
+}
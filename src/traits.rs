@@ -1,6 +1,11 @@
 //! Traits used in the crate
+use std::borrow::Cow;
+
 use deku::prelude::*;
 
+/// Command or response ID, and data chunks returned by [Serializable::chunks]
+pub type Chunks<'a> = (u8, Vec<Cow<'a, [u8]>>);
+
 /// Serialize to a bytestream
 pub trait Serializable: Clone {
     /// Returns the ID of the [Command] or [Response]
@@ -16,6 +21,26 @@ pub trait Serializable: Clone {
     /// Use this function to split the byte representation into smaller chunks. This is useful to
     /// send bigger images to the ActiveLook glasses.
     fn as_bytes_chunks(&self, chunk_size: usize) -> Result<(u8, Vec<Vec<u8>>), DekuError>;
+
+    /// Same as [Serializable::as_bytes_chunks], borrowing the data when possible.
+    ///
+    /// The default implementation allocates, [Command] borrows the image and font data.
+    fn chunks(&self, chunk_size: usize) -> Result<Chunks<'_>, DekuError> {
+        let (id, chunks) = self.as_bytes_chunks(chunk_size)?;
+        Ok((id, chunks.into_iter().map(Cow::Owned).collect()))
+    }
+
+    /// Write the data bytes into `buf`, returning their length.
+    /// Fails with [DekuError::Io] if `buf` is too small.
+    ///
+    /// The default implementation allocates, [Command] and [Response] write directly into `buf`.
+    fn write_data_into(&self, buf: &mut [u8]) -> Result<usize, DekuError> {
+        let data = self.data_bytes()?;
+        buf.get_mut(..data.len())
+            .ok_or(DekuError::Io(std::io::ErrorKind::WriteZero))?
+            .copy_from_slice(&data);
+        Ok(data.len())
+    }
 }
 
 /// Deserialize from a bytestream
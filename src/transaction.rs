@@ -0,0 +1,148 @@
+//! Batches of drawing commands displayed at once
+//!
+//! [DisplayTransaction] holds the graphic engine while drawing, so the whole batch appears at
+//! once without flickering. The matching flush is sent when the transaction is committed or
+//! dropped. If a command failed, the glasses state is unknown: [HoldFlushAction::ResetFlush] is
+//! sent instead, releasing every pending hold.
+
+use crate::{
+    commands::{Command, HoldFlushAction, Response},
+    glasses::{GlassesApi, GlassesError},
+};
+
+/// Guard flushing the graphic engine when dropped, see [GlassesApi::transaction].
+///
+/// It implements [GlassesApi], so every helper can be used inside the transaction.
+pub struct DisplayTransaction<'a, G: GlassesApi + ?Sized> {
+    glasses: &'a mut G,
+    failed: bool,
+    done: bool,
+}
+
+impl<'a, G: GlassesApi + ?Sized> DisplayTransaction<'a, G> {
+    /// Hold the graphic engine until the transaction ends
+    pub fn begin(glasses: &'a mut G) -> Result<Self, GlassesError> {
+        glasses.hold_flush(HoldFlushAction::Hold)?;
+        Ok(Self {
+            glasses,
+            failed: false,
+            done: false,
+        })
+    }
+
+    /// Display the batch. Returns an error if the flush could not be sent.
+    pub fn commit(mut self) -> Result<(), GlassesError> {
+        self.done = true;
+        self.glasses.hold_flush(self.end_action())
+    }
+
+    fn end_action(&self) -> HoldFlushAction {
+        match self.failed {
+            true => HoldFlushAction::ResetFlush,
+            false => HoldFlushAction::Flush,
+        }
+    }
+
+    fn track<T>(&mut self, res: Result<T, GlassesError>) -> Result<T, GlassesError> {
+        if res.is_err() {
+            self.failed = true;
+        }
+        res
+    }
+}
+
+impl<G: GlassesApi + ?Sized> GlassesApi for DisplayTransaction<'_, G> {
+    fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        let res = self.glasses.send(cmd);
+        self.track(res)
+    }
+
+    fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+        let res = self.glasses.query(cmd);
+        self.track(res)
+    }
+
+    fn send_chunked(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+        let res = self.glasses.send_chunked(cmd);
+        self.track(res)
+    }
+}
+
+impl<G: GlassesApi + ?Sized> Drop for DisplayTransaction<'_, G> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.glasses.hold_flush(self.end_action());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{Grey, Point, TextRotation};
+
+    /// Records the sent commands, and fails on [Command::Clear]
+    #[derive(Default)]
+    struct Log(Vec<Command>);
+
+    impl GlassesApi for Log {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            if let Command::Clear = cmd {
+                return Err(GlassesError::Unsupported);
+            }
+            self.0.push(cmd.clone());
+            Ok(())
+        }
+
+        fn query(&mut self, _cmd: &Command) -> Result<Response, GlassesError> {
+            Err(GlassesError::Unsupported)
+        }
+    }
+
+    fn hold_flush(action: HoldFlushAction) -> Command {
+        Command::HoldFlush { action }
+    }
+
+    #[test]
+    fn test_transaction() {
+        let mut glasses = Log::default();
+        let mut transaction = glasses.transaction().unwrap();
+        transaction
+            .text(
+                Point { x: 1, y: 2 },
+                TextRotation::TOP_LR,
+                1,
+                Grey::WHITE,
+                "a",
+            )
+            .unwrap();
+        transaction.commit().unwrap();
+        assert_eq!(3, glasses.0.len());
+        assert_eq!(hold_flush(HoldFlushAction::Hold), glasses.0[0]);
+        assert_eq!(hold_flush(HoldFlushAction::Flush), glasses.0[2]);
+
+        // Flushed on drop
+        glasses.0.clear();
+        {
+            let mut transaction = glasses.transaction().unwrap();
+            transaction.layout_display(1, "b").unwrap();
+        }
+        assert_eq!(Some(&hold_flush(HoldFlushAction::Flush)), glasses.0.last());
+    }
+
+    #[test]
+    fn test_transaction_error() {
+        let mut glasses = Log::default();
+        let draw = |glasses: &mut Log| -> Result<(), GlassesError> {
+            let mut transaction = glasses.transaction()?;
+            transaction.layout_display(1, "b")?;
+            transaction.clear()?;
+            transaction.commit()
+        };
+        assert_eq!(Err(GlassesError::Unsupported), draw(&mut glasses));
+        assert_eq!(
+            Some(&hold_flush(HoldFlushAction::ResetFlush)),
+            glasses.0.last()
+        );
+    }
+}
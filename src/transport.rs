@@ -0,0 +1,36 @@
+//! Transports connecting [crate::client::ActiveLookClient] to ActiveLook glasses
+//!
+//! The client only needs [embedded_io::Read] / [embedded_io::Write] implementations for each
+//! characteristic of the ActiveLook commands interface. [loopback] connects it to an
+//! [crate::server::ActiveLookServer] in memory.
+
+#[cfg(feature = "btleplug")]
+pub mod btleplug;
+#[cfg(feature = "embassy")]
+pub mod embassy;
+pub mod loopback;
+#[cfg(all(feature = "wasm", web_sys_unstable_apis))]
+pub mod web_bluetooth;
+#[cfg(all(feature = "wasm", not(web_sys_unstable_apis)))]
+compile_error!(
+    "The wasm feature needs the unstable Web Bluetooth bindings of web-sys: \
+     build with RUSTFLAGS=\"--cfg=web_sys_unstable_apis\""
+);
+
+/// ActiveLook commands interface GATT service
+pub const ACTIVELOOK_SERVICE_UUID: u128 = 0x0783b03e_8535_b5a0_7140_a304d2495cb7;
+/// Tx server: the glasses notify responses on this characteristic
+pub const TX_CHARACTERISTIC_UUID: u128 = 0x0783b03e_8535_b5a0_7140_a304d2495cb8;
+/// Rx server: commands are written on this characteristic
+pub const RX_CHARACTERISTIC_UUID: u128 = 0x0783b03e_8535_b5a0_7140_a304d2495cba;
+/// Control server: the glasses notify flow control values on this characteristic
+pub const CONTROL_CHARACTERISTIC_UUID: u128 = 0x0783b03e_8535_b5a0_7140_a304d2495cb9;
+/// Gesture event characteristic
+pub const GESTURE_CHARACTERISTIC_UUID: u128 = 0x0783b03e_8535_b5a0_7140_a304d2495cbb;
+/// Touch event characteristic
+pub const TOUCH_CHARACTERISTIC_UUID: u128 = 0x0783b03e_8535_b5a0_7140_a304d2495cbc;
+
+/// Default ATT MTU, before any negotiation
+pub const DEFAULT_MTU: usize = 23;
+/// ATT header taking room in each BLE write
+pub const ATT_HEADER_LEN: usize = 3;
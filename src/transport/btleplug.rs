@@ -0,0 +1,216 @@
+//! [btleplug] transport, for desktop applications
+//!
+//! ```ignore
+//! let connection = connect(peripheral, ConnectionConfig::default()).await?;
+//! // From a thread outside of the tokio runtime
+//! let client = ActiveLookClient::new(connection.tx, connection.rx, connection.ctrl);
+//! ```
+//!
+//! The readers and writers are blocking: they must not be used from within the tokio runtime.
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+
+use ::btleplug::{
+    api::{Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write};
+use futures::StreamExt;
+use log::*;
+use thiserror::Error;
+use tokio::runtime::Handle;
+use uuid::Uuid;
+
+use super::{
+    ACTIVELOOK_SERVICE_UUID, ATT_HEADER_LEN, CONTROL_CHARACTERISTIC_UUID, DEFAULT_MTU,
+    RX_CHARACTERISTIC_UUID, TX_CHARACTERISTIC_UUID,
+};
+
+/// Errors returned by the btleplug transport
+#[derive(Error, Debug)]
+pub enum TransportError {
+    /// Error coming from [btleplug]
+    #[error(transparent)]
+    Ble(#[from] ::btleplug::Error),
+    /// The peripheral does not expose the ActiveLook commands interface
+    #[error("Missing characteristic {0}")]
+    MissingCharacteristic(Uuid),
+    /// The notification stream ended
+    #[error("Disconnected")]
+    Disconnected,
+}
+
+impl embedded_io::Error for TransportError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            TransportError::Disconnected => ErrorKind::NotConnected,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Connection options
+#[derive(Copy, Clone, Debug)]
+pub struct ConnectionConfig {
+    /// ATT MTU used to split the writes.
+    /// The MTU is negotiated by the OS Bluetooth stack and btleplug does not expose it: set the
+    /// value negotiated by your platform to send bigger writes.
+    pub mtu: usize,
+    /// Write type used for commands
+    pub write_type: WriteType,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            mtu: DEFAULT_MTU,
+            write_type: WriteType::WithoutResponse,
+        }
+    }
+}
+
+/// Endpoints of a connection to ActiveLook glasses, to be given to
+/// [crate::client::ActiveLookClient::new]
+pub struct Connection {
+    /// Notifications of the Tx characteristic
+    pub tx: NotificationReader,
+    /// Writes to the Rx characteristic
+    pub rx: CharacteristicWriter,
+    /// Notifications of the Control characteristic
+    pub ctrl: NotificationReader,
+}
+
+/// Connect to `peripheral`, discover the ActiveLook commands interface and subscribe to its
+/// notifications.
+pub async fn connect(
+    peripheral: Peripheral,
+    config: ConnectionConfig,
+) -> Result<Connection, TransportError> {
+    if !peripheral.is_connected().await? {
+        peripheral.connect().await?;
+    }
+    peripheral.discover_services().await?;
+
+    let find = |uuid: u128| -> Result<Characteristic, TransportError> {
+        let uuid = Uuid::from_u128(uuid);
+        peripheral
+            .characteristics()
+            .into_iter()
+            .find(|c| c.uuid == uuid && c.service_uuid == Uuid::from_u128(ACTIVELOOK_SERVICE_UUID))
+            .ok_or(TransportError::MissingCharacteristic(uuid))
+    };
+    let tx_char = find(TX_CHARACTERISTIC_UUID)?;
+    let rx_char = find(RX_CHARACTERISTIC_UUID)?;
+    let ctrl_char = find(CONTROL_CHARACTERISTIC_UUID)?;
+
+    peripheral.subscribe(&tx_char).await?;
+    peripheral.subscribe(&ctrl_char).await?;
+
+    // Dispatch notifications to the readers
+    let mut notifications = peripheral.notifications().await?;
+    let (tx_sender, tx_receiver) = mpsc::channel();
+    let (ctrl_sender, ctrl_receiver) = mpsc::channel();
+    tokio::spawn(async move {
+        while let Some(notification) = notifications.next().await {
+            let sender: &Sender<Vec<u8>> = if notification.uuid == tx_char.uuid {
+                &tx_sender
+            } else if notification.uuid == ctrl_char.uuid {
+                &ctrl_sender
+            } else {
+                continue;
+            };
+            if sender.send(notification.value).is_err() {
+                break;
+            }
+        }
+        debug!("Notification stream ended");
+    });
+
+    Ok(Connection {
+        tx: NotificationReader::new(tx_receiver),
+        rx: CharacteristicWriter {
+            peripheral,
+            characteristic: rx_char,
+            handle: Handle::current(),
+            config,
+        },
+        ctrl: NotificationReader::new(ctrl_receiver),
+    })
+}
+
+/// Blocking reader over the notifications of a characteristic
+pub struct NotificationReader {
+    receiver: Receiver<Vec<u8>>,
+    /// Part of the last notification which did not fit in the read buffer
+    pending: Vec<u8>,
+}
+
+impl NotificationReader {
+    fn new(receiver: Receiver<Vec<u8>>) -> Self {
+        Self {
+            receiver,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl ErrorType for NotificationReader {
+    type Error = TransportError;
+}
+
+impl Read for NotificationReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.pending.is_empty() {
+            self.pending = self
+                .receiver
+                .recv()
+                .map_err(|_| TransportError::Disconnected)?;
+        }
+        let len = self.pending.len().min(buf.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl ReadReady for NotificationReader {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        if self.pending.is_empty() {
+            match self.receiver.try_recv() {
+                Ok(value) => self.pending = value,
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => return Err(TransportError::Disconnected),
+            }
+        }
+        Ok(!self.pending.is_empty())
+    }
+}
+
+/// Blocking writer to a characteristic, splitting the data according to the MTU
+pub struct CharacteristicWriter {
+    peripheral: Peripheral,
+    characteristic: Characteristic,
+    handle: Handle,
+    config: ConnectionConfig,
+}
+
+impl ErrorType for CharacteristicWriter {
+    type Error = TransportError;
+}
+
+impl Write for CharacteristicWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let chunk_size = self.config.mtu.saturating_sub(ATT_HEADER_LEN).max(1);
+        for chunk in buf.chunks(chunk_size) {
+            self.handle.block_on(self.peripheral.write(
+                &self.characteristic,
+                chunk,
+                self.config.write_type,
+            ))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+//! [embassy_sync] transport, for ActiveLook compatible devices running
+//! [crate::server::ActiveLookServer]
+//!
+//! [ServerPipes] sits between the BLE stack and the server: the GATT event handler pushes the
+//! values written on the Rx characteristic, and async tasks send the bytes written by the server
+//! as Tx and Control notifications. With [nrf-softdevice](https://github.com/embassy-rs/nrf-softdevice):
+//!
+//! ```ignore
+//! static PIPES: ServerPipes<CriticalSectionRawMutex, PACKET_MAX_SIZE> = ServerPipes::new();
+//!
+//! #[nrf_softdevice::gatt_service(uuid = "0783b03e-8535-b5a0-7140-a304d2495cb7")]
+//! struct ActiveLookService {
+//!     #[characteristic(uuid = "0783b03e-8535-b5a0-7140-a304d2495cb8", notify)]
+//!     tx: Vec<u8, 244>,
+//!     #[characteristic(uuid = "0783b03e-8535-b5a0-7140-a304d2495cba", write, write_without_response)]
+//!     rx: Vec<u8, 244>,
+//!     #[characteristic(uuid = "0783b03e-8535-b5a0-7140-a304d2495cb9", notify)]
+//!     ctrl: Vec<u8, 1>,
+//! }
+//!
+//! // GATT server events
+//! gatt_server::run(&conn, &server, |event| {
+//!     if let ServerEvent::ActiveLook(ActiveLookServiceEvent::RxWrite(value)) = event {
+//!         PIPES.on_rx_write(&value);
+//!     }
+//! });
+//!
+//! // Notification task
+//! let mut buf = [0; 244];
+//! loop {
+//!     let len = PIPES.next_tx_notification(&mut buf[..mtu - ATT_HEADER_LEN]).await;
+//!     server.activelook.tx_notify(&conn, &Vec::from_slice(&buf[..len]).unwrap())?;
+//! }
+//!
+//! // Server task
+//! let (rx, tx, ctrl) = PIPES.endpoints();
+//! let mut server = ActiveLookServer::new(rx, tx, ctrl);
+//! loop {
+//!     match server.serve(&mut emulator) {
+//!         Err(ProtocolError::Empty) => PIPES.rx_ready().await,
+//!         res => res?,
+//!     }
+//! }
+//! ```
+//!
+//! The endpoints never block: reading returns 0 when no data is pending, and writing fails with
+//! [TransportError::Full] until the notification task makes room.
+use embassy_sync::{
+    blocking_mutex::raw::RawMutex,
+    pipe::{Pipe, TryReadError, TryWriteError},
+    signal::Signal,
+};
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write};
+use log::*;
+use thiserror::Error;
+
+/// Errors returned by the embassy transport
+#[derive(Error, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TransportError {
+    /// The notification task did not send the previous bytes yet
+    #[error("Pipe full")]
+    Full,
+}
+
+impl embedded_io::Error for TransportError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::OutOfMemory
+    }
+}
+
+/// Buffers of the Rx, Tx and Control characteristics, holding `N` bytes each
+pub struct ServerPipes<M: RawMutex, const N: usize> {
+    rx: Pipe<M, N>,
+    tx: Pipe<M, N>,
+    ctrl: Pipe<M, N>,
+    /// Raised on each Rx write, to wake up the server task
+    rx_written: Signal<M, ()>,
+}
+
+impl<M: RawMutex, const N: usize> Default for ServerPipes<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, const N: usize> ServerPipes<M, N> {
+    pub const fn new() -> Self {
+        Self {
+            rx: Pipe::new(),
+            tx: Pipe::new(),
+            ctrl: Pipe::new(),
+            rx_written: Signal::new(),
+        }
+    }
+
+    /// Rx reader, Tx writer and Control writer, in the order of
+    /// [crate::server::ActiveLookServer::new]
+    pub fn endpoints(
+        &self,
+    ) -> (
+        PipeReader<'_, M, N>,
+        PipeWriter<'_, M, N>,
+        PipeWriter<'_, M, N>,
+    ) {
+        (
+            PipeReader(&self.rx),
+            PipeWriter(&self.tx),
+            PipeWriter(&self.ctrl),
+        )
+    }
+
+    /// Tx reader, Rx writer and Control reader, in the order of
+    /// [crate::client::ActiveLookClient::new]. A client using them talks to the server of
+    /// [ServerPipes::endpoints] without any BLE stack, see [super::loopback] on std.
+    pub fn client_endpoints(
+        &self,
+    ) -> (
+        PipeReader<'_, M, N>,
+        PipeWriter<'_, M, N>,
+        PipeReader<'_, M, N>,
+    ) {
+        (
+            PipeReader(&self.tx),
+            PipeWriter(&self.rx),
+            PipeReader(&self.ctrl),
+        )
+    }
+
+    /// Value written by the central on the Rx characteristic.
+    /// Returns the number of bytes kept: the rest is dropped if the server is too slow.
+    pub fn on_rx_write(&self, value: &[u8]) -> usize {
+        let len = self.rx.try_write(value).unwrap_or(0);
+        self.rx_written.signal(());
+        if len < value.len() {
+            warn!("Rx pipe full, {} bytes dropped", value.len() - len);
+        }
+        len
+    }
+
+    /// Wait until some Rx bytes can be read by the server
+    pub async fn rx_ready(&self) {
+        if self.rx.is_empty() {
+            self.rx_written.wait().await;
+        }
+    }
+
+    /// Wait for the next Tx notification, of at most `buf.len()` bytes
+    pub async fn next_tx_notification(&self, buf: &mut [u8]) -> usize {
+        self.tx.read(buf).await
+    }
+
+    /// Wait for the next Control notification, of at most `buf.len()` bytes
+    pub async fn next_ctrl_notification(&self, buf: &mut [u8]) -> usize {
+        self.ctrl.read(buf).await
+    }
+}
+
+/// Server side of the Rx characteristic
+pub struct PipeReader<'p, M: RawMutex, const N: usize>(&'p Pipe<M, N>);
+
+impl<M: RawMutex, const N: usize> ErrorType for PipeReader<'_, M, N> {
+    type Error = TransportError;
+}
+
+impl<M: RawMutex, const N: usize> Read for PipeReader<'_, M, N> {
+    /// Returns 0 if no data is pending
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self.0.try_read(buf) {
+            Ok(len) => Ok(len),
+            Err(TryReadError::Empty) => Ok(0),
+        }
+    }
+}
+
+impl<M: RawMutex, const N: usize> ReadReady for PipeReader<'_, M, N> {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.0.is_empty())
+    }
+}
+
+/// Server side of the Tx and Control characteristics
+pub struct PipeWriter<'p, M: RawMutex, const N: usize>(&'p Pipe<M, N>);
+
+impl<M: RawMutex, const N: usize> ErrorType for PipeWriter<'_, M, N> {
+    type Error = TransportError;
+}
+
+impl<M: RawMutex, const N: usize> Write for PipeWriter<'_, M, N> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.0
+            .try_write(buf)
+            .map_err(|TryWriteError::Full| TransportError::Full)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+    use crate::{
+        client::ActiveLookClient,
+        commands::{Command, Response},
+        emulator::Emulator,
+        protocol::{Packet, ResponsePacket, PACKET_MAX_SIZE},
+        server::ActiveLookServer,
+    };
+
+    #[test]
+    fn test_serve_through_pipes() {
+        let pipes = ServerPipes::<NoopRawMutex, PACKET_MAX_SIZE>::new();
+        let (rx, tx, ctrl) = pipes.endpoints();
+        let mut server = ActiveLookServer::new(rx, tx, ctrl);
+        let mut emulator = Emulator::new();
+
+        // Query split across two BLE writes
+        let bytes = Packet::new(&Command::Battery).unwrap().to_bytes();
+        let (first, second) = bytes.split_at(3);
+        assert_eq!(3, pipes.on_rx_write(first));
+        assert!(server.read_data().is_err());
+        pipes.on_rx_write(second);
+        server.serve(&mut emulator).unwrap();
+
+        let mut buf = [0; PACKET_MAX_SIZE];
+        let len = pipes.tx.try_read(&mut buf).unwrap();
+        let response = ResponsePacket::from_bytes(&buf[..len]).unwrap();
+        assert!(matches!(response.data, Response::Battery { .. }));
+    }
+
+    #[test]
+    fn test_client_endpoints() {
+        let pipes = ServerPipes::<NoopRawMutex, PACKET_MAX_SIZE>::new();
+        let (rx, tx, ctrl) = pipes.endpoints();
+        let mut server = ActiveLookServer::new(rx, tx, ctrl);
+        let (tx, rx, ctrl) = pipes.client_endpoints();
+        let mut client = ActiveLookClient::new(tx, rx, ctrl);
+        let mut emulator = Emulator::new();
+
+        let query_id = client.send_query(&Command::Battery).unwrap();
+        server.serve(&mut emulator).unwrap();
+        let response = client.wait_response(query_id).unwrap();
+        assert!(matches!(response, Response::Battery { .. }));
+    }
+
+    #[test]
+    fn test_full_pipe() {
+        let pipes = ServerPipes::<NoopRawMutex, 4>::new();
+        assert_eq!(4, pipes.on_rx_write(&[1, 2, 3, 4, 5]));
+        let (_, mut tx, _) = pipes.endpoints();
+        assert_eq!(Ok(4), tx.write(&[0; 8]));
+        assert_eq!(Err(TransportError::Full), tx.write(&[0]));
+    }
+}
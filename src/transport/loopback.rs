@@ -0,0 +1,165 @@
+//! In-memory transport, connecting a client to a server in the same process
+//!
+//! [loopback] wires an [crate::client::ActiveLookClient] to an
+//! [crate::server::ActiveLookServer], for tests and examples running without glasses. The
+//! endpoints can be moved to different threads.
+//!
+//! ```
+//! use activelook_rs::{
+//!     client::ActiveLookClient, commands::{Command, Response}, emulator::Emulator,
+//!     server::ActiveLookServer, transport::loopback::loopback,
+//! };
+//!
+//! let (client, server) = loopback();
+//! let mut client = ActiveLookClient::new(client.tx, client.rx, client.ctrl);
+//! let mut server = ActiveLookServer::new(server.rx, server.tx, server.ctrl);
+//! let mut emulator = Emulator::new();
+//!
+//! let query_id = client.send_query(&Command::Battery).unwrap();
+//! server.serve(&mut emulator).unwrap();
+//! let response = client.wait_response(query_id).unwrap();
+//! assert!(matches!(response, Response::Battery { .. }));
+//! ```
+//!
+//! Without std, [super::embassy::ServerPipes::client_endpoints] provides the same wiring.
+use core::convert::Infallible;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+
+type Queue = Arc<Mutex<VecDeque<u8>>>;
+
+/// Receiving end of a characteristic. Reading returns 0 when no data is pending.
+#[derive(Clone, Debug, Default)]
+pub struct LoopbackReader(Queue);
+
+impl ErrorType for LoopbackReader {
+    type Error = Infallible;
+}
+
+impl Read for LoopbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let mut queue = self.0.lock().unwrap();
+        let len = buf.len().min(queue.len());
+        for (byte, value) in buf.iter_mut().zip(queue.drain(..len)) {
+            *byte = value;
+        }
+        Ok(len)
+    }
+}
+
+impl ReadReady for LoopbackReader {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.0.lock().unwrap().is_empty())
+    }
+}
+
+/// Sending end of a characteristic
+#[derive(Clone, Debug, Default)]
+pub struct LoopbackWriter(Queue);
+
+impl ErrorType for LoopbackWriter {
+    type Error = Infallible;
+}
+
+impl Write for LoopbackWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.lock().unwrap().extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Endpoints given to [crate::client::ActiveLookClient::new]
+pub struct ClientEndpoints {
+    /// Notifications of the Tx characteristic
+    pub tx: LoopbackReader,
+    /// Writes to the Rx characteristic
+    pub rx: LoopbackWriter,
+    /// Notifications of the Control characteristic
+    pub ctrl: LoopbackReader,
+}
+
+/// Endpoints given to [crate::server::ActiveLookServer::new]
+pub struct ServerEndpoints {
+    /// Writes received on the Rx characteristic
+    pub rx: LoopbackReader,
+    /// Notifications of the Tx characteristic
+    pub tx: LoopbackWriter,
+    /// Notifications of the Control characteristic
+    pub ctrl: LoopbackWriter,
+}
+
+fn link() -> (LoopbackReader, LoopbackWriter) {
+    let queue = Queue::default();
+    (LoopbackReader(queue.clone()), LoopbackWriter(queue))
+}
+
+/// Client and server endpoints of the three characteristics, connected to each other
+pub fn loopback() -> (ClientEndpoints, ServerEndpoints) {
+    let (server_rx, client_rx) = link();
+    let (client_tx, server_tx) = link();
+    let (client_ctrl, server_ctrl) = link();
+    (
+        ClientEndpoints {
+            tx: client_tx,
+            rx: client_rx,
+            ctrl: client_ctrl,
+        },
+        ServerEndpoints {
+            rx: server_rx,
+            tx: server_tx,
+            ctrl: server_ctrl,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::ActiveLookClient,
+        commands::{Command, Response},
+        emulator::Emulator,
+        protocol::FlowErrorCtrl,
+        server::ActiveLookServer,
+    };
+
+    #[test]
+    fn test_loopback_threads() {
+        let (client, server) = loopback();
+        let mut ctrl = server.ctrl.clone();
+        let mut client = ActiveLookClient::new(client.tx, client.rx, client.ctrl);
+        let mut server = ActiveLookServer::new(server.rx, server.tx, server.ctrl);
+
+        let serving = std::thread::spawn(move || {
+            let mut emulator = Emulator::new();
+            let mut nb_served = 0;
+            while nb_served < 2 {
+                match server.serve(&mut emulator) {
+                    Ok(()) => nb_served += 1,
+                    Err(_) => std::thread::yield_now(),
+                }
+            }
+        });
+        let flow = [
+            FlowErrorCtrl::ClientShouldWait as u8,
+            FlowErrorCtrl::ClientCanSend as u8,
+        ];
+        ctrl.write_all(&flow).unwrap();
+        let response = client.send_command_expect_response(&Command::Battery);
+        assert!(
+            matches!(response, Ok(Response::Battery { .. })),
+            "{:?}",
+            response
+        );
+        client.send(&Command::Clear).unwrap();
+        serving.join().unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+//! Web Bluetooth transport, for web applications compiled to WASM
+//!
+//! ```ignore
+//! // From the handler of a user gesture, as required by the browser
+//! let device = request_device().await?;
+//! let connection = connect(&device, ConnectionConfig::default()).await?;
+//! let mut client = ActiveLookClient::new(connection.tx, connection.rx, connection.ctrl);
+//! let battery = query(&mut client, &Command::Battery).await?;
+//! ```
+//!
+//! The browser runs the application on a single thread, and delivers notifications only while
+//! it awaits. The readers never block: they return 0 when no notification is pending, and the
+//! client returns [ProtocolError::Empty]. Wait for responses with [query] or [wait_response],
+//! which yield to the browser between reads, instead of the blocking
+//! [ActiveLookClient::wait_response]. Writes are queued, and sent in order by a background task.
+//!
+//! The Web Bluetooth bindings of `web-sys` are unstable: build with
+//! `RUSTFLAGS="--cfg=web_sys_unstable_apis"`.
+use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+use embedded_io::{ErrorKind, ErrorType, Read, ReadReady, Write};
+use js_sys::{Promise, Uint8Array};
+use log::*;
+use thiserror::Error;
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{
+    BluetoothDevice, BluetoothLeScanFilterInit, BluetoothRemoteGattCharacteristic, Event,
+    RequestDeviceOptions,
+};
+
+use super::{
+    ACTIVELOOK_SERVICE_UUID, ATT_HEADER_LEN, CONTROL_CHARACTERISTIC_UUID, DEFAULT_MTU,
+    RX_CHARACTERISTIC_UUID, TX_CHARACTERISTIC_UUID,
+};
+use crate::{
+    client::ActiveLookClient,
+    commands::{Command, Response},
+    protocol::ProtocolError,
+};
+
+/// Errors returned by the Web Bluetooth transport
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum TransportError {
+    /// Exception raised by the browser
+    #[error("{0}")]
+    Js(String),
+    /// The browser does not support Web Bluetooth
+    #[error("Web Bluetooth is not available")]
+    Unavailable,
+    /// The glasses are not connected
+    #[error("Disconnected")]
+    Disconnected,
+}
+
+impl From<JsValue> for TransportError {
+    fn from(value: JsValue) -> Self {
+        TransportError::Js(format!("{:?}", value))
+    }
+}
+
+impl embedded_io::Error for TransportError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            TransportError::Disconnected => ErrorKind::NotConnected,
+            TransportError::Unavailable => ErrorKind::Unsupported,
+            TransportError::Js(_) => ErrorKind::Other,
+        }
+    }
+}
+
+/// Connection options
+#[derive(Copy, Clone, Debug)]
+pub struct ConnectionConfig {
+    /// ATT MTU used to split the writes.
+    /// Web Bluetooth does not expose the MTU negotiated by the browser: set the value negotiated
+    /// by your platform to send bigger writes.
+    pub mtu: usize,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self { mtu: DEFAULT_MTU }
+    }
+}
+
+/// Endpoints of a connection to ActiveLook glasses, to be given to [ActiveLookClient::new]
+pub struct Connection {
+    /// Notifications of the Tx characteristic
+    pub tx: NotificationReader,
+    /// Writes to the Rx characteristic
+    pub rx: CharacteristicWriter,
+    /// Notifications of the Control characteristic
+    pub ctrl: NotificationReader,
+    pub device: BluetoothDevice,
+}
+
+/// UUID formatted as expected by Web Bluetooth
+fn uuid(uuid: u128) -> String {
+    let hex = format!("{:032x}", uuid);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Ask the user to choose ActiveLook glasses.
+/// Browsers only allow it from the handler of a user gesture, like a click.
+pub async fn request_device() -> Result<BluetoothDevice, TransportError> {
+    let bluetooth = web_sys::window()
+        .and_then(|window| window.navigator().bluetooth())
+        .ok_or(TransportError::Unavailable)?;
+    let filter = BluetoothLeScanFilterInit::new();
+    filter.set_services(&[uuid(ACTIVELOOK_SERVICE_UUID).into()]);
+    let options = RequestDeviceOptions::new();
+    options.set_filters(&[filter]);
+    let device = JsFuture::from(bluetooth.request_device(&options)).await?;
+    Ok(device.unchecked_into())
+}
+
+/// Connect to `device`, discover the ActiveLook commands interface and subscribe to its
+/// notifications.
+pub async fn connect(
+    device: &BluetoothDevice,
+    config: ConnectionConfig,
+) -> Result<Connection, TransportError> {
+    let gatt = device.gatt().ok_or(TransportError::Disconnected)?;
+    JsFuture::from(gatt.connect()).await?;
+    let service = JsFuture::from(gatt.get_primary_service_with_str(&uuid(ACTIVELOOK_SERVICE_UUID)))
+        .await?
+        .unchecked_into::<web_sys::BluetoothRemoteGattService>();
+    let find = |id: u128| {
+        let promise = service.get_characteristic_with_str(&uuid(id));
+        async move {
+            let characteristic = JsFuture::from(promise).await?;
+            Ok::<_, TransportError>(
+                characteristic.unchecked_into::<BluetoothRemoteGattCharacteristic>(),
+            )
+        }
+    };
+    let tx_char = find(TX_CHARACTERISTIC_UUID).await?;
+    let rx_char = find(RX_CHARACTERISTIC_UUID).await?;
+    let ctrl_char = find(CONTROL_CHARACTERISTIC_UUID).await?;
+
+    Ok(Connection {
+        tx: NotificationReader::subscribe(tx_char).await?,
+        rx: CharacteristicWriter {
+            characteristic: rx_char,
+            queue: Rc::default(),
+            config,
+        },
+        ctrl: NotificationReader::subscribe(ctrl_char).await?,
+        device: device.clone(),
+    })
+}
+
+/// Wait for the response to `query_id`, letting the browser deliver the notifications.
+/// Responses to other pending queries received meanwhile are kept for their callers.
+pub async fn wait_response<Ctrl>(
+    client: &mut ActiveLookClient<NotificationReader, CharacteristicWriter, Ctrl>,
+    query_id: u32,
+) -> Result<Response, ProtocolError>
+where
+    Ctrl: Read + ReadReady,
+{
+    if !client.pending().is_pending(query_id) {
+        return Err(ProtocolError::IncorrectQueryId);
+    }
+    loop {
+        if let Some(response) = client.pending().take(query_id) {
+            return Ok(response);
+        }
+        match client.read_tx_char() {
+            Ok(packet) => client.pending().dispatch(packet),
+            Err(ProtocolError::Empty) => yield_now().await,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Send a command and wait for its response, see [wait_response]
+pub async fn query<Ctrl>(
+    client: &mut ActiveLookClient<NotificationReader, CharacteristicWriter, Ctrl>,
+    cmd: &Command,
+) -> Result<Response, ProtocolError>
+where
+    Ctrl: Read + ReadReady,
+{
+    let query_id = client.send_query(cmd)?;
+    wait_response(client, query_id).await
+}
+
+/// Give control back to the browser until its next task
+async fn yield_now() {
+    let promise = Promise::new(&mut |resolve, _reject| {
+        if let Some(window) = web_sys::window() {
+            let _ = window.set_timeout_with_callback(&resolve);
+        }
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+/// Non-blocking reader over the notifications of a characteristic
+pub struct NotificationReader {
+    characteristic: BluetoothRemoteGattCharacteristic,
+    pending: Rc<RefCell<VecDeque<u8>>>,
+    /// Kept alive as long as the browser may call it
+    _listener: Closure<dyn FnMut(Event)>,
+}
+
+impl NotificationReader {
+    async fn subscribe(
+        characteristic: BluetoothRemoteGattCharacteristic,
+    ) -> Result<Self, TransportError> {
+        let pending = Rc::new(RefCell::new(VecDeque::new()));
+        let listener = {
+            let characteristic = characteristic.clone();
+            let pending = pending.clone();
+            Closure::<dyn FnMut(Event)>::new(move |_event| {
+                if let Some(value) = characteristic.value() {
+                    let bytes = Uint8Array::new_with_byte_offset_and_length(
+                        &value.buffer(),
+                        value.byte_offset() as u32,
+                        value.byte_length() as u32,
+                    );
+                    pending.borrow_mut().extend(bytes.to_vec());
+                }
+            })
+        };
+        characteristic.set_oncharacteristicvaluechanged(Some(listener.as_ref().unchecked_ref()));
+        JsFuture::from(characteristic.start_notifications()).await?;
+        Ok(Self {
+            characteristic,
+            pending,
+            _listener: listener,
+        })
+    }
+}
+
+impl Drop for NotificationReader {
+    fn drop(&mut self) {
+        self.characteristic.set_oncharacteristicvaluechanged(None);
+    }
+}
+
+impl ErrorType for NotificationReader {
+    type Error = TransportError;
+}
+
+impl Read for NotificationReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let mut pending = self.pending.borrow_mut();
+        let len = buf.len().min(pending.len());
+        for (byte, value) in buf.iter_mut().zip(pending.drain(..len)) {
+            *byte = value;
+        }
+        Ok(len)
+    }
+}
+
+impl ReadReady for NotificationReader {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.pending.borrow().is_empty())
+    }
+}
+
+/// Writes waiting to be sent, one at a time as required by Web Bluetooth
+#[derive(Default)]
+struct WriteQueue {
+    chunks: VecDeque<Vec<u8>>,
+    sending: bool,
+    /// Last write rejected by the browser, returned by the next write
+    error: Option<TransportError>,
+}
+
+/// Writer to a characteristic, splitting the data according to the MTU
+pub struct CharacteristicWriter {
+    characteristic: BluetoothRemoteGattCharacteristic,
+    queue: Rc<RefCell<WriteQueue>>,
+    config: ConnectionConfig,
+}
+
+impl CharacteristicWriter {
+    /// Send the queued chunks, until the queue is empty
+    async fn send_queued(
+        characteristic: BluetoothRemoteGattCharacteristic,
+        queue: Rc<RefCell<WriteQueue>>,
+    ) {
+        loop {
+            let Some(chunk) = queue.borrow_mut().chunks.pop_front() else {
+                break;
+            };
+            let written = match characteristic
+                .write_value_without_response_with_u8_array(&Uint8Array::from(&chunk[..]))
+            {
+                Ok(promise) => JsFuture::from(promise).await.map(|_| ()),
+                Err(error) => Err(error),
+            };
+            if let Err(error) = written {
+                error!("Write failed: {:?}", error);
+                let mut queue = queue.borrow_mut();
+                queue.chunks.clear();
+                queue.error = Some(error.into());
+            }
+        }
+        queue.borrow_mut().sending = false;
+    }
+}
+
+impl ErrorType for CharacteristicWriter {
+    type Error = TransportError;
+}
+
+impl Write for CharacteristicWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let chunk_size = self.config.mtu.saturating_sub(ATT_HEADER_LEN).max(1);
+        let mut queue = self.queue.borrow_mut();
+        if let Some(error) = queue.error.take() {
+            return Err(error);
+        }
+        queue
+            .chunks
+            .extend(buf.chunks(chunk_size).map(<[u8]>::to_vec));
+        if !queue.sending {
+            queue.sending = true;
+            spawn_local(Self::send_queued(
+                self.characteristic.clone(),
+                self.queue.clone(),
+            ));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+//! Verified uploads
+//!
+//! Uploads of images, fonts and configurations are sent without acknowledgement: a write rejected
+//! by the glasses only shows up as an unsolicited [Response::CmdError]. [Uploader] checks each
+//! saved element after sending it, retries the failed ones, and summarizes the upload in an
+//! [UploadReport].
+//!
+//! A [CmdError::MemoryAccess] means the glasses memory is failing or full: the upload is aborted,
+//! retrying would only make things worse.
+//!
+//! Large images are better sent with [crate::client::ClientSender::upload_image], which reports
+//! a [ChunkProgress] after each chunk and stops when its [CancelToken] is cancelled.
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use crate::{
+    commands::{CmdError, Command, Response},
+    config::{Config, ElementKind, ElementRef},
+    glasses::{GlassesApi, GlassesError},
+    traits::*,
+};
+
+/// How to check that a command was stored
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Verification {
+    /// Trust the transport
+    None,
+    /// The saved element must be listed afterwards
+    List,
+    /// The free space must decrease. Replacing an element by one of the same size fails this
+    /// check, use it for fresh configurations.
+    FreeSpace,
+}
+
+/// Why a command was not stored
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FailureReason {
+    /// The glasses answered with an error
+    Glasses(CmdError),
+    /// The verification did not find the element
+    NotStored,
+    /// The transport failed
+    Transport(String),
+}
+
+/// Failed attempt to send a command
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UploadFailure {
+    /// Index of the command in the upload
+    pub index: usize,
+    pub cmd_id: u8,
+    pub reason: FailureReason,
+}
+
+/// Summary of an upload
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct UploadReport {
+    /// Data bytes sent, including retries
+    pub bytes_sent: usize,
+    /// Commands stored successfully
+    pub commands_sent: usize,
+    pub retries: usize,
+    /// Every failed attempt, including the retried ones
+    pub failures: Vec<UploadFailure>,
+    /// The upload stopped before the last command
+    pub aborted: bool,
+}
+
+impl UploadReport {
+    /// Every command was stored
+    pub fn is_success(&self) -> bool {
+        !self.aborted
+    }
+}
+
+/// Progress of a chunked upload, reported after each chunk
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChunkProgress {
+    /// Data bytes of the element sent so far
+    pub bytes_sent: usize,
+    pub total_bytes: usize,
+    /// Packets sent so far, the first one holds the header of the command
+    pub chunks_sent: usize,
+    pub total_chunks: usize,
+    /// The glasses asked to pause: the next chunk is sent once they accept data again
+    pub waiting: bool,
+}
+
+impl ChunkProgress {
+    /// Sent fraction, from 0.0 to 1.0
+    pub fn fraction(&self) -> f32 {
+        match self.total_chunks {
+            0 => 1.0,
+            total => self.chunks_sent as f32 / total as f32,
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.chunks_sent == self.total_chunks
+    }
+}
+
+/// Stops an upload between two chunks. Clones share the same state, keep one to cancel from
+/// another thread or a GUI callback.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Element saved by `cmd`, if any
+fn saved_element(cmd: &Command) -> Option<ElementRef> {
+    let (kind, id) = match cmd {
+        Command::ImgSave { id, .. } => (ElementKind::Image, id),
+        Command::FontSave { id, .. } => (ElementKind::Font, id),
+        Command::LayoutSave { id, .. } => (ElementKind::Layout, id),
+        Command::GaugeSave { id, .. } => (ElementKind::Gauge, id),
+        Command::PageSave { id, .. } => (ElementKind::Page, id),
+        Command::AnimSave { id, .. } => (ElementKind::Animation, id),
+        _ => return None,
+    };
+    Some(ElementRef::new(kind, *id))
+}
+
+fn free_space<G: GlassesApi>(glasses: &mut G) -> Result<u32, GlassesError> {
+    match glasses.query(&Command::CfgFreeSpace)? {
+        Response::CfgFreeSpace { free_space, .. } => Ok(free_space),
+        other => Err(GlassesError::UnexpectedResponse(other)),
+    }
+}
+
+/// Sends commands, checking that each saved element is stored
+pub struct Uploader<'a, G: GlassesApi> {
+    glasses: &'a mut G,
+    verification: Verification,
+    max_retries: usize,
+}
+
+impl<'a, G: GlassesApi> Uploader<'a, G> {
+    pub const DEFAULT_MAX_RETRIES: usize = 2;
+
+    /// Uploader verifying elements with [Verification::List]
+    pub fn new(glasses: &'a mut G) -> Self {
+        Self {
+            glasses,
+            verification: Verification::List,
+            max_retries: Self::DEFAULT_MAX_RETRIES,
+        }
+    }
+
+    pub fn verification(mut self, verification: Verification) -> Self {
+        self.verification = verification;
+        self
+    }
+
+    /// Number of times a command is sent again after failing
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Upload a whole configuration
+    pub fn upload_config(&mut self, config: &Config) -> UploadReport {
+        self.upload(&config.commands())
+    }
+
+    /// Send `cmds` in order, stopping at the first command failing more than the allowed retries
+    pub fn upload(&mut self, cmds: &[Command]) -> UploadReport {
+        let mut report = UploadReport::default();
+        for (index, cmd) in cmds.iter().enumerate() {
+            let cmd_id = cmd.id().unwrap_or_default();
+            let mut attempt = 0;
+            loop {
+                report.bytes_sent += cmd.data_bytes().map_or(0, |data| data.len());
+                let reason = match self.send_verified(cmd) {
+                    Ok(()) => {
+                        report.commands_sent += 1;
+                        break;
+                    }
+                    Err(reason) => reason,
+                };
+                let fatal = matches!(
+                    reason,
+                    FailureReason::Glasses(CmdError::MemoryAccess) | FailureReason::Transport(_)
+                );
+                report.failures.push(UploadFailure {
+                    index,
+                    cmd_id,
+                    reason,
+                });
+                if fatal || attempt >= self.max_retries {
+                    report.aborted = true;
+                    return report;
+                }
+                attempt += 1;
+                report.retries += 1;
+            }
+        }
+        report
+    }
+
+    fn send_verified(&mut self, cmd: &Command) -> Result<(), FailureReason> {
+        let element = saved_element(cmd);
+        let verification = match element {
+            Some(_) => self.verification,
+            None => Verification::None,
+        };
+        let before = match verification {
+            Verification::FreeSpace => Some(free_space(self.glasses).map_err(classify)?),
+            _ => None,
+        };
+        let sent = match cmd.chunk_policy() {
+            Some(_) => self.glasses.send_chunked(cmd),
+            None => self.glasses.send(cmd),
+        };
+        sent.map_err(classify)?;
+        let stored = match (verification, element) {
+            (Verification::List, Some(element)) => self
+                .glasses
+                .list(element.kind)
+                .map_err(classify)?
+                .contains(&element.id),
+            (Verification::FreeSpace, _) => {
+                free_space(self.glasses).map_err(classify)? < before.unwrap_or_default()
+            }
+            _ => true,
+        };
+        match stored {
+            true => Ok(()),
+            false => Err(FailureReason::NotStored),
+        }
+    }
+}
+
+/// Errors reported by the glasses are answered to the verification query
+fn classify(error: GlassesError) -> FailureReason {
+    match error {
+        GlassesError::UnexpectedResponse(Response::CmdError { error, .. }) => {
+            FailureReason::Glasses(error)
+        }
+        GlassesError::Command(error) => FailureReason::Glasses(error.error),
+        other => FailureReason::Transport(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Emulator;
+
+    /// Emulated glasses, returning the errors of sent commands to the next query
+    struct ErrorChannel {
+        emulator: Emulator,
+        /// Number of commands to drop
+        drop: usize,
+        error: Option<Response>,
+    }
+
+    impl ErrorChannel {
+        fn new() -> Self {
+            Self {
+                emulator: Emulator::new(),
+                drop: 0,
+                error: None,
+            }
+        }
+    }
+
+    impl GlassesApi for ErrorChannel {
+        fn send(&mut self, cmd: &Command) -> Result<(), GlassesError> {
+            if self.drop > 0 {
+                self.drop -= 1;
+                return Ok(());
+            }
+            if let Some(response) = self.emulator.handle(cmd) {
+                self.error = Some(response);
+            }
+            Ok(())
+        }
+
+        fn query(&mut self, cmd: &Command) -> Result<Response, GlassesError> {
+            match self.error.take() {
+                Some(error) => Err(GlassesError::UnexpectedResponse(error)),
+                None => self.emulator.handle(cmd).ok_or(GlassesError::Unsupported),
+            }
+        }
+    }
+
+    fn font(id: u8) -> Command {
+        Command::FontSave {
+            id,
+            size: 4,
+            data: vec![1, 10, 0, 0],
+        }
+    }
+
+    fn cfg_write() -> Command {
+        Command::CfgWrite {
+            name: String::from("test"),
+            version: 1,
+            password: 0,
+        }
+    }
+
+    #[test]
+    fn test_upload_retry() {
+        let mut glasses = ErrorChannel::new();
+        glasses.send(&cfg_write()).unwrap();
+        glasses.drop = 1;
+        let report = Uploader::new(&mut glasses).upload(&[font(5), font(6)]);
+        assert!(report.is_success());
+        assert_eq!(2, report.commands_sent);
+        assert_eq!(1, report.retries);
+        assert_eq!(21, report.bytes_sent);
+        assert_eq!(
+            vec![UploadFailure {
+                index: 0,
+                cmd_id: 0x51,
+                reason: FailureReason::NotStored
+            }],
+            report.failures
+        );
+
+        // Replacing font 5 does not change the free space
+        let report = Uploader::new(&mut glasses)
+            .verification(Verification::FreeSpace)
+            .max_retries(0)
+            .upload(&[font(5)]);
+        assert!(report.aborted);
+    }
+
+    #[test]
+    fn test_upload_abort() {
+        let mut glasses = ErrorChannel::new();
+        // No CfgWrite
+        let report = Uploader::new(&mut glasses).upload(&[font(5), font(6)]);
+        assert!(report.aborted);
+        assert_eq!(3, report.failures.len());
+        assert_eq!(
+            FailureReason::Glasses(CmdError::MissingCfgWrite),
+            report.failures[0].reason
+        );
+
+        glasses.error = Some(Response::CmdError {
+            cmd_id: 0x51,
+            error: CmdError::MemoryAccess,
+            sub_error: 0,
+        });
+        let report = Uploader::new(&mut glasses).upload(&[cfg_write(), font(5)]);
+        assert_eq!(1, report.commands_sent);
+        assert_eq!(0, report.retries);
+        assert!(report.aborted);
+    }
+}
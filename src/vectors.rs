@@ -0,0 +1,430 @@
+//! Test vectors
+//!
+//! Canonical encoding of a representative instance of every [Command] and [Response] variant.
+//! Teams implementing the protocol on other platforms can validate their encoders and decoders
+//! against them, see `activelook-cli gen-vectors`.
+use core::fmt::Debug;
+
+use crate::{
+    commands::*,
+    protocol::Packet,
+    traits::{Deserializable, Serializable},
+};
+
+/// Query ID used in [Vector::packet_with_query_id]
+pub const QUERY_ID: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+
+/// Encoding of one [Command] or [Response]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Vector {
+    /// Name of the variant
+    pub name: String,
+    pub id: u8,
+    /// Data bytes, after the protocol header
+    pub data: Vec<u8>,
+    /// Whole packet, without query ID
+    pub packet: Vec<u8>,
+    /// Whole packet, with [QUERY_ID]
+    pub packet_with_query_id: Vec<u8>,
+}
+
+impl Vector {
+    pub fn new<T: Serializable + Debug>(item: &T) -> Self {
+        let debug = format!("{:?}", item);
+        let name = debug
+            .split(|c: char| !c.is_alphanumeric())
+            .next()
+            .unwrap_or_default();
+        Self {
+            name: String::from(name),
+            id: item.id().expect("Valid item"),
+            data: item.data_bytes().expect("Valid item"),
+            packet: Packet::new(item).expect("Valid item").to_bytes(),
+            packet_with_query_id: Packet::new_with_query_id(item, &QUERY_ID)
+                .expect("Valid item")
+                .to_bytes(),
+        }
+    }
+
+    /// One line: name, ID, data, packet and packet with query ID, separated by tabs.
+    /// Bytes are in hexadecimal, `-` if empty.
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}\t{:02x}\t{}\t{}\t{}",
+            self.name,
+            self.id,
+            to_hex(&self.data),
+            to_hex(&self.packet),
+            to_hex(&self.packet_with_query_id)
+        )
+    }
+}
+
+/// Header line describing the columns of [Vector::to_line]
+pub const HEADER: &str = "# name\tid\tdata\tpacket\tpacket_with_query_id";
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return String::from("-");
+    }
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Vectors of every [Command] variant
+pub fn command_vectors() -> Vec<Vector> {
+    sample_commands().iter().map(Vector::new).collect()
+}
+
+/// Vectors of every [Response] variant
+pub fn response_vectors() -> Vec<Vector> {
+    sample_responses().iter().map(Vector::new).collect()
+}
+
+fn text(s: &str) -> String {
+    String::from(s)
+}
+
+/// One instance of each [Command] variant, with distinct field values
+pub fn sample_commands() -> Vec<Command> {
+    let p = Point { x: 10, y: -20 };
+    let q = Point { x: 300, y: 250 };
+    let lp = LayoutPosition { x: 30, y: 40 };
+    let layout_save = Command::from_data(
+        0x60,
+        Some(&[
+            1, // id
+            3, // size of additional commands
+            0, 30, 40, // position
+            0, 200, 60, // width and height
+            15, 0, // colors
+            2, 1, // font, text valid
+            0, 195, 55, // text position
+            4, 1, // rotation, opacity
+            0x30, 0x00, 0x0F, // additional commands
+        ]),
+    )
+    .expect("Valid LayoutSave");
+    vec![
+        Command::PowerDisplay { en: true },
+        Command::Clear,
+        Command::Grey { lvl: 7.into() },
+        Command::Demo {
+            demo_id: DemoID::Rect,
+        },
+        Command::Battery,
+        Command::Version,
+        Command::Led {
+            state: LedState::Blinking,
+        },
+        Command::Shift {
+            shift: Shift { x: -3, y: 4 },
+        },
+        Command::Settings,
+        Command::Luma { level: 12.into() },
+        Command::Sensor { en: true },
+        Command::Gesture { en: false },
+        Command::Als { en: true },
+        Command::Color { color: 15.into() },
+        Command::Point { coord: p },
+        Command::Line { from: p, to: q },
+        Command::Rect { from: p, to: q },
+        Command::RectFull { from: p, to: q },
+        Command::Circ { center: q, r: 25 },
+        Command::CircFull { center: q, r: 25 },
+        Command::Txt {
+            pos: q,
+            rotation: TextRotation::TOP_LR,
+            font_size: 2,
+            color: Grey::WHITE,
+            string: text("Hello"),
+        },
+        Command::Polyline {
+            thickness: 2,
+            _reserved: 0,
+            points: vec![p, q, Point { x: 0, y: 0 }],
+        },
+        Command::HoldFlush {
+            action: HoldFlushAction::Flush,
+        },
+        Command::Arc {
+            center: q,
+            r: 30,
+            angle_start: -90,
+            angle_end: 180,
+            thickness: 3,
+        },
+        Command::ImgSave {
+            id: 4,
+            size: 4,
+            width: 8,
+            format: ImgFormat::Img4bpp,
+            data: vec![0x01, 0x23, 0x45, 0x67],
+        },
+        Command::ImgDisplay { id: 4, coord: p },
+        Command::ImgStream {
+            size: 2,
+            width: 16,
+            coord: q,
+            format: StreamImgFormat::Img1bpp,
+            data: vec![0xF0, 0x0F],
+        },
+        Command::ImgDelete { id: Selector::All },
+        Command::ImgList,
+        Command::FontList,
+        Command::FontSave {
+            id: 5,
+            size: 3,
+            data: vec![0x01, 0x12, 0x20],
+        },
+        Command::FontSelect { id: 5 },
+        Command::FontDelete {
+            id: Selector::one(5).unwrap(),
+        },
+        layout_save,
+        Command::LayoutDelete {
+            id: Selector::one(1).unwrap(),
+        },
+        Command::LayoutDisplay {
+            id: 1,
+            text: text("42"),
+        },
+        Command::LayoutClear { id: 1 },
+        Command::LayoutList,
+        Command::LayoutPosition {
+            id: 1,
+            pos: lp.clone(),
+        },
+        Command::LayoutDisplayExtended {
+            id: 1,
+            pos: lp.clone(),
+            text: text("42"),
+            extra_cmd: vec![0x30, 0x00, 0x0F],
+        },
+        Command::LayoutGet { id: 1 },
+        Command::LayoutClearExtended {
+            id: 1,
+            pos: lp.clone(),
+        },
+        Command::LayoutClearAndDisplay {
+            id: 1,
+            text: text("42"),
+        },
+        Command::LayoutClearAndDisplayExtended {
+            id: 1,
+            pos: lp,
+            text: text("42"),
+            extra_cmd: Vec::new(),
+        },
+        Command::GaugeDisplay { id: 2, value: 75 },
+        Command::GaugeSave {
+            id: 2,
+            params: GaugeParameters {
+                pos: q,
+                radius: 50,
+                inner: 40,
+                start: 2,
+                end: 14,
+                clockwise: true,
+            },
+        },
+        Command::GaugeDelete {
+            id: Selector::one(2).unwrap(),
+        },
+        Command::GaugeList,
+        Command::GaugeGet { id: 2 },
+        Command::PageSave {
+            id: 3,
+            layouts: vec![10, 11],
+        },
+        Command::PageGet { id: 3 },
+        Command::PageDelete {
+            id: Selector::one(3).unwrap(),
+        },
+        Command::PageDisplay {
+            id: 3,
+            strings: vec![text("12"), text("km/h")],
+        },
+        Command::PageClear { id: 3 },
+        Command::PageList,
+        Command::PageClearAndDisplay {
+            id: 3,
+            strings: vec![text(""), text("5")],
+        },
+        Command::AnimSave {
+            id: 6,
+            total_size: 1000,
+            img_size: 400,
+            width: 20,
+            fmt: 0,
+            img_compressed_size: 400,
+        },
+        Command::AnimDelete {
+            id: Selector::one(6).unwrap(),
+        },
+        Command::AnimDisplay {
+            handler_id: 1,
+            id: 6,
+            delay: 100,
+            repeat: ALL,
+            pos: p,
+        },
+        Command::AnimClear {
+            handler_id: Selector::one(1).unwrap(),
+        },
+        Command::AnimList,
+        Command::PixelCount,
+        Command::CfgWrite {
+            name: text("demo"),
+            version: 3,
+            password: 0xDEADBEEF,
+        },
+        Command::CfgRead { name: text("demo") },
+        Command::CfgSet { name: text("demo") },
+        Command::CfgList,
+        Command::CfgRename {
+            old: text("demo"),
+            new: text("demo2"),
+            password: 0xDEADBEEF,
+        },
+        Command::CfgDelete {
+            name: text("demo2"),
+        },
+        Command::CfgDeleteLessUsed,
+        Command::CfgFreeSpace,
+        Command::CfgGetNb,
+        Command::shutdown(),
+        Command::reset(),
+        Command::Info {
+            id: DeviceInfo::SerialNumber,
+        },
+    ]
+}
+
+/// One instance of each [Response] variant, with distinct field values
+pub fn sample_responses() -> Vec<Response> {
+    let layout_get = Response::from_data(
+        0x67,
+        Some(&[
+            3, 0, 30, 40, 0, 200, 60, 15, 0, 2, 1, 0, 195, 55, 4, 1, 0x30, 0x00, 0x0F,
+        ]),
+    )
+    .expect("Valid LayoutGet");
+    vec![
+        Response::Battery { level: 87 },
+        Response::Version {
+            fw_version: [4, 12, 1, b'b'],
+            mfc_year: 24,
+            mfc_week: 12,
+            serial_number: [0x01, 0x02, 0x03],
+        },
+        Response::Settings {
+            x: -3,
+            y: 4,
+            luma: 12.into(),
+            als_enable: true,
+            gesture_enable: false,
+        },
+        Response::ImgList {
+            list: vec![
+                ImgListItem {
+                    id: 4,
+                    height: 2,
+                    width: 8,
+                },
+                ImgListItem {
+                    id: 7,
+                    height: 300,
+                    width: 256,
+                },
+            ],
+        },
+        Response::FontList {
+            list: vec![
+                FontItem { id: 1, height: 24 },
+                FontItem { id: 5, height: 18 },
+            ],
+        },
+        Response::LayoutList {
+            list: vec![1, 2, 10],
+        },
+        layout_get,
+        Response::GaugeList { list: vec![2] },
+        Response::GaugeGet {
+            params: GaugeParameters {
+                pos: Point { x: 300, y: 250 },
+                radius: 50,
+                inner: 40,
+                start: 2,
+                end: 14,
+                clockwise: true,
+            },
+        },
+        Response::PageGet {
+            id: 3,
+            layouts: vec![10, 11],
+        },
+        Response::PageList { list: vec![3, 4] },
+        Response::AnimList { list: vec![6] },
+        Response::PixelCount { count: 12345 },
+        Response::CfgRead {
+            version: 3,
+            nb_img: 1,
+            nb_layout: 2,
+            nb_font: 3,
+            nb_page: 4,
+            nb_gauge: 5,
+        },
+        Response::CfgList {
+            list: vec![CfgItem {
+                name: text("demo"),
+                size: 2048,
+                version: 3,
+                usage_counter: 7,
+                install_counter: 2,
+                is_system: false,
+            }],
+        },
+        Response::CfgFreeSpace {
+            total_size: 1_000_000,
+            free_space: 600_000,
+        },
+        Response::CfgGetNb { nb_config: 2 },
+        Response::CmdError {
+            cmd_id: 0x62,
+            error: CmdError::MissingCfgWrite,
+            sub_error: 1,
+        },
+        Response::RdDevInfo {
+            parameters: Vec::from(*b"ABC123"),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{CommandPacket, ResponsePacket};
+
+    #[test]
+    fn test_vectors_round_trip() {
+        for (cmd, vector) in sample_commands().iter().zip(command_vectors()) {
+            let packet = CommandPacket::from_bytes(&vector.packet_with_query_id).unwrap();
+            assert_eq!(Some(Vec::from(QUERY_ID)), packet.query_id);
+            assert_eq!(cmd, &packet.data, "{}", vector.name);
+        }
+        for (response, vector) in sample_responses().iter().zip(response_vectors()) {
+            let packet = ResponsePacket::from_bytes(&vector.packet).unwrap();
+            assert_eq!(response, &packet.data, "{}", vector.name);
+        }
+    }
+
+    #[test]
+    fn test_line() {
+        let vector = Vector::new(&Command::Grey { lvl: 7.into() });
+        assert_eq!(
+            "Grey\t02\t07\tff020006 07aa\tff0204 0a1234567807aa".replace(' ', ""),
+            vector.to_line()
+        );
+        assert_eq!("Clear", Vector::new(&Command::Clear).name);
+    }
+}